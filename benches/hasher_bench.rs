@@ -0,0 +1,72 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use fnv::FnvBuildHasher;
+use iron_rose::{DefaultBuildHasher, SeededHasher, SketchParams, StrataEstimator, IBF};
+use rustc_hash::FxHasher;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, BuildHasherDefault, Hasher};
+use twox_hash::XxHash64;
+
+const ELEMENTS: u64 = 10_000;
+
+/// MurmurHash3 (x64, 128 bit) of the bytes written, truncated to 64 bits. The murmur3 crate
+/// hashes readers rather than implementing Hasher, so the bytes are gathered in a fixed buffer
+/// first, which is plenty for the u64 elements benchmarked here.
+#[derive(Default)]
+struct Murmur3Hasher {
+    buf: [u8; 32],
+    len: usize,
+}
+
+impl Hasher for Murmur3Hasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn finish(&self) -> u64 {
+        let hash = murmur3::murmur3_x64_128(&mut &self.buf[..self.len], 0).expect("in memory");
+        hash as u64
+    }
+}
+
+fn bench_with<H: BuildHasher + Clone>(c: &mut Criterion, name: &str, hasher: H) {
+    let mut group = c.benchmark_group("hasher_encode");
+    group.throughput(Throughput::Elements(ELEMENTS));
+    group.bench_function(BenchmarkId::from_parameter(name), |b| {
+        let mut ibf = IBF::with_hasher(SketchParams::new(1000, 3), hasher.clone());
+        b.iter(|| {
+            for i in 0..ELEMENTS {
+                ibf.encode(black_box(i));
+            }
+        })
+    });
+    group.finish();
+
+    // An estimator places elements by a 64 bit hash, which is where the hasher comes in
+    let mut group = c.benchmark_group("hasher_estimator_ingest");
+    group.throughput(Throughput::Elements(ELEMENTS));
+    group.bench_function(BenchmarkId::from_parameter(name), |b| {
+        let mut estimator = StrataEstimator::default();
+        b.iter(|| {
+            for i in 0..ELEMENTS {
+                estimator.encode_prehashed(hasher.hash_one(black_box(i)));
+            }
+        })
+    });
+    group.finish();
+}
+
+/// MetroHash is the default, SipHash-2-4 the keyed hasher for untrusted peers; MurmurHash3,
+/// xxHash64, std's SipHash-1-3, FNV and FxHash are there for comparison
+pub fn hashers(c: &mut Criterion) {
+    bench_with(c, "metro", DefaultBuildHasher::default());
+    bench_with(c, "sip24_seeded", SeededHasher::new(*b"benchmark key 16"));
+    bench_with(c, "murmur3", BuildHasherDefault::<Murmur3Hasher>::default());
+    bench_with(c, "xxhash64", BuildHasherDefault::<XxHash64>::default());
+    bench_with(c, "std_sip13", RandomState::new());
+    bench_with(c, "fnv", FnvBuildHasher::default());
+    bench_with(c, "fx", BuildHasherDefault::<FxHasher>::default());
+}
+
+criterion_group!(benches, hashers);
+criterion_main!(benches);
@@ -1,5 +1,7 @@
-use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
-use iron_rose::IBF;
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
+use iron_rose::{Cell, DenseIbf, IBF};
 
 pub fn encode(c: &mut Criterion) {
     let mut group = c.benchmark_group("encode");
@@ -9,7 +11,7 @@ pub fn encode(c: &mut Criterion) {
         group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, &size| {
             b.iter(|| {
                 for i in 0..size {
-                    let _ = ibf.encode(black_box(i));
+                    ibf.encode(black_box(i));
                 }
             })
         });
@@ -17,5 +19,139 @@ pub fn encode(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, encode);
+pub fn encode_k(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode_k");
+    for size in [1000, 100000].iter() {
+        group.throughput(Throughput::Elements(*size as u64));
+        group.bench_with_input(BenchmarkId::new("dynamic", size), size, |b, &size| {
+            let mut ibf = IBF::new(1000);
+            b.iter(|| {
+                for i in 0..size {
+                    ibf.encode(black_box(i));
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("k3", size), size, |b, &size| {
+            let mut ibf = IBF::new(1000);
+            b.iter(|| {
+                for i in 0..size {
+                    ibf.encode_k::<3>(black_box(i));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn filled_pair(cells: usize) -> (IBF<u128>, IBF<u128>) {
+    let (mut left, mut right) = (IBF::new(cells), IBF::new(cells));
+    for i in 0..(cells as u128) {
+        left.encode(i);
+        right.encode(i + 1);
+    }
+    (left, right)
+}
+
+pub fn subtract(c: &mut Criterion) {
+    let mut group = c.benchmark_group("subtract");
+    for cells in [10000, 100000, 1000000].iter() {
+        let (left, right) = filled_pair(*cells);
+        group.throughput(Throughput::Elements(*cells as u64));
+        group.bench_with_input(BenchmarkId::new("serial", cells), cells, |b, _| {
+            b.iter(|| black_box(&left - &right))
+        });
+        group.bench_with_input(BenchmarkId::new("owned", cells), cells, |b, _| {
+            b.iter_batched(
+                || (left.clone(), right.clone()),
+                |(l, r)| black_box(l - r),
+                BatchSize::LargeInput,
+            )
+        });
+        #[cfg(feature = "rayon")]
+        group.bench_with_input(BenchmarkId::new("rayon", cells), cells, |b, _| {
+            b.iter(|| black_box(left.par_sub(&right)))
+        });
+        let (left, right) = (DenseIbf::from(left), DenseIbf::from(right));
+        group.bench_with_input(BenchmarkId::new("dense", cells), cells, |b, _| {
+            b.iter(|| black_box(&left - &right))
+        });
+    }
+    group.finish();
+}
+
+/// Cell<u128> subtraction on its own, against the same XORs and saturating subtractions over
+/// parallel arrays, the layout the compiler vectorizes best. If the generic cells fall far behind,
+/// they're worth specializing.
+pub fn cell_arithmetic(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cell_arithmetic");
+    for cells in [10000, 100000].iter() {
+        let cell = |i: u128| Cell::from_parts(i << 64 | i, i as u64 * 0x9e37_79b9, i as i32 % 3);
+        let left: Vec<Cell<u128>> = (0..*cells as u128).map(cell).collect();
+        let right: Vec<Cell<u128>> = (1..=*cells as u128).map(cell).collect();
+        group.throughput(Throughput::Elements(*cells as u64));
+        group.bench_with_input(BenchmarkId::new("cells", cells), cells, |b, _| {
+            b.iter_batched_ref(
+                || left.clone(),
+                |left| {
+                    for (l, r) in left.iter_mut().zip(right.iter()) {
+                        *l -= r;
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+
+        type Arrays = (Vec<u128>, Vec<u64>, Vec<i32>);
+        let arrays = |cells: &[Cell<u128>]| -> Arrays {
+            (
+                cells.iter().map(|c| *c.id_sum()).collect(),
+                cells.iter().map(Cell::hash_sum).collect(),
+                cells.iter().map(Cell::count).collect(),
+            )
+        };
+        let (left, right) = (arrays(&left), arrays(&right));
+        group.bench_with_input(BenchmarkId::new("arrays", cells), cells, |b, _| {
+            b.iter_batched_ref(
+                || left.clone(),
+                |(ids, hashes, counts)| {
+                    ids.iter_mut().zip(&right.0).for_each(|(l, r)| *l ^= r);
+                    hashes.iter_mut().zip(&right.1).for_each(|(l, r)| *l ^= r);
+                    counts
+                        .iter_mut()
+                        .zip(&right.2)
+                        .for_each(|(l, r)| *l = l.saturating_sub(*r));
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+pub fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    for cells in [10000, 100000, 1000000].iter() {
+        let (left, right) = filled_pair(*cells);
+        let diff = (&left - &right).expect("same shape");
+        group.throughput(Throughput::Elements(*cells as u64));
+        group.bench_with_input(BenchmarkId::new("serial", cells), cells, |b, _| {
+            b.iter(|| black_box(diff.clone().decode()))
+        });
+        #[cfg(feature = "rayon")]
+        group.bench_with_input(BenchmarkId::new("rayon", cells), cells, |b, _| {
+            b.iter(|| black_box(diff.clone().par_decode()))
+        });
+        let dense = DenseIbf::from(diff);
+        group.bench_with_input(BenchmarkId::new("dense", cells), cells, |b, _| {
+            b.iter(|| {
+                let mut out = Vec::new();
+                black_box(dense.clone().decode_into(&mut out)).ok();
+                out
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encode, encode_k, subtract, cell_arithmetic, decode);
 criterion_main!(benches);
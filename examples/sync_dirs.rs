@@ -0,0 +1,71 @@
+//! Reconciles two directories by the contents of their files, the first step of an rsync like
+//! tool: find out which files each side is missing, with messages sized by the differences
+//! rather than by the directories.
+//!
+//! Both peers run on this machine, but only exchange what two machines would send each other:
+//! an estimator each way, then IBFs of the size the estimate calls for, twice as large on every
+//! retry, and finally the hashes each side has to ask the other for.
+//!
+//! ```text
+//! cargo run --example sync_dirs --features fs -- path/to/left path/to/right
+//! ```
+use iron_rose::{
+    DirectorySketch, ErrorKind, FileDifference, IbfBuilder, SketchParams, StrataEstimator, IBF,
+};
+use std::{env, error::Error, process};
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.len() != 2 {
+        eprintln!("usage: sync_dirs LEFT RIGHT");
+        process::exit(2);
+    }
+    if let Err(err) = run(&args[0], &args[1]) {
+        eprintln!("sync_dirs: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run(left: &str, right: &str) -> Result<(), Box<dyn Error>> {
+    let (left, right) = (DirectorySketch::scan(left)?, DirectorySketch::scan(right)?);
+    let mut sent = 0;
+
+    // Left sends its estimator, right estimates from it
+    let estimator = left.estimator().to_bytes();
+    sent += estimator.len();
+    let estimate = right
+        .estimator()
+        .estimate_differences(&StrataEstimator::from_bytes(&estimator)?)?;
+
+    // Right sends IBFs until left can decode them
+    let mut params = IbfBuilder::new(estimate.max(1)).params();
+    let diff: FileDifference = loop {
+        let ibf = right.ibf(params).to_bytes();
+        sent += ibf.len();
+        match left.difference(&IBF::from_bytes(&ibf)?) {
+            Ok(diff) => break diff,
+            Err(err) if err.kind() == ErrorKind::DecodeFailed => {
+                params = SketchParams::new(params.size() * 2, params.hash_count());
+            }
+            Err(err) => return Err(err.into()),
+        }
+    };
+    // Left asks right for the files behind the hashes it's missing
+    sent += diff.remote_only.len() * 32;
+
+    println!(
+        "{} distinct files on the left, {} on the right, {} bytes exchanged",
+        left.len(),
+        right.len(),
+        sent
+    );
+    for path in diff.local_only.iter() {
+        println!("missing on the right: {}", path.display());
+    }
+    for hash in diff.remote_only.iter() {
+        for path in right.paths(hash) {
+            println!("missing on the left:  {}", path.display());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,55 @@
+#![no_main]
+use iron_rose::{Side, IBF};
+use libfuzzer_sys::fuzz_target;
+use std::collections::{HashMap, HashSet};
+
+/// Large enough that any difference of up to 256 elements almost always decodes
+const SIZE: usize = 1024;
+
+// Every two bytes are an operation: the low bit of the first picks encode or remove, the second
+// is the element. Elements come from a small domain so they repeat and interleave.
+fuzz_target!(|data: &[u8]| {
+    let mut ibf = IBF::new(SIZE);
+    let mut model: HashMap<u64, i64> = HashMap::new();
+    for op in data.chunks_exact(2) {
+        let element = u64::from(op[1]);
+        if op[0] & 1 == 0 {
+            ibf.encode(element);
+            *model.entry(element).or_default() += 1;
+        } else {
+            ibf.remove(element);
+            *model.entry(element).or_default() -= 1;
+        }
+    }
+    model.retain(|_, count| *count != 0);
+
+    // Cells only depend on how many times each element is in the multiset, not on the order
+    let mut rebuilt = IBF::new(SIZE);
+    for (&element, &count) in model.iter() {
+        for _ in 0..count.abs() {
+            if count > 0 {
+                rebuilt.encode(element);
+            } else {
+                rebuilt.remove(element);
+            }
+        }
+    }
+    assert_eq!(ibf, rebuilt);
+
+    // With every element in or out once, decoding recovers the model exactly
+    if model.values().all(|count| count.abs() == 1) {
+        let expected = model
+            .iter()
+            .map(|(&element, &count)| {
+                if count > 0 {
+                    Side::Left(element)
+                } else {
+                    Side::Right(element)
+                }
+            })
+            .collect::<HashSet<_>>();
+        if let Ok(decoded) = ibf.decode() {
+            assert_eq!(decoded, expected);
+        }
+    }
+});
@@ -0,0 +1,39 @@
+#![no_main]
+use bincode::Options;
+use iron_rose::{StrataEstimator, IBF};
+use libfuzzer_sys::fuzz_target;
+
+/// Deserializing should never allocate more than this, however large the claimed lengths are
+const LIMIT: u64 = 1 << 20;
+
+fn options() -> impl Options {
+    bincode::DefaultOptions::new().with_limit(LIMIT)
+}
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(ibf) = options().deserialize::<IBF<u128>>(data) {
+        let bytes = options().serialize(&ibf).expect("accepted IBFs serialize");
+        let again = options()
+            .deserialize::<IBF<u128>>(&bytes)
+            .expect("serialized IBFs deserialize");
+        assert_eq!(bytes, options().serialize(&again).unwrap());
+        let _ = ibf.decode();
+    }
+
+    if let Ok(estimator) = options().deserialize::<StrataEstimator>(data) {
+        let bytes = options()
+            .serialize(&estimator)
+            .expect("accepted estimators serialize");
+        let again = options()
+            .deserialize::<StrataEstimator>(&bytes)
+            .expect("serialized estimators deserialize");
+        assert_eq!(bytes, options().serialize(&again).unwrap());
+        let wire = StrataEstimator::from_bytes(&estimator.to_bytes())
+            .expect("accepted estimators round trip through the wire format");
+        assert_eq!(wire.to_bytes(), estimator.to_bytes());
+        let _ = estimator.estimate(&again);
+        let _ = estimator.estimate_overlapping(&StrataEstimator::default());
+        let mut estimator = estimator;
+        estimator.encode(0u64);
+    }
+});
@@ -0,0 +1,28 @@
+#![no_main]
+use iron_rose::{SeededHasher, IBF};
+use libfuzzer_sys::fuzz_target;
+
+// Two sketches straight from an attacker, counts, hashes and all. Subtracting, merging and
+// decoding them may fail, but must never panic or hang.
+fuzz_target!(|data: &[u8]| {
+    let (split, data) = match data.split_first() {
+        Some((split, data)) => (*split as usize * data.len() / 256, data),
+        None => return,
+    };
+    let (left, right) = data.split_at(split);
+    let (left, right) = match (IBF::<u64>::from_bytes(left), IBF::<u64>::from_bytes(right)) {
+        (Ok(left), Ok(right)) => (left, right),
+        _ => return,
+    };
+
+    let mut merged = left.clone();
+    let _ = merged.merge(&right);
+    if let Ok(diff) = &left - &right {
+        let _ = diff.clone().decode();
+        let _ = diff.clone().negate().decode_difference();
+        let _ = diff.clone().into_decoder().count();
+        let _ = diff.stuck_core();
+        let _ = diff.into_hasher(SeededHasher::new([7; 16])).decode();
+    }
+    let _ = left - right;
+});
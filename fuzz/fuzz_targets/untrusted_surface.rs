@@ -0,0 +1,5 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// Everything iron_rose::untrusted promises not to panic on, over arbitrary bytes
+fuzz_target!(|data: &[u8]| iron_rose::untrusted::exercise(data));
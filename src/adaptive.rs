@@ -0,0 +1,284 @@
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::hash::Hash;
+use serde::{Deserialize, Serialize};
+
+use crate::wire::{self, Reader, WireElement};
+use crate::{element_hash, profiles, Estimate, IronRoseError, SketchParams, IBF};
+
+/// A strata estimator that starts shallow and adds deeper strata as the set grows, so small
+/// sets get a small estimator and large sets still get estimates that decode.
+///
+/// Stratum i holds the elements whose hash has i trailing zeros, except the deepest, which holds
+/// every element with at least as many. Unlike a [StrataEstimator](crate::StrataEstimator)
+/// nothing wraps around, so the deepest stratum of a shallow estimator holds exactly what the
+/// deepest strata of a deeper one hold together. Estimators of different depths are compared
+/// by folding the deeper one's extra strata into its stratum at the shallower one's depth,
+/// which compares them over their common prefix of strata.
+///
+/// The hashes in the deepest stratum are kept aside, and when there are more of them than half
+/// the stratum's cells, enough that it might not decode, they are split off into a new stratum
+/// one deeper. Growth stops at [MAX_STRATA](profiles::MAX_STRATA). An estimator read from
+/// bytes doesn't know those hashes, so it keeps its depth.
+/// ```rust
+/// use iron_rose::{AdaptiveStrataEstimator, Estimate};
+///
+/// let (mut small, mut large) =
+///     (AdaptiveStrataEstimator::default(), AdaptiveStrataEstimator::default());
+/// for i in 0..3_000_000u64 {
+///     large.encode(i);
+///     if i < 1000 {
+///         small.encode(i);
+///     }
+/// }
+/// assert_eq!(small.strata(), 16);
+/// assert!(large.strata() > 16);
+///
+/// let mut peer = AdaptiveStrataEstimator::from_bytes(&small.to_bytes()).unwrap();
+/// peer.encode(5000u64);
+/// assert_eq!(small.estimate(&peer), Ok(Estimate::Exact(1)));
+/// assert!(!small.estimate(&large).unwrap().is_exact());
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdaptiveStrataEstimator {
+    ibfs: Vec<IBF<u64>>,
+    /// Hashes in the deepest stratum, local only, None if they aren't known
+    #[serde(skip)]
+    deepest: Option<Vec<u64>>,
+}
+
+impl Default for AdaptiveStrataEstimator {
+    fn default() -> Self {
+        Self::new(profiles::ADAPTIVE_STRATA, profiles::STRATUM)
+    }
+}
+
+impl AdaptiveStrataEstimator {
+    /// Empty estimator of strata IBFs shaped by stratum, starting with the given number of
+    /// strata. Panics unless `0 < strata <= MAX_STRATA`, see
+    /// [checked_strata](profiles::checked_strata).
+    pub fn new(strata: usize, stratum: SketchParams) -> Self {
+        let strata = profiles::checked_strata(strata);
+        Self {
+            ibfs: (0..strata).map(|_| IBF::with_params(stratum)).collect(),
+            deepest: Some(Vec::new()),
+        }
+    }
+
+    /// Number of strata so far
+    pub fn strata(&self) -> usize {
+        self.ibfs.len()
+    }
+
+    /// Shape of the IBF in each stratum
+    pub fn stratum(&self) -> SketchParams {
+        self.ibfs[0].params()
+    }
+
+    /// True if the estimator can still add strata, that is it wasn't read from bytes and isn't
+    /// [MAX_STRATA](profiles::MAX_STRATA) deep yet
+    pub fn can_grow(&self) -> bool {
+        self.deepest.is_some() && self.strata() < profiles::MAX_STRATA
+    }
+
+    /// Encodes an element, adding a stratum if the deepest one fills up
+    pub fn encode<T: Hash>(&mut self, element: T) {
+        self.encode_prehashed(element_hash(&element))
+    }
+
+    /// Encodes an element by its [element_hash](crate::element_hash) alone
+    pub fn encode_prehashed(&mut self, hash: u64) {
+        let stratum = self.stratum_of(hash);
+        self.ibfs[stratum].encode(hash);
+        if stratum == self.strata() - 1 {
+            if let Some(deepest) = self.deepest.as_mut() {
+                deepest.push(hash);
+            }
+            self.grow();
+        }
+    }
+
+    /// Undoes an [encode](AdaptiveStrataEstimator::encode) of element. Strata are never taken
+    /// away, so an estimator that shrinks keeps its depth.
+    pub fn remove<T: Hash>(&mut self, element: T) {
+        let hash = element_hash(&element);
+        let stratum = self.stratum_of(hash);
+        self.ibfs[stratum].remove(hash);
+        if stratum == self.strata() - 1 {
+            if let Some(deepest) = self.deepest.as_mut() {
+                if let Some(at) = deepest.iter().position(|h| *h == hash) {
+                    deepest.swap_remove(at);
+                }
+            }
+        }
+    }
+
+    fn stratum_of(&self, hash: u64) -> usize {
+        (hash.trailing_zeros() as usize).min(self.strata() - 1)
+    }
+
+    /// Splits the deepest stratum while it holds more than half its cells' worth of elements
+    fn grow(&mut self) {
+        while self.can_grow() {
+            let deepest = self.deepest.as_mut().expect("can_grow checked");
+            if deepest.len() * 2 <= self.ibfs[0].params().size() {
+                return;
+            }
+            let depth = self.ibfs.len();
+            let mut next = IBF::with_params(self.ibfs[0].params());
+            let last = self.ibfs.last_mut().expect("at least one stratum");
+            deepest.retain(|&hash| {
+                let deeper = hash.trailing_zeros() as usize >= depth;
+                if deeper {
+                    last.remove(hash);
+                    next.encode(hash);
+                }
+                deeper
+            });
+            self.ibfs.push(next);
+        }
+    }
+
+    /// Stratum i as an estimator only depth strata deep would hold it
+    fn folded(&self, i: usize, depth: usize) -> Result<IBF<u64>, IronRoseError> {
+        let mut stratum = self.ibfs[i].clone();
+        if i == depth - 1 {
+            for deeper in self.ibfs[depth..].iter() {
+                stratum.merge(deeper)?;
+            }
+        }
+        Ok(stratum)
+    }
+
+    /// Estimates the number of differences between the sets encoded into the two estimators,
+    /// over the strata they have in common. Fails with
+    /// [EstimatorSizeMismatch](IronRoseError::EstimatorSizeMismatch) unless their strata are
+    /// the same shape.
+    ///
+    /// Strata are decoded from the deepest down, until one fails. The strata below stratum i
+    /// hold a share of `2^-(i+1)` of the elements, so when it fails the differences counted
+    /// so far are scaled up by `2^(i+1)`.
+    pub fn estimate(&self, other: &AdaptiveStrataEstimator) -> Result<Estimate, IronRoseError> {
+        if self.stratum() != other.stratum() {
+            return Err(IronRoseError::EstimatorSizeMismatch {
+                left_strata: self.strata(),
+                left: self.stratum(),
+                right_strata: other.strata(),
+                right: other.stratum(),
+            });
+        }
+        let depth = self.strata().min(other.strata());
+        let mut count = 0usize;
+        for i in (0..depth).rev() {
+            let diff = (&self.folded(i, depth)? - &other.folded(i, depth)?)?;
+            let mut sides = Vec::new();
+            if diff.decode_into(&mut sides).is_err() {
+                let scale = 2_usize.saturating_pow(i as u32 + 1);
+                return Ok(Estimate::Approximate(count.saturating_mul(scale)));
+            }
+            count += sides.len();
+        }
+        Ok(Estimate::Exact(count))
+    }
+
+    /// The estimate, doubled to size an IBF with, like
+    /// [StrataEstimator::estimate_differences](crate::StrataEstimator::estimate_differences)
+    pub fn estimate_differences(
+        &self,
+        other: &AdaptiveStrataEstimator,
+    ) -> Result<usize, IronRoseError> {
+        Ok(self.estimate(other)?.value() * 2)
+    }
+
+    /// Serializes the estimator in the binary wire format, laid out like
+    /// [StrataEstimator::to_bytes](crate::StrataEstimator::to_bytes) with a kind byte of 3.
+    /// The number of strata is whatever the estimator has grown to.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_as(crate::WIRE_VERSION)
+            .expect("the current version is supported")
+    }
+
+    /// Serializes the estimator in the given version of the binary wire format, like
+    /// [IBF::to_bytes_as](crate::IBF::to_bytes_as), failing only on a version this build can't
+    /// write
+    pub fn to_bytes_as(&self, version: u8) -> Result<Vec<u8>, IronRoseError> {
+        wire::check_version(version)?;
+        let mut out = Vec::new();
+        wire::write_header(&mut out, version, wire::KIND_ADAPTIVE_STRATA, u64::WIDTH, 0);
+        let strata = u32::try_from(self.strata()).expect("at most MAX_STRATA strata");
+        out.extend_from_slice(&strata.to_le_bytes());
+        self.ibfs[0].write_shape(&mut out);
+        for ibf in self.ibfs.iter() {
+            ibf.write_cells(&mut out);
+        }
+        wire::seal(&mut out);
+        Ok(out)
+    }
+
+    /// Reads an estimator written by [to_bytes](AdaptiveStrataEstimator::to_bytes) or
+    /// [to_bytes_as](AdaptiveStrataEstimator::to_bytes_as). It can be
+    /// compared and encoded into, but won't grow.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, IronRoseError> {
+        let mut reader = Reader::new(bytes);
+        reader.header(wire::KIND_ADAPTIVE_STRATA, u64::WIDTH)?;
+        let strata = reader.u32()? as usize;
+        if strata == 0 || strata > profiles::MAX_STRATA {
+            return Err(IronRoseError::MalformedBytes {
+                reason: "number of strata out of range",
+            });
+        }
+        let (hash_count, size) = (reader.u32()? as usize, reader.u32()? as usize);
+        let ibfs = (0..strata)
+            .map(|_| IBF::read_cells(&mut reader, hash_count, size))
+            .collect::<Result<Vec<_>, _>>()?;
+        reader.finish()?;
+        Ok(Self {
+            ibfs,
+            deepest: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depths_compare_over_their_common_strata() {
+        let stratum = SketchParams::new(20, 3);
+        let (mut shallow, mut deep) = (
+            AdaptiveStrataEstimator::new(2, stratum),
+            AdaptiveStrataEstimator::new(2, stratum),
+        );
+        for i in 0..2000u64 {
+            deep.encode(i);
+            if i >= 10 {
+                shallow.encode(i);
+            }
+        }
+        assert!(deep.strata() > 2);
+        assert!(deep.deepest.as_ref().unwrap().len() * 2 <= 20);
+        assert_eq!(shallow.strata(), deep.strata());
+
+        // Strata split off when growing hold exactly what the deeper ones would have
+        let mut fresh = AdaptiveStrataEstimator::new(deep.strata(), stratum);
+        (0..2000u64).for_each(|i| fresh.encode(i));
+        assert_eq!(fresh.ibfs, deep.ibfs);
+
+        let received = AdaptiveStrataEstimator::from_bytes(&shallow.to_bytes()).unwrap();
+        assert!(!received.can_grow());
+        // One that couldn't grow folds the deeper one's strata into its last
+        let mut stunted = AdaptiveStrataEstimator::new(2, stratum);
+        stunted.deepest = None;
+        (10..2000u64).for_each(|i| stunted.encode(i));
+        assert_eq!(stunted.strata(), 2);
+        for other in [&received, &stunted] {
+            let estimate = deep.estimate(other).unwrap();
+            assert_eq!(estimate, other.estimate(&deep).unwrap());
+            assert!(estimate.value() >= 5, "{:?}", estimate);
+        }
+        assert_eq!(deep.estimate(&deep), Ok(Estimate::Exact(0)));
+        assert!(deep.estimate(&AdaptiveStrataEstimator::default()).is_err());
+        assert!(AdaptiveStrataEstimator::from_bytes(&deep.to_bytes()[..40]).is_err());
+    }
+}
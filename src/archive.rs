@@ -0,0 +1,299 @@
+use crate::wire::{self, Reader};
+use crate::{IronRoseError, MessageKind};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, convert::TryFrom};
+
+/// First wire version with archives
+pub(crate) const ARCHIVE_SINCE: u8 = 3;
+
+/// Longest string an archive holds, as its length is written as a u16
+const MAX_STRING_BYTES: usize = u16::MAX as usize;
+
+pub(crate) const FLAG_CREATOR: u8 = 1;
+pub(crate) const FLAG_CREATED_AT: u8 = 2;
+pub(crate) const FLAG_LABEL: u8 = 4;
+
+/// Provenance of an archived sketch: who made it, when, and what of, plus whatever else the
+/// application wants to record. Every part is optional, and strings are at most 64 KiB, the
+/// setters panic on longer ones.
+/// ```rust
+/// use iron_rose::SketchMetadata;
+/// use std::time::{SystemTime, UNIX_EPOCH};
+///
+/// let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+/// let metadata = SketchMetadata::default()
+///     .with_creator("billing-7")
+///     .with_created_at(now)
+///     .with_label("orders, EU region")
+///     .with_entry("schema", "v12");
+/// assert_eq!(metadata.label(), Some("orders, EU region"));
+/// assert_eq!(metadata.entry("schema"), Some("v12"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SketchMetadata {
+    creator: Option<String>,
+    created_at: Option<u64>,
+    label: Option<String>,
+    entries: BTreeMap<String, String>,
+}
+
+impl SketchMetadata {
+    /// Records who made the sketch, such as a host or service
+    pub fn with_creator(mut self, creator: &str) -> Self {
+        self.creator = Some(checked_string(creator));
+        self
+    }
+
+    /// Records when the sketch was made, in seconds since the Unix epoch
+    pub fn with_created_at(mut self, secs: u64) -> Self {
+        self.created_at = Some(secs);
+        self
+    }
+
+    /// Records what the sketch is of, such as the dataset it was built from
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = Some(checked_string(label));
+        self
+    }
+
+    /// Records value under key, replacing any value it had
+    pub fn with_entry(mut self, key: &str, value: &str) -> Self {
+        self.entries
+            .insert(checked_string(key), checked_string(value));
+        self
+    }
+
+    /// Who made the sketch
+    pub fn creator(&self) -> Option<&str> {
+        self.creator.as_deref()
+    }
+
+    /// When the sketch was made, in seconds since the Unix epoch
+    pub fn created_at(&self) -> Option<u64> {
+        self.created_at
+    }
+
+    /// What the sketch is of
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// The value recorded under key
+    pub fn entry(&self, key: &str) -> Option<&str> {
+        self.entries.get(key).map(String::as_str)
+    }
+
+    /// Every recorded key and value, by key
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+fn checked_string(s: &str) -> String {
+    assert!(
+        s.len() <= MAX_STRING_BYTES,
+        "archive metadata strings are at most 64 KiB"
+    );
+    s.to_string()
+}
+
+/// A sketch in the binary wire format, wrapped with [SketchMetadata](SketchMetadata) for long
+/// term storage, so sketches kept for audits can be identified months later without external
+/// bookkeeping. The sketch is kept as bytes, and read back with the `from_bytes` of its
+/// [kind](SketchArchive::kind).
+///
+/// Layout, from wire version 3 on: the 4 byte header with a kind byte of 4, an element width of
+/// 0, and flags telling which of creator, created-at and label follow; then each of those that
+/// is there, strings as a u16 length and UTF-8 bytes and the time as a u64; the number of
+/// entries as a u32 and each key and value as strings; the sketch's length as a u32 and its
+/// bytes; and the CRC32 footer. Integers are little endian.
+/// ```rust
+/// use iron_rose::{MessageKind, SketchArchive, SketchMetadata, StrataEstimator};
+///
+/// let mut estimator = StrataEstimator::default();
+/// (0..100u64).for_each(|x| estimator.encode(x));
+/// let metadata = SketchMetadata::default().with_label("orders 2026-10-17");
+/// let stored = SketchArchive::new(estimator.to_bytes(), metadata).unwrap().to_bytes();
+///
+/// // Months later
+/// let archive = SketchArchive::from_bytes(&stored).unwrap();
+/// assert_eq!(archive.metadata().label(), Some("orders 2026-10-17"));
+/// assert_eq!(archive.kind(), MessageKind::StrataEstimator);
+/// let back = StrataEstimator::from_bytes(archive.sketch()).unwrap();
+/// assert!(back.estimate(&estimator).unwrap().is_exact());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SketchArchive {
+    metadata: SketchMetadata,
+    kind: MessageKind,
+    sketch: Vec<u8>,
+}
+
+impl SketchArchive {
+    /// Wraps sketch, the bytes of any message in the binary wire format, failing if its header
+    /// isn't one, or if it's 4 GiB or more
+    pub fn new(sketch: Vec<u8>, metadata: SketchMetadata) -> Result<Self, IronRoseError> {
+        let kind = MessageKind::of(&sketch)?;
+        if u32::try_from(sketch.len()).is_err() {
+            return Err(IronRoseError::MalformedBytes {
+                reason: "sketch too large to archive",
+            });
+        }
+        Ok(Self {
+            metadata,
+            kind,
+            sketch,
+        })
+    }
+
+    /// The metadata
+    pub fn metadata(&self) -> &SketchMetadata {
+        &self.metadata
+    }
+
+    /// What kind of sketch is archived
+    pub fn kind(&self) -> MessageKind {
+        self.kind
+    }
+
+    /// The archived sketch, in the binary wire format
+    pub fn sketch(&self) -> &[u8] {
+        &self.sketch
+    }
+
+    /// Unwraps the archived sketch
+    pub fn into_sketch(self) -> Vec<u8> {
+        self.sketch
+    }
+
+    /// Serializes the archive in the binary wire format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let metadata = &self.metadata;
+        let mut flags = 0;
+        if metadata.creator.is_some() {
+            flags |= FLAG_CREATOR;
+        }
+        if metadata.created_at.is_some() {
+            flags |= FLAG_CREATED_AT;
+        }
+        if metadata.label.is_some() {
+            flags |= FLAG_LABEL;
+        }
+        let mut out = Vec::new();
+        wire::write_header(&mut out, crate::WIRE_VERSION, wire::KIND_ARCHIVE, 0, flags);
+        if let Some(creator) = &metadata.creator {
+            write_string(&mut out, creator);
+        }
+        if let Some(created_at) = metadata.created_at {
+            out.extend_from_slice(&created_at.to_le_bytes());
+        }
+        if let Some(label) = &metadata.label {
+            write_string(&mut out, label);
+        }
+        let entries = u32::try_from(metadata.entries.len()).expect("under 2^32 entries");
+        out.extend_from_slice(&entries.to_le_bytes());
+        for (key, value) in metadata.entries.iter() {
+            write_string(&mut out, key);
+            write_string(&mut out, value);
+        }
+        // Checked in new
+        out.extend_from_slice(&(self.sketch.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.sketch);
+        wire::seal(&mut out);
+        out
+    }
+
+    /// Reads an archive written by [to_bytes](SketchArchive::to_bytes), failing on an
+    /// unsupported format version, a checksum mismatch or malformed input, including an
+    /// archived sketch whose header isn't one
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, IronRoseError> {
+        if let Some(&version) = bytes.first().filter(|version| **version < ARCHIVE_SINCE) {
+            return Err(IronRoseError::UnsupportedVersion { version });
+        }
+        let mut reader = Reader::new(bytes);
+        let flags = reader.header(wire::KIND_ARCHIVE, 0)?;
+        let mut metadata = SketchMetadata::default();
+        if flags & FLAG_CREATOR != 0 {
+            metadata.creator = Some(read_string(&mut reader)?);
+        }
+        if flags & FLAG_CREATED_AT != 0 {
+            metadata.created_at = Some(reader.u64()?);
+        }
+        if flags & FLAG_LABEL != 0 {
+            metadata.label = Some(read_string(&mut reader)?);
+        }
+        for _ in 0..reader.u32()? {
+            let key = read_string(&mut reader)?;
+            metadata.entries.insert(key, read_string(&mut reader)?);
+        }
+        let len = reader.u32()? as usize;
+        let sketch = reader.take(len)?.to_vec();
+        reader.finish()?;
+        Self::new(sketch, metadata)
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    // Checked when the metadata was set
+    out.extend_from_slice(&(s.len() as u16).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn read_string(reader: &mut Reader<'_>) -> Result<String, IronRoseError> {
+    let len = reader.u16()? as usize;
+    let s = std::str::from_utf8(reader.take(len)?).map_err(|_| IronRoseError::MalformedBytes {
+        reason: "metadata is not UTF-8",
+    })?;
+    Ok(s.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IBF;
+
+    #[test]
+    fn archives_round_trip_and_are_checked() {
+        let mut ibf = IBF::new(20);
+        (0..10u64).for_each(|x| ibf.encode(x));
+        let metadata = SketchMetadata::default()
+            .with_creator("host-1")
+            .with_created_at(1_792_195_200)
+            .with_entry("rows", "10")
+            .with_entry("", "");
+        let archive = SketchArchive::new(ibf.to_bytes(), metadata).unwrap();
+        let bytes = archive.to_bytes();
+        let back = SketchArchive::from_bytes(&bytes).unwrap();
+        assert_eq!(back, archive);
+        assert_eq!(back.metadata().label(), None);
+        assert_eq!(back.metadata().entries().count(), 2);
+        assert_eq!(IBF::<u64>::from_bytes(back.sketch()), Ok(ibf.clone()));
+
+        // Without metadata, an archive is only the sketch and some framing
+        let bare = SketchArchive::new(ibf.to_bytes(), SketchMetadata::default()).unwrap();
+        assert_eq!(bare.to_bytes().len(), ibf.to_bytes().len() + 4 + 4 + 4 + 4);
+
+        let mut flipped = bytes.clone();
+        flipped[10] ^= 1;
+        assert_eq!(
+            SketchArchive::from_bytes(&flipped),
+            Err(IronRoseError::ChecksumMismatch)
+        );
+        let mut older = bytes.clone();
+        older[0] = 2;
+        assert_eq!(
+            SketchArchive::from_bytes(&older),
+            Err(IronRoseError::UnsupportedVersion { version: 2 })
+        );
+        assert!(SketchArchive::new(b"junk".to_vec(), SketchMetadata::default()).is_err());
+        assert_eq!(MessageKind::of(&bytes), Ok(MessageKind::Archive));
+    }
+
+    #[test]
+    #[should_panic(expected = "at most 64 KiB")]
+    fn long_metadata_is_refused() {
+        let _ = SketchMetadata::default().with_label(&"x".repeat(MAX_STRING_BYTES + 1));
+    }
+}
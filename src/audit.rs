@@ -0,0 +1,210 @@
+use crate::{DefaultBuildHasher, SketchParams, IBF};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fmt::Debug,
+    hash::{BuildHasher, Hash},
+    ops::{BitXor, BitXorAssign},
+};
+
+/// Collision statistics of a dataset's ids under a sketch configuration, from
+/// [audit_collisions](audit_collisions). Each count comes with what ids hashing at random would
+/// give, so ids that hash worse than random, such as ids a custom [Hash](Hash) impl maps to few
+/// values, stand out before the configuration is deployed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CollisionAudit {
+    /// The audited configuration
+    pub params: SketchParams,
+    /// Number of distinct ids
+    pub elements: usize,
+    /// Ids that appeared more than once. Encoding an id twice leaves it in no pure cell, so the
+    /// sketch of such a dataset can't decode it.
+    pub duplicates: usize,
+    /// Distinct ids whose full 64 bit hash, the one checked for a pure cell, was already taken
+    /// by another id
+    pub hash_collisions: usize,
+    /// Expected hash_collisions for random hashes, by the birthday bound
+    pub expected_hash_collisions: f64,
+    /// Distinct ids placed in the exact same cells as another id. Two such ids can never be
+    /// peeled apart if both end up in a difference.
+    pub placement_collisions: usize,
+    /// Expected placement_collisions for random hashes
+    pub expected_placement_collisions: f64,
+    /// Ids that more than one hash function placed in the same cell, so they occupy fewer than
+    /// hash_count cells
+    pub self_collisions: usize,
+    /// Expected self_collisions for random hashes
+    pub expected_self_collisions: f64,
+    /// Largest number of ids placed in one cell
+    pub max_cell_load: usize,
+    /// Variance over mean of the number of ids per cell, about 1 when ids spread out at random,
+    /// and much higher when they cluster on some cells
+    pub load_dispersion: f64,
+}
+
+impl CollisionAudit {
+    /// True when no id is duplicated and each collision count stays within a few standard
+    /// deviations of what random hashes would give. Clustering is judged by load_dispersion,
+    /// which has to stay under 1.5.
+    pub fn looks_random(&self) -> bool {
+        let within =
+            |seen: usize, expected: f64| seen as f64 <= expected + 4.0 * expected.sqrt() + 1.0;
+        self.duplicates == 0
+            && within(self.hash_collisions, self.expected_hash_collisions)
+            && within(
+                self.placement_collisions,
+                self.expected_placement_collisions,
+            )
+            && within(self.self_collisions, self.expected_self_collisions)
+            && self.load_dispersion < 1.5
+    }
+}
+
+/// Audits how ids, such as a sample of a production dataset, hash under params with the
+/// [default hasher](DefaultBuildHasher). This places every id as an IBF would, without
+/// building one, and keeps every distinct id in memory to tell collisions from duplicates.
+/// ```rust
+/// use iron_rose::{audit_collisions, SketchParams};
+///
+/// let params = SketchParams::new(1000, 3);
+/// let audit = audit_collisions(params, 0..100_000u64);
+/// assert_eq!(audit.elements, 100_000);
+/// assert!(audit.looks_random());
+///
+/// // Ids that only differ in bits the hash ignores collide on everything
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// struct Truncated(u64);
+/// impl std::hash::Hash for Truncated {
+///     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+///         (self.0 as u8).hash(state)
+///     }
+/// }
+/// # impl std::ops::BitXor for Truncated {
+/// #     type Output = Self;
+/// #     fn bitxor(self, rhs: Self) -> Self { Truncated(self.0 ^ rhs.0) }
+/// # }
+/// # impl std::ops::BitXorAssign for Truncated {
+/// #     fn bitxor_assign(&mut self, rhs: Self) { self.0 ^= rhs.0 }
+/// # }
+/// let audit = audit_collisions(params, (0..1000).map(Truncated));
+/// assert_eq!(audit.hash_collisions, 1000 - 256);
+/// assert!(!audit.looks_random());
+/// ```
+pub fn audit_collisions<T, I>(params: SketchParams, ids: I) -> CollisionAudit
+where
+    T: Clone + Hash + BitXor<Output = T> + BitXorAssign + Default + PartialEq + Eq + Debug,
+    I: IntoIterator<Item = T>,
+{
+    audit_collisions_with_hasher(params, DefaultBuildHasher::default(), ids)
+}
+
+/// Same as [audit_collisions](audit_collisions), for IBFs built
+/// [with](IBF::with_hasher) hasher
+pub fn audit_collisions_with_hasher<T, H, I>(
+    params: SketchParams,
+    hasher: H,
+    ids: I,
+) -> CollisionAudit
+where
+    T: Clone + Hash + BitXor<Output = T> + BitXorAssign + Default + PartialEq + Eq + Debug,
+    H: BuildHasher + Clone,
+    I: IntoIterator<Item = T>,
+{
+    let ibf: IBF<T, H> = IBF::with_hasher(params, hasher.clone());
+    let (size, hash_count) = (params.size(), params.hash_count());
+    let mut hashes: HashMap<u64, Vec<T>> = HashMap::new();
+    let mut placements: HashMap<Vec<usize>, usize> = HashMap::new();
+    let mut loads = vec![0usize; size];
+    let (mut elements, mut duplicates, mut hash_collisions, mut self_collisions) = (0, 0, 0, 0);
+
+    for id in ids {
+        let taken = match hashes.entry(hasher.hash_one(&id)) {
+            Entry::Occupied(mut same_hash) => {
+                if same_hash.get().contains(&id) {
+                    duplicates += 1;
+                    continue;
+                }
+                same_hash.get_mut().push(id.clone());
+                true
+            }
+            Entry::Vacant(slot) => {
+                slot.insert(vec![id.clone()]);
+                false
+            }
+        };
+        elements += 1;
+        if taken {
+            hash_collisions += 1;
+        }
+        let mut cells = ibf.cell_indexes(&id);
+        if cells.len() < hash_count {
+            self_collisions += 1;
+        }
+        for &cell in cells.iter() {
+            loads[cell] += 1;
+        }
+        cells.sort_unstable();
+        *placements.entry(cells).or_insert(0) += 1;
+    }
+    let placement_collisions = placements.values().map(|ids| ids - 1).sum();
+
+    let n = elements as f64;
+    let pairs = n * (n - 1.0) / 2.0;
+    let m = size as f64;
+    let k = hash_count as i32;
+    // P(all k picks distinct), and the chance a random id lands on one given set of k cells
+    let distinct: f64 = (0..hash_count).map(|i| (m - i as f64) / m).product();
+    let factorial: f64 = (1..=hash_count).map(|i| i as f64).product();
+    let mean = loads.iter().sum::<usize>() as f64 / m;
+    let variance = loads
+        .iter()
+        .map(|&load| (load as f64 - mean).powi(2))
+        .sum::<f64>()
+        / m;
+
+    CollisionAudit {
+        params,
+        elements,
+        duplicates,
+        hash_collisions,
+        expected_hash_collisions: pairs / 2f64.powi(64),
+        placement_collisions,
+        expected_placement_collisions: pairs * distinct * factorial / m.powi(k),
+        self_collisions,
+        expected_self_collisions: n * (1.0 - distinct),
+        max_cell_load: loads.iter().copied().max().unwrap_or(0),
+        load_dispersion: if mean > 0.0 { variance / mean } else { 1.0 },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SeededHasher;
+
+    #[test]
+    fn flags_clustered_and_duplicate_ids() {
+        let params = SketchParams::new(64, 3);
+        let audit = audit_collisions(params, (0..2000u64).chain(0..10));
+        assert_eq!(audit.elements, 2000);
+        assert_eq!(audit.duplicates, 10);
+        assert!(!audit.looks_random());
+
+        let audit = audit_collisions_with_hasher(
+            params,
+            SeededHasher::new([7; 16]),
+            (0..2000u64).map(|x| x << 32),
+        );
+        assert_eq!(audit.hash_collisions, 0);
+        assert!(audit.looks_random(), "{:?}", audit);
+        assert!((audit.load_dispersion - 1.0).abs() < 0.5);
+        let expected = audit.expected_placement_collisions;
+        assert!((audit.placement_collisions as f64 - expected).abs() < 4.0 * expected.sqrt() + 1.0);
+
+        // Everything in one cell
+        let single = audit_collisions(SketchParams::new(1, 3), 0..100u64);
+        assert_eq!(single.placement_collisions, 99);
+        assert_eq!(single.self_collisions, 100);
+        assert_eq!(single.max_cell_load, 100);
+    }
+}
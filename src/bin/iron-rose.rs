@@ -0,0 +1,328 @@
+//! Command line tool for building, diffing and inspecting sketches in the binary wire format,
+//! for debugging sync failures from captured sketches and id dumps.
+//!
+//! Id files hold one id per line, in decimal, `0x` prefixed hex, or as a hyphenated UUID, and
+//! `-` reads them from stdin. Sketches hold u64 ids unless built with `--u128`; every other
+//! command reads the element width from the file.
+use iron_rose::{IbfBuilder, IronRoseError, MessageKind, SketchParams, StrataEstimator, IBF};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    env,
+    error::Error,
+    fmt::Debug,
+    fs,
+    hash::Hash,
+    io::{self, BufRead, BufReader, Read},
+    ops::{BitXor, BitXorAssign},
+    process,
+};
+
+const USAGE: &str = "\
+usage: iron-rose <command> [options]
+
+commands:
+  build <ids> <out> [--size N] [--hash-count K] [--differences D] [--u128] [--estimator]
+      Encode the ids into an IBF, sized for D differences unless --size is given, or into a
+      default strata estimator with --estimator
+  estimate <left> <right>
+      Estimate the differences between two strata estimators
+  diff <left> <right> <out>
+      Subtract the right IBF from the left one
+  decode <ibf>
+      Decode a subtracted IBF, printing +id for left only and -id for right only ids
+  inspect <file>
+      Print the shape and cell occupancy of an IBF or strata estimator";
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(err) = run(&args) {
+        eprintln!("iron-rose: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.as_str(), Args::parse(args)?),
+        None => return Err(USAGE.into()),
+    };
+    match (command, args.positional.as_slice()) {
+        ("build", [ids, out]) if args.flag("estimator") => {
+            let mut estimator = StrataEstimator::default();
+            if args.flag("u128") {
+                read_ids::<u128>(ids)?
+                    .into_iter()
+                    .for_each(|id| estimator.encode(id));
+            } else {
+                read_ids::<u64>(ids)?
+                    .into_iter()
+                    .for_each(|id| estimator.encode(id));
+            }
+            Ok(fs::write(out, estimator.to_bytes())?)
+        }
+        ("build", [ids, out]) if args.flag("u128") => build::<u128>(&args, ids, out),
+        ("build", [ids, out]) => build::<u64>(&args, ids, out),
+        ("estimate", [left, right]) => {
+            let left = StrataEstimator::from_bytes(&fs::read(left)?)?;
+            let right = StrataEstimator::from_bytes(&fs::read(right)?)?;
+            let estimate = left.estimate(&right)?;
+            let exact = if estimate.is_exact() {
+                "exact"
+            } else {
+                "estimated"
+            };
+            println!("{} differences ({})", estimate.value(), exact);
+            Ok(())
+        }
+        ("diff", [left, right, out]) => {
+            let left = fs::read(left)?;
+            match element_width(&left, MessageKind::Ibf)? {
+                8 => diff::<u64>(&left, &fs::read(right)?, out),
+                _ => diff::<u128>(&left, &fs::read(right)?, out),
+            }
+        }
+        ("decode", [ibf]) => {
+            let bytes = fs::read(ibf)?;
+            match element_width(&bytes, MessageKind::Ibf)? {
+                8 => decode(IBF::<u64>::from_bytes(&bytes)?),
+                _ => decode(IBF::<u128>::from_bytes(&bytes)?),
+            }
+        }
+        ("inspect", [file]) => {
+            let bytes = fs::read(file)?;
+            match MessageKind::of(&bytes)? {
+                MessageKind::StrataEstimator => {
+                    inspect_estimator(&StrataEstimator::from_bytes(&bytes)?)
+                }
+                _ => match element_width(&bytes, MessageKind::Ibf)? {
+                    8 => inspect(&IBF::<u64>::from_bytes(&bytes)?),
+                    _ => inspect(&IBF::<u128>::from_bytes(&bytes)?),
+                },
+            }
+        }
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// Positional arguments and `--name [value]` options
+struct Args {
+    positional: Vec<String>,
+    options: BTreeMap<String, Option<String>>,
+}
+
+impl Args {
+    /// Options that take no value
+    const FLAGS: [&'static str; 2] = ["u128", "estimator"];
+
+    fn parse(args: &[String]) -> Result<Self> {
+        let mut parsed = Args {
+            positional: Vec::new(),
+            options: BTreeMap::new(),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if Self::FLAGS.contains(&name) => {
+                    parsed.options.insert(name.to_string(), None);
+                }
+                Some(name) => {
+                    let value = args.next().ok_or(format!("--{} needs a value", name))?;
+                    parsed.options.insert(name.to_string(), Some(value.clone()));
+                }
+                None => parsed.positional.push(arg.clone()),
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.options.contains_key(name)
+    }
+
+    fn number(&self, name: &str) -> Result<Option<usize>> {
+        match self.options.get(name) {
+            Some(Some(value)) => {
+                Ok(Some(value.parse().map_err(|_| {
+                    format!("--{} expects a number, not {:?}", name, value)
+                })?))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Element width from the header of a sketch of the given kind
+fn element_width(bytes: &[u8], kind: MessageKind) -> Result<usize> {
+    if MessageKind::of(bytes)? != kind {
+        return Err(IronRoseError::MalformedBytes {
+            reason: "not the expected kind of sketch",
+        }
+        .into());
+    }
+    match bytes.get(2) {
+        Some(&width) if width == 8 || width == 16 => Ok(width as usize),
+        _ => Err("only u64 and u128 ids are supported".into()),
+    }
+}
+
+/// Reads ids from path, or stdin for `-`
+fn read_ids<T: TryFrom<u128>>(path: &str) -> Result<Vec<T>> {
+    let reader: Box<dyn Read> = if path == "-" {
+        Box::new(io::stdin())
+    } else {
+        Box::new(fs::File::open(path)?)
+    };
+    let mut ids = Vec::new();
+    for (n, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let id = parse_id(line)
+            .and_then(|id| T::try_from(id).ok())
+            .ok_or_else(|| format!("line {}: {:?} is not a valid id", n + 1, line))?;
+        ids.push(id);
+    }
+    Ok(ids)
+}
+
+fn parse_id(text: &str) -> Option<u128> {
+    if let Some(hex) = text.strip_prefix("0x") {
+        u128::from_str_radix(hex, 16).ok()
+    } else if text.len() == 36 && text.contains('-') {
+        u128::from_str_radix(&text.replace('-', ""), 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+fn build<T>(args: &Args, ids: &str, out: &str) -> Result<()>
+where
+    T: Clone
+        + Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + iron_rose::WireElement
+        + TryFrom<u128>,
+{
+    let ids = read_ids::<T>(ids)?;
+    let params = match (args.number("size")?, args.number("differences")?) {
+        (Some(size), _) => SketchParams::new(size, args.number("hash-count")?.unwrap_or(3)),
+        (None, differences) => {
+            let mut builder = IbfBuilder::new(differences.unwrap_or(100)).total_items(ids.len());
+            if let Some(hash_count) = args.number("hash-count")? {
+                builder = builder.hash_count(hash_count);
+            }
+            builder.params()
+        }
+    };
+    let mut ibf = IBF::try_with_params(params)?;
+    ids.into_iter().for_each(|id| ibf.encode(id));
+    Ok(fs::write(out, ibf.to_bytes())?)
+}
+
+fn diff<T>(left: &[u8], right: &[u8], out: &str) -> Result<()>
+where
+    T: Clone
+        + Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + iron_rose::WireElement,
+{
+    let diff = (IBF::<T>::from_bytes(left)? - IBF::<T>::from_bytes(right)?)?;
+    Ok(fs::write(out, diff.to_bytes())?)
+}
+
+fn decode<T>(ibf: IBF<T>) -> Result<()>
+where
+    T: Clone
+        + Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + std::fmt::Display,
+{
+    match ibf.decode_difference() {
+        Ok(diff) => {
+            diff.iter_left().for_each(|id| println!("+{}", id));
+            diff.iter_right().for_each(|id| println!("-{}", id));
+            Ok(())
+        }
+        Err(err) => Err(format!(
+            "decoded {} ids before getting stuck with {} cells left, the IBF is too small",
+            err.decoded().len(),
+            err.remaining_cells()
+        )
+        .into()),
+    }
+}
+
+fn inspect<T>(ibf: &IBF<T>) -> Result<()>
+where
+    T: Clone + Hash + BitXor<Output = T> + BitXorAssign + Default + PartialEq + Eq + Debug,
+{
+    let params = ibf.params();
+    println!("ibf of {} byte ids", std::mem::size_of::<T>());
+    println!("cells: {}", params.size());
+    println!("hash count: {}", params.hash_count());
+    if let Some(keyspace) = ibf.keyspace() {
+        println!("keyspace: {}", keyspace);
+    }
+    println!("occupied cells: {}", ibf.occupied_cells());
+    println!("estimated elements: {:.1}", ibf.occupancy_estimate());
+    println!("count histogram:");
+    for (count, cells) in ibf.load_histogram() {
+        println!("  {:>6}: {}", count, cells);
+    }
+    Ok(())
+}
+
+fn inspect_estimator(estimator: &StrataEstimator) -> Result<()> {
+    println!("strata estimator");
+    println!("strata: {}", estimator.strata());
+    println!(
+        "stratum cells: {}, hash count: {}",
+        estimator.stratum_size(),
+        estimator.hash_count()
+    );
+    println!("stratum  occupied  estimated elements");
+    for load in estimator.distribution_report() {
+        println!(
+            "  {:>6}  {:>8}  {:.1}",
+            load.stratum, load.occupied_cells, load.estimated_elements
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_id_formats() {
+        assert_eq!(parse_id("42"), Some(42));
+        assert_eq!(parse_id("0xff"), Some(255));
+        assert_eq!(
+            parse_id("550e8400-e29b-41d4-a716-446655440000"),
+            Some(0x550e_8400_e29b_41d4_a716_4466_5544_0000)
+        );
+        assert_eq!(parse_id("nope"), None);
+        assert!(u64::try_from(parse_id("0x1ffffffffffffffff").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use crate::{theory, SketchParams, IBF};
+use std::{
+    fmt::Debug,
+    ops::{BitXor, BitXorAssign},
+};
+
+/// Failure probability [IbfBuilder](IbfBuilder) sizes for unless told otherwise
+pub const DEFAULT_FAILURE_PROBABILITY: f64 = 0.01;
+
+/// Below this many differences, 4 hash functions need fewer cells than 3 for the same failure
+/// probability, as small stopping sets (a few elements sharing all their cells) are far rarer.
+const FOUR_HASHES_BELOW: usize = 1000;
+
+/// From this many [total items](IbfBuilder::total_items) on, hash_count defaults to 3, as every
+/// item costs hash_count cell updates and building the sketch outweighs a few extra cells
+const CHEAP_UPDATES_FROM: usize = 1_000_000;
+
+/// Largest mean count of a cell a sketch is sized for, leaving room for the busiest cells to
+/// stay well clear of the limit of an i32 count
+const MAX_MEAN_CELL_LOAD: usize = (i32::MAX / 4) as usize;
+
+/// Sizes an IBF from the number of differences it has to decode, so call sites don't need a
+/// magic multiplier. The cell count is the larger of two bounds:
+///
+/// - the [peeling threshold](theory::threshold) times the differences, plus headroom that grows
+///   with their square root and with `ln(1 / failure_probability)`, which covers large IBFs
+/// - enough cells that two elements landing on the exact same `hash_count` cells, which no
+///   amount of peeling can separate, is less likely than `failure_probability`, which dominates
+///   for a few dozen differences
+///
+/// Both were fitted to measured failure rates, so decoding fails at roughly the requested rate
+/// or less. Unless set, hash_count is 4 for fewer than 1000 differences and 3 from there on.
+/// With a hash_count of 2 the second bound always dominates, and with 1, which can't peel, the
+/// [birthday bound](theory::single_hash_cells) is used instead; see [theory](theory) for what
+/// low hash counts trade off.
+/// ```rust
+/// use iron_rose::{IbfBuilder, IBF};
+///
+/// let params = IbfBuilder::new(100).params();
+/// assert_eq!(params.hash_count(), 4);
+/// assert!(params.size() > 129 && params.size() < 180);
+///
+/// let strict = IbfBuilder::new(100).failure_probability(0.001).params();
+/// assert!(strict.size() > params.size());
+///
+/// let mut left = IBF::for_expected_differences(100);
+/// let mut right = IbfBuilder::new(100).build();
+/// for i in 0..1000u64 {
+///     left.encode(i);
+///     right.encode(i + 50);
+/// }
+/// assert_eq!((left - right).unwrap().decode().unwrap().len(), 100);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IbfBuilder {
+    differences: usize,
+    hash_count: Option<usize>,
+    failure_probability: f64,
+    total_items: Option<usize>,
+}
+
+impl IbfBuilder {
+    /// Builder for an IBF expected to hold up to differences elements once subtracted
+    pub fn new(differences: usize) -> Self {
+        Self {
+            differences,
+            hash_count: None,
+            failure_probability: DEFAULT_FAILURE_PROBABILITY,
+            total_items: None,
+        }
+    }
+
+    /// The number of items each side encodes in all, as opposed to the differences left once
+    /// subtracted. Decoding only depends on the differences, since common items cancel out, but
+    /// the total changes two things:
+    ///
+    /// - from a million items on, hash_count defaults to 3 rather than 4, as encoding the items,
+    ///   one cell update per hash function each, costs more than the few extra cells
+    /// - there are enough cells to keep the mean cell count under `i32::MAX / 4`, so counts
+    ///   can't saturate before subtraction, which only matters for billions of items
+    ///
+    /// The 64 bit hash_sum of a cell doesn't depend on it. A cell of several elements passes
+    /// for pure with probability 2^-64, and only the cells left after subtraction are checked.
+    /// ```rust
+    /// use iron_rose::IbfBuilder;
+    ///
+    /// assert_eq!(IbfBuilder::new(100).params().hash_count(), 4);
+    /// let busy = IbfBuilder::new(100).total_items(50_000_000).params();
+    /// assert_eq!(busy.hash_count(), 3);
+    /// let huge = IbfBuilder::new(100).total_items(1 << 40).params();
+    /// assert!(huge.size() * (i32::MAX as usize / 4) >= 3 << 40);
+    /// ```
+    pub fn total_items(mut self, total_items: usize) -> Self {
+        self.total_items = Some(total_items);
+        self
+    }
+
+    /// Overrides the number of hash functions. Panics if hash_count is 0.
+    pub fn hash_count(mut self, hash_count: usize) -> Self {
+        assert!(hash_count >= 1, "hash_count must be at least 1");
+        self.hash_count = Some(hash_count);
+        self
+    }
+
+    /// Target probability of the IBF failing to decode, 0.01 by default. Panics unless it is
+    /// strictly between 0 and 1.
+    pub fn failure_probability(mut self, failure_probability: f64) -> Self {
+        assert!(
+            failure_probability > 0.0 && failure_probability < 1.0,
+            "failure_probability must be between 0 and 1"
+        );
+        self.failure_probability = failure_probability;
+        self
+    }
+
+    /// The shape of the IBF
+    pub fn params(&self) -> SketchParams {
+        let total_items = self.total_items.unwrap_or(0);
+        let hash_count = self.hash_count.unwrap_or(
+            if self.differences < FOUR_HASHES_BELOW && total_items < CHEAP_UPDATES_FROM {
+                4
+            } else {
+                3
+            },
+        );
+        let for_load = total_items
+            .saturating_mul(hash_count)
+            .div_ceil(MAX_MEAN_CELL_LOAD);
+        if hash_count == 1 {
+            let size = theory::single_hash_cells(self.differences, self.failure_probability);
+            return SketchParams::new(size.max(for_load), 1);
+        }
+        let d = self.differences as f64;
+        let log_odds = (1.0 / self.failure_probability).ln();
+
+        let bulk = theory::threshold(hash_count) * d
+            + 0.35 * log_odds * d.sqrt()
+            + log_odds * log_odds / 3.0;
+        // Two of d elements share all their cells with probability ~ d^2 k! / (2 m^k), the
+        // constant is raised a little to account for larger stopping sets.
+        let factorial: f64 = (1..=hash_count).map(|i| i as f64).product();
+        let small =
+            (0.75 * factorial * d * d / self.failure_probability).powf(1.0 / hash_count as f64);
+
+        let size = (bulk.max(small).ceil() as usize)
+            .max(hash_count)
+            .max(for_load);
+        SketchParams::new(size, hash_count)
+    }
+
+    /// An empty IBF of the [params](IbfBuilder::params)
+    pub fn build<T>(&self) -> IBF<T>
+    where
+        T: Clone
+            + std::hash::Hash
+            + BitXor<Output = T>
+            + BitXorAssign
+            + Default
+            + PartialEq
+            + Eq
+            + Debug,
+    {
+        IBF::with_params(self.params())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_at_about_the_target_rate() {
+        let mut x = 12345u64;
+        for &(differences, hash_count) in [
+            (5, None),
+            (50, None),
+            (50, Some(3)),
+            (2000, None),
+            (20, Some(2)),
+            (10, Some(1)),
+        ]
+        .iter()
+        {
+            let mut builder = IbfBuilder::new(differences);
+            if let Some(k) = hash_count {
+                builder = builder.hash_count(k);
+            }
+            let trials = 200_000 / differences.max(100);
+            let failures = (0..trials)
+                .filter(|_| {
+                    let mut ibf = builder.build();
+                    for _ in 0..differences {
+                        x = x
+                            .wrapping_mul(6364136223846793005)
+                            .wrapping_add(1442695040888963407);
+                        ibf.encode(x);
+                    }
+                    ibf.decode().is_err()
+                })
+                .count();
+            assert!(
+                failures * 100 <= trials * 2,
+                "{} of {} failed for {:?}",
+                failures,
+                trials,
+                builder.params()
+            );
+        }
+        assert_eq!(IbfBuilder::new(0).params().hash_count(), 4);
+        assert!(IbfBuilder::new(0).params().size() >= 4);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{MIN_WIRE_VERSION, WIRE_VERSION};
+use serde::{Deserialize, Serialize};
+
+/// What this build of the crate can do, from [capabilities](capabilities). Peers built with
+/// different features interoperate as long as they share a wire version and use the same
+/// kinds of sketch, so exchanging capabilities before a sync lets each side check that the
+/// other can read what it's about to send, and lets operators check what a binary was built
+/// with.
+///
+/// More capabilities may be added, so it can't be built with a struct literal outside the
+/// crate. It serializes with serde, to send to the peer. Fields a newer peer sends that this
+/// build doesn't know are ignored, and ones an older peer doesn't send read as off.
+/// ```rust
+/// use iron_rose::capabilities;
+///
+/// let local = capabilities();
+/// assert!(local.std);
+/// // In practice, read from the peer
+/// let remote = local;
+/// assert!(local.compatible_with(&remote));
+/// assert_eq!(local.missing(&remote).count(), 0);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// The [wire format](crate::WIRE_VERSION) version sketches are written in
+    pub wire_version: u8,
+    /// The oldest wire format version still read and written, 0 from peers that don't send it,
+    /// which only read their own
+    pub min_wire_version: u8,
+    /// Everything beyond the core IBF, estimators and wire format, the `std` feature
+    pub std: bool,
+    /// Parallel subtraction and decoding, the `rayon` feature
+    pub rayon: bool,
+    /// Reconciliation over async streams, the `tokio` feature
+    pub tokio: bool,
+    /// Reconciling key-value pairs, which comes with `std`
+    pub keyed: bool,
+    /// Rateless streams of cells, which comes with `std`
+    pub rateless: bool,
+    /// Subtraction on the GPU, the `gpu` feature
+    pub gpu: bool,
+    /// Reconciling directories by content hash, the `fs` feature
+    pub fs: bool,
+    /// Differentially private estimators, the `dp` feature
+    pub dp: bool,
+    /// Sketches built from roaring bitmaps, the `roaring` feature
+    pub roaring: bool,
+    /// Reading and writing the v0 formats, the `legacy-v0` feature
+    pub legacy_v0: bool,
+    /// Recording and replaying element placements, the `replay` feature
+    pub replay: bool,
+    /// ULID elements, the `ulid` feature
+    pub ulid: bool,
+}
+
+/// The capabilities of this build
+pub const fn capabilities() -> Capabilities {
+    Capabilities {
+        wire_version: WIRE_VERSION,
+        min_wire_version: MIN_WIRE_VERSION,
+        std: cfg!(feature = "std"),
+        rayon: cfg!(feature = "rayon"),
+        tokio: cfg!(feature = "tokio"),
+        keyed: cfg!(feature = "std"),
+        rateless: cfg!(feature = "std"),
+        gpu: cfg!(feature = "gpu"),
+        fs: cfg!(feature = "fs"),
+        dp: cfg!(feature = "dp"),
+        roaring: cfg!(feature = "roaring"),
+        legacy_v0: cfg!(feature = "legacy-v0"),
+        replay: cfg!(feature = "replay"),
+        ulid: cfg!(feature = "ulid"),
+    }
+}
+
+impl Capabilities {
+    /// Names of the capabilities that are on, as the features are named
+    pub fn enabled(&self) -> impl Iterator<Item = &'static str> {
+        IntoIterator::into_iter(self.flags())
+            .filter(|(_, on)| *on)
+            .map(|(name, _)| name)
+    }
+
+    /// Names of the capabilities the peer has and this build lacks, which the peer shouldn't
+    /// rely on when talking to this build
+    pub fn missing(&self, peer: &Capabilities) -> impl Iterator<Item = &'static str> {
+        IntoIterator::into_iter(self.flags())
+            .zip(IntoIterator::into_iter(peer.flags()))
+            .filter(|((_, local), (_, remote))| *remote && !local)
+            .map(|((name, _), _)| name)
+    }
+
+    /// True if the two builds read each other's sketches, that is share a wire version. Which
+    /// optional sketches they may exchange is up to [missing](Capabilities::missing).
+    pub fn compatible_with(&self, peer: &Capabilities) -> bool {
+        self.common_wire_version(peer).is_some()
+    }
+
+    /// The newest wire format version both builds read and write, if any
+    pub fn common_wire_version(&self, peer: &Capabilities) -> Option<u8> {
+        let newest = self.wire_version.min(peer.wire_version);
+        let oldest = self.oldest_wire_version().max(peer.oldest_wire_version());
+        Some(newest).filter(|newest| *newest >= oldest)
+    }
+
+    fn oldest_wire_version(&self) -> u8 {
+        match self.min_wire_version {
+            0 => self.wire_version,
+            min => min,
+        }
+    }
+
+    fn flags(&self) -> [(&'static str, bool); 12] {
+        [
+            ("std", self.std),
+            ("rayon", self.rayon),
+            ("tokio", self.tokio),
+            ("keyed", self.keyed),
+            ("rateless", self.rateless),
+            ("gpu", self.gpu),
+            ("fs", self.fs),
+            ("dp", self.dp),
+            ("roaring", self.roaring),
+            ("legacy-v0", self.legacy_v0),
+            ("replay", self.replay),
+            ("ulid", self.ulid),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capabilities_follow_features() {
+        let local = capabilities();
+        assert_eq!(local.wire_version, WIRE_VERSION);
+        assert_eq!(local.rayon, cfg!(feature = "rayon"));
+        assert!(local.enabled().any(|name| name == "std"));
+
+        let mut remote = local;
+        remote.gpu = true;
+        remote.roaring = true;
+        let missing = local.missing(&remote).collect::<Vec<_>>();
+        let expected = ["gpu", "roaring"]
+            .iter()
+            .copied()
+            .filter(|name| local.enabled().all(|enabled| enabled != *name))
+            .collect::<Vec<_>>();
+        assert_eq!(missing, expected);
+        assert_eq!(remote.missing(&local).count(), 0);
+        assert!(local.compatible_with(&remote));
+
+        // A newer peer that still writes this build's version
+        remote.wire_version += 1;
+        assert_eq!(local.common_wire_version(&remote), Some(WIRE_VERSION));
+        remote.min_wire_version = WIRE_VERSION + 1;
+        assert!(!local.compatible_with(&remote));
+        // An older peer that doesn't send its oldest version only reads its own
+        remote.wire_version = MIN_WIRE_VERSION;
+        remote.min_wire_version = 0;
+        assert_eq!(local.common_wire_version(&remote), Some(MIN_WIRE_VERSION));
+        remote.wire_version = MIN_WIRE_VERSION - 1;
+        assert!(!local.compatible_with(&remote));
+        let remote: Capabilities =
+            serde_json::from_str(&serde_json::to_string(&local).unwrap()).unwrap();
+        assert_eq!(remote, local);
+        let older: Capabilities =
+            serde_json::from_str(r#"{"wire_version":2,"rayon":true}"#).unwrap();
+        assert!(older.rayon && !older.std);
+    }
+}
@@ -1,16 +1,36 @@
-use fasthash::MetroHasher as ElmHasher;
+use crate::count::Count;
+use crate::metro::MetroHasher as ElmHasher;
+use crate::wire::{Reader, WireElement};
+use crate::IronRoseError;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::hash::{BuildHasher, Hash, Hasher};
+use core::ops::{Add, AddAssign, BitXor, BitXorAssign, Deref, Sub, SubAssign};
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
-use std::hash::Hash;
-use std::hash::Hasher;
-use std::ops::{Add, BitXor, BitXorAssign, Deref, Sub, SubAssign};
 
-/// Which side of the IBF is this from
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Which side of the IBF is this from. Ordered with every Left before every Right, then by
+/// element, so a decoded set can be sorted for logs and tests. Serializes with serde as an
+/// externally tagged enum, `{"Left": 1}` in JSON, to send decoded differences back to a peer or
+/// persist them.
+/// ```rust
+/// use iron_rose::{Side, IBF};
+///
+/// let (mut left, mut right) = (IBF::new(20), IBF::new(20));
+/// left.encode(3u64);
+/// left.encode(1);
+/// right.encode(2);
+/// let mut sides: Vec<_> = (left - right).unwrap().decode().unwrap().into_iter().collect();
+/// sides.sort();
+/// assert_eq!(sides, [Side::Left(1), Side::Left(3), Side::Right(2)]);
+/// let json = serde_json::to_string(&sides).unwrap();
+/// assert_eq!(json, r#"[{"Left":1},{"Left":3},{"Right":2}]"#);
+/// assert_eq!(serde_json::from_str::<Vec<Side<u64>>>(&json).unwrap(), sides);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Side<T>
 where
     T: Clone
-        + std::hash::Hash
+        + core::hash::Hash
         + BitXor<Output = T>
         + BitXorAssign
         + Default
@@ -27,7 +47,7 @@ where
 impl<T> Deref for Side<T>
 where
     T: Clone
-        + std::hash::Hash
+        + core::hash::Hash
         + BitXor<Output = T>
         + BitXorAssign
         + Default
@@ -45,58 +65,120 @@ where
     }
 }
 
-#[derive(Debug, Copy, Clone, Deserialize, Serialize, Default)]
-pub(crate) struct Cell<T>
+/// One cell of an [IBF](crate::IBF): the XOR of the elements hashed to it, the XOR of their
+/// hashes, and how many there are. Only needed to move sketches around as
+/// [raw parts](crate::IBF::into_raw_parts).
+///
+/// Counts are i32 unless the IBF was built with another [Count](Count) width. They saturate at
+/// the bounds of their type rather than wrap or panic, so cells from a crafted sketch can't crash
+/// the process. IBFs check for overflow before combining cells, see
+/// [CountOverflow](IronRoseError::CountOverflow).
+#[derive(Debug, Copy, Clone, Deserialize, Serialize, Default, PartialEq, Eq)]
+pub struct Cell<T, C = i32>
 where
     T: Clone
-        + std::hash::Hash
+        + core::hash::Hash
         + BitXor<Output = T>
         + BitXorAssign
         + Default
         + PartialEq
         + Eq
         + Debug,
+    C: Count,
 {
     id_sum: T,
     hash_sum: u64,
-    count: i32,
+    count: C,
 }
-impl<T> Cell<T>
+impl<T, C> Cell<T, C>
 where
     T: Clone
-        + std::hash::Hash
+        + core::hash::Hash
         + BitXor<Output = T>
         + BitXorAssign
         + Default
         + PartialEq
         + Eq
         + Debug,
+    C: Count,
 {
-    pub(crate) fn encode(&mut self, element: T) {
-        let mut hasher: ElmHasher = Default::default();
-        element.hash(&mut hasher);
+    /// A cell from its XOR of elements, XOR of element hashes and count
+    pub fn from_parts(id_sum: T, hash_sum: u64, count: C) -> Self {
+        Self {
+            id_sum,
+            hash_sum,
+            count,
+        }
+    }
+
+    /// XOR of the elements in the cell
+    pub fn id_sum(&self) -> &T {
+        &self.id_sum
+    }
+
+    /// XOR of the [element_hash](crate::element_hash)es of the elements in the cell
+    pub fn hash_sum(&self) -> u64 {
+        self.hash_sum
+    }
 
+    /// Adds element, saturating the count, see [IBF::encode](crate::IBF::encode)
+    pub(crate) fn encode<H: BuildHasher>(&mut self, element: T, hasher: &H) {
+        self.hash_sum ^= hasher.hash_one(&element);
         self.id_sum ^= element;
-        self.hash_sum ^= hasher.finish();
-        self.count += 1;
+        self.count = self.count.saturating_add(C::ONE);
     }
 
-    pub(crate) fn is_pure(&self) -> bool {
-        let mut hasher: ElmHasher = Default::default();
-        self.id_sum.hash(&mut hasher);
+    /// Undoes an [encode](Cell::encode) of element
+    pub(crate) fn unencode<H: BuildHasher>(&mut self, element: T, hasher: &H) {
+        self.hash_sum ^= hasher.hash_one(&element);
+        self.id_sum ^= element;
+        self.count = self.count.saturating_sub(C::ONE);
+    }
 
-        (self.count == 1 || self.count == -1) && self.hash_sum == hasher.finish()
+    /// True if the cell holds a single element, checked against its hash from hasher
+    pub(crate) fn is_pure<H: BuildHasher>(&self, hasher: &H) -> bool {
+        (self.count == C::ONE || self.count == C::ONE.saturating_neg())
+            && self.hash_sum == hasher.hash_one(&self.id_sum)
     }
 
+    #[inline]
     pub(crate) fn is_empty(&self) -> bool {
-        self.count == 0 && self.hash_sum == 0 && self.id_sum == Default::default()
+        self.count == C::ZERO && self.hash_sum == 0 && self.id_sum == Default::default()
+    }
+
+    /// Net number of elements in the cell, left side positive
+    pub fn count(&self) -> C {
+        self.count
+    }
+
+    /// The same cell with its count in another width, None if the count doesn't fit
+    pub(crate) fn into_count_width<C2: Count>(self) -> Option<Cell<T, C2>> {
+        Some(Cell {
+            id_sum: self.id_sum,
+            hash_sum: self.hash_sum,
+            count: C2::from_i64(self.count.to_i64())?,
+        })
+    }
+
+    /// Flips which side the cell's elements are on
+    pub(crate) fn negate(&mut self) {
+        self.count = self.count.saturating_neg();
+    }
+
+    /// Hash of the whole cell, to cheaply compare cells between replicas
+    pub(crate) fn fingerprint(&self) -> u64 {
+        let mut hasher: ElmHasher = Default::default();
+        self.id_sum.hash(&mut hasher);
+        self.hash_sum.hash(&mut hasher);
+        self.count.hash(&mut hasher);
+        hasher.finish()
     }
 
-    pub(crate) fn decode(&self) -> Result<Side<T>, String> {
-        if !self.is_pure() {
-            return Err("Impure bucket".to_string());
+    pub(crate) fn decode<H: BuildHasher>(&self, hasher: &H) -> Result<Side<T>, &'static str> {
+        if !self.is_pure(hasher) {
+            return Err("Impure bucket");
         }
-        Ok(if self.count == 1 {
+        Ok(if self.count == C::ONE {
             Side::Left(self.id_sum.clone())
         } else {
             Side::Right(self.id_sum.clone())
@@ -104,85 +186,166 @@ where
     }
 }
 
-impl<T> Add for Cell<T>
+impl<T, C> Cell<T, C>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + WireElement,
+    C: Count,
+{
+    /// Bytes a cell takes in the binary wire format
+    pub(crate) const WIRE_WIDTH: usize = T::WIDTH + 8 + C::WIDTH;
+
+    /// Appends the cell as `id_sum`, `hash_sum`, `count`, little endian
+    pub(crate) fn write_bytes(&self, out: &mut Vec<u8>) {
+        self.id_sum.write_le(out);
+        out.extend_from_slice(&self.hash_sum.to_le_bytes());
+        self.count.write_le(out);
+    }
+
+    /// Reads a cell written by [write_bytes](Cell::write_bytes)
+    pub(crate) fn read_bytes(reader: &mut Reader<'_>) -> Result<Self, IronRoseError> {
+        Ok(Self {
+            id_sum: T::read_le(reader.take(T::WIDTH)?),
+            hash_sum: reader.u64()?,
+            count: C::read_le(reader.take(C::WIDTH)?),
+        })
+    }
+}
+
+impl<T, C> Add for Cell<T, C>
 where
     T: Clone
-        + std::hash::Hash
+        + core::hash::Hash
         + BitXor<Output = T>
         + BitXorAssign
         + Default
         + PartialEq
         + Eq
         + Debug,
+    C: Count,
 {
-    type Output = Cell<T>;
+    type Output = Cell<T, C>;
 
+    #[inline]
     fn add(self, rhs: Self) -> Self::Output {
         Self {
             id_sum: self.id_sum ^ rhs.id_sum,
             hash_sum: self.hash_sum ^ rhs.hash_sum,
-            count: self.count + rhs.count,
+            count: self.count.saturating_add(rhs.count),
         }
     }
 }
-impl<T> SubAssign for Cell<T>
+impl<T, C> AddAssign<&Cell<T, C>> for Cell<T, C>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    C: Count,
+{
+    #[inline]
+    fn add_assign(&mut self, rhs: &Self) {
+        self.id_sum ^= rhs.id_sum.clone();
+        self.hash_sum ^= rhs.hash_sum;
+        self.count = self.count.saturating_add(rhs.count);
+    }
+}
+
+impl<T, C> SubAssign for Cell<T, C>
 where
     T: Clone
-        + std::hash::Hash
+        + core::hash::Hash
         + BitXor<Output = T>
         + BitXorAssign
         + Default
         + PartialEq
         + Eq
         + Debug,
+    C: Count,
 {
+    #[inline]
     fn sub_assign(&mut self, rhs: Self) {
         self.id_sum ^= rhs.id_sum;
         self.hash_sum ^= rhs.hash_sum;
-        self.count -= rhs.count;
+        self.count = self.count.saturating_sub(rhs.count);
+    }
+}
+
+impl<T, C> SubAssign<&Cell<T, C>> for Cell<T, C>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    C: Count,
+{
+    #[inline]
+    fn sub_assign(&mut self, rhs: &Self) {
+        self.id_sum ^= rhs.id_sum.clone();
+        self.hash_sum ^= rhs.hash_sum;
+        self.count = self.count.saturating_sub(rhs.count);
     }
 }
 
-impl<T> Sub for Cell<T>
+impl<T, C> Sub for Cell<T, C>
 where
     T: Clone
-        + std::hash::Hash
+        + core::hash::Hash
         + BitXor<Output = T>
         + BitXorAssign
         + Default
         + PartialEq
         + Eq
         + Debug,
+    C: Count,
 {
-    type Output = Cell<T>;
+    type Output = Cell<T, C>;
 
+    #[inline]
     fn sub(self, rhs: Self) -> Self::Output {
         Self {
             id_sum: self.id_sum ^ rhs.id_sum,
             hash_sum: self.hash_sum ^ rhs.hash_sum,
-            count: self.count - rhs.count,
+            count: self.count.saturating_sub(rhs.count),
         }
     }
 }
 
-impl<T> Sub for &Cell<T>
+impl<T, C> Sub for &Cell<T, C>
 where
     T: Clone
-        + std::hash::Hash
+        + core::hash::Hash
         + BitXor<Output = T>
         + BitXorAssign
         + Default
         + PartialEq
         + Eq
         + Debug,
+    C: Count,
 {
-    type Output = Cell<T>;
+    type Output = Cell<T, C>;
 
+    #[inline]
     fn sub(self, rhs: Self) -> Self::Output {
         Cell {
             id_sum: self.id_sum.clone() ^ rhs.id_sum.clone(),
             hash_sum: self.hash_sum ^ rhs.hash_sum,
-            count: self.count - rhs.count,
+            count: self.count.saturating_sub(rhs.count),
         }
     }
 }
@@ -190,39 +353,44 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::DefaultBuildHasher;
 
     #[test]
     fn round_trip() {
+        let h = DefaultBuildHasher::default();
         let mut bucket: Cell<u128> = Default::default();
-        bucket.encode(1);
-        assert!(bucket.is_pure());
-        assert_eq!(bucket.decode(), Ok(Side::Left(1)));
+        bucket.encode(1, &h);
+        assert!(bucket.is_pure(&h));
+        assert_eq!(bucket.decode(&h), Ok(Side::Left(1)));
     }
 
     #[test]
     fn subtract() {
-        let (mut b1, mut b2) = (Cell::default(), Cell::default());
-        b1.encode(2);
-        b1.encode(2);
-        b2.encode(1);
-        assert_eq!((b1 - b2).decode(), Ok(Side::Left(1)));
-        assert_eq!((b2 - b1).decode(), Ok(Side::Right(1)));
+        let h = DefaultBuildHasher::default();
+        let (mut b1, mut b2) = (Cell::<u64>::default(), Cell::default());
+        b1.encode(2, &h);
+        b1.encode(2, &h);
+        b2.encode(1, &h);
+        assert_eq!((b1 - b2).decode(&h), Ok(Side::Left(1)));
+        assert_eq!((b2 - b1).decode(&h), Ok(Side::Right(1)));
     }
 
     #[test]
     fn impure() {
-        let mut b1 = Cell::default();
-        b1.encode(1);
-        b1.encode(2);
-        assert!(!b1.is_pure())
+        let h = DefaultBuildHasher::default();
+        let mut b1 = Cell::<u64>::default();
+        b1.encode(1, &h);
+        b1.encode(2, &h);
+        assert!(!b1.is_pure(&h))
     }
 
     #[test]
     fn impure_disjoint() {
-        let (mut b1, mut b2) = (Cell::default(), Cell::default());
-        b1.encode(1);
-        b1.encode(2);
-        b2.encode(3);
-        assert!(!(b1 - b2).is_pure());
+        let h = DefaultBuildHasher::default();
+        let (mut b1, mut b2) = (Cell::<u64>::default(), Cell::default());
+        b1.encode(1, &h);
+        b1.encode(2, &h);
+        b2.encode(3, &h);
+        assert!(!(b1 - b2).is_pure(&h));
     }
 }
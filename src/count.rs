@@ -0,0 +1,109 @@
+use crate::wire::WireElement;
+use core::convert::TryFrom;
+use core::fmt::Debug;
+use core::hash::Hash;
+use serde::Serialize;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Integer type of the net element count in each [Cell](crate::Cell) of an
+/// [IBF](crate::IBF): i32 by default, i16 where no cell will ever hold more than 32767 elements,
+/// or i64 where one might hold more than 2^31. See
+/// [with_count_width](crate::IBF::with_count_width).
+///
+/// The count width only changes the [wire format](crate::IBF::to_bytes), 2 bytes less per cell
+/// with i16, a tenth of a u64 sketch. Cells are padded to the alignment of their element and
+/// hash sum in memory, so they take the same space there whatever the width.
+///
+/// Sealed, only i16, i32 and i64 implement it.
+pub trait Count:
+    sealed::Sealed
+    + Copy
+    + Debug
+    + Default
+    + Eq
+    + Ord
+    + Hash
+    + Send
+    + Sync
+    + Serialize
+    + WireElement
+    + 'static
+{
+    /// No elements
+    const ZERO: Self;
+    /// One element on the left side
+    const ONE: Self;
+    /// Bits of the wire format's flag byte that tell this width apart, 0 for i32, the only
+    /// width written before widths were configurable
+    #[doc(hidden)]
+    const WIRE_FLAGS: u8;
+
+    /// Sum, saturating at the bounds of the type
+    fn saturating_add(self, rhs: Self) -> Self;
+    /// Difference, saturating at the bounds of the type
+    fn saturating_sub(self, rhs: Self) -> Self;
+    /// Negation, saturating at the bounds of the type
+    fn saturating_neg(self) -> Self;
+    /// Sum, None if it overflows
+    fn checked_add(self, rhs: Self) -> Option<Self>;
+    /// Difference, None if it overflows
+    fn checked_sub(self, rhs: Self) -> Option<Self>;
+    /// The count as an i64, which holds every width
+    fn to_i64(self) -> i64;
+    /// An i64 count as this width, None if it doesn't fit
+    fn from_i64(count: i64) -> Option<Self>;
+}
+
+macro_rules! count_int {
+    ($($t:ty => $flags:expr),*) => {
+        $(
+            impl sealed::Sealed for $t {}
+
+            impl Count for $t {
+                const ZERO: Self = 0;
+                const ONE: Self = 1;
+                const WIRE_FLAGS: u8 = $flags;
+
+                #[inline]
+                fn saturating_add(self, rhs: Self) -> Self {
+                    <$t>::saturating_add(self, rhs)
+                }
+
+                #[inline]
+                fn saturating_sub(self, rhs: Self) -> Self {
+                    <$t>::saturating_sub(self, rhs)
+                }
+
+                #[inline]
+                fn saturating_neg(self) -> Self {
+                    <$t>::saturating_neg(self)
+                }
+
+                #[inline]
+                fn checked_add(self, rhs: Self) -> Option<Self> {
+                    <$t>::checked_add(self, rhs)
+                }
+
+                #[inline]
+                fn checked_sub(self, rhs: Self) -> Option<Self> {
+                    <$t>::checked_sub(self, rhs)
+                }
+
+                #[inline]
+                fn to_i64(self) -> i64 {
+                    self as i64
+                }
+
+                #[inline]
+                fn from_i64(count: i64) -> Option<Self> {
+                    <$t>::try_from(count).ok()
+                }
+            }
+        )*
+    };
+}
+
+count_int!(i32 => 0, i16 => 2, i64 => 4);
@@ -0,0 +1,316 @@
+use crate::metro::metrohash64crc_1;
+use crate::{Cell, IronRoseError, Side, SketchParams, IBF};
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::ops::Sub;
+
+/// Cell indexes deduplicated on the stack, enough for any practical hash_count
+const INLINE_INDEXES: usize = 8;
+
+/// An IBF of u128s, with its cells stored as three parallel arrays (element XORs, hash XORs and
+/// counts) rather than an array of [Cells](Cell). Subtraction and merging are then plain
+/// element-wise loops over each array, which the compiler vectorizes, and elements are hashed
+/// on the stack instead of through a buffering [Hasher](core::hash::Hasher).
+///
+/// Elements land in the same cells as in an [IBF](IBF) of the same params with the
+/// [default hasher](crate::DefaultBuildHasher), so the two convert into each other for free
+/// and a dense sketch can be subtracted from one received over the wire. Keyspaces aren't
+/// carried over, as with [into_raw_parts](IBF::into_raw_parts).
+/// ```rust
+/// use iron_rose::{DenseIbf, Side, SketchParams, IBF};
+///
+/// let params = SketchParams::new(20, 3);
+/// let mut local = DenseIbf::with_params(params);
+/// let mut remote = IBF::with_params(params);
+/// for i in 0..100u128 {
+///     local.encode(i);
+///     remote.encode(i + 1);
+/// }
+/// let mut sides = Vec::new();
+/// (&local - &DenseIbf::from(remote))
+///     .unwrap()
+///     .decode_into(&mut sides)
+///     .unwrap();
+/// sides.sort();
+/// assert_eq!(sides, vec![Side::Left(0), Side::Right(100)]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DenseIbf {
+    ids: Vec<u128>,
+    hashes: Vec<u64>,
+    counts: Vec<i32>,
+    hash_count: usize,
+    non_empty: usize,
+}
+
+impl DenseIbf {
+    /// New dense IBF with size cells and the default hash_count of 3
+    pub fn new(size: usize) -> Self {
+        Self::with_params(SketchParams::new(size, 3))
+    }
+
+    /// New, empty dense IBF of the given shape
+    pub fn with_params(params: SketchParams) -> Self {
+        Self {
+            ids: vec![0; params.size()],
+            hashes: vec![0; params.size()],
+            counts: vec![0; params.size()],
+            hash_count: params.hash_count(),
+            non_empty: 0,
+        }
+    }
+
+    /// The shape of this IBF
+    pub fn params(&self) -> SketchParams {
+        SketchParams::new(self.ids.len(), self.hash_count)
+    }
+
+    /// True when every cell is empty
+    pub fn is_empty(&self) -> bool {
+        self.non_empty == 0
+    }
+
+    /// Encodes an element into hash_count cells
+    pub fn encode(&mut self, element: u128) {
+        self.apply(element, 1);
+    }
+
+    /// Undoes an [encode](DenseIbf::encode) of element
+    pub fn remove(&mut self, element: u128) {
+        self.apply(element, -1);
+    }
+
+    /// Adds other's elements to this IBF, failing unless both are the same shape
+    pub fn merge(&mut self, other: &DenseIbf) -> Result<(), IronRoseError> {
+        self.check_compatible(other)?;
+        for (l, r) in self.ids.iter_mut().zip(other.ids.iter()) {
+            *l ^= r;
+        }
+        for (l, r) in self.hashes.iter_mut().zip(other.hashes.iter()) {
+            *l ^= r;
+        }
+        for (l, r) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *l += r;
+        }
+        self.recount();
+        Ok(())
+    }
+
+    /// Decodes into a caller provided Vec, in the order elements are recovered, like
+    /// [IBF::decode_into](IBF::decode_into). On error the Vec holds the elements recovered
+    /// before decoding got stuck.
+    pub fn decode_into(mut self, out: &mut Vec<Side<u128>>) -> Result<(), IronRoseError> {
+        let mut candidates = (0..self.ids.len())
+            .filter(|&idx| self.is_pure(idx))
+            .collect::<Vec<_>>();
+        let mut recovered = 0;
+        while let Some(idx) = candidates.pop() {
+            if !self.is_pure(idx) {
+                continue;
+            }
+            if recovered >= self.ids.len() {
+                // More elements than cells only comes out of forged cells, see IBF::peel
+                break;
+            }
+            recovered += 1;
+            let (element, count) = (self.ids[idx], self.counts[idx]);
+            out.push(if count == 1 {
+                Side::Left(element)
+            } else {
+                Side::Right(element)
+            });
+            let hash = element_hash(element);
+            self.for_each_cell(element, |dense, cell| {
+                dense.update(cell, element, hash, -count);
+                if dense.is_pure(cell) {
+                    candidates.push(cell);
+                }
+            });
+        }
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(IronRoseError::DecodeFailed {
+                remaining_cells: self.non_empty,
+            })
+        }
+    }
+
+    fn check_compatible(&self, other: &DenseIbf) -> Result<(), IronRoseError> {
+        if self.params() != other.params() {
+            return Err(IronRoseError::ShapeMismatch {
+                left: self.params(),
+                right: other.params(),
+            });
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, element: u128, count: i32) {
+        let hash = element_hash(element);
+        self.for_each_cell(element, |dense, cell| {
+            dense.update(cell, element, hash, count)
+        });
+    }
+
+    /// XORs element and its hash into a cell, adding count, keeping the non-empty count in sync
+    fn update(&mut self, cell: usize, element: u128, hash: u64, count: i32) {
+        let was_empty = self.is_empty_cell(cell);
+        self.ids[cell] ^= element;
+        self.hashes[cell] ^= hash;
+        self.counts[cell] = self.counts[cell].saturating_add(count);
+        match (was_empty, self.is_empty_cell(cell)) {
+            (true, false) => self.non_empty += 1,
+            (false, true) => self.non_empty -= 1,
+            _ => {}
+        }
+    }
+
+    fn is_empty_cell(&self, cell: usize) -> bool {
+        self.counts[cell] == 0 && self.hashes[cell] == 0 && self.ids[cell] == 0
+    }
+
+    fn is_pure(&self, cell: usize) -> bool {
+        (self.counts[cell] == 1 || self.counts[cell] == -1)
+            && self.hashes[cell] == element_hash(self.ids[cell])
+    }
+
+    fn recount(&mut self) {
+        self.non_empty = (0..self.ids.len())
+            .filter(|&cell| !self.is_empty_cell(cell))
+            .count();
+    }
+
+    /// Calls f with each cell element is placed in, once per cell, as
+    /// [IBF](IBF) does
+    fn for_each_cell<F: FnMut(&mut Self, usize)>(&mut self, element: u128, mut f: F) {
+        let mut inline = [0; INLINE_INDEXES];
+        let mut spilled = Vec::new();
+        for i in 0..self.hash_count {
+            let cell = cell_index(i, element, self.ids.len());
+            let seen = inline[..i.min(INLINE_INDEXES)].contains(&cell) || spilled.contains(&cell);
+            if i < INLINE_INDEXES {
+                inline[i] = cell;
+            } else {
+                spilled.push(cell);
+            }
+            if !seen {
+                f(self, cell);
+            }
+        }
+    }
+}
+
+/// The [element_hash](crate::element_hash) of a u128, hashing the bytes `Hash` would write
+fn element_hash(element: u128) -> u64 {
+    metrohash64crc_1(&element.to_ne_bytes(), 0)
+}
+
+/// The cell hash function i places element in, the same as for an [IBF](IBF) with the default
+/// hasher, which hashes the element's bytes followed by i's
+fn cell_index(i: usize, element: u128, size: usize) -> usize {
+    const ELEMENT: usize = core::mem::size_of::<u128>();
+    let mut bytes = [0; ELEMENT + core::mem::size_of::<usize>()];
+    bytes[..ELEMENT].copy_from_slice(&element.to_ne_bytes());
+    bytes[ELEMENT..].copy_from_slice(&i.to_ne_bytes());
+    (metrohash64crc_1(&bytes, 0) % size as u64) as usize
+}
+
+impl Sub for &DenseIbf {
+    type Output = Result<DenseIbf, IronRoseError>;
+
+    fn sub(self, rhs: &DenseIbf) -> Self::Output {
+        self.check_compatible(rhs)?;
+        let mut diff = DenseIbf {
+            ids: self
+                .ids
+                .iter()
+                .zip(rhs.ids.iter())
+                .map(|(l, r)| l ^ r)
+                .collect(),
+            hashes: self
+                .hashes
+                .iter()
+                .zip(rhs.hashes.iter())
+                .map(|(l, r)| l ^ r)
+                .collect(),
+            counts: self
+                .counts
+                .iter()
+                .zip(rhs.counts.iter())
+                .enumerate()
+                .map(|(cell, (l, r))| {
+                    l.checked_sub(*r)
+                        .ok_or(IronRoseError::CountOverflow { cell })
+                })
+                .collect::<Result<_, _>>()?,
+            hash_count: self.hash_count,
+            non_empty: 0,
+        };
+        diff.recount();
+        Ok(diff)
+    }
+}
+
+impl From<IBF<u128>> for DenseIbf {
+    fn from(ibf: IBF<u128>) -> Self {
+        let (cells, params) = ibf.into_raw_parts();
+        let mut dense = DenseIbf::with_params(params);
+        for (idx, cell) in cells.iter().enumerate() {
+            dense.ids[idx] = *cell.id_sum();
+            dense.hashes[idx] = cell.hash_sum();
+            dense.counts[idx] = cell.count();
+        }
+        dense.recount();
+        dense
+    }
+}
+
+impl From<DenseIbf> for IBF<u128> {
+    fn from(dense: DenseIbf) -> Self {
+        let params = dense.params();
+        let cells = dense
+            .ids
+            .iter()
+            .zip(dense.hashes.iter())
+            .zip(dense.counts.iter())
+            .map(|((&id, &hash), &count)| Cell::from_parts(id, hash, count))
+            .collect::<Box<[_]>>();
+        IBF::from_raw_parts(cells, params).expect("one cell per index")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_generic_ibf() {
+        for &hash_count in [1, 3, 4, 12].iter() {
+            let params = SketchParams::new(200, hash_count);
+            let (mut dense, mut ibf) = (DenseIbf::with_params(params), IBF::with_params(params));
+            for i in 0..300u128 {
+                let element = i.wrapping_mul(0x9e37_79b9_7f4a_7c15_f39c_c060_5ced_c835);
+                dense.encode(element);
+                ibf.encode(element);
+            }
+            dense.remove(7);
+            ibf.remove(7);
+            assert_eq!(IBF::from(dense.clone()), ibf);
+            assert_eq!(DenseIbf::from(ibf), dense);
+        }
+
+        let (mut left, mut right) = (DenseIbf::new(60), DenseIbf::new(60));
+        for i in 0..1000u128 {
+            left.encode(i);
+            right.encode(i + 10);
+        }
+        let mut sides = Vec::new();
+        (&left - &right).unwrap().decode_into(&mut sides).unwrap();
+        assert_eq!(sides.len(), 20);
+        let mut merged = left.clone();
+        merged.merge(&right).unwrap();
+        assert!(!merged.is_empty());
+        assert!((&left - &left).unwrap().is_empty());
+        assert!((&left - &DenseIbf::new(61)).is_err());
+    }
+}
@@ -0,0 +1,477 @@
+use crate::Side;
+use alloc::vec::{self, Vec};
+use core::{
+    fmt::Debug,
+    iter::{Chain, Map, Peekable},
+    ops::{BitXor, BitXorAssign},
+    slice,
+};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::collections::{hash_set, HashSet};
+
+/// The outcome of decoding a subtracted IBF, with the elements split by which side they were
+/// found on, and some details about the sketch and the decode. Serializable so reconciliation
+/// outcomes can be shipped to reporting systems as is. Elements are in the order they were
+/// recovered, so decoding the same IBF serializes to the same bytes on every run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Difference<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    left_only: Vec<T>,
+    right_only: Vec<T>,
+    peel_iterations: usize,
+    size: usize,
+    hash_count: usize,
+}
+
+impl<T> Difference<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    pub(crate) fn new(
+        left_only: Vec<T>,
+        right_only: Vec<T>,
+        peel_iterations: usize,
+        size: usize,
+        hash_count: usize,
+    ) -> Self {
+        Self {
+            left_only,
+            right_only,
+            peel_iterations,
+            size,
+            hash_count,
+        }
+    }
+
+    /// Elements that were only on the "Left" side of the subtraction
+    pub fn left(&self) -> &[T] {
+        &self.left_only
+    }
+
+    /// Elements that were only on the "Right" side of the subtraction
+    pub fn right(&self) -> &[T] {
+        &self.right_only
+    }
+
+    /// Iterates over the elements that were only on the "Left" side
+    pub fn iter_left(&self) -> slice::Iter<'_, T> {
+        self.left_only.iter()
+    }
+
+    /// Iterates over the elements that were only on the "Right" side
+    pub fn iter_right(&self) -> slice::Iter<'_, T> {
+        self.right_only.iter()
+    }
+
+    /// Iterates over every difference, tagged with its side, left side first
+    /// ```rust
+    /// use iron_rose::{IBF, Side};
+    ///
+    /// let (mut left, mut right) = (IBF::new(20), IBF::new(20));
+    /// left.encode(1u64);
+    /// right.encode(2u64);
+    /// let diff = (left - right).unwrap().decode_difference().unwrap();
+    /// let sides = diff.iter().collect::<Vec<_>>();
+    /// assert_eq!(sides, vec![Side::Left(1), Side::Right(2)]);
+    /// assert_eq!(diff.into_iter().filter(|side| matches!(side, Side::Right(_))).count(), 1);
+    /// ```
+    pub fn iter(&self) -> DifferenceIter<'_, T> {
+        self.into_iter()
+    }
+
+    /// Total number of differences
+    pub fn len(&self) -> usize {
+        self.left_only.len() + self.right_only.len()
+    }
+
+    /// True if the two sides were identical
+    pub fn is_empty(&self) -> bool {
+        self.left_only.is_empty() && self.right_only.is_empty()
+    }
+
+    /// Number of candidate cells the peeling process examined
+    pub fn peel_iterations(&self) -> usize {
+        self.peel_iterations
+    }
+
+    /// Number of cells in the decoded IBF
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// hash_count of the decoded IBF
+    pub fn hash_count(&self) -> usize {
+        self.hash_count
+    }
+
+    /// Sorts the elements of each side. Elements are otherwise in the order they were recovered,
+    /// which depends on the sketch's shape and hasher, so sorting gives the same
+    /// serialization for the same difference wherever it was decoded.
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let (mut left, mut right) = (IBF::new(20), IBF::new(20));
+    /// let (mut left_wide, mut right_wide) = (IBF::new(30), IBF::new(30));
+    /// for x in [5u64, 3, 9] {
+    ///     left.encode(x);
+    ///     left_wide.encode(x);
+    /// }
+    /// right.encode(4);
+    /// right_wide.encode(4);
+    /// let mut diff = (left - right).unwrap().decode_difference().unwrap();
+    /// let mut wide = (left_wide - right_wide).unwrap().decode_difference().unwrap();
+    /// diff.sort();
+    /// wide.sort();
+    /// assert_eq!(diff.left(), [3, 5, 9]);
+    /// assert_eq!(diff.into_parts(), wide.into_parts());
+    /// ```
+    pub fn sort(&mut self)
+    where
+        T: Ord,
+    {
+        self.left_only.sort_unstable();
+        self.right_only.sort_unstable();
+    }
+
+    /// Sorts the difference and hands it out in runs of up to run_size elements, tagged with
+    /// their side, in ascending order across both sides and across runs. Each run can be
+    /// applied as one batch against a database index with a merge join over the range it
+    /// spans, and runs are only put together as they're taken, so the application never holds
+    /// a sorted copy of the whole difference. Panics if run_size is 0.
+    /// ```rust
+    /// use iron_rose::{IBF, Side};
+    ///
+    /// let (mut left, mut right) = (IBF::new(40), IBF::new(40));
+    /// for x in [9u64, 2, 7] {
+    ///     left.encode(x);
+    /// }
+    /// for x in [4u64, 1] {
+    ///     right.encode(x);
+    /// }
+    /// let diff = (left - right).unwrap().decode_difference().unwrap();
+    /// let runs = diff.into_sorted_runs(2).collect::<Vec<_>>();
+    /// assert_eq!(runs, vec![
+    ///     vec![Side::Right(1), Side::Left(2)],
+    ///     vec![Side::Right(4), Side::Left(7)],
+    ///     vec![Side::Left(9)],
+    /// ]);
+    /// ```
+    pub fn into_sorted_runs(mut self, run_size: usize) -> SortedRuns<T>
+    where
+        T: Ord,
+    {
+        assert!(run_size > 0, "run_size must be positive");
+        self.sort();
+        SortedRuns {
+            left: self.left_only.into_iter().peekable(),
+            right: self.right_only.into_iter().peekable(),
+            run_size,
+        }
+    }
+
+    /// Splits the difference into the left only and right only elements
+    pub fn into_parts(self) -> (Vec<T>, Vec<T>) {
+        (self.left_only, self.right_only)
+    }
+
+    /// Builds an index over the differences with O(1) membership and side lookups, for
+    /// answering "do I need to push item X?" repeatedly.
+    /// ```rust
+    /// use iron_rose::{IBF, Side};
+    ///
+    /// let (mut left, mut right) = (IBF::new(20), IBF::new(20));
+    /// left.encode(1u64);
+    /// right.encode(2u64);
+    /// let index = (left - right).unwrap().decode_difference().unwrap().to_lookup();
+    /// assert!(index.is_left(&1));
+    /// assert_eq!(index.side_of(&2), Some(Side::Right(2)));
+    /// assert!(!index.contains(&3));
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn to_lookup(&self) -> DifferenceIndex<T> {
+        DifferenceIndex {
+            left: self.left_only.iter().cloned().collect(),
+            right: self.right_only.iter().cloned().collect(),
+        }
+    }
+}
+
+/// Iterator over the sides of a borrowed [Difference](Difference)
+pub type DifferenceIter<'a, T> =
+    Chain<Map<slice::Iter<'a, T>, fn(&T) -> Side<T>>, Map<slice::Iter<'a, T>, fn(&T) -> Side<T>>>;
+
+/// Iterator over the sides of an owned [Difference](Difference)
+pub type DifferenceIntoIter<T> =
+    Chain<Map<vec::IntoIter<T>, fn(T) -> Side<T>>, Map<vec::IntoIter<T>, fn(T) -> Side<T>>>;
+
+impl<T> IntoIterator for Difference<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    type Item = Side<T>;
+    type IntoIter = DifferenceIntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let left: fn(T) -> Side<T> = Side::Left;
+        let right: fn(T) -> Side<T> = Side::Right;
+        self.left_only
+            .into_iter()
+            .map(left)
+            .chain(self.right_only.into_iter().map(right))
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Difference<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    type Item = Side<T>;
+    type IntoIter = DifferenceIter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let left: fn(&T) -> Side<T> = |elm| Side::Left(elm.clone());
+        let right: fn(&T) -> Side<T> = |elm| Side::Right(elm.clone());
+        self.left_only
+            .iter()
+            .map(left)
+            .chain(self.right_only.iter().map(right))
+    }
+}
+
+/// Runs of a sorted [Difference](Difference), built by
+/// [Difference::into_sorted_runs](Difference::into_sorted_runs)
+#[derive(Debug, Clone)]
+pub struct SortedRuns<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    left: Peekable<vec::IntoIter<T>>,
+    right: Peekable<vec::IntoIter<T>>,
+    run_size: usize,
+}
+
+impl<T> Iterator for SortedRuns<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + Ord,
+{
+    type Item = Vec<Side<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let remaining = self.left.len() + self.right.len();
+        if remaining == 0 {
+            return None;
+        }
+        let mut run = Vec::with_capacity(self.run_size.min(remaining));
+        while run.len() < self.run_size {
+            let side = match (self.left.peek(), self.right.peek()) {
+                (Some(l), Some(r)) if r < l => self.right.next().map(Side::Right),
+                (Some(_), _) => self.left.next().map(Side::Left),
+                (None, _) => self.right.next().map(Side::Right),
+            };
+            match side {
+                Some(side) => run.push(side),
+                None => break,
+            }
+        }
+        Some(run)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let runs = (self.left.len() + self.right.len()).div_ceil(self.run_size);
+        (runs, Some(runs))
+    }
+}
+
+impl<T> ExactSizeIterator for SortedRuns<T> where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + Ord
+{
+}
+
+/// Hashed index over a [Difference](Difference), built by
+/// [Difference::to_lookup](Difference::to_lookup)
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DifferenceIndex<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    left: HashSet<T>,
+    right: HashSet<T>,
+}
+
+#[cfg(feature = "std")]
+impl<T> DifferenceIndex<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// True if element is a difference on either side
+    pub fn contains(&self, element: &T) -> bool {
+        self.is_left(element) || self.is_right(element)
+    }
+
+    /// True if element was only on the "Left" side
+    pub fn is_left(&self, element: &T) -> bool {
+        self.left.contains(element)
+    }
+
+    /// True if element was only on the "Right" side
+    pub fn is_right(&self, element: &T) -> bool {
+        self.right.contains(element)
+    }
+
+    /// Which side element was found on, if it is a difference at all
+    pub fn side_of(&self, element: &T) -> Option<Side<T>> {
+        if self.is_left(element) {
+            Some(Side::Left(element.clone()))
+        } else if self.is_right(element) {
+            Some(Side::Right(element.clone()))
+        } else {
+            None
+        }
+    }
+
+    /// Iterates over the elements that were only on the "Left" side, in no particular order
+    pub fn iter_left(&self) -> hash_set::Iter<'_, T> {
+        self.left.iter()
+    }
+
+    /// Iterates over the elements that were only on the "Right" side, in no particular order
+    pub fn iter_right(&self) -> hash_set::Iter<'_, T> {
+        self.right.iter()
+    }
+
+    /// Total number of differences in the index
+    pub fn len(&self) -> usize {
+        self.left.len() + self.right.len()
+    }
+
+    /// True if there are no differences in the index
+    pub fn is_empty(&self) -> bool {
+        self.left.is_empty() && self.right.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::IBF;
+
+    #[test]
+    fn serde_round_trip() {
+        let (mut left, mut right) = (IBF::new(30), IBF::new(30));
+        left.encode(7u64);
+        left.encode(8u64);
+        right.encode(9u64);
+        let diff = (left - right).unwrap().decode_difference().unwrap();
+        assert_eq!(diff.len(), 3);
+        assert_eq!(diff.size(), 30);
+
+        let json = serde_json::to_string(&diff).unwrap();
+        assert_eq!(
+            serde_json::from_str::<super::Difference<u64>>(&json).unwrap(),
+            diff
+        );
+    }
+
+    #[test]
+    fn iterators_agree() {
+        let (mut left, mut right) = (IBF::new(30), IBF::new(30));
+        left.encode(7u64);
+        left.encode(8u64);
+        right.encode(9u64);
+        let diff = (left - right).unwrap().decode_difference().unwrap();
+        let borrowed = (&diff).into_iter().collect::<Vec<_>>();
+        assert_eq!(borrowed.len(), 3);
+        assert_eq!(diff.iter_left().count(), 2);
+        assert_eq!(diff.iter_right().copied().collect::<Vec<_>>(), vec![9]);
+        let index = diff.to_lookup();
+        assert!(index.iter_left().all(|x| diff.left().contains(x)));
+        assert_eq!(diff.into_iter().collect::<Vec<_>>(), borrowed);
+    }
+
+    #[test]
+    fn sorted_runs_cover_the_difference_in_order() {
+        let (mut left, mut right) = (IBF::new(300), IBF::new(300));
+        (0..100u64)
+            .map(|x| x * 7 % 101)
+            .for_each(|x| left.encode(x));
+        (200..240u64).for_each(|x| right.encode(x));
+        let diff = (left - right).unwrap().decode_difference().unwrap();
+        let runs = diff.clone().into_sorted_runs(32);
+        assert_eq!(runs.len(), 5);
+        let runs = runs.collect::<Vec<_>>();
+        assert!(runs[..4].iter().all(|run| run.len() == 32));
+        let flat = runs.into_iter().flatten().collect::<Vec<_>>();
+        assert!(flat.windows(2).all(|pair| *pair[0] < *pair[1]));
+        let mut sides = diff.into_iter().collect::<Vec<_>>();
+        sides.sort_by_key(|side| **side);
+        assert_eq!(flat, sides);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{metro::metrohash64crc_1, IronRoseError, Side, IBF};
+use std::{collections::HashMap, fmt::Debug};
+
+/// Elements of any length, such as string keys or blobs, that are put in an IBF as a fixed
+/// width 128 bit digest instead, see [DigestIBF](DigestIBF). The digest of some bytes is the same
+/// in every process and on every platform.
+pub trait IbfElement {
+    /// The bytes the digest is taken over
+    fn digest_bytes(&self) -> &[u8];
+
+    /// 128 bit digest of the element: two MetroHashes of its bytes, with different seeds
+    fn digest(&self) -> u128 {
+        let bytes = self.digest_bytes();
+        ((metrohash64crc_1(bytes, 0) as u128) << 64) | metrohash64crc_1(bytes, 1) as u128
+    }
+}
+
+impl IbfElement for str {
+    fn digest_bytes(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl IbfElement for String {
+    fn digest_bytes(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl IbfElement for [u8] {
+    fn digest_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+impl IbfElement for Vec<u8> {
+    fn digest_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<E: IbfElement + ?Sized> IbfElement for &E {
+    fn digest_bytes(&self) -> &[u8] {
+        (**self).digest_bytes()
+    }
+}
+
+/// A set of variable length elements, reconciled through an IBF of their
+/// [digests](IbfElement::digest). Each side keeps an index from digest back to element, so the
+/// differences it holds come back as elements, while the peer's come back as digests, which
+/// the peer turns into elements with [elements_for](DigestIBF::elements_for) when asked.
+///
+/// Two of n distinct elements share a digest with probability about n² / 2^129, which is below
+/// 10^-20 for a billion elements. Collisions between elements of the same side are caught by
+/// [encode](DigestIBF::encode), ones across sides can't be, and would hide both elements.
+/// ```rust
+/// use iron_rose::DigestIBF;
+///
+/// let (mut alice, mut bob) = (DigestIBF::new(20), DigestIBF::new(20));
+/// for key in ["apple", "banana", "cherry"].iter().copied() {
+///     alice.encode(key).unwrap();
+/// }
+/// for key in ["apple", "banana", "damson"].iter().copied() {
+///     bob.encode(key).unwrap();
+/// }
+/// let diff = alice.difference(bob.sketch()).unwrap();
+/// assert_eq!(diff.local, vec!["cherry"]);
+/// // Bob resolves the digests only he has
+/// assert_eq!(bob.elements_for(&diff.remote), vec![&"damson"]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct DigestIBF<E>
+where
+    E: IbfElement + Clone + Debug,
+{
+    ibf: IBF<u128>,
+    elements: HashMap<u128, E>,
+}
+
+/// Result of [DigestIBF::difference](DigestIBF::difference)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigestDifference<E> {
+    /// Elements only in the local set
+    pub local: Vec<E>,
+    /// Digests of the elements only in the remote set
+    pub remote: Vec<u128>,
+}
+
+impl<E> DigestIBF<E>
+where
+    E: IbfElement + Clone + Debug,
+{
+    /// New digest IBF with size buckets and the default hash_count of 3
+    pub fn new(size: usize) -> Self {
+        Self::new_with_hash_count(size, 3)
+    }
+
+    /// New digest IBF with size buckets and a settable hash_count
+    pub fn new_with_hash_count(size: usize, hash_count: usize) -> Self {
+        Self {
+            ibf: IBF::new_with_hash_count(size, hash_count),
+            elements: HashMap::new(),
+        }
+    }
+
+    /// Encodes element's digest, failing with [DigestCollision](IronRoseError::DigestCollision)
+    /// if a different element with the same digest was already encoded, or with
+    /// [LikelyDuplicate](IronRoseError::LikelyDuplicate) if the element itself was.
+    pub fn encode(&mut self, element: E) -> Result<(), IronRoseError> {
+        let digest = element.digest();
+        if let Some(existing) = self.elements.get(&digest) {
+            if existing.digest_bytes() == element.digest_bytes() {
+                return Err(IronRoseError::LikelyDuplicate);
+            }
+            return Err(IronRoseError::DigestCollision { digest });
+        }
+        self.ibf.encode(digest);
+        self.elements.insert(digest, element);
+        Ok(())
+    }
+
+    /// Removes an element that was encoded, returning false if it wasn't
+    pub fn remove(&mut self, element: &E) -> bool {
+        let digest = element.digest();
+        match self.elements.get(&digest) {
+            Some(existing) if existing.digest_bytes() == element.digest_bytes() => {
+                self.elements.remove(&digest);
+                self.ibf.remove(digest);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The IBF of digests, to send to the peer
+    pub fn sketch(&self) -> &IBF<u128> {
+        &self.ibf
+    }
+
+    /// Subtracts the peer's [sketch](DigestIBF::sketch) and decodes the differences
+    pub fn difference(&self, remote: &IBF<u128>) -> Result<DigestDifference<E>, IronRoseError> {
+        let mut sides = Vec::new();
+        (&self.ibf - remote)?.decode_into(&mut sides)?;
+        let mut diff = DigestDifference {
+            local: Vec::new(),
+            remote: Vec::new(),
+        };
+        for side in sides {
+            match side {
+                Side::Left(digest) => match self.elements.get(&digest) {
+                    Some(element) => diff.local.push(element.clone()),
+                    None => return Err(IronRoseError::UnknownDigest { digest }),
+                },
+                Side::Right(digest) => diff.remote.push(digest),
+            }
+        }
+        Ok(diff)
+    }
+
+    /// The local elements with the given digests, skipping any that aren't known, to answer a
+    /// peer's request for the elements it is missing
+    pub fn elements_for(&self, digests: &[u128]) -> Vec<&E> {
+        digests
+            .iter()
+            .filter_map(|digest| self.elements.get(digest))
+            .collect()
+    }
+
+    /// Number of elements encoded
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    /// True if no elements are encoded
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blobs_reconcile_through_digests() {
+        let blobs: Vec<Vec<u8>> = (0..50u8).map(|i| vec![i; i as usize]).collect();
+        let (mut left, mut right) = (DigestIBF::new(30), DigestIBF::new(30));
+        for blob in blobs.iter() {
+            left.encode(blob.clone()).unwrap();
+            right.encode(blob.clone()).unwrap();
+        }
+        assert!(right.remove(&blobs[3]));
+        assert!(!right.remove(&blobs[3]));
+        right.encode(b"new".to_vec()).unwrap();
+        assert_eq!(
+            left.encode(blobs[5].clone()),
+            Err(IronRoseError::LikelyDuplicate)
+        );
+
+        let diff = left.difference(right.sketch()).unwrap();
+        assert_eq!(diff.local, vec![blobs[3].clone()]);
+        assert_eq!(right.elements_for(&diff.remote), vec![&b"new".to_vec()]);
+        assert_eq!("abc".digest(), b"abc"[..].digest());
+        assert_ne!("abc".digest(), "abd".digest());
+    }
+}
@@ -0,0 +1,647 @@
+use crate::params::is_default_hasher;
+use crate::{ParamMismatch, Side, SketchParams};
+use alloc::{string::String, vec::Vec};
+use core::{
+    fmt::{self, Debug, Display},
+    ops::{BitXor, BitXorAssign},
+};
+#[cfg(feature = "std")]
+use std::{collections::HashSet, error::Error};
+
+/// Everything that can go wrong in Iron Rose, so callers can tell a misconfiguration apart from
+/// sets that are too different to reconcile.
+///
+/// New failure modes are added as new variants, so matches outside the crate need a wildcard
+/// arm. Code that only needs to know what to do about an error can match on its
+/// [kind](IronRoseError::kind) instead, which new variants fall into as well.
+/// ```rust
+/// use iron_rose::{IronRoseError, IBF};
+///
+/// match IBF::<u64>::new(10) - IBF::new(20) {
+///     Err(IronRoseError::ShapeMismatch { left, right }) => {
+///         assert_eq!((left.size(), right.size()), (10, 20))
+///     }
+///     _ => unreachable!(),
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IronRoseError {
+    /// Two IBFs, or an IBF and a [CellPatch](crate::CellPatch) or [segment](crate::IbfSegment),
+    /// don't have the same size and hash_count
+    ShapeMismatch {
+        /// Shape of the left hand side
+        left: SketchParams,
+        /// Shape of the right hand side
+        right: SketchParams,
+    },
+    /// Two IBFs, or an IBF and a [CellPatch](crate::CellPatch) or [segment](crate::IbfSegment),
+    /// are tagged with different [keyspaces](crate::IBF::with_keyspace)
+    KeyspaceMismatch {
+        /// Keyspace of the left hand side
+        left: Option<String>,
+        /// Keyspace of the right hand side
+        right: Option<String>,
+    },
+    /// Two IBFs place elements in cells with different hashers, see
+    /// [IbfParams::hasher_id](crate::IbfParams::hasher_id)
+    HasherMismatch {
+        /// Hasher id of the left hand side
+        left: u64,
+        /// Hasher id of the right hand side
+        right: u64,
+    },
+    /// Two IBFs hold elements of different widths
+    ElementWidthMismatch {
+        /// Element width in bytes on the left hand side
+        left: usize,
+        /// Element width in bytes on the right hand side
+        right: usize,
+    },
+    /// Peeling got stuck with cells left over, the IBF was too small for the differences. See
+    /// [DecodeError](DecodeError) for the elements that were recovered before that.
+    DecodeFailed {
+        /// Cells that were still not empty
+        remaining_cells: usize,
+    },
+    /// Two strata estimators don't have the same number of strata, or their strata are shaped
+    /// differently
+    EstimatorSizeMismatch {
+        /// Number of strata on the left hand side
+        left_strata: usize,
+        /// Shape of each stratum on the left hand side
+        left: SketchParams,
+        /// Number of strata on the right hand side
+        right_strata: usize,
+        /// Shape of each stratum on the right hand side
+        right: SketchParams,
+    },
+    /// Two [min-wise estimators](crate::MinWiseEstimator) don't keep the same number of hashes
+    MinWiseSizeMismatch {
+        /// Number of hashes kept on the left hand side
+        left: usize,
+        /// Number of hashes kept on the right hand side
+        right: usize,
+    },
+    /// A peer's [Hello](crate::reconcile::Hello) shows the two sides can't run a session
+    /// together
+    IncompatiblePeer {
+        /// What they don't have in common
+        reason: &'static str,
+    },
+    /// A sketch would be larger on the wire than its
+    /// [budget](crate::SketchParams::with_max_wire_bytes)
+    OverWireBudget {
+        /// Would-be size on the wire
+        bytes: usize,
+        /// The budget
+        max: usize,
+    },
+    /// A peer's IBF has more cells than the [budget](crate::RetryBudget) allows building
+    OverCellBudget {
+        /// Number of cells of the IBF
+        cells: usize,
+        /// The budget
+        max: usize,
+    },
+    /// A serialized IBF, or one put together from raw parts, has a different number of cells
+    /// than its size
+    CellCountMismatch {
+        /// The size the IBF claims
+        size: usize,
+        /// The number of cells it has
+        cells: usize,
+    },
+    /// A serialized IBF, or one put together from raw parts, hashes elements into no cells or
+    /// into more cells than it has
+    HashCountOutOfRange {
+        /// The hash count the IBF claims
+        hash_count: usize,
+        /// Its number of cells
+        size: usize,
+    },
+    /// A [keyspace](crate::IBF::with_keyspace) is longer than the wire format allows
+    KeyspaceTooLong {
+        /// Its length in bytes
+        len: usize,
+    },
+    /// An IBF has too many cells, or too large a hash count, to be written in the
+    /// [binary wire format](crate::IBF::to_bytes), which holds them as u32s
+    TooLargeForWire {
+        /// Its number of cells
+        size: usize,
+        /// Its hash count
+        hash_count: usize,
+    },
+    /// A cell index past the end of an IBF
+    CellOutOfRange {
+        /// The index
+        cell: usize,
+        /// Number of cells in the IBF
+        size: usize,
+    },
+    /// A range of cells that is backwards, or runs past the end of an IBF
+    InvalidRange {
+        /// Start of the range
+        start: usize,
+        /// End of the range
+        end: usize,
+        /// Number of cells in the IBF
+        size: usize,
+    },
+    /// [Segments](crate::IbfSegment) don't cover every cell of the IBF exactly once
+    IncompleteSegments {
+        /// The first cell that is missing, or in more than one segment
+        cell: usize,
+    },
+    /// A [segment](crate::IbfSegment)'s checksum doesn't match its cells
+    SegmentChecksum {
+        /// First cell of the segment
+        start: usize,
+    },
+    /// The [duplicate filter](crate::StrataEstimator::with_duplicate_detection) has probably
+    /// seen the element before
+    LikelyDuplicate,
+    /// After reconciling, the [digests](crate::SetDigest) of the two sets still differ
+    NotConverged {
+        /// Number of elements in the local set
+        local: u64,
+        /// Number of elements in the remote set
+        remote: u64,
+    },
+    /// After reconciling, the [roots](crate::SetRoot) or
+    /// [set hashes](crate::IncrementalSetHash) of the two sets still differ
+    RootMismatch,
+    /// A lock around a sketch was poisoned by a panicking thread
+    LockPoisoned,
+    /// Bytes were written in a [wire format version](crate::WIRE_VERSION) this build can't read
+    UnsupportedVersion {
+        /// The version the bytes claim
+        version: u8,
+    },
+    /// Bytes are not a valid sketch in the binary wire format
+    MalformedBytes {
+        /// What was wrong with them
+        reason: &'static str,
+    },
+    /// A message in the binary wire format doesn't match its
+    /// [checksum](crate::WIRE_VERSION), usually because it was truncated or corrupted in transfer
+    ChecksumMismatch,
+    /// Two different elements of a [DigestIBF](crate::DigestIBF) have the same digest
+    DigestCollision {
+        /// The digest
+        digest: u128,
+    },
+    /// A [DigestIBF](crate::DigestIBF) decoded a local digest it has no element for
+    UnknownDigest {
+        /// The digest
+        digest: u128,
+    },
+    /// Combining two sketches would overflow a cell's count. With the default i32 counts,
+    /// honest sketches only get near that with billions of elements in one cell, so it usually
+    /// means one was crafted. Also returned by [IBF::try_encode](crate::IBF::try_encode) and
+    /// [IBF::into_count_width](crate::IBF::into_count_width) for counts that don't fit their
+    /// [Count](crate::Count) type.
+    CountOverflow {
+        /// Index of the first cell that would overflow
+        cell: usize,
+    },
+    /// A change feed skipped events, see [FeedCursor](crate::FeedCursor)
+    FeedGap {
+        /// Sequence number of the next event the cursor expected
+        expected: u64,
+        /// Sequence number of the event that arrived instead
+        found: u64,
+    },
+    /// A peer sent a sketch older than one already received from it, see
+    /// [SequenceWatermark](crate::SequenceWatermark)
+    StaleSketch {
+        /// Highest sequence number received before
+        seen: u64,
+        /// Sequence number of the stale sketch
+        received: u64,
+    },
+    /// A [snapshot](crate::IBF::snapshot) doesn't match its checksum, usually because a crash
+    /// left it partly written
+    SnapshotChecksum,
+    /// The GPU couldn't be used for [gpu_sub](crate::IBF::gpu_sub)
+    Gpu {
+        /// What went wrong
+        reason: &'static str,
+    },
+}
+
+/// What an [IronRoseError](IronRoseError) means for the caller, from
+/// [kind](IronRoseError::kind). More kinds may be added, though far more rarely than errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The two sketches are configured differently and can't be combined, the peers need to
+    /// agree on parameters first
+    Incompatible,
+    /// The IBF was too small for the differences, retrying with a larger one may work
+    DecodeFailed,
+    /// Reconciling finished, but the sets still differ
+    NotConverged,
+    /// A sketch would go over its wire budget
+    OverBudget,
+    /// Input from elsewhere isn't a valid sketch, or was written by an unsupported version
+    Malformed,
+    /// The caller passed arguments that don't fit the sketch or its elements
+    InvalidInput,
+    /// Sketches or events arrived out of order
+    OutOfOrder,
+    /// The element was probably seen before
+    Duplicate,
+    /// Something outside the sketches failed, such as a lock or the GPU
+    Internal,
+}
+
+impl IronRoseError {
+    /// The kind of error, for handling errors by what they mean rather than by variant
+    /// ```rust
+    /// use iron_rose::{ErrorKind, IBF};
+    ///
+    /// let err = (IBF::<u64>::new(10) - IBF::new(20)).unwrap_err();
+    /// assert_eq!(err.kind(), ErrorKind::Incompatible);
+    /// assert_eq!(IBF::<u64>::from_bytes(b"junk").unwrap_err().kind(), ErrorKind::Malformed);
+    /// ```
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            IronRoseError::ShapeMismatch { .. }
+            | IronRoseError::KeyspaceMismatch { .. }
+            | IronRoseError::HasherMismatch { .. }
+            | IronRoseError::ElementWidthMismatch { .. }
+            | IronRoseError::EstimatorSizeMismatch { .. }
+            | IronRoseError::MinWiseSizeMismatch { .. }
+            | IronRoseError::IncompatiblePeer { .. } => ErrorKind::Incompatible,
+            IronRoseError::DecodeFailed { .. } => ErrorKind::DecodeFailed,
+            IronRoseError::NotConverged { .. } | IronRoseError::RootMismatch => {
+                ErrorKind::NotConverged
+            }
+            IronRoseError::OverWireBudget { .. } | IronRoseError::OverCellBudget { .. } => {
+                ErrorKind::OverBudget
+            }
+            IronRoseError::CellCountMismatch { .. }
+            | IronRoseError::HashCountOutOfRange { .. }
+            | IronRoseError::IncompleteSegments { .. }
+            | IronRoseError::SegmentChecksum { .. }
+            | IronRoseError::UnsupportedVersion { .. }
+            | IronRoseError::MalformedBytes { .. }
+            | IronRoseError::CountOverflow { .. }
+            | IronRoseError::ChecksumMismatch
+            | IronRoseError::SnapshotChecksum => ErrorKind::Malformed,
+            IronRoseError::KeyspaceTooLong { .. }
+            | IronRoseError::TooLargeForWire { .. }
+            | IronRoseError::CellOutOfRange { .. }
+            | IronRoseError::InvalidRange { .. }
+            | IronRoseError::DigestCollision { .. }
+            | IronRoseError::UnknownDigest { .. } => ErrorKind::InvalidInput,
+            IronRoseError::FeedGap { .. } | IronRoseError::StaleSketch { .. } => {
+                ErrorKind::OutOfOrder
+            }
+            IronRoseError::LikelyDuplicate => ErrorKind::Duplicate,
+            IronRoseError::LockPoisoned | IronRoseError::Gpu { .. } => ErrorKind::Internal,
+        }
+    }
+
+    /// The parameters the two sides disagree on, for errors caused by mismatched configurations,
+    /// and empty for any other error. A [ShapeMismatch](IronRoseError::ShapeMismatch) lists
+    /// every field of the shapes that differs, and a
+    /// [HasherMismatch](IronRoseError::HasherMismatch) says which side uses a hasher other than
+    /// the default one.
+    /// ```rust
+    /// use iron_rose::{ParamMismatch, IBF};
+    ///
+    /// let err = (IBF::<u64>::new(10) - IBF::new(20)).unwrap_err();
+    /// assert_eq!(err.param_diff(), [ParamMismatch::Size { left: 10, right: 20 }]);
+    /// let json = serde_json::to_string(&err.param_diff()).unwrap();
+    /// assert_eq!(json, r#"[{"Size":{"left":10,"right":20}}]"#);
+    /// ```
+    pub fn param_diff(&self) -> Vec<ParamMismatch> {
+        match self {
+            IronRoseError::ShapeMismatch { left, right } => left.diff(right),
+            IronRoseError::EstimatorSizeMismatch {
+                left_strata,
+                left,
+                right_strata,
+                right,
+            } => {
+                let mut diff = Vec::new();
+                if left_strata != right_strata {
+                    diff.push(ParamMismatch::Strata {
+                        left: *left_strata,
+                        right: *right_strata,
+                    });
+                }
+                diff.extend(left.diff(right));
+                diff
+            }
+            IronRoseError::KeyspaceMismatch { left, right } => {
+                alloc::vec![ParamMismatch::Keyspace {
+                    left: left.clone(),
+                    right: right.clone(),
+                }]
+            }
+            IronRoseError::HasherMismatch { left, right } => {
+                alloc::vec![ParamMismatch::hasher(*left, *right)]
+            }
+            IronRoseError::ElementWidthMismatch { left, right } => {
+                alloc::vec![ParamMismatch::ElementWidth {
+                    left: *left,
+                    right: *right,
+                }]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Display for IronRoseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IronRoseError::ShapeMismatch { left, right } => write!(
+                f,
+                "IBFs are not configured the same: {} cells with hash_count {} and {} cells \
+                 with hash_count {}",
+                left.size(),
+                left.hash_count(),
+                right.size(),
+                right.hash_count()
+            ),
+            IronRoseError::KeyspaceMismatch { left, right } => write!(
+                f,
+                "IBFs are of different keyspaces: {:?} and {:?}",
+                left, right
+            ),
+            IronRoseError::HasherMismatch { left, right } => {
+                let kind = |id| {
+                    if is_default_hasher(id) {
+                        "default"
+                    } else {
+                        "custom"
+                    }
+                };
+                write!(
+                    f,
+                    "IBFs use different hashers: ids {:#018x} ({}) and {:#018x} ({})",
+                    left,
+                    kind(*left),
+                    right,
+                    kind(*right)
+                )
+            }
+            IronRoseError::ElementWidthMismatch { left, right } => write!(
+                f,
+                "IBFs hold elements of different widths: {} and {} bytes",
+                left, right
+            ),
+            IronRoseError::DecodeFailed { remaining_cells } => write!(
+                f,
+                "Unable to fully decode: {} cells are not empty",
+                remaining_cells
+            ),
+            IronRoseError::EstimatorSizeMismatch {
+                left_strata,
+                left,
+                right_strata,
+                right,
+            } => write!(
+                f,
+                "Strata Estimators are of different shapes: expected {} strata of {} cells with \
+                 hash_count {}, found {} strata of {} cells with hash_count {}",
+                left_strata,
+                left.size(),
+                left.hash_count(),
+                right_strata,
+                right.size(),
+                right.hash_count()
+            ),
+            IronRoseError::OverWireBudget { bytes, max } => write!(
+                f,
+                "Sketch would be {} bytes on the wire, over the budget of {} bytes",
+                bytes, max
+            ),
+            IronRoseError::IncompatiblePeer { reason } => {
+                write!(f, "Peer can't reconcile with this one: {}", reason)
+            }
+            IronRoseError::OverCellBudget { cells, max } => write!(
+                f,
+                "IBF of {} cells is over the budget of {} cells",
+                cells, max
+            ),
+            IronRoseError::CellCountMismatch { size, cells } => {
+                write!(f, "IBF claims {} cells but has {}", size, cells)
+            }
+            IronRoseError::HashCountOutOfRange { hash_count, size } => write!(
+                f,
+                "IBF of {} cells claims a hash count of {}",
+                size, hash_count
+            ),
+            IronRoseError::KeyspaceTooLong { len } => write!(
+                f,
+                "Keyspace of {} bytes is over the limit of {} bytes",
+                len,
+                crate::ibf::MAX_KEYSPACE_BYTES
+            ),
+            IronRoseError::TooLargeForWire { size, hash_count } => write!(
+                f,
+                "IBF of {} cells with hash count {} is too large for the wire format",
+                size, hash_count
+            ),
+            IronRoseError::CellOutOfRange { cell, size } => write!(
+                f,
+                "Cell {} is out of range for an IBF of {} cells",
+                cell, size
+            ),
+            IronRoseError::InvalidRange { start, end, size } => write!(
+                f,
+                "Cells {:?} are out of range for an IBF of {} cells",
+                start..end,
+                size
+            ),
+            IronRoseError::IncompleteSegments { cell } => write!(
+                f,
+                "Cell {} is missing from the segments, or in more than one",
+                cell
+            ),
+            IronRoseError::SegmentChecksum { start } => {
+                write!(f, "Segment starting at cell {} failed its checksum", start)
+            }
+            IronRoseError::LikelyDuplicate => write!(f, "Element was probably already encoded"),
+            IronRoseError::MinWiseSizeMismatch { left, right } => write!(
+                f,
+                "Min-wise estimators keep different numbers of hashes: {} and {}",
+                left, right
+            ),
+            IronRoseError::NotConverged { local, remote } => write!(
+                f,
+                "Sets did not converge: local has {} elements, remote has {} elements",
+                local, remote
+            ),
+            IronRoseError::RootMismatch => {
+                write!(f, "Sets did not converge: their hashes differ")
+            }
+            IronRoseError::LockPoisoned => write!(f, "Sketch lock was poisoned"),
+            IronRoseError::UnsupportedVersion { version } => write!(
+                f,
+                "Wire format version {} is not supported, expected {} to {}",
+                version,
+                crate::MIN_WIRE_VERSION,
+                crate::WIRE_VERSION
+            ),
+            IronRoseError::ChecksumMismatch => {
+                write!(
+                    f,
+                    "Sketch bytes failed their checksum, they may be truncated"
+                )
+            }
+            IronRoseError::MalformedBytes { reason } => {
+                write!(f, "Malformed sketch bytes: {}", reason)
+            }
+            IronRoseError::DigestCollision { digest } => {
+                write!(f, "Two different elements have the digest {:032x}", digest)
+            }
+            IronRoseError::UnknownDigest { digest } => {
+                write!(f, "No local element has the digest {:032x}", digest)
+            }
+            IronRoseError::CountOverflow { cell } => {
+                write!(f, "The count of cell {} would overflow", cell)
+            }
+            IronRoseError::FeedGap { expected, found } => write!(
+                f,
+                "Change feed skipped events: expected sequence {}, got {}",
+                expected, found
+            ),
+            IronRoseError::StaleSketch { seen, received } => write!(
+                f,
+                "Sketch at sequence {} is older than the one already seen at {}",
+                received, seen
+            ),
+            IronRoseError::SnapshotChecksum => {
+                write!(f, "Snapshot failed its checksum, it may be partly written")
+            }
+            IronRoseError::Gpu { reason } => write!(f, "GPU subtraction failed: {}", reason),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for IronRoseError {}
+
+/// A decode that got stuck, along with the elements that were recovered before it did. Those
+/// are real differences, so a caller can act on them and retry the rest with a bigger IBF.
+/// Converts into [DecodeFailed](IronRoseError::DecodeFailed) with `?`.
+/// ```rust
+/// use iron_rose::IBF;
+///
+/// let mut ibf = IBF::new(4);
+/// (0..20u64).for_each(|x| ibf.encode(x));
+/// let err = ibf.decode().unwrap_err();
+/// assert!(err.remaining_cells() > 0);
+/// assert!(err.decoded().len() < 20);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    decoded: Vec<Side<T>>,
+    remaining_cells: usize,
+}
+
+impl<T> DecodeError<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    pub(crate) fn new(decoded: Vec<Side<T>>, remaining_cells: usize) -> Self {
+        Self {
+            decoded,
+            remaining_cells,
+        }
+    }
+
+    /// The elements recovered before decoding got stuck, in the order they were recovered.
+    /// That order only depends on the cells, so decoding the same IBF fails the same way, down
+    /// to the [Debug](Debug) output, on every run.
+    pub fn decoded(&self) -> &[Side<T>] {
+        &self.decoded
+    }
+
+    /// Takes the elements recovered before decoding got stuck
+    #[cfg(feature = "std")]
+    pub fn into_decoded(self) -> HashSet<Side<T>> {
+        self.decoded.into_iter().collect()
+    }
+
+    /// Number of cells that were still not empty
+    pub fn remaining_cells(&self) -> usize {
+        self.remaining_cells
+    }
+}
+
+impl<T> Display for DecodeError<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unable to fully decode: {} cells are not empty after recovering {} elements",
+            self.remaining_cells,
+            self.decoded.len()
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> Error for DecodeError<T> where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+{
+}
+
+impl<T> From<DecodeError<T>> for IronRoseError
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    fn from(err: DecodeError<T>) -> Self {
+        IronRoseError::DecodeFailed {
+            remaining_cells: err.remaining_cells,
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{
+    IncrementalSetHash, IronRoseError, Sequenced, SetDigest, SetRoot, StrataEstimator, IBF,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Debug,
+    hash::{BuildHasher, Hash},
+    ops::{BitXor, BitXorAssign},
+};
+
+/// One change to a set, as read from a change data capture stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ChangeEvent<T> {
+    /// The element was added to the set
+    Added(T),
+    /// The element was removed from the set
+    Removed(T),
+}
+
+impl<T> ChangeEvent<T> {
+    /// The element that changed
+    pub fn element(&self) -> &T {
+        match self {
+            ChangeEvent::Added(element) | ChangeEvent::Removed(element) => element,
+        }
+    }
+}
+
+/// Sketches and summaries that can follow a set through its [changes](ChangeEvent). Tuples of
+/// them apply each change to every member, so an IBF, its estimator and a digest stay in step.
+pub trait ApplyChange<T> {
+    /// Updates for one change
+    fn apply_change(&mut self, event: &ChangeEvent<T>);
+}
+
+impl<T, H> ApplyChange<T> for IBF<T, H>
+where
+    T: Clone + Hash + BitXor<Output = T> + BitXorAssign + Default + PartialEq + Eq + Debug,
+    H: BuildHasher + Clone,
+{
+    fn apply_change(&mut self, event: &ChangeEvent<T>) {
+        match event {
+            ChangeEvent::Added(element) => self.encode(element.clone()),
+            ChangeEvent::Removed(element) => self.remove(element.clone()),
+        }
+    }
+}
+
+impl<T: Hash> ApplyChange<T> for StrataEstimator {
+    fn apply_change(&mut self, event: &ChangeEvent<T>) {
+        match event {
+            ChangeEvent::Added(element) => self.encode(element),
+            ChangeEvent::Removed(element) => self.remove(element),
+        }
+    }
+}
+
+macro_rules! apply_to_summary {
+    ($($summary:ty),*) => {
+        $(
+            impl<T: Hash> ApplyChange<T> for $summary {
+                fn apply_change(&mut self, event: &ChangeEvent<T>) {
+                    match event {
+                        ChangeEvent::Added(element) => self.insert(element),
+                        ChangeEvent::Removed(element) => self.remove(element),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+apply_to_summary!(SetDigest, SetRoot, IncrementalSetHash);
+
+impl<T, S: ApplyChange<T>> ApplyChange<T> for Sequenced<S> {
+    fn apply_change(&mut self, event: &ChangeEvent<T>) {
+        self.mutate(|sketch| sketch.apply_change(event))
+    }
+}
+
+impl<T, A: ApplyChange<T>, B: ApplyChange<T>> ApplyChange<T> for (A, B) {
+    fn apply_change(&mut self, event: &ChangeEvent<T>) {
+        self.0.apply_change(event);
+        self.1.apply_change(event);
+    }
+}
+
+impl<T, A: ApplyChange<T>, B: ApplyChange<T>, C: ApplyChange<T>> ApplyChange<T> for (A, B, C) {
+    fn apply_change(&mut self, event: &ChangeEvent<T>) {
+        self.0.apply_change(event);
+        self.1.apply_change(event);
+        self.2.apply_change(event);
+    }
+}
+
+/// Position in a change feed whose events carry consecutive sequence numbers, for applying
+/// each event exactly once even when the stream redelivers or drops some. Events before the
+/// cursor were already applied and are skipped, and an event past it means some were lost, so
+/// applying stops with a [FeedGap](IronRoseError::FeedGap) naming the sequence number to resume
+/// the stream from.
+///
+/// The cursor is serializable, to be stored along with the sketches it covers, such as the
+/// sequence number of a [snapshot](IBF::snapshot).
+/// ```rust
+/// use iron_rose::{ChangeEvent, FeedCursor, IronRoseError, SetDigest, StrataEstimator, IBF};
+///
+/// let mut sketches = (IBF::new(20), StrataEstimator::default(), SetDigest::new());
+/// let mut cursor = FeedCursor::new(1);
+/// let batch = vec![
+///     (1, ChangeEvent::Added(10u64)),
+///     (2, ChangeEvent::Added(11)),
+///     (2, ChangeEvent::Added(11)), // Redelivered
+///     (3, ChangeEvent::Removed(10)),
+/// ];
+/// let applied = cursor.apply_events(&mut sketches, batch).unwrap();
+/// assert_eq!((applied.applied, applied.duplicates), (3, 1));
+/// assert_eq!(cursor.next_sequence(), 4);
+/// assert_eq!(sketches.2, [11u64].iter().collect());
+///
+/// let lost = vec![(5, ChangeEvent::Added(12u64))];
+/// assert_eq!(
+///     cursor.apply_events(&mut sketches, lost),
+///     Err(IronRoseError::FeedGap { expected: 4, found: 5 })
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FeedCursor {
+    next: u64,
+}
+
+/// What [FeedCursor::apply_events](FeedCursor::apply_events) did with a batch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AppliedEvents {
+    /// Events applied to the sketches
+    pub applied: usize,
+    /// Events skipped as already applied
+    pub duplicates: usize,
+}
+
+impl FeedCursor {
+    /// Cursor expecting the event numbered next first
+    pub fn new(next: u64) -> Self {
+        Self { next }
+    }
+
+    /// Sequence number of the next event to apply
+    pub fn next_sequence(&self) -> u64 {
+        self.next
+    }
+
+    /// Checks an event's sequence number against the cursor, moving the cursor past it. Ok(true)
+    /// if the event is next and should be applied, Ok(false) if it was already applied, for
+    /// callers that apply events their own way.
+    pub fn advance(&mut self, sequence: u64) -> Result<bool, IronRoseError> {
+        if sequence < self.next {
+            return Ok(false);
+        }
+        if sequence > self.next {
+            return Err(IronRoseError::FeedGap {
+                expected: self.next,
+                found: sequence,
+            });
+        }
+        self.next += 1;
+        Ok(true)
+    }
+
+    /// Applies the events, numbered by sequence, that come next to sketches, skipping the ones
+    /// already applied. On a gap the events before it stay applied and the cursor stays at the
+    /// first missing one.
+    pub fn apply_events<T, S, I>(
+        &mut self,
+        sketches: &mut S,
+        events: I,
+    ) -> Result<AppliedEvents, IronRoseError>
+    where
+        S: ApplyChange<T>,
+        I: IntoIterator<Item = (u64, ChangeEvent<T>)>,
+    {
+        let mut applied = AppliedEvents::default();
+        for (sequence, event) in events {
+            if self.advance(sequence)? {
+                sketches.apply_change(&event);
+                applied.applied += 1;
+            } else {
+                applied.duplicates += 1;
+            }
+        }
+        Ok(applied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SketchParams;
+
+    #[test]
+    fn feeds_match_rebuilt_sketches() {
+        let params = SketchParams::new(40, 3);
+        let mut live = (IBF::with_params(params), IncrementalSetHash::new());
+        let mut cursor = FeedCursor::new(0);
+        let events: Vec<_> = (0..200u64)
+            .map(ChangeEvent::Added)
+            .chain((0..100u64).map(|x| ChangeEvent::Removed(x * 2)))
+            .enumerate()
+            .map(|(i, event)| (i as u64, event))
+            .collect();
+        // Delivered twice over, in overlapping batches
+        for batch in events.chunks(70).chain(events.chunks(50)) {
+            cursor
+                .apply_events(&mut live, batch.iter().copied())
+                .unwrap();
+        }
+        assert_eq!(cursor.next_sequence(), 300);
+
+        let mut rebuilt = (IBF::with_params(params), IncrementalSetHash::new());
+        for x in (0..200u64).filter(|x| x % 2 == 1) {
+            rebuilt.0.encode(x);
+            rebuilt.1.insert(&x);
+        }
+        assert_eq!(live, rebuilt);
+        assert_eq!(*ChangeEvent::Removed(3).element(), 3);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::{IronRoseError, Side, SketchParams, StrataEstimator, WireElement, IBF};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    ops::{BitXor, BitXorAssign},
+    path::{Path, PathBuf},
+};
+
+/// The 256 bit BLAKE3 hash of a file's contents, as an IBF element. Files with the same
+/// contents have the same hash wherever they are, so directories are reconciled by what their
+/// files hold, whatever they're named.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ContentHash([u8; 32]);
+
+impl ContentHash {
+    /// The hash of contents
+    pub fn of(contents: &[u8]) -> Self {
+        Self(*blake3::hash(contents).as_bytes())
+    }
+
+    /// The hash of the contents of the file at path, read in a streaming fashion
+    pub fn of_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut hasher = blake3::Hasher::new();
+        io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+        Ok(Self(*hasher.finalize().as_bytes()))
+    }
+
+    /// The hash with these 32 bytes
+    pub const fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// The hash's 32 bytes
+    pub const fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+/// Written in hex, like `b3sum` does
+impl fmt::Debug for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+impl BitXor for ContentHash {
+    type Output = ContentHash;
+
+    fn bitxor(mut self, rhs: Self) -> Self::Output {
+        self ^= rhs;
+        self
+    }
+}
+
+impl BitXorAssign for ContentHash {
+    fn bitxor_assign(&mut self, rhs: Self) {
+        for (l, r) in self.0.iter_mut().zip(rhs.0.iter()) {
+            *l ^= r;
+        }
+    }
+}
+
+impl WireElement for ContentHash {
+    const WIDTH: usize = 32;
+
+    fn write_le(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.0);
+    }
+
+    fn read_le(bytes: &[u8]) -> Self {
+        let mut hash = [0; 32];
+        hash.copy_from_slice(bytes);
+        Self(hash)
+    }
+}
+
+/// The files under a directory, by the [hash](ContentHash) of their contents, to build sketches
+/// from and to map decoded hashes back to files. Files with the same contents are one element,
+/// listed under every path that holds them. Symbolic links aren't followed.
+///
+/// Peers exchange [estimators](DirectorySketch::estimator) and [IBFs](DirectorySketch::ibf)
+/// like for any other set, and [difference](DirectorySketch::difference) turns the peer's IBF
+/// into the local files it's missing, and the hashes of the files it has that are missing
+/// locally, for it to send. See `examples/sync_dirs.rs` for both sides run on one machine.
+/// ```rust
+/// use iron_rose::{DirectorySketch, SketchParams};
+/// use std::fs;
+///
+/// let root = std::env::temp_dir().join(format!("iron_rose_fs_doc_{}", std::process::id()));
+/// let (left, right) = (root.join("left"), root.join("right"));
+/// fs::create_dir_all(left.join("nested")).unwrap();
+/// fs::create_dir_all(&right).unwrap();
+/// fs::write(left.join("a.txt"), "apple").unwrap();
+/// fs::write(left.join("nested/b.txt"), "banana").unwrap();
+/// fs::write(right.join("renamed.txt"), "apple").unwrap();
+/// fs::write(right.join("c.txt"), "cherry").unwrap();
+///
+/// let left = DirectorySketch::scan(&left).unwrap();
+/// let right = DirectorySketch::scan(&right).unwrap();
+/// let params = SketchParams::new(20, 3);
+/// let diff = left.difference(&right.ibf(params)).unwrap();
+/// assert_eq!(diff.local_only, vec![std::path::PathBuf::from("nested/b.txt")]);
+/// assert_eq!(right.paths(&diff.remote_only[0]), [std::path::PathBuf::from("c.txt")]);
+/// # fs::remove_dir_all(&root).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct DirectorySketch {
+    root: PathBuf,
+    files: HashMap<ContentHash, Vec<PathBuf>>,
+}
+
+impl DirectorySketch {
+    /// Hashes every regular file under root, recursively
+    pub fn scan(root: impl AsRef<Path>) -> io::Result<Self> {
+        let mut sketch = Self {
+            root: root.as_ref().to_path_buf(),
+            files: HashMap::new(),
+        };
+        let mut pending = vec![sketch.root.clone()];
+        while let Some(dir) = pending.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    pending.push(entry.path());
+                } else if file_type.is_file() {
+                    let path = entry.path();
+                    let hash = ContentHash::of_file(&path)?;
+                    let relative = path
+                        .strip_prefix(&sketch.root)
+                        .expect("found under root")
+                        .to_path_buf();
+                    sketch.files.entry(hash).or_default().push(relative);
+                }
+            }
+        }
+        sketch.files.values_mut().for_each(|paths| paths.sort());
+        Ok(sketch)
+    }
+
+    /// The directory that was scanned
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Number of distinct contents
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// True if there are no files
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Paths, relative to the root, of the files holding the contents with this hash, empty if
+    /// there are none
+    pub fn paths(&self, hash: &ContentHash) -> &[PathBuf] {
+        self.files.get(hash).map_or(&[], Vec::as_slice)
+    }
+
+    /// A default estimator of the file contents, to send to the peer
+    pub fn estimator(&self) -> StrataEstimator {
+        let mut estimator = StrataEstimator::default();
+        self.files.keys().for_each(|hash| estimator.encode(hash));
+        estimator
+    }
+
+    /// An IBF of the given shape holding the file contents, to send to the peer
+    pub fn ibf(&self, params: SketchParams) -> IBF<ContentHash> {
+        let mut ibf = IBF::with_params(params);
+        self.files.keys().for_each(|hash| ibf.encode(*hash));
+        ibf
+    }
+
+    /// Subtracts the peer's IBF from a local one of the same shape and decodes them into the
+    /// files the peer is missing and the hashes of the ones missing locally. Fails like
+    /// [decode_difference](IBF::decode_difference) when the IBF is too small for the
+    /// differences, and the peer should be asked for a larger one.
+    pub fn difference(&self, remote: &IBF<ContentHash>) -> Result<FileDifference, IronRoseError> {
+        let local = self.ibf(remote.params());
+        let mut diff = FileDifference::default();
+        for side in (&local - remote)?.decode_difference()? {
+            match side {
+                Side::Left(hash) => diff.local_only.extend_from_slice(self.paths(&hash)),
+                Side::Right(hash) => diff.remote_only.push(hash),
+            }
+        }
+        diff.local_only.sort();
+        diff.remote_only.sort();
+        Ok(diff)
+    }
+}
+
+/// How two directories differ, from [DirectorySketch::difference](DirectorySketch::difference)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileDifference {
+    /// Local files, relative to the root, whose contents the peer doesn't have
+    pub local_only: Vec<PathBuf>,
+    /// Hashes of contents only the peer has, which it can look up with
+    /// [paths](DirectorySketch::paths)
+    pub remote_only: Vec<ContentHash>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directories_reconcile_by_content() {
+        let root = std::env::temp_dir().join(format!("iron_rose_fs_{}", std::process::id()));
+        let (left, right) = (root.join("left"), root.join("right"));
+        fs::create_dir_all(left.join("deep/er")).unwrap();
+        fs::create_dir_all(&right).unwrap();
+        for i in 0..50 {
+            fs::write(left.join(format!("deep/er/{}", i)), i.to_string()).unwrap();
+            fs::write(right.join(format!("{}.bak", i + 3)), (i + 3).to_string()).unwrap();
+        }
+        // Duplicates are one element, listed under both paths
+        fs::write(left.join("copy"), "7").unwrap();
+
+        let (left, right) = (
+            DirectorySketch::scan(&left).unwrap(),
+            DirectorySketch::scan(&right).unwrap(),
+        );
+        assert_eq!(left.len(), 50);
+        assert_eq!(left.paths(&ContentHash::of(b"7")).len(), 2);
+        assert_eq!(
+            left.estimator().estimate_differences(&right.estimator()),
+            Ok(12)
+        );
+
+        let remote = IBF::from_bytes(&right.ibf(SketchParams::new(30, 3)).to_bytes()).unwrap();
+        let diff = left.difference(&remote).unwrap();
+        let expected = (0..3)
+            .map(|i| PathBuf::from(format!("deep/er/{}", i)))
+            .collect::<Vec<_>>();
+        assert_eq!(diff.local_only, expected);
+        let mut remote_only = diff
+            .remote_only
+            .iter()
+            .flat_map(|hash| right.paths(hash).iter().cloned())
+            .collect::<Vec<_>>();
+        remote_only.sort();
+        assert_eq!(
+            remote_only,
+            [PathBuf::from("50.bak"), "51.bak".into(), "52.bak".into()]
+        );
+        assert!(left
+            .difference(&right.ibf(SketchParams::new(3, 3)))
+            .is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
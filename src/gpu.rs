@@ -0,0 +1,245 @@
+use crate::IronRoseError;
+use std::sync::mpsc;
+use wgpu::util::DeviceExt;
+
+/// Threads per workgroup of the subtraction kernel
+const WORKGROUP_SIZE: u32 = 256;
+
+/// Classification of a cell the kernel has finished with
+pub(crate) const EMPTY: u32 = 0;
+/// Classification of a cell whose count is 1 or -1. It's only a candidate for being pure, the
+/// CPU still checks its hash.
+pub(crate) const CANDIDATE: u32 = 1;
+
+/// Subtracts the right cells from the left ones in place, and classifies each result. Cells are
+/// laid out as in the [wire format](crate::IBF::to_bytes), `stride` words each, with the count
+/// in the last word.
+const SHADER: &str = r#"
+struct Params {
+    cells: u32,
+    stride: u32,
+    _pad0: u32,
+    _pad1: u32,
+}
+
+@group(0) @binding(0) var<storage, read_write> left: array<u32>;
+@group(0) @binding(1) var<storage, read> right: array<u32>;
+@group(0) @binding(2) var<storage, read_write> flags: array<u32>;
+@group(0) @binding(3) var<uniform> params: Params;
+
+@compute @workgroup_size(256)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let cell = id.x;
+    if (cell >= params.cells) {
+        return;
+    }
+    let base = cell * params.stride;
+    let last = base + params.stride - 1u;
+    var bits = 0u;
+    for (var i = base; i < last; i = i + 1u) {
+        let word = left[i] ^ right[i];
+        left[i] = word;
+        bits = bits | word;
+    }
+    let count = bitcast<i32>(left[last]) - bitcast<i32>(right[last]);
+    left[last] = bitcast<u32>(count);
+    var flag = 2u;
+    if (count == 1 || count == -1) {
+        flag = 1u;
+    } else if (count == 0 && bits == 0u) {
+        flag = 0u;
+    }
+    flags[cell] = flag;
+}
+"#;
+
+/// A GPU device set up to subtract IBFs, for [IBF::gpu_sub](crate::IBF::gpu_sub). Opening the
+/// device is slow, so keep the context around rather than making one per subtraction.
+/// ```rust,no_run
+/// use iron_rose::{GpuContext, IBF};
+///
+/// let gpu = GpuContext::new().expect("a GPU adapter");
+/// let (mut left, mut right) = (IBF::new(1 << 20), IBF::new(1 << 20));
+/// left.encode(1u64);
+/// right.encode(2u64);
+/// let set = left.gpu_sub(&right, &gpu).unwrap().decode().unwrap();
+/// assert_eq!(set.len(), 2);
+/// ```
+#[derive(Debug)]
+pub struct GpuContext {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    max_binding_bytes: u64,
+    max_workgroups: u32,
+}
+
+impl GpuContext {
+    /// Opens the system's default GPU adapter, failing if there is none
+    pub fn new() -> Result<Self, IronRoseError> {
+        pollster::block_on(Self::request())
+    }
+
+    async fn request() -> Result<Self, IronRoseError> {
+        let instance = wgpu::Instance::default();
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions::default())
+            .await
+            .ok_or(IronRoseError::Gpu {
+                reason: "no GPU adapter found",
+            })?;
+        let limits = adapter.limits();
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: Some("iron_rose"),
+                    required_features: wgpu::Features::empty(),
+                    required_limits: limits.clone(),
+                    memory_hints: wgpu::MemoryHints::Performance,
+                },
+                None,
+            )
+            .await
+            .map_err(|_| IronRoseError::Gpu {
+                reason: "couldn't open the GPU device",
+            })?;
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("iron_rose subtraction"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("iron_rose subtraction"),
+            layout: None,
+            module: &module,
+            entry_point: "main",
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        Ok(Self {
+            device,
+            queue,
+            pipeline,
+            max_binding_bytes: (limits.max_storage_buffer_binding_size as u64)
+                .min(limits.max_buffer_size),
+            max_workgroups: limits.max_compute_workgroups_per_dimension,
+        })
+    }
+
+    /// Most cells of stride words that fit in one dispatch
+    pub(crate) fn max_cells(&self, stride: usize) -> usize {
+        let by_bytes = self.max_binding_bytes / (stride as u64 * 4);
+        let by_dispatch = self.max_workgroups as u64 * WORKGROUP_SIZE as u64;
+        by_bytes.min(by_dispatch).max(1) as usize
+    }
+
+    /// Subtracts right from left, cells cells of stride words each, returning the resulting
+    /// cells and a classification of each
+    pub(crate) fn subtract(
+        &self,
+        left: &[u8],
+        right: &[u8],
+        stride: usize,
+        cells: usize,
+    ) -> Result<(Vec<u8>, Vec<u32>), IronRoseError> {
+        let storage = wgpu::BufferUsages::STORAGE;
+        let left_buf = self.buffer_init(left, storage | wgpu::BufferUsages::COPY_SRC);
+        let right_buf = self.buffer_init(right, storage);
+        let flags_size = (cells * 4) as u64;
+        let flags_buf = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: flags_size,
+            usage: storage | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let params = [cells as u32, stride as u32, 0, 0];
+        let params_bytes: Vec<u8> = params.iter().flat_map(|p| p.to_le_bytes()).collect();
+        let params_buf = self.buffer_init(&params_bytes, wgpu::BufferUsages::UNIFORM);
+
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: left_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: right_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: flags_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: params_buf.as_entire_binding(),
+                },
+            ],
+        });
+
+        let left_staging = self.staging(left.len() as u64);
+        let flags_staging = self.staging(flags_size);
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: None,
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups((cells as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&left_buf, 0, &left_staging, 0, left.len() as u64);
+        encoder.copy_buffer_to_buffer(&flags_buf, 0, &flags_staging, 0, flags_size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let diff = self.read(&left_staging)?;
+        let flags = self
+            .read(&flags_staging)?
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+        Ok((diff, flags))
+    }
+
+    fn buffer_init(&self, contents: &[u8], usage: wgpu::BufferUsages) -> wgpu::Buffer {
+        self.device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents,
+                usage,
+            })
+    }
+
+    fn staging(&self, size: u64) -> wgpu::Buffer {
+        self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Waits for the GPU and copies a staging buffer back to the CPU
+    fn read(&self, staging: &wgpu::Buffer) -> Result<Vec<u8>, IronRoseError> {
+        let slice = staging.slice(..);
+        let (tx, rx) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |mapped| {
+            let _ = tx.send(mapped);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        let unreadable = IronRoseError::Gpu {
+            reason: "couldn't read results back from the GPU",
+        };
+        match rx.recv() {
+            Ok(Ok(())) => {}
+            _ => return Err(unreadable),
+        }
+        let bytes = slice.get_mapped_range().to_vec();
+        staging.unmap();
+        Ok(bytes)
+    }
+}
@@ -1,13 +1,66 @@
-use crate::{cell::Cell, Side};
-use fasthash::MetroHasher as ElmHasher;
-use serde::{Deserialize, Serialize};
-use std::hash::Hasher;
-use std::{
-    collections::HashSet,
+use crate::metro::MetroHasher as ElmHasher;
+use crate::wire::{self, Reader, WireElement};
+#[cfg(feature = "std")]
+use crate::IbfBuilder;
+use crate::{
+    cell::Cell, CellStorage, Count, DecodeError, Difference, IbfParams, IronRoseError, Side,
+    SketchKind, SketchParams,
+};
+use alloc::{boxed::Box, collections::BTreeMap, string::ToString, sync::Arc, vec, vec::Vec};
+use core::hash::{BuildHasher, BuildHasherDefault, Hasher};
+use core::{
+    convert::TryFrom,
     fmt::Debug,
     hash::Hash,
-    ops::{BitXor, BitXorAssign, Sub},
+    marker::PhantomData,
+    ops::{Add, BitXor, BitXorAssign, Neg, Range, Sub},
 };
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::{collections::HashSet, time::Instant};
+
+/// The 64 bit hash the crate uses for elements. In hash only mode, sketches hold these hashes in
+/// place of the elements themselves, and decoded hashes are mapped back to elements through the
+/// caller's own index.
+pub fn element_hash<T: Hash + ?Sized>(element: &T) -> u64 {
+    let mut hasher: ElmHasher = Default::default();
+    element.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Identifies a hasher by the hash it gives a fixed probe value, the same for hashers that
+/// place elements the same way, and almost certainly different otherwise
+pub(crate) fn hasher_id<H: BuildHasher>(hasher: &H) -> u64 {
+    hasher.hash_one("iron_rose/hasher/v1")
+}
+
+/// Checks a hash count read from elsewhere puts every element in at least one of size cells,
+/// and in no more cells than there are, which would make every operation loop for nothing
+pub(crate) fn check_hash_count(hash_count: usize, size: usize) -> Result<(), IronRoseError> {
+    if hash_count == 0 || hash_count > size {
+        return Err(IronRoseError::HashCountOutOfRange { hash_count, size });
+    }
+    Ok(())
+}
+
+/// Longest [keyspace](IBF::with_keyspace), in bytes, as the wire format writes its length as
+/// a u16
+pub(crate) const MAX_KEYSPACE_BYTES: usize = u16::MAX as usize;
+
+/// Checks a keyspace, from a peer or otherwise, fits in the wire format
+pub(crate) fn check_keyspace(keyspace: &str) -> Result<(), IronRoseError> {
+    if keyspace.len() > MAX_KEYSPACE_BYTES {
+        return Err(IronRoseError::KeyspaceTooLong {
+            len: keyspace.len(),
+        });
+    }
+    Ok(())
+}
+
+/// The hasher IBFs use unless given [another](IBF::with_hasher): MetroHash, seeded the same in
+/// every process, so IBFs built on different machines line up. It hashes elements to the same
+/// values as [element_hash](element_hash).
+pub type DefaultBuildHasher = BuildHasherDefault<ElmHasher>;
 
 /// Core Invertible Bloom Filter Data Structure. This allows us to store and differentially retreive
 /// a set of u128s, provided that the two IBFs have enough information in them. This is a
@@ -24,27 +77,162 @@ use std::{
 /// assert!(set.contains(&Side::Left(20)));
 /// assert!(set.contains(&Side::Right(42)));
 /// ```
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct IBF<T>
+///
+/// ## Serialization
+///
+/// IBFs serialize canonically: the fields are always `cells`, `hash_count`, `size` in that
+/// order, and the cells in index order, each as `id_sum`, `hash_sum`, `count`. A cell depends only
+/// on the elements hashed to it, so two IBFs of the same set serialize to the same bytes however
+/// the set was encoded, and in a fixed width format (such as bincode) adding or removing an
+/// element only changes the bytes of its hash_count cells. Snapshots of a slowly changing sketch
+/// are therefore binary diff and chunk dedup friendly. This layout is part of the public API and
+/// won't change without a major version bump.
+///
+/// For sending sketches between peers, [to_bytes](IBF::to_bytes) gives a smaller, versioned
+/// binary format that doesn't depend on serde.
+///
+/// IBFs can be embedded in application messages, as a field or `#[serde(flatten)]`ed into the
+/// message, as long as the message has no fields of its own named `cells`, `hash_count` or
+/// `size`. When it might, nest the IBF under a field instead.
+/// ```rust
+/// use iron_rose::IBF;
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct SyncRequest {
+///     request_id: u64,
+///     #[serde(flatten)]
+///     sketch: IBF<u64>,
+/// }
+///
+/// let mut sketch = IBF::new(10);
+/// sketch.encode(1);
+/// let json = serde_json::to_string(&SyncRequest { request_id: 7, sketch: sketch.clone() }).unwrap();
+/// let back: SyncRequest = serde_json::from_str(&json).unwrap();
+/// assert_eq!(back.sketch, sketch);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawIBF<T, C>")]
+#[serde(bound(
+    serialize = "T: Serialize",
+    deserialize = "T: Deserialize<'de>, H: BuildHasher + Clone + Default, C: Deserialize<'de>, \
+                   S: From<Box<[Cell<T, C>]>>"
+))]
+pub struct IBF<T, H = DefaultBuildHasher, C = i32, S = Box<[Cell<T, C>]>>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    C: Count,
+    S: CellStorage<T, C>,
+{
+    /// Kept in any [CellStorage](CellStorage), see [with_storage](IBF::with_storage)
+    #[serde(serialize_with = "serialize_cells")]
+    cells: S,
+    hash_count: usize,
+    size: usize,
+    /// Application keyspace the sketch belongs to, see [with_keyspace](IBF::with_keyspace)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    keyspace: Option<Arc<str>>,
+    /// Number of cells that are currently not empty, kept up to date on every mutation so that
+    /// emptiness checks don't need to scan the cells.
+    #[serde(skip)]
+    non_empty: usize,
+    /// Places elements in cells and checks cells for purity, see [with_hasher](IBF::with_hasher)
+    #[serde(skip)]
+    hasher: H,
+    #[serde(skip)]
+    stored: PhantomData<Cell<T, C>>,
+}
+
+/// Serializes cells the same whatever they're stored in
+fn serialize_cells<T, C, S, Z>(cells: &S, serializer: Z) -> Result<Z::Ok, Z::Error>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + Serialize,
+    C: Count,
+    S: core::ops::Deref<Target = [Cell<T, C>]>,
+    Z: serde::Serializer,
+{
+    cells[..].serialize(serializer)
+}
+
+/// On the wire representation of an [IBF](IBF). It is validated on the way in, and the count of
+/// non-empty cells is recomputed rather than trusted from the sender.
+#[derive(Deserialize)]
+#[serde(rename = "IBF")]
+struct RawIBF<T, C>
 where
     T: Clone
-        + std::hash::Hash
+        + core::hash::Hash
         + BitXor<Output = T>
         + BitXorAssign
         + Default
         + PartialEq
         + Eq
         + Debug,
+    C: Count,
 {
-    cells: Box<[Cell<T>]>,
+    cells: Box<[Cell<T, C>]>,
     hash_count: usize,
     size: usize,
+    #[serde(default)]
+    keyspace: Option<Arc<str>>,
+}
+
+impl<T, H, C, S> TryFrom<RawIBF<T, C>> for IBF<T, H, C, S>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    C: Count,
+    H: BuildHasher + Clone + Default,
+    S: CellStorage<T, C> + From<Box<[Cell<T, C>]>>,
+{
+    type Error = IronRoseError;
+
+    fn try_from(raw: RawIBF<T, C>) -> Result<Self, Self::Error> {
+        if raw.cells.len() != raw.size {
+            return Err(IronRoseError::CellCountMismatch {
+                size: raw.size,
+                cells: raw.cells.len(),
+            });
+        }
+        check_hash_count(raw.hash_count, raw.size)?;
+        if let Some(keyspace) = raw.keyspace.as_deref() {
+            check_keyspace(keyspace)?;
+        }
+        Ok(Self::from_cells(
+            raw.cells.into(),
+            raw.hash_count,
+            raw.size,
+            raw.keyspace,
+            H::default(),
+        ))
+    }
 }
 
 impl<T> IBF<T>
 where
     T: Clone
-        + std::hash::Hash
+        + core::hash::Hash
         + BitXor<Output = T>
         + BitXorAssign
         + Default
@@ -64,114 +252,3611 @@ where
             cells: buckets,
             hash_count,
             size,
+            keyspace: None,
+            non_empty: 0,
+            hasher: Default::default(),
+            stored: PhantomData,
         }
     }
 
-    /// Encodes an element into hash_count # of buckets for future retreival
-    pub fn encode(&mut self, element: T) {
-        for i in 0..self.hash_count {
-            self.get_ith_cell(i, &element).encode(element.clone())
-        }
+    /// New IBF sized to decode up to differences elements, with the defaults of
+    /// [IbfBuilder](IbfBuilder), such as a 1% chance of failing to decode
+    #[cfg(feature = "std")]
+    pub fn for_expected_differences(differences: usize) -> Self {
+        IbfBuilder::new(differences).build()
     }
 
-    /// Allows you to decode an IBF into a [HashSet](HashSet) of [Sides](Side). Each side tells
-    /// You from which original IBF the data came from (After a subtraction). Returns an Err
-    /// In the case that we don't have enough information to fully decode the IBF.
-    pub fn decode(mut self) -> Result<HashSet<Side<T>>, String> {
-        let mut set = HashSet::new();
-        loop {
-            if let Some(next_pure) = self.cells.iter().find(|cell| cell.is_pure()) {
-                let next_pure = next_pure.clone();
-                let element = next_pure.decode().expect("Only grabbing pure elements");
-                set.insert(element);
-                self.remove(next_pure);
-            } else {
-                if self.cells.iter().all(|cell| cell.is_empty()) {
-                    return Ok(set);
-                } else {
-                    let not_empty = self
-                        .cells
-                        .iter()
-                        .filter(|cell| !cell.is_empty())
-                        .collect::<Vec<_>>();
-                    return Err(format!("Unable to fully decode: {:#?}", not_empty));
-                }
+    /// New IBF sized to decode up to expected_diff elements between sets of about exact_items
+    /// items each, see [IbfBuilder::total_items](IbfBuilder::total_items) for what the item
+    /// count changes
+    /// ```rust
+    /// use iron_rose::{IbfBuilder, IBF};
+    ///
+    /// let ibf: IBF<u64> = IBF::with_capacity_for(10_000_000, 500);
+    /// let params = IbfBuilder::new(500).total_items(10_000_000).params();
+    /// assert_eq!(ibf.params(), params);
+    /// assert_eq!(params.hash_count(), 3);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn with_capacity_for(exact_items: usize, expected_diff: usize) -> Self {
+        IbfBuilder::new(expected_diff)
+            .total_items(exact_items)
+            .build()
+    }
+
+    /// New IBF with the given shape
+    pub fn with_params(params: SketchParams) -> Self {
+        Self::new_with_hash_count(params.size(), params.hash_count())
+    }
+
+    /// New IBF with the given shape, unless it would exceed the params'
+    /// [wire budget](SketchParams::with_max_wire_bytes), in which case the Err reports the
+    /// would-be size.
+    pub fn try_with_params(params: SketchParams) -> Result<Self, IronRoseError> {
+        params.check_wire_budget::<T>()?;
+        Ok(Self::with_params(params))
+    }
+
+    /// New IBF with the given shape, holding only the elements of src that match predicate. Both
+    /// sides building their sketch with the same predicate reconcile just that slice of their
+    /// sets, such as a single tenant's keys.
+    /// ```rust
+    /// use iron_rose::{IBF, Side, SketchParams};
+    ///
+    /// let params = SketchParams::new(20, 3);
+    /// let left = IBF::filtered_from(params, 0..1000u64, |id| id % 100 == 7);
+    /// let right = IBF::filtered_from(params, (0..1000u64).filter(|&id| id != 407), |id| id % 100 == 7);
+    /// let set = (left - right).unwrap().decode().unwrap();
+    /// assert_eq!(set.into_iter().collect::<Vec<_>>(), vec![Side::Left(407)]);
+    /// ```
+    pub fn filtered_from<I, P>(params: SketchParams, src: I, mut predicate: P) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        P: FnMut(&T) -> bool,
+    {
+        let mut ibf = Self::with_params(params);
+        for element in src {
+            if predicate(&element) {
+                ibf.encode(element);
             }
         }
+        ibf
     }
 
-    fn remove(&mut self, cell: Cell<T>) {
-        let element = &*cell.decode().expect("Only removing pure cells");
-        for i in 0..self.hash_count {
-            *self.get_ith_cell(i, &element) -= cell.clone();
+    /// Puts an IBF back together from [raw parts](IBF::into_raw_parts), failing unless there
+    /// is exactly one cell per cell of params.
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let mut ibf = IBF::new(10);
+    /// ibf.encode(1u64);
+    /// let (cells, params) = ibf.clone().into_raw_parts();
+    /// assert_eq!(IBF::from_raw_parts(cells.clone(), params).unwrap(), ibf);
+    /// assert!(IBF::from_raw_parts(cells, IBF::<u64>::new(11).params()).is_err());
+    /// ```
+    pub fn from_raw_parts(
+        cells: Box<[Cell<T>]>,
+        params: SketchParams,
+    ) -> Result<Self, IronRoseError> {
+        if cells.len() != params.size() {
+            return Err(IronRoseError::CellCountMismatch {
+                size: params.size(),
+                cells: cells.len(),
+            });
         }
+        check_hash_count(params.hash_count(), params.size())?;
+        Ok(Self::from_cells(
+            cells,
+            params.hash_count(),
+            params.size(),
+            None,
+            Default::default(),
+        ))
     }
 
-    fn get_ith_cell(&mut self, i: usize, element: &T) -> &mut Cell<T> {
-        let mut hasher: ElmHasher = Default::default();
-        element.hash(&mut hasher);
-        i.hash(&mut hasher);
+    /// Rebuilds an IBF from segments [sliced](IBF::slice) out of it, in any order. Fails if a
+    /// segment's checksum doesn't match its cells, if the segments are of differently shaped
+    /// IBFs, or if they don't cover every cell exactly once. Every segment is checked before
+    /// anything is allocated for the IBF, so a corrupted shape can't cause a large allocation.
+    pub fn assemble<I: IntoIterator<Item = IbfSegment<T>>>(
+        segments: I,
+    ) -> Result<Self, IronRoseError> {
+        let mut segments = segments.into_iter().collect::<Vec<_>>();
+        for segment in segments.iter() {
+            if !segment.verify() {
+                return Err(IronRoseError::SegmentChecksum {
+                    start: segment.start,
+                });
+            }
+        }
+        let first = segments
+            .first()
+            .ok_or(IronRoseError::IncompleteSegments { cell: 0 })?;
+        let (params, keyspace) = (first.params, first.keyspace.clone());
+        check_hash_count(params.hash_count(), params.size())?;
+        if let Some(keyspace) = keyspace.as_deref() {
+            check_keyspace(keyspace)?;
+        }
+        for segment in segments.iter() {
+            if segment.params != params {
+                return Err(IronRoseError::ShapeMismatch {
+                    left: params,
+                    right: segment.params,
+                });
+            }
+            if segment.keyspace != keyspace {
+                return Err(IronRoseError::KeyspaceMismatch {
+                    left: keyspace.as_deref().map(str::to_string),
+                    right: segment.keyspace.as_deref().map(str::to_string),
+                });
+            }
+            match segment.start.checked_add(segment.cells.len()) {
+                Some(end) if end <= params.size() => {}
+                _ => {
+                    return Err(IronRoseError::CellOutOfRange {
+                        cell: segment.start.saturating_add(segment.cells.len()) - 1,
+                        size: params.size(),
+                    })
+                }
+            }
+        }
 
-        let cell_idx = (hasher.finish() % (self.size as u64)) as usize;
-        &mut self.cells[cell_idx]
+        // In order, the segments have to follow on from each other
+        segments.sort_by_key(|segment| segment.start);
+        let mut next = 0;
+        for segment in segments.iter() {
+            if segment.start != next {
+                return Err(IronRoseError::IncompleteSegments {
+                    cell: next.min(segment.start),
+                });
+            }
+            next += segment.cells.len();
+        }
+        if next != params.size() {
+            return Err(IronRoseError::IncompleteSegments { cell: next });
+        }
+        let mut cells = Vec::with_capacity(params.size());
+        segments
+            .into_iter()
+            .for_each(|segment| cells.extend(segment.cells));
+        Ok(Self::from_cells(
+            cells.into_boxed_slice(),
+            params.hash_count(),
+            params.size(),
+            keyspace,
+            Default::default(),
+        ))
     }
 }
 
-impl<T> Sub for IBF<T>
+/// Sketches whose cells count elements in another [Count](Count) width than the default i32
+impl<T, C> IBF<T, DefaultBuildHasher, C>
 where
     T: Clone
-        + std::hash::Hash
+        + core::hash::Hash
         + BitXor<Output = T>
         + BitXorAssign
         + Default
         + PartialEq
         + Eq
         + Debug,
+    C: Count,
 {
-    type Output = Result<IBF<T>, String>;
+    /// New IBF with the given shape whose cells count their elements in C: i16 takes 2 bytes
+    /// less per cell on the wire, for sets where no cell will hold more than 32767 elements,
+    /// and i64 is for ones where a cell might hold more than 2^31. Both sides of a subtraction
+    /// need the same width, and [try_encode](IBF::try_encode) reports a full cell instead of
+    /// saturating it.
+    /// ```rust
+    /// use iron_rose::{DefaultBuildHasher, SketchParams, IBF};
+    ///
+    /// let params = SketchParams::new(100, 3);
+    /// let mut compact: IBF<u64, DefaultBuildHasher, i16> = IBF::with_count_width(params);
+    /// let mut default = IBF::with_params(params);
+    /// for x in 0..50u64 {
+    ///     compact.encode(x);
+    ///     default.encode(x);
+    /// }
+    /// assert_eq!(compact.to_bytes().len() + 2 * 100, default.to_bytes().len());
+    /// assert!(IBF::<u64>::from_bytes(&compact.to_bytes()).is_err());
+    /// assert_eq!(compact.into_count_width::<i32>(), Ok(default));
+    /// ```
+    pub fn with_count_width(params: SketchParams) -> Self {
+        Self::from_cells(
+            vec![Cell::default(); params.size()].into_boxed_slice(),
+            params.hash_count(),
+            params.size(),
+            None,
+            Default::default(),
+        )
+    }
+}
 
-    fn sub(self, rhs: Self) -> Self::Output {
-        if self.hash_count != rhs.hash_count || self.size != rhs.size {
-            return Err("IBFs are not configured the same".to_string());
+impl<T, H> IBF<T, H>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    H: BuildHasher + Clone,
+{
+    /// New IBF with the given shape that hashes elements with hasher instead of the
+    /// [default](DefaultBuildHasher). Both sides of a subtraction have to use the same hasher,
+    /// seeded the same, which isn't checked: IBFs that hash differently subtract fine but don't
+    /// decode. Keyed hashers such as SipHash with a shared secret stop outsiders from crafting
+    /// elements that collide in the sketch.
+    /// ```rust
+    /// use iron_rose::{Side, SketchParams, IBF};
+    /// use std::collections::hash_map::DefaultHasher;
+    /// use std::hash::BuildHasherDefault;
+    ///
+    /// let hasher = BuildHasherDefault::<DefaultHasher>::default();
+    /// let params = SketchParams::new(10, 3);
+    /// let mut left = IBF::with_hasher(params, hasher.clone());
+    /// let mut right = IBF::with_hasher(params, hasher);
+    /// left.encode(1u64);
+    /// right.encode(2u64);
+    /// let set = (left - right).unwrap().decode().unwrap();
+    /// assert!(set.contains(&Side::Left(1)));
+    /// assert!(set.contains(&Side::Right(2)));
+    /// ```
+    pub fn with_hasher(params: SketchParams, hasher: H) -> Self {
+        Self {
+            cells: vec![Cell::default(); params.size()].into_boxed_slice(),
+            hash_count: params.hash_count(),
+            size: params.size(),
+            keyspace: None,
+            non_empty: 0,
+            hasher,
+            stored: PhantomData,
         }
-        Ok(Self {
-            cells: self
-                .cells
-                .iter()
-                .zip(rhs.cells.iter())
-                .map(|(l, r)| l - r)
-                .collect(),
-            ..self
-        })
     }
 }
 
-impl<T> Sub for &IBF<T>
+impl<T, H, C, S> IBF<T, H, C, S>
 where
     T: Clone
-        + std::hash::Hash
+        + core::hash::Hash
         + BitXor<Output = T>
         + BitXorAssign
         + Default
         + PartialEq
         + Eq
         + Debug,
+    H: BuildHasher + Clone,
+    C: Count,
+    S: CellStorage<T, C>,
 {
-    type Output = Result<IBF<T>, String>;
+    /// New IBF with the given shape and hasher that keeps its cells in storage rather than in a
+    /// box on the heap, such as memory from a hugepage or NUMA pinned allocator for a sketch of
+    /// several GiB. The storage is cleared, and has to hold exactly one cell per cell of params.
+    /// See [CellStorage](CellStorage) for what can be used.
+    /// ```rust
+    /// use iron_rose::{Cell, DefaultBuildHasher, SketchParams, IBF};
+    /// use std::ops::{Deref, DerefMut};
+    ///
+    /// /// Cells in memory the application allocated itself
+    /// #[derive(Clone)]
+    /// struct Pinned(Vec<Cell<u64>>);
+    ///
+    /// impl Deref for Pinned {
+    ///     type Target = [Cell<u64>];
+    ///     fn deref(&self) -> &[Cell<u64>] {
+    ///         &self.0
+    ///     }
+    /// }
+    ///
+    /// impl DerefMut for Pinned {
+    ///     fn deref_mut(&mut self) -> &mut [Cell<u64>] {
+    ///         &mut self.0
+    ///     }
+    /// }
+    ///
+    /// let params = SketchParams::new(50, 3);
+    /// let storage = || Pinned(vec![Cell::default(); 50]);
+    /// let hasher = DefaultBuildHasher::default();
+    /// let mut left = IBF::with_storage(params, hasher.clone(), storage()).unwrap();
+    /// let mut right = IBF::with_storage(params, hasher.clone(), storage()).unwrap();
+    /// (0..20u64).for_each(|x| left.encode(x));
+    /// (5..25u64).for_each(|x| right.encode(x));
+    /// assert_eq!((left - right).unwrap().decode().unwrap().len(), 10);
+    /// assert!(IBF::with_storage(SketchParams::new(60, 3), hasher, storage()).is_err());
+    /// ```
+    pub fn with_storage(
+        params: SketchParams,
+        hasher: H,
+        mut storage: S,
+    ) -> Result<Self, IronRoseError> {
+        if storage.len() != params.size() {
+            return Err(IronRoseError::CellCountMismatch {
+                size: params.size(),
+                cells: storage.len(),
+            });
+        }
+        check_hash_count(params.hash_count(), params.size())?;
+        storage.fill(Cell::default());
+        Ok(Self::from_cells(
+            storage,
+            params.hash_count(),
+            params.size(),
+            None,
+            hasher,
+        ))
+    }
 
-    fn sub(self, rhs: Self) -> Self::Output {
-        if self.hash_count != rhs.hash_count || self.size != rhs.size {
-            return Err("IBFs are not configured the same".to_string());
+    /// The hasher that places elements in cells
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// The same cells, hashed with hasher from now on. Sketches read from the wire or serde
+    /// come with the default hasher, as the hasher isn't sent, so one that was built with a
+    /// [keyed hasher](crate::SeededHasher) needs it put back before it can be subtracted.
+    pub fn into_hasher<H2: BuildHasher + Clone>(self, hasher: H2) -> IBF<T, H2, C, S> {
+        IBF::from_cells(
+            self.cells,
+            self.hash_count,
+            self.size,
+            self.keyspace,
+            hasher,
+        )
+    }
+
+    /// The same sketch with its cells counting in another [Count](Count) width, see
+    /// [with_count_width](IBF::with_count_width). Fails with
+    /// [CountOverflow](IronRoseError::CountOverflow) naming the first cell whose count doesn't
+    /// fit the new width.
+    pub fn into_count_width<C2: Count>(self) -> Result<IBF<T, H, C2>, IronRoseError> {
+        let cells = self
+            .cells
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(cell, c)| {
+                c.into_count_width()
+                    .ok_or(IronRoseError::CountOverflow { cell })
+            })
+            .collect::<Result<Box<[_]>, _>>()?;
+        Ok(IBF::from_cells(
+            cells,
+            self.hash_count,
+            self.size,
+            self.keyspace,
+            self.hasher,
+        ))
+    }
+
+    /// The shape of this IBF, which has to match for subtraction to succeed
+    pub fn params(&self) -> SketchParams {
+        SketchParams::new(self.size, self.hash_count)
+    }
+
+    /// Everything that has to match for this IBF to be subtracted from another, beyond its
+    /// [params](IBF::params): the hasher, the width of the elements and the keyspace. Peers
+    /// can exchange these before any cells and check them with
+    /// [compatible_with](IBF::compatible_with).
+    /// ```rust
+    /// use iron_rose::{IronRoseError, IBF};
+    ///
+    /// let local: IBF<u64> = IBF::new(100);
+    /// let remote: IBF<u128> = IBF::new(100);
+    /// // Sent ahead of the sketch
+    /// let offer = remote.ibf_params();
+    /// assert_eq!(
+    ///     local.compatible_with(&offer),
+    ///     Err(IronRoseError::ElementWidthMismatch { left: 8, right: 16 })
+    /// );
+    /// ```
+    pub fn ibf_params(&self) -> IbfParams {
+        IbfParams::new(
+            self.params(),
+            hasher_id(&self.hasher),
+            core::mem::size_of::<T>(),
+            self.keyspace(),
+        )
+    }
+
+    /// Fails with an error naming the first thing that doesn't match, unless an IBF with the
+    /// given params can be subtracted from this one
+    pub fn compatible_with(&self, params: &IbfParams) -> Result<(), IronRoseError> {
+        self.ibf_params().check_compatible(params)
+    }
+
+    /// `self - other`, after checking every one of the [ibf_params](IBF::ibf_params), including
+    /// the hasher, which plain subtraction takes on trust
+    /// ```rust
+    /// use iron_rose::{IronRoseError, IBF};
+    ///
+    /// let (mut left, mut right) = (IBF::new(20), IBF::new(20));
+    /// left.encode(1u64);
+    /// right.encode(2u64);
+    /// assert_eq!(left.subtract_checked(&right).unwrap().decode().unwrap().len(), 2);
+    /// assert!(matches!(
+    ///     left.subtract_checked(&IBF::new(21)),
+    ///     Err(IronRoseError::ShapeMismatch { .. })
+    /// ));
+    /// ```
+    pub fn subtract_checked(&self, other: &Self) -> Result<Self, IronRoseError> {
+        self.compatible_with(&other.ibf_params())?;
+        self - other
+    }
+
+    /// Tags the IBF with the application keyspace it covers, such as "orders". Subtracting IBFs
+    /// fails unless their keyspaces are the same (untagged IBFs only subtract from untagged
+    /// ones), so a routing bug can't reconcile one keyspace's sketch against another's. The tag
+    /// is serialized with the IBF, and panics if it's over 64 KiB, the most the wire format
+    /// holds.
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let orders = IBF::<u64>::new(10).with_keyspace("orders");
+    /// let users = IBF::<u64>::new(10).with_keyspace("users");
+    /// assert_eq!(orders.keyspace(), Some("orders"));
+    /// assert!((&orders - &users).is_err());
+    /// assert!((&orders - &IBF::new(10)).is_err());
+    /// assert!((&orders - &orders).is_ok());
+    /// ```
+    pub fn with_keyspace(mut self, keyspace: &str) -> Self {
+        check_keyspace(keyspace).expect("keyspaces are at most 64 KiB");
+        self.keyspace = Some(keyspace.into());
+        self
+    }
+
+    /// The application keyspace the IBF was tagged with, if any
+    pub fn keyspace(&self) -> Option<&str> {
+        self.keyspace.as_deref()
+    }
+
+    /// Takes the IBF apart into its cells, in index order, and its shape, for systems that keep
+    /// cells in their own storage (shared memory, GPU buffers, columnar stores). The keyspace
+    /// isn't part of the raw parts.
+    pub fn into_raw_parts(self) -> (S, SketchParams) {
+        let params = self.params();
+        (self.cells, params)
+    }
+
+    /// The cells, in index order
+    #[cfg(feature = "legacy-v0")]
+    pub(crate) fn cells(&self) -> &[Cell<T, C>] {
+        &self.cells
+    }
+
+    pub(crate) fn from_cells(
+        cells: S,
+        hash_count: usize,
+        size: usize,
+        keyspace: Option<Arc<str>>,
+        hasher: H,
+    ) -> Self {
+        let non_empty = cells.iter().filter(|cell| !cell.is_empty()).count();
+        Self {
+            cells,
+            hash_count,
+            size,
+            keyspace,
+            non_empty,
+            hasher,
+            stored: PhantomData,
         }
-        Ok(IBF {
-            cells: self
-                .cells
-                .iter()
-                .zip(rhs.cells.iter())
-                .map(|(l, r)| l - r)
-                .collect(),
-            hash_count: self.hash_count,
+    }
+
+    /// Checks that changing the count of each of indexes by one, with op a checked add or
+    /// subtract, doesn't overflow, naming the first cell that would
+    fn check_headroom(
+        &self,
+        indexes: &[usize],
+        op: fn(C, C) -> Option<C>,
+    ) -> Result<(), IronRoseError> {
+        match indexes
+            .iter()
+            .find(|&&idx| op(self.cells[idx].count(), C::ONE).is_none())
+        {
+            Some(&cell) => Err(IronRoseError::CountOverflow { cell }),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that combining each cell's count with rhs's by op, a checked add or subtract,
+    /// doesn't overflow, naming the first cell that would
+    fn check_counts(&self, rhs: &Self, op: fn(C, C) -> Option<C>) -> Result<(), IronRoseError> {
+        match self
+            .cells
+            .iter()
+            .zip(rhs.cells.iter())
+            .position(|(l, r)| op(l.count(), r.count()).is_none())
+        {
+            Some(cell) => Err(IronRoseError::CountOverflow { cell }),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that rhs can be subtracted from this IBF
+    fn check_compatible(&self, rhs: &Self) -> Result<(), IronRoseError> {
+        if self.params() != rhs.params() {
+            return Err(IronRoseError::ShapeMismatch {
+                left: self.params(),
+                right: rhs.params(),
+            });
+        }
+        if self.keyspace != rhs.keyspace {
+            return Err(IronRoseError::KeyspaceMismatch {
+                left: self.keyspace().map(str::to_string),
+                right: rhs.keyspace().map(str::to_string),
+            });
+        }
+        Ok(())
+    }
+
+    /// Encodes an element into hash_count # of buckets for future retreival
+    ///
+    /// Cell counts saturate at the bounds of their [Count](Count) type rather than wrapping,
+    /// which takes over two billion elements in one cell with the default i32, but only 32767
+    /// with i16. A saturated cell's count no longer matches its sums, so a difference involving
+    /// it fails to decode with [DecodeFailed](IronRoseError::DecodeFailed) instead of recovering
+    /// wrong elements. [Merging](IBF::merge) and subtracting report the same overflow as
+    /// [CountOverflow](IronRoseError::CountOverflow), and so does [try_encode](IBF::try_encode).
+    pub fn encode(&mut self, element: T) {
+        for idx in self.cell_indexes(&element) {
+            self.modify_cell(idx, |cell, hasher| cell.encode(element.clone(), hasher));
+        }
+    }
+
+    /// Same as [encode](IBF::encode), unless one of element's cells already holds as many
+    /// elements as its [Count](Count) type can, in which case the IBF is left unchanged and
+    /// the Err names that cell
+    /// ```rust
+    /// use iron_rose::{DefaultBuildHasher, IronRoseError, SketchParams, IBF};
+    ///
+    /// let mut ibf: IBF<u64, DefaultBuildHasher, i16> =
+    ///     IBF::with_count_width(SketchParams::new(1, 1));
+    /// (0..i16::MAX as u64).for_each(|x| ibf.encode(x));
+    /// assert_eq!(ibf.try_encode(7), Err(IronRoseError::CountOverflow { cell: 0 }));
+    /// assert_eq!(ibf.try_remove(7), Ok(()));
+    /// assert_eq!(ibf.try_encode(7), Ok(()));
+    /// ```
+    pub fn try_encode(&mut self, element: T) -> Result<(), IronRoseError> {
+        let indexes = self.cell_indexes(&element);
+        self.check_headroom(&indexes, C::checked_add)?;
+        for idx in indexes {
+            self.modify_cell(idx, |cell, hasher| cell.encode(element.clone(), hasher));
+        }
+        Ok(())
+    }
+
+    /// Same as [encode](IBF::encode), for when the hash_count is known at compile time (usually
+    /// 3 or 4), so the per element index loop is unrolled. Gives the same sketch as encode. Panics
+    /// if K isn't the IBF's hash_count.
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let (mut fixed, mut dynamic) = (IBF::new(20), IBF::new(20));
+    /// fixed.encode_k::<3>(7u64);
+    /// dynamic.encode(7u64);
+    /// assert_eq!(fixed, dynamic);
+    /// ```
+    pub fn encode_k<const K: usize>(&mut self, element: T) {
+        assert_eq!(
+            K, self.hash_count,
+            "IBF has {} hash functions, not {}",
+            self.hash_count, K
+        );
+        let indexes: [usize; K] = core::array::from_fn(|i| self.cell_index(i, &element));
+        for (i, &idx) in indexes.iter().enumerate() {
+            if indexes[..i].contains(&idx) {
+                continue;
+            }
+            self.modify_cell(idx, |cell, hasher| cell.encode(element.clone(), hasher));
+        }
+    }
+
+    /// Brings this sketch in line with the set state after a reconciliation, by encoding the
+    /// elements the local set gained and removing the ones it lost, so a live sketch doesn't have
+    /// to be rebuilt. diff is the decode of a subtraction, and direction says which side of it
+    /// the local set now matches.
+    /// ```rust
+    /// use iron_rose::{ApplyDirection, IBF};
+    ///
+    /// let (mut local, mut remote) = (IBF::new(20), IBF::new(20));
+    /// local.encode(1); local.encode(2);
+    /// remote.encode(2); remote.encode(3);
+    /// let diff = (&local - &remote).unwrap().decode_difference().unwrap();
+    /// local.apply_difference(&diff, ApplyDirection::ToRight);
+    /// assert_eq!(local, remote);
+    /// ```
+    pub fn apply_difference(&mut self, diff: &Difference<T>, direction: ApplyDirection) {
+        let (gained, lost) = match direction {
+            ApplyDirection::ToRight => (diff.right(), diff.left()),
+            ApplyDirection::ToLeft => (diff.left(), diff.right()),
+        };
+        for element in gained {
+            self.encode(element.clone());
+        }
+        for element in lost {
+            self.remove(element.clone());
+        }
+    }
+
+    /// Removes an element that was previously [encoded](IBF::encode), so a long lived sketch can
+    /// track a set that changes over time instead of being rebuilt for every sync. Removing an
+    /// element that was never encoded isn't detected here, it shows up as a
+    /// [Right](Side::Right) difference of this sketch.
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let (mut live, mut fresh) = (IBF::new(20), IBF::new(20));
+    /// live.encode(1u64);
+    /// live.encode(2u64);
+    /// live.remove(1u64);
+    /// fresh.encode(2u64);
+    /// assert_eq!(live, fresh);
+    /// ```
+    pub fn remove(&mut self, element: T) {
+        for idx in self.cell_indexes(&element) {
+            self.modify_cell(idx, |cell, hasher| cell.unencode(element.clone(), hasher));
+        }
+    }
+
+    /// Same as [remove](IBF::remove), unless one of element's cells already holds as many
+    /// right side elements as its [Count](Count) type can, see [try_encode](IBF::try_encode)
+    pub fn try_remove(&mut self, element: T) -> Result<(), IronRoseError> {
+        let indexes = self.cell_indexes(&element);
+        self.check_headroom(&indexes, C::checked_sub)?;
+        for idx in indexes {
+            self.modify_cell(idx, |cell, hasher| cell.unencode(element.clone(), hasher));
+        }
+        Ok(())
+    }
+
+    /// True when every cell is empty, which after a subtraction means the two sets were
+    /// identical. This is O(1), as the number of non-empty cells is tracked as cells change.
+    pub fn is_empty(&self) -> bool {
+        self.non_empty == 0
+    }
+
+    /// True if this is the zero element of IBF subtraction, every cell is empty. Subtracting
+    /// IBFs of identical sets gives zero, and subtracting zero changes nothing.
+    pub fn is_zero(&self) -> bool {
+        self.is_empty()
+    }
+
+    /// Estimates the number of elements in the IBF from the share of empty cells, without
+    /// decoding. Each element fills hash_count cells, so with n elements in m cells about
+    /// `(1 - 1/m)^(k * n)` of them stay empty, which is solved for n. On a subtracted IBF this
+    /// estimates the number of differences. Infinite if no cell is empty.
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let mut ibf = IBF::new(1000);
+    /// (0..200u64).for_each(|x| ibf.encode(x));
+    /// let estimate = ibf.occupancy_estimate();
+    /// assert!(estimate > 150.0 && estimate < 250.0);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn occupancy_estimate(&self) -> f64 {
+        if self.size < 2 || self.hash_count == 0 {
+            return if self.is_empty() { 0.0 } else { f64::INFINITY };
+        }
+        let empty = (self.size - self.non_empty) as f64 / self.size as f64;
+        empty.ln() / (self.hash_count as f64 * (1.0 - 1.0 / self.size as f64).ln())
+    }
+
+    /// Estimated probability that this IBF's shape decodes num_differences elements, see
+    /// [theory::decode_probability](crate::theory::decode_probability) for how it is worked out.
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let ibf: IBF<u64> = IBF::new(1000);
+    /// assert!(ibf.estimated_decode_probability(700) > 0.99);
+    /// assert!(ibf.estimated_decode_probability(700) > ibf.estimated_decode_probability(800));
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn estimated_decode_probability(&self, num_differences: usize) -> f64 {
+        crate::theory::decode_probability(self.size, self.hash_count, num_differences)
+    }
+
+    /// Whether element may be in the set this IBF was encoded from, as a counting Bloom filter
+    /// would answer, for a quick check before a full reconciliation. False means element is
+    /// definitely not in the set: one of its cells is empty, or holds exactly one element that
+    /// isn't it. True means it probably is.
+    ///
+    /// There are no false negatives for a sketch of a single set, built with
+    /// [encode](IBF::encode) and [remove](IBF::remove). After a subtraction the elements common to
+    /// both sides cancel out, so the answer is only meaningful for the sketch as is.
+    ///
+    /// With n elements in m cells and k hash functions, each cell holds about Poisson(λ)
+    /// elements for `λ = k n / m`. An absent element is only let through when all of its cells
+    /// hold two or more, so the false positive rate is about `(1 - e^-λ - λ e^-λ)^k`, well below
+    /// the `(1 - e^-λ)^k` of a Bloom filter of the same shape. IBFs are sized for differences
+    /// rather than whole sets though, so with n well beyond m nearly every cell is crowded and
+    /// nearly every answer is true.
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let mut ibf = IBF::new(100);
+    /// (0..20u64).for_each(|x| ibf.encode(x));
+    /// assert!((0..20u64).all(|x| ibf.maybe_contains(&x)));
+    /// let false_positives = (1000..2000u64).filter(|x| ibf.maybe_contains(x)).count();
+    /// assert!(false_positives < 20);
+    /// ```
+    pub fn maybe_contains(&self, element: &T) -> bool {
+        (0..self.hash_count).all(|i| {
+            let cell = &self.cells[self.cell_index(i, element)];
+            match cell.count() {
+                c if c < C::ONE => false,
+                c if c == C::ONE && cell.is_pure(&self.hasher) => cell.id_sum() == element,
+                _ => true,
+            }
+        })
+    }
+
+    /// Number of cells that aren't empty
+    pub fn occupied_cells(&self) -> usize {
+        self.non_empty
+    }
+
+    /// How many cells hold each count, for looking at how load is spread over the cells. Counts
+    /// are negative for cells where the right side of a subtraction dominates.
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let mut ibf = IBF::new_with_hash_count(10, 1);
+    /// ibf.encode(1u64);
+    /// let histogram = ibf.load_histogram();
+    /// assert_eq!(histogram[&0], 9);
+    /// assert_eq!(histogram[&1], 1);
+    /// ```
+    pub fn load_histogram(&self) -> BTreeMap<C, usize> {
+        let mut histogram = BTreeMap::new();
+        for cell in self.cells.iter() {
+            *histogram.entry(cell.count()).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Adds other's elements to this IBF, failing like subtraction does unless both have the
+    /// same shape and keyspace. IBFs built over disjoint shards of a table merge into the IBF of
+    /// the whole table, so shards can be encoded in parallel. An element encoded into both
+    /// ends up counted twice. `a + b` does the same for owned or borrowed IBFs.
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let (mut whole, mut even, mut odd) = (IBF::new(20), IBF::new(20), IBF::new(20));
+    /// for i in 0..100u64 {
+    ///     whole.encode(i);
+    ///     if i % 2 == 0 { even.encode(i) } else { odd.encode(i) }
+    /// }
+    /// even.merge(&odd).unwrap();
+    /// assert_eq!(even, whole);
+    /// assert!(even.merge(&IBF::new(21)).is_err());
+    /// ```
+    pub fn merge(&mut self, other: &Self) -> Result<(), IronRoseError> {
+        self.check_compatible(other)?;
+        self.check_counts(other, C::checked_add)?;
+        for (idx, cell) in other.cells.iter().enumerate() {
+            self.modify_cell(idx, |c, _| *c += cell);
+        }
+        Ok(())
+    }
+
+    /// Flips every element to the other side, so that `-(a - b) == b - a`
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let (mut a, mut b) = (IBF::new(20), IBF::new(20));
+    /// a.encode(1u64);
+    /// b.encode(2u64);
+    /// assert_eq!((&a - &b).unwrap().negate(), (&b - &a).unwrap());
+    /// ```
+    pub fn negate(mut self) -> Self {
+        for cell in self.cells.iter_mut() {
+            cell.negate();
+        }
+        self
+    }
+
+    /// Allows you to decode an IBF into a [HashSet](HashSet) of [Sides](Side). Each side tells
+    /// You from which original IBF the data came from (After a subtraction). Returns an Err
+    /// In the case that we don't have enough information to fully decode the IBF.
+    ///
+    /// The set is the same on every run, but its iteration order isn't. Where that matters,
+    /// such as for reproducible logs, [decode_difference](IBF::decode_difference) and
+    /// [decode_into](IBF::decode_into) list elements in the order they were recovered, which
+    /// only depends on the cells.
+    #[cfg(feature = "std")]
+    pub fn decode(self) -> Result<HashSet<Side<T>>, DecodeError<T>> {
+        self.decode_into_container()
+    }
+
+    /// Same as [decode](IBF::decode), but into any container that can be extended with
+    /// [Sides](Side), such as a [BTreeSet](alloc::collections::BTreeSet) for ordered results or
+    /// a Vec, which gets them in the order they were recovered, without going through a
+    /// [HashSet](HashSet) first.
+    /// ```rust
+    /// use std::collections::BTreeSet;
+    /// use iron_rose::{IBF, Side};
+    ///
+    /// let mut ibf = IBF::new(20);
+    /// for i in [3u64, 1, 2].iter().copied() {
+    ///     ibf.encode(i);
+    /// }
+    /// let set = ibf.decode_into_container::<BTreeSet<_>>().expect("decodable");
+    /// assert_eq!(set.into_iter().collect::<Vec<_>>(), vec![Side::Left(1), Side::Left(2), Side::Left(3)]);
+    /// ```
+    pub fn decode_into_container<B>(mut self) -> Result<B, DecodeError<T>>
+    where
+        B: Extend<Side<T>> + Default,
+    {
+        let mut recovered = Vec::new();
+        let candidates = self.pure_cells();
+        self.peel(candidates, None, |side| recovered.push(side));
+        if !self.is_empty() {
+            return Err(DecodeError::new(recovered, self.non_empty));
+        }
+        let mut out = B::default();
+        out.extend(recovered);
+        Ok(out)
+    }
+
+    /// Peels everything that can be peeled, returning the recovered elements along with the
+    /// residual IBF holding only the elements that couldn't be, or None if the decode was
+    /// complete. The recovered elements are real differences, so a caller can act on them and
+    /// combine them with a retry at a larger size or a second round trip.
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let mut ibf = IBF::new(4);
+    /// (0..20u64).for_each(|x| ibf.encode(x));
+    /// let (recovered, residual) = ibf.decode_partial();
+    /// let residual = residual.expect("too many differences for 4 cells");
+    /// assert!(recovered.len() < 20);
+    /// assert!(!residual.is_empty());
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn decode_partial(mut self) -> (HashSet<Side<T>>, Option<Self>) {
+        let mut set = HashSet::new();
+        let candidates = self.pure_cells();
+        self.peel(candidates, None, |side| {
+            set.insert(side);
+        });
+        if self.is_empty() {
+            (set, None)
+        } else {
+            (set, Some(self))
+        }
+    }
+
+    /// Same as [decode](IBF::decode), but leaves this IBF untouched so it can still be retried,
+    /// merged or resent afterwards. The cells are copied once up front, decoding already visits
+    /// every cell, so this costs a constant factor over a consuming decode.
+    #[cfg(feature = "std")]
+    pub fn decode_cloned(&self) -> Result<HashSet<Side<T>>, DecodeError<T>> {
+        self.clone().decode()
+    }
+
+    /// Decodes lazily, yielding each element as it is peeled, so that work on the differences
+    /// can start before decoding is over and they never have to be held all at once. If peeling
+    /// gets stuck the last item is an Err with the number of cells left; its
+    /// [decoded](DecodeError::decoded) is empty, as those elements were already yielded.
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let (mut left, mut right) = (IBF::new(20), IBF::new(20));
+    /// left.encode(1u64);
+    /// right.encode(2u64);
+    /// let mut sides = (left - right)
+    ///     .unwrap()
+    ///     .into_decoder()
+    ///     .collect::<Result<Vec<_>, _>>()
+    ///     .expect("decodable");
+    /// sides.sort_by_key(|side| **side);
+    /// assert_eq!(sides.len(), 2);
+    ///
+    /// let mut ibf = IBF::new(4);
+    /// (0..20u64).for_each(|x| ibf.encode(x));
+    /// assert!(ibf.into_decoder().last().unwrap().is_err());
+    /// ```
+    pub fn into_decoder(self) -> Decoder<T, H, C, S> {
+        let candidates = self.pure_cells();
+        Decoder {
+            ibf: self,
+            candidates,
+            peeled: Peeled::default(),
+            done: false,
+        }
+    }
+
+    /// Decodes into a caller provided Vec, avoiding the hashing and rehashing of building a
+    /// [HashSet](HashSet), which is cheaper for small `Copy` element types. Capacity for the
+    /// smallest possible number of differences is reserved up front, and the Vec can be reused
+    /// across calls. On error, like for [decode](IBF::decode), the elements that were recovered
+    /// before decoding got stuck are in the [DecodeError](DecodeError), and also left in the
+    /// Vec.
+    /// ```rust
+    /// use iron_rose::{IBF, Side};
+    ///
+    /// let mut ibf = IBF::new(20);
+    /// ibf.encode(1u64);
+    /// let mut out = Vec::new();
+    /// ibf.decode_into(&mut out).expect("decodable");
+    /// assert_eq!(out, vec![Side::Left(1)]);
+    /// ```
+    pub fn decode_into(mut self, out: &mut Vec<Side<T>>) -> Result<(), DecodeError<T>> {
+        out.reserve(self.non_empty / self.hash_count.max(1));
+        let start = out.len();
+        let candidates = self.pure_cells();
+        self.peel(candidates, None, |side| out.push(side));
+        if !self.is_empty() {
+            return Err(DecodeError::new(out[start..].to_vec(), self.non_empty));
+        }
+        Ok(())
+    }
+
+    /// Decodes into a [Difference](Difference), splitting the elements by side and recording
+    /// how the decode went, ready to be serialized for reporting.
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let (mut left, mut right) = (IBF::new(20), IBF::new(20));
+    /// left.encode(1u64);
+    /// right.encode(2u64);
+    /// let diff = (left - right).unwrap().decode_difference().expect("decodable");
+    /// assert_eq!(diff.left(), &[1]);
+    /// assert_eq!(diff.right(), &[2]);
+    /// ```
+    pub fn decode_difference(mut self) -> Result<Difference<T>, DecodeError<T>> {
+        let mut recovered = Vec::new();
+        let candidates = self.pure_cells();
+        let peeled = self.peel(candidates, None, |side| recovered.push(side));
+        if !self.is_empty() {
+            return Err(DecodeError::new(recovered, self.non_empty));
+        }
+        Ok(self.difference_of(recovered, peeled.iterations))
+    }
+
+    /// Like [decode_difference](IBF::decode_difference), but every peeled element is checked
+    /// with validate, such as a UUID version check or an id range lookup, and the ones it
+    /// rejects are set aside as [suspected](ValidatedDecode::suspected) false decodes instead of
+    /// being returned as differences. A cell that only looks pure decodes to garbage, which the
+    /// hash check catches with high probability; validation is a cheap semantic check on top.
+    /// Suspects are still peeled, so the decode fails the same way it otherwise would.
+    /// ```rust
+    /// use iron_rose::{Side, IBF};
+    ///
+    /// let (mut left, mut right) = (IBF::new(20), IBF::new(20));
+    /// left.encode(10u64);
+    /// right.encode(20u64);
+    /// // Ids are handed out below 1_000_000
+    /// left.encode(u64::MAX);
+    /// let decoded = (left - right)
+    ///     .unwrap()
+    ///     .decode_validated(|id| *id < 1_000_000)
+    ///     .expect("decodable");
+    /// assert_eq!(decoded.difference.left(), &[10]);
+    /// assert_eq!(decoded.difference.right(), &[20]);
+    /// assert_eq!(decoded.suspected, vec![Side::Left(u64::MAX)]);
+    /// ```
+    pub fn decode_validated<F>(
+        mut self,
+        mut validate: F,
+    ) -> Result<ValidatedDecode<T>, DecodeError<T>>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let (mut recovered, mut suspected) = (Vec::new(), Vec::new());
+        let candidates = self.pure_cells();
+        let peeled = self.peel(candidates, None, |side| {
+            let valid = match &side {
+                Side::Left(x) | Side::Right(x) => validate(x),
+            };
+            if valid {
+                recovered.push(side);
+            } else {
+                suspected.push(side);
+            }
+        });
+        if !self.is_empty() {
+            recovered.extend(suspected);
+            return Err(DecodeError::new(recovered, self.non_empty));
+        }
+        Ok(ValidatedDecode {
+            difference: self.difference_of(recovered, peeled.iterations),
+            suspected,
+        })
+    }
+
+    /// Like [decode_difference](IBF::decode_difference), but gives up once deadline has passed,
+    /// returning the elements recovered so far, for decodes that have to fit a time budget. The
+    /// clock is checked every few hundred cells, so a decode may overrun the deadline slightly.
+    /// ```rust
+    /// use iron_rose::{TimedDecode, IBF};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let (mut left, mut right) = (IBF::new(20), IBF::new(20));
+    /// left.encode(1u64);
+    /// right.encode(2u64);
+    /// let diff = (left - right).unwrap();
+    /// let deadline = Instant::now() + Duration::from_secs(10);
+    /// match diff.clone().decode_before(deadline).expect("decodable") {
+    ///     TimedDecode::Complete(difference) => assert_eq!(difference.len(), 2),
+    ///     TimedDecode::OutOfTime { .. } => unreachable!("plenty of time"),
+    /// }
+    /// assert!(matches!(
+    ///     diff.decode_before(Instant::now()),
+    ///     Ok(TimedDecode::OutOfTime { .. })
+    /// ));
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn decode_before(mut self, deadline: Instant) -> Result<TimedDecode<T>, DecodeError<T>> {
+        let mut recovered = Vec::new();
+        if Instant::now() >= deadline {
+            return Ok(TimedDecode::OutOfTime { recovered });
+        }
+        let candidates = self.pure_cells();
+        let peeled = self.peel_until(
+            candidates,
+            None,
+            |side| recovered.push(side),
+            |peeled| peeled.recovered % DEADLINE_CHECK_INTERVAL == 0 && Instant::now() >= deadline,
+        );
+        if peeled.stopped && !self.is_empty() {
+            return Ok(TimedDecode::OutOfTime { recovered });
+        }
+        if !self.is_empty() {
+            return Err(DecodeError::new(recovered, self.non_empty));
+        }
+        Ok(TimedDecode::Complete(
+            self.difference_of(recovered, peeled.iterations),
+        ))
+    }
+
+    fn difference_of(&self, recovered: Vec<Side<T>>, peel_iterations: usize) -> Difference<T> {
+        let (mut left, mut right) = (Vec::new(), Vec::new());
+        for side in recovered {
+            match side {
+                Side::Left(x) => left.push(x),
+                Side::Right(x) => right.push(x),
+            }
+        }
+        Difference::new(left, right, peel_iterations, self.size, self.hash_count)
+    }
+
+    /// Like [decode](IBF::decode), but stops once `limit` elements have been recovered. This
+    /// bounds the memory a decode can use, which matters when the IBF came from an untrusted
+    /// peer. If there were more than `limit` differences,
+    /// [TooManyDifferences](BoundedDecode::TooManyDifferences) is returned along with the
+    /// elements recovered so far.
+    /// ```rust
+    /// use iron_rose::{BoundedDecode, IBF};
+    ///
+    /// let mut ibf = IBF::new(40);
+    /// for i in 0..5u64 {
+    ///     ibf.encode(i);
+    /// }
+    /// match ibf.decode_up_to(2).expect("decodable") {
+    ///     BoundedDecode::TooManyDifferences { recovered, .. } => assert_eq!(recovered.len(), 2),
+    ///     BoundedDecode::Complete(_) => panic!("there are 5 differences"),
+    /// }
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn decode_up_to(mut self, limit: usize) -> Result<BoundedDecode<T>, DecodeError<T>> {
+        let mut recovered = Vec::new();
+        let candidates = self.pure_cells();
+        let peeled = self.peel(candidates, Some(limit), |side| recovered.push(side));
+        if peeled.limited {
+            return Ok(BoundedDecode::TooManyDifferences {
+                limit,
+                recovered: recovered.into_iter().collect(),
+            });
+        }
+        self.finish(recovered).map(BoundedDecode::Complete)
+    }
+
+    /// Decodes with the given [DecodeStrategy](DecodeStrategy), returning the recovered
+    /// elements along with [diagnostics](DecodeDiagnostics) about how the decode went. A bounded
+    /// strategy that hits its limit is not an error, check
+    /// [limit_reached](DecodeDiagnostics::limit_reached).
+    /// ```rust
+    /// use iron_rose::{DecodeStrategy, IBF};
+    ///
+    /// let mut ibf = IBF::new(40);
+    /// (0..5u64).for_each(|x| ibf.encode(x));
+    /// let (set, diagnostics) = ibf
+    ///     .decode_with_strategy(DecodeStrategy::PeelUpTo { limit: 3 })
+    ///     .expect("decodable");
+    /// assert_eq!(set.len(), 3);
+    /// assert!(diagnostics.limit_reached);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn decode_with_strategy(
+        mut self,
+        strategy: DecodeStrategy,
+    ) -> Result<(HashSet<Side<T>>, DecodeDiagnostics), DecodeError<T>> {
+        let mut recovered = Vec::new();
+        let candidates = self.pure_cells();
+        let emit = |side| recovered.push(side);
+        let peeled = match strategy {
+            DecodeStrategy::Peel => self.peel(candidates, None, emit),
+            DecodeStrategy::PeelUpTo { limit } => self.peel(candidates, Some(limit), emit),
+            DecodeStrategy::PeelBalanced { limit } => self.peel_balanced(candidates, limit, emit),
+        };
+        let diagnostics = DecodeDiagnostics {
+            strategy,
+            peel_iterations: peeled.iterations,
+            recovered: recovered.len(),
+            limit_reached: peeled.limited,
+        };
+        if peeled.limited {
+            return Ok((recovered.into_iter().collect(), diagnostics));
+        }
+        Ok((self.finish(recovered)?, diagnostics))
+    }
+
+    /// Returns up to max_n elements that are currently sitting alone in a pure cell, without
+    /// decoding or consuming the IBF. Useful to preview what kind of things differ before
+    /// committing to a full decode. Elements are returned in cell order.
+    /// ```rust
+    /// use iron_rose::{IBF, Side};
+    ///
+    /// let mut ibf = IBF::new(30);
+    /// ibf.encode(3u64);
+    /// assert_eq!(ibf.sample_pure(5), vec![Side::Left(3)]);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn sample_pure(&self, max_n: usize) -> Vec<Side<T>> {
+        let mut seen = HashSet::new();
+        self.cells
+            .iter()
+            .filter(|cell| cell.is_pure(&self.hasher))
+            .map(|cell| {
+                cell.decode(&self.hasher)
+                    .expect("Only grabbing pure elements")
+            })
+            .filter(|side| seen.insert(side.clone()))
+            .take(max_n)
+            .collect()
+    }
+
+    /// Peels a copy of this IBF and describes the 2-core it got stuck on, or None if it decodes.
+    /// Which cells belong to which component of the core can't be known without the elements
+    /// in it, but its size tells the two kinds of failure apart: a core covering a large share
+    /// of the cells means the IBF is undersized, while a handful of cells is a few unlucky
+    /// cycles that a different hash_count or a re-salt would avoid.
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let mut ibf = IBF::new(10);
+    /// (0..50u64).for_each(|x| ibf.encode(x));
+    /// let core = ibf.stuck_core().unwrap();
+    /// assert!(core.fraction() > 0.5);
+    /// assert!(IBF::<u64>::new(10).stuck_core().is_none());
+    /// ```
+    pub fn stuck_core(&self) -> Option<StuckCore> {
+        let mut ibf = self.clone();
+        let candidates = ibf.pure_cells();
+        ibf.peel(candidates, None, |_| {});
+        if ibf.is_empty() {
+            return None;
+        }
+        let count_mass: usize = ibf
+            .cells
+            .iter()
+            .map(|cell| cell.count().to_i64().unsigned_abs() as usize)
+            .sum();
+        Some(StuckCore {
+            cells: ibf.non_empty,
             size: self.size,
+            min_elements: count_mass.div_ceil(self.hash_count.max(1)),
         })
     }
+
+    /// Builds a small IBF over the (cell index, cell fingerprint) pairs of this IBF's non-empty
+    /// cells. Two replicas of the same large sketch can exchange these, subtract and decode them
+    /// to find exactly which cells diverged, and repair those with a
+    /// [CellPatch](CellPatch) rather than transferring the whole sketch.
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let mut primary = IBF::new(1000);
+    /// (0..500u64).for_each(|x| primary.encode(x));
+    /// let mut replica = primary.clone();
+    /// replica.encode(1234);
+    ///
+    /// let diverged = (primary.fingerprint_sketch(30) - replica.fingerprint_sketch(30))
+    ///     .unwrap()
+    ///     .decode()
+    ///     .expect("only a few cells diverged");
+    /// let patch = primary.cell_patch(&diverged);
+    /// replica.apply_cell_patch(&patch).unwrap();
+    /// assert!((primary - replica).unwrap().is_empty());
+    /// ```
+    pub fn fingerprint_sketch(&self, size: usize) -> IBF<u128> {
+        let mut sketch = IBF::new(size);
+        for (idx, cell) in self.cells.iter().enumerate() {
+            if !cell.is_empty() {
+                sketch.encode(((idx as u128) << 64) | cell.fingerprint() as u128);
+            }
+        }
+        sketch
+    }
+
+    /// Exports the cells named in a decoded difference of two
+    /// [fingerprint sketches](IBF::fingerprint_sketch), for applying to a diverged replica.
+    #[cfg(feature = "std")]
+    pub fn cell_patch(&self, fingerprint_diff: &HashSet<Side<u128>>) -> CellPatch<T, C> {
+        let mut indices = fingerprint_diff
+            .iter()
+            .map(|side| (**side >> 64) as usize)
+            .filter(|idx| *idx < self.size)
+            .collect::<Vec<_>>();
+        indices.sort_unstable();
+        indices.dedup();
+        CellPatch {
+            params: self.params(),
+            keyspace: self.keyspace.clone(),
+            cells: indices
+                .into_iter()
+                .map(|idx| (idx, self.cells[idx].clone()))
+                .collect(),
+        }
+    }
+
+    /// Overwrites cells with the ones from a [CellPatch](CellPatch) exported from a replica of
+    /// the same shape and keyspace
+    pub fn apply_cell_patch(&mut self, patch: &CellPatch<T, C>) -> Result<(), IronRoseError> {
+        if patch.params != self.params() {
+            return Err(IronRoseError::ShapeMismatch {
+                left: self.params(),
+                right: patch.params,
+            });
+        }
+        if patch.keyspace != self.keyspace {
+            return Err(IronRoseError::KeyspaceMismatch {
+                left: self.keyspace().map(str::to_string),
+                right: patch.keyspace.as_deref().map(str::to_string),
+            });
+        }
+        if let Some((idx, _)) = patch.cells.iter().find(|(idx, _)| *idx >= self.size) {
+            return Err(IronRoseError::CellOutOfRange {
+                cell: *idx,
+                size: self.size,
+            });
+        }
+        for (idx, cell) in patch.cells.iter() {
+            self.modify_cell(*idx, |c, _| *c = cell.clone());
+        }
+        Ok(())
+    }
+
+    /// Copies the cells in range into a checksummed [IbfSegment](IbfSegment), so a large
+    /// sketch can be sent in pieces over a flaky link, resuming from the last segment that got
+    /// through, and put back together with [assemble](IBF::assemble).
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let mut ibf = IBF::new(100);
+    /// (0..50u64).for_each(|x| ibf.encode(x));
+    /// let segments = vec![ibf.slice(0..60).unwrap(), ibf.slice(60..100).unwrap()];
+    /// assert_eq!(IBF::assemble(segments).unwrap(), ibf);
+    /// assert!(IBF::assemble(vec![ibf.slice(0..60).unwrap()]).is_err());
+    /// ```
+    pub fn slice(&self, range: Range<usize>) -> Result<IbfSegment<T, C>, IronRoseError> {
+        if range.start > range.end || range.end > self.size {
+            return Err(IronRoseError::InvalidRange {
+                start: range.start,
+                end: range.end,
+                size: self.size,
+            });
+        }
+        let cells = self.cells[range.clone()].to_vec();
+        let checksum = IbfSegment::checksum_of(self.params(), range.start, &cells);
+        Ok(IbfSegment {
+            params: self.params(),
+            keyspace: self.keyspace.clone(),
+            start: range.start,
+            cells,
+            checksum,
+        })
+    }
+
+    /// Indexes of all the cells that are currently pure
+    fn pure_cells(&self) -> Vec<usize> {
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| cell.is_pure(&self.hasher))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// Peels pure cells into emit, starting from the candidate cell indexes and following any
+    /// cells that become pure along the way. Stops early if limit elements have been recovered
+    /// while more remain.
+    fn peel<F: FnMut(Side<T>)>(
+        &mut self,
+        candidates: Vec<usize>,
+        limit: Option<usize>,
+        emit: F,
+    ) -> Peeled {
+        self.peel_until(candidates, limit, emit, |_| false)
+    }
+
+    /// Like [peel](IBF::peel), but after every element, stops with
+    /// [stopped](Peeled::stopped) set if stop returns true for the peel so far
+    fn peel_until<F, U>(
+        &mut self,
+        mut candidates: Vec<usize>,
+        limit: Option<usize>,
+        mut emit: F,
+        mut stop: U,
+    ) -> Peeled
+    where
+        F: FnMut(Side<T>),
+        U: FnMut(&Peeled) -> bool,
+    {
+        let mut peeled = Peeled::default();
+        while let Some(idx) = self.next_pure(|| candidates.pop(), limit, &mut peeled) {
+            emit(self.peel_at(idx, &mut candidates, &mut peeled));
+            if stop(&peeled) {
+                peeled.stopped = true;
+                break;
+            }
+        }
+        peeled
+    }
+
+    /// Like [peel](IBF::peel), but takes turns between cells with a count of 1 and of -1, so
+    /// the elements emitted alternate sides while both have pure cells
+    #[cfg(feature = "std")]
+    fn peel_balanced<F: FnMut(Side<T>)>(
+        &mut self,
+        candidates: Vec<usize>,
+        limit: Option<usize>,
+        mut emit: F,
+    ) -> Peeled {
+        let mut peeled = Peeled::default();
+        // Candidates by the side their cell was on when it became pure, left then right
+        let mut sides = [Vec::new(), Vec::new()];
+        self.sort_by_side(&mut sides, candidates);
+        let mut turn = 0;
+        let mut next = Vec::new();
+        while let Some(idx) = self.next_pure(
+            || sides[turn].pop().or_else(|| sides[1 - turn].pop()),
+            limit,
+            &mut peeled,
+        ) {
+            let element = self.peel_at(idx, &mut next, &mut peeled);
+            turn = matches!(element, Side::Left(_)) as usize;
+            emit(element);
+            self.sort_by_side(&mut sides, core::mem::take(&mut next));
+        }
+        peeled
+    }
+
+    /// Pops candidates with pop until one is pure and returns it, or None once they run out,
+    /// limit elements have been recovered (setting [limited](Peeled::limited)), or as many
+    /// elements as there are cells.
+    ///
+    /// Every peel empties the cell it came from for good, so an honest IBF never gives up more
+    /// elements than it has cells. Crafted cells can forge purity so that peeling one element
+    /// refills a cell it emptied, which would loop forever, so peeling stops there too and
+    /// leaves the IBF undecoded.
+    fn next_pure<P>(&self, mut pop: P, limit: Option<usize>, peeled: &mut Peeled) -> Option<usize>
+    where
+        P: FnMut() -> Option<usize>,
+    {
+        while let Some(idx) = pop() {
+            peeled.iterations += 1;
+            if !self.cells[idx].is_pure(&self.hasher) {
+                continue;
+            }
+            if limit.is_some_and(|limit| peeled.recovered >= limit) {
+                peeled.limited = true;
+                return None;
+            }
+            if peeled.recovered >= self.size {
+                return None;
+            }
+            return Some(idx);
+        }
+        None
+    }
+
+    /// Peels the pure cell at idx, returning its element and pushing any cells that became pure
+    /// onto candidates
+    fn peel_at(&mut self, idx: usize, candidates: &mut Vec<usize>, peeled: &mut Peeled) -> Side<T> {
+        let next_pure = self.cells[idx].clone();
+        let element = next_pure
+            .decode(&self.hasher)
+            .expect("Only grabbing pure elements");
+        peeled.recovered += 1;
+        self.peel_cell(next_pure, candidates);
+        element
+    }
+
+    /// Pushes candidates onto the left or right stack of sides, by the sign of their cell
+    #[cfg(feature = "std")]
+    fn sort_by_side(&self, sides: &mut [Vec<usize>; 2], candidates: Vec<usize>) {
+        for idx in candidates {
+            sides[(self.cells[idx].count() < C::ZERO) as usize].push(idx);
+        }
+    }
+
+    /// The elements recovered by a decode, or the Err with them in the order they were
+    /// recovered if there are cells left
+    #[cfg(feature = "std")]
+    fn finish(&self, recovered: Vec<Side<T>>) -> Result<HashSet<Side<T>>, DecodeError<T>> {
+        if self.is_empty() {
+            Ok(recovered.into_iter().collect())
+        } else {
+            Err(DecodeError::new(recovered, self.non_empty))
+        }
+    }
+
+    /// Removes a pure cell's element from the IBF, pushing any cells that became pure onto
+    /// candidates.
+    fn peel_cell(&mut self, cell: Cell<T, C>, candidates: &mut Vec<usize>) {
+        let element = &*cell.decode(&self.hasher).expect("Only removing pure cells");
+        for idx in self.cell_indexes(element) {
+            self.modify_cell(idx, |c, _| *c -= cell.clone());
+            if self.cells[idx].is_pure(&self.hasher) {
+                candidates.push(idx);
+            }
+        }
+    }
+
+    /// Applies `f` to the cell at `idx`, along with the hasher, keeping the non-empty cell count in sync.
+    fn modify_cell<F: FnOnce(&mut Cell<T, C>, &H)>(&mut self, idx: usize, f: F) {
+        let cell = &mut self.cells[idx];
+        let was_empty = cell.is_empty();
+        f(cell, &self.hasher);
+        match (was_empty, cell.is_empty()) {
+            (true, false) => self.non_empty += 1,
+            (false, true) => self.non_empty -= 1,
+            _ => {}
+        }
+    }
+
+    /// The cell each hash function places element in
+    #[cfg(feature = "replay")]
+    pub(crate) fn placement(&self, element: &T) -> Vec<usize> {
+        (0..self.hash_count)
+            .map(|i| self.cell_index(i, element))
+            .collect()
+    }
+
+    /// The cells element is placed in, one per hash function, except that a cell picked by more
+    /// than one of them is only used once. Adding the element to a cell twice would cancel out
+    /// its id and hash there, leaving a count of 2 that can never look pure.
+    pub(crate) fn cell_indexes(&self, element: &T) -> Vec<usize> {
+        let mut indexes = Vec::with_capacity(self.hash_count);
+        self.fill_cell_indexes(element, &mut indexes);
+        indexes
+    }
+
+    /// Replaces the contents of indexes with the [cell_indexes](IBF::cell_indexes) of element
+    fn fill_cell_indexes(&self, element: &T, indexes: &mut Vec<usize>) {
+        indexes.clear();
+        for i in 0..self.hash_count {
+            let idx = self.cell_index(i, element);
+            if !indexes.contains(&idx) {
+                indexes.push(idx);
+            }
+        }
+    }
+
+    /// Same as [encode](IBF::encode), with the cell indexes collected in indexes, so a loop
+    /// encoding many elements reuses one buffer instead of allocating for each
+    #[cfg(feature = "roaring")]
+    pub(crate) fn encode_buffered(&mut self, element: T, indexes: &mut Vec<usize>) {
+        self.fill_cell_indexes(&element, indexes);
+        for &idx in indexes.iter() {
+            self.modify_cell(idx, |cell, hasher| cell.encode(element.clone(), hasher));
+        }
+    }
+
+    fn cell_index(&self, i: usize, element: &T) -> usize {
+        let mut hasher = self.hasher.build_hasher();
+        element.hash(&mut hasher);
+        i.hash(&mut hasher);
+
+        (hasher.finish() % (self.size as u64)) as usize
+    }
+}
+
+/// Hash only mode, where the sketch holds the [element_hash](element_hash) of each element. Cells
+/// are half the size of a u128 sketch, and elements don't need to meet the sketch's trait bounds.
+/// ```rust
+/// use iron_rose::{element_hash, Side, IBF};
+///
+/// let (mut left, mut right) = (IBF::new(20), IBF::new(20));
+/// left.encode_hashed("alice");
+/// left.encode_hashed("bob");
+/// right.encode_hashed("alice");
+/// let diff = (left - right).unwrap().decode().unwrap();
+/// assert!(diff.contains(&Side::Left(element_hash("bob"))));
+/// ```
+impl IBF<u64> {
+    /// Encodes the [element_hash](element_hash) of element
+    pub fn encode_hashed<E: Hash + ?Sized>(&mut self, element: &E) {
+        self.encode(element_hash(element))
+    }
+}
+
+/// Binary wire format: a fixed layout that is much smaller than the serde representations and
+/// independent of the in memory one. After a 4 byte header (format [version](crate::WIRE_VERSION),
+/// kind, element width and flags) come `hash_count` and `size` as little endian u32s, the
+/// keyspace if there is one (u16 length and UTF-8 bytes), every cell in index order as
+/// `id_sum`, `hash_sum`, `count`, little endian, and a CRC32 of everything before it. Counts take
+/// 4 bytes, or the width of the IBF's [Count](Count) type, which is recorded in the flags; older
+/// builds reject a sketch with other widths as truncated or having trailing bytes.
+/// ```rust
+/// use iron_rose::IBF;
+///
+/// let mut ibf = IBF::new(20);
+/// ibf.encode(1u64);
+/// let bytes = ibf.to_bytes();
+/// assert_eq!(bytes.len(), 12 + 20 * (8 + 8 + 4) + 4);
+/// assert_eq!(IBF::<u64>::from_bytes(&bytes).unwrap(), ibf);
+/// assert!(IBF::<u32>::from_bytes(&bytes).is_err());
+/// ```
+impl<T, C> IBF<T, DefaultBuildHasher, C>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + WireElement,
+    C: Count,
+{
+    /// Reads an IBF written by [to_bytes](IBF::to_bytes) or [to_bytes_as](IBF::to_bytes_as),
+    /// failing on an unsupported format version, another element or [count](Count) width, a
+    /// [checksum](IronRoseError::ChecksumMismatch) mismatch or malformed input.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, IronRoseError> {
+        let mut reader = Reader::new(bytes);
+        let flags = reader.header(wire::KIND_IBF, T::WIDTH)?;
+        if wire::count_width(flags)? != C::WIDTH {
+            return Err(IronRoseError::MalformedBytes {
+                reason: "cell count width doesn't match the count type",
+            });
+        }
+        let (hash_count, size) = (reader.u32()? as usize, reader.u32()? as usize);
+        let keyspace = if flags & wire::FLAG_KEYSPACE != 0 {
+            let len = reader.u16()? as usize;
+            let keyspace = core::str::from_utf8(reader.take(len)?).map_err(|_| {
+                IronRoseError::MalformedBytes {
+                    reason: "keyspace is not UTF-8",
+                }
+            })?;
+            Some(Arc::from(keyspace))
+        } else {
+            None
+        };
+        let mut ibf = Self::read_cells(&mut reader, hash_count, size)?;
+        ibf.keyspace = keyspace;
+        reader.finish()?;
+        Ok(ibf)
+    }
+
+    /// Reads size cells written by [write_cells](IBF::write_cells) into an untagged IBF
+    pub(crate) fn read_cells(
+        reader: &mut Reader<'_>,
+        hash_count: usize,
+        size: usize,
+    ) -> Result<Self, IronRoseError> {
+        check_hash_count(hash_count, size)?;
+        reader.expect_at_least(size.checked_mul(Cell::<T, C>::WIRE_WIDTH))?;
+        let cells = (0..size)
+            .map(|_| Cell::read_bytes(reader))
+            .collect::<Result<Box<[_]>, _>>()?;
+        Ok(Self::from_cells(
+            cells,
+            hash_count,
+            size,
+            None,
+            Default::default(),
+        ))
+    }
+}
+
+/// Writing works whatever the hasher, which isn't part of the format. A sketch built with a
+/// [keyed hasher](crate::SeededHasher) is read back with the default one and given its hasher
+/// again with [into_hasher](IBF::into_hasher).
+impl<T, H, C, S> IBF<T, H, C, S>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + WireElement,
+    H: BuildHasher + Clone,
+    C: Count,
+    S: CellStorage<T, C>,
+{
+    /// Serializes the IBF in the binary wire format. Panics if it has 2^32 cells or more, which
+    /// the format can't hold, see [try_to_bytes](IBF::try_to_bytes).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.try_to_bytes()
+            .expect("IBFs on the wire are under 2^32 cells")
+    }
+
+    /// Serializes the IBF in the binary wire format, failing if it's too large for the format
+    pub fn try_to_bytes(&self) -> Result<Vec<u8>, IronRoseError> {
+        self.to_bytes_as(crate::WIRE_VERSION)
+    }
+
+    /// Serializes the IBF in the given version of the binary wire format, for peers that don't
+    /// read the current one yet. Fails on a version this build can't write, see
+    /// [MIN_WIRE_VERSION](crate::MIN_WIRE_VERSION), or if the IBF is too large for the format.
+    /// ```rust
+    /// use iron_rose::{IBF, MIN_WIRE_VERSION};
+    ///
+    /// let ibf = IBF::<u64>::new(20);
+    /// let old = ibf.to_bytes_as(MIN_WIRE_VERSION).unwrap();
+    /// assert_eq!(old.len(), ibf.to_bytes().len() - 4);
+    /// assert_eq!(IBF::<u64>::from_bytes(&old).unwrap(), ibf);
+    /// assert!(ibf.to_bytes_as(MIN_WIRE_VERSION - 1).is_err());
+    /// ```
+    pub fn to_bytes_as(&self, version: u8) -> Result<Vec<u8>, IronRoseError> {
+        wire::check_version(version)?;
+        if u32::try_from(self.size).is_err() || u32::try_from(self.hash_count).is_err() {
+            return Err(IronRoseError::TooLargeForWire {
+                size: self.size,
+                hash_count: self.hash_count,
+            });
+        }
+        let mut out = Vec::with_capacity(12 + self.size * Cell::<T, C>::WIRE_WIDTH + 4);
+        let flags = if self.keyspace.is_some() {
+            wire::FLAG_KEYSPACE | C::WIRE_FLAGS
+        } else {
+            C::WIRE_FLAGS
+        };
+        wire::write_header(&mut out, version, wire::KIND_IBF, T::WIDTH, flags);
+        self.write_shape(&mut out);
+        if let Some(keyspace) = self.keyspace() {
+            // Checked when the keyspace was set
+            let len = keyspace.len() as u16;
+            out.extend_from_slice(&len.to_le_bytes());
+            out.extend_from_slice(keyspace.as_bytes());
+        }
+        self.write_cells(&mut out);
+        wire::seal(&mut out);
+        Ok(out)
+    }
+
+    /// Serializes the IBF in the binary wire format, unless that would take more than
+    /// max_wire_bytes, in which case the Err reports the would-be size. The size is worked out
+    /// before anything is allocated.
+    /// ```rust
+    /// use iron_rose::{IronRoseError, IBF};
+    ///
+    /// let ibf = IBF::<u64>::new(1000);
+    /// assert_eq!(ibf.to_bytes_within(64 * 1024).unwrap(), ibf.to_bytes());
+    /// assert_eq!(
+    ///     ibf.to_bytes_within(1024),
+    ///     Err(IronRoseError::OverWireBudget { bytes: 20_016, max: 1024 })
+    /// );
+    /// ```
+    pub fn to_bytes_within(&self, max_wire_bytes: usize) -> Result<Vec<u8>, IronRoseError> {
+        let kind = SketchKind::Ibf {
+            element_width: T::WIDTH,
+        };
+        let keyspace = self.keyspace().map_or(0, |keyspace| 2 + keyspace.len());
+        // The estimate is for i32 counts
+        let bytes = self
+            .params()
+            .estimated_bytes(kind)
+            .wire
+            .saturating_add(keyspace)
+            .saturating_add(self.size.saturating_mul(C::WIDTH))
+            .saturating_sub(self.size.saturating_mul(4));
+        if bytes > max_wire_bytes {
+            return Err(IronRoseError::OverWireBudget {
+                bytes,
+                max: max_wire_bytes,
+            });
+        }
+        self.try_to_bytes()
+    }
+
+    /// Appends hash_count and size
+    pub(crate) fn write_shape(&self, out: &mut Vec<u8>) {
+        for n in [self.hash_count, self.size] {
+            let n = u32::try_from(n).expect("IBFs on the wire are under 2^32 cells");
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+    }
+
+    /// Appends every cell in index order
+    pub(crate) fn write_cells(&self, out: &mut Vec<u8>) {
+        for cell in self.cells.iter() {
+            cell.write_bytes(out);
+        }
+    }
+}
+
+/// Random, internally consistent IBFs for fuzzing: an arbitrary shape with arbitrary elements
+/// encoded on both sides, like the result of a subtraction.
+#[cfg(feature = "arbitrary")]
+impl<'a, T> arbitrary::Arbitrary<'a> for IBF<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + arbitrary::Arbitrary<'a>,
+{
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut ibf = IBF::with_params(u.arbitrary()?);
+        for element in u.arbitrary_iter::<(bool, T)>()? {
+            match element? {
+                (true, element) => ibf.encode(element),
+                (false, element) => ibf.remove(element),
+            }
+        }
+        Ok(ibf)
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<T> IBF<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + Send
+        + Sync,
+{
+    /// Encodes every element, spread across the rayon thread pool. Each thread encodes its share
+    /// of the elements into an IBF of its own, and those are [merged](IBF::merge) into this one,
+    /// so building a sketch of millions of elements scales with the number of cores. The result
+    /// is the same as encoding them one by one.
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let mut parallel = IBF::new(100);
+    /// parallel.encode_batch(0..100_000u64);
+    /// let mut serial = IBF::new(100);
+    /// (0..100_000u64).for_each(|x| serial.encode(x));
+    /// assert_eq!(parallel, serial);
+    /// ```
+    pub fn encode_batch<I>(&mut self, elements: I)
+    where
+        I: rayon::iter::IntoParallelIterator<Item = T>,
+    {
+        use rayon::prelude::*;
+
+        let empty = || {
+            let mut ibf = IBF::with_params(self.params());
+            ibf.keyspace = self.keyspace.clone();
+            ibf
+        };
+        let batch = elements
+            .into_par_iter()
+            .fold(empty, |mut ibf, element| {
+                ibf.encode(element);
+                ibf
+            })
+            .reduce_with(|mut left, right| {
+                left.merge(&right).expect("Batches are the same shape");
+                left
+            });
+        if let Some(batch) = batch {
+            self.merge(&batch).expect("Batches are the same shape");
+        }
+    }
+
+    /// New IBF with the given shape holding elements, encoded with
+    /// [encode_batch](IBF::encode_batch)
+    pub fn from_par_iter<I>(params: SketchParams, elements: I) -> Self
+    where
+        I: rayon::iter::IntoParallelIterator<Item = T>,
+    {
+        let mut ibf = IBF::with_params(params);
+        ibf.encode_batch(elements);
+        ibf
+    }
+
+    /// Parallel version of subtraction, splitting the cell-wise work across the rayon thread
+    /// pool. Only worthwhile for very large IBFs.
+    pub fn par_sub(&self, rhs: &Self) -> Result<IBF<T>, IronRoseError> {
+        use rayon::prelude::*;
+
+        self.check_compatible(rhs)?;
+        self.check_counts(rhs, i32::checked_sub)?;
+        let cells = self
+            .cells
+            .par_iter()
+            .zip(rhs.cells.par_iter())
+            .map(|(l, r)| l - r)
+            .collect::<Vec<_>>();
+        Ok(IBF::from_cells(
+            cells.into_boxed_slice(),
+            self.hash_count,
+            self.size,
+            self.keyspace.clone(),
+            self.hasher.clone(),
+        ))
+    }
+
+    /// Same as [decode](IBF::decode), but the initial scan for pure cells is done in parallel.
+    /// Peeling itself stays single threaded.
+    pub fn par_decode(mut self) -> Result<HashSet<Side<T>>, DecodeError<T>> {
+        use rayon::prelude::*;
+
+        let candidates = self
+            .cells
+            .par_iter()
+            .enumerate()
+            .filter(|(_, cell)| cell.is_pure(&self.hasher))
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+        let mut recovered = Vec::new();
+        self.peel(candidates, None, |side| recovered.push(side));
+        self.finish(recovered)
+    }
+}
+
+#[cfg(feature = "gpu")]
+impl<T, H> IBF<T, H>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + WireElement,
+    H: BuildHasher + Clone,
+{
+    /// GPU version of subtraction, for sketches of hundreds of millions of cells. The cells are
+    /// subtracted on the GPU, in as few dispatches as its buffer limits allow, and classified
+    /// there as empty or possibly pure in the same pass, so decoding starts from the candidate
+    /// cells without scanning the difference again. Elements have to be a multiple of 4 bytes
+    /// wide, and counts that would overflow fail with
+    /// [CountOverflow](IronRoseError::CountOverflow), as they do on the CPU.
+    pub fn gpu_sub(
+        &self,
+        rhs: &Self,
+        gpu: &crate::GpuContext,
+    ) -> Result<GpuDifference<T, H>, IronRoseError> {
+        self.check_compatible(rhs)?;
+        self.check_counts(rhs, i32::checked_sub)?;
+        if T::WIDTH % 4 != 0 {
+            return Err(IronRoseError::Gpu {
+                reason: "element width must be a multiple of 4 bytes",
+            });
+        }
+        let stride = Cell::<T>::WIRE_WIDTH / 4;
+        let chunk = gpu.max_cells(stride);
+        let mut cells = Vec::with_capacity(self.size);
+        let mut candidates = Vec::new();
+        let mut non_empty = 0;
+        let (mut left, mut right) = (Vec::new(), Vec::new());
+        for (l, r) in self.cells.chunks(chunk).zip(rhs.cells.chunks(chunk)) {
+            left.clear();
+            right.clear();
+            l.iter().for_each(|cell| cell.write_bytes(&mut left));
+            r.iter().for_each(|cell| cell.write_bytes(&mut right));
+            let (diff, flags) = gpu.subtract(&left, &right, stride, l.len())?;
+            let mut reader = Reader::new(&diff);
+            for flag in flags {
+                if flag == crate::gpu::CANDIDATE {
+                    candidates.push(cells.len());
+                }
+                if flag != crate::gpu::EMPTY {
+                    non_empty += 1;
+                }
+                cells.push(Cell::read_bytes(&mut reader)?);
+            }
+        }
+        Ok(GpuDifference {
+            ibf: Self {
+                cells: cells.into_boxed_slice(),
+                hash_count: self.hash_count,
+                size: self.size,
+                keyspace: self.keyspace.clone(),
+                non_empty,
+                hasher: self.hasher.clone(),
+                stored: PhantomData,
+            },
+            candidates,
+        })
+    }
+}
+
+/// Difference of two IBFs worked out on the GPU by [IBF::gpu_sub](IBF::gpu_sub), along with
+/// the cells the GPU found might be pure.
+#[cfg(feature = "gpu")]
+#[derive(Debug, Clone)]
+pub struct GpuDifference<T, H = DefaultBuildHasher>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    ibf: IBF<T, H>,
+    candidates: Vec<usize>,
+}
+
+#[cfg(feature = "gpu")]
+impl<T, H> GpuDifference<T, H>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    H: BuildHasher + Clone,
+{
+    /// Indexes of the cells with a count of 1 or -1, which peeling starts from
+    pub fn candidates(&self) -> &[usize] {
+        &self.candidates
+    }
+
+    /// The difference, as an IBF
+    pub fn into_ibf(self) -> IBF<T, H> {
+        self.ibf
+    }
+
+    /// Same as [decode](IBF::decode) on the difference, starting from the GPU's candidates
+    pub fn decode(self) -> Result<HashSet<Side<T>>, DecodeError<T>> {
+        let mut ibf = self.ibf;
+        let mut recovered = Vec::new();
+        ibf.peel(self.candidates, None, |side| recovered.push(side));
+        ibf.finish(recovered)
+    }
+}
+
+/// A set of cells exported from one replica of an IBF, with its shape and keyspace, to overwrite
+/// the same cells of another replica. See [IBF::fingerprint_sketch](IBF::fingerprint_sketch).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CellPatch<T, C = i32>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    C: Count,
+{
+    params: SketchParams,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    keyspace: Option<Arc<str>>,
+    cells: Vec<(usize, Cell<T, C>)>,
+}
+
+impl<T, C> CellPatch<T, C>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    C: Count,
+{
+    /// Indexes of the cells in this patch, in ascending order
+    pub fn indices(&self) -> Vec<usize> {
+        self.cells.iter().map(|(idx, _)| *idx).collect()
+    }
+}
+
+/// Which side of a [Difference](Difference) a sketch is rolled forward to by
+/// [IBF::apply_difference](IBF::apply_difference)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ApplyDirection {
+    /// The local set now matches the left side: left only elements are added, right only removed
+    ToLeft,
+    /// The local set now matches the right side: right only elements are added, left only removed
+    ToRight,
+}
+
+/// A contiguous run of cells from an IBF, with a checksum, made by [IBF::slice](IBF::slice)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IbfSegment<T, C = i32>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    C: Count,
+{
+    params: SketchParams,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    keyspace: Option<Arc<str>>,
+    start: usize,
+    cells: Vec<Cell<T, C>>,
+    checksum: u64,
+}
+
+impl<T, C> IbfSegment<T, C>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    C: Count,
+{
+    /// Shape of the IBF the segment was sliced from
+    pub fn params(&self) -> SketchParams {
+        self.params
+    }
+
+    /// Cells of the IBF covered by this segment
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.start.saturating_add(self.cells.len())
+    }
+
+    /// True if the checksum matches the cells
+    pub fn verify(&self) -> bool {
+        Self::checksum_of(self.params, self.start, &self.cells) == self.checksum
+    }
+
+    fn checksum_of(params: SketchParams, start: usize, cells: &[Cell<T, C>]) -> u64 {
+        let mut hasher: ElmHasher = Default::default();
+        params.hash(&mut hasher);
+        start.hash(&mut hasher);
+        for cell in cells {
+            cell.fingerprint().hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+}
+
+/// Elements [decode_before](IBF::decode_before) peels between looks at the clock
+#[cfg(feature = "std")]
+const DEADLINE_CHECK_INTERVAL: usize = 256;
+
+/// Bookkeeping from a peel
+#[derive(Debug, Clone, Default)]
+struct Peeled {
+    /// Peeling stopped because the limit was hit
+    limited: bool,
+    /// Peeling stopped because the caller's check said so, see [peel_until](IBF::peel_until)
+    stopped: bool,
+    /// Number of candidate cells examined
+    iterations: usize,
+    /// Number of elements recovered
+    recovered: usize,
+}
+
+/// How [IBF::decode_with_strategy](IBF::decode_with_strategy) should go about decoding.
+/// Currently every strategy is based on peeling, there is no Gaussian elimination fallback for
+/// IBFs that get stuck.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DecodeStrategy {
+    /// Peel until no pure cells are left, the same as [IBF::decode](IBF::decode)
+    Peel,
+    /// Peel, but stop once limit elements have been recovered, the same as
+    /// [IBF::decode_up_to](IBF::decode_up_to)
+    PeelUpTo {
+        /// Maximum number of elements to recover
+        limit: usize,
+    },
+    /// Peel, alternating between elements only on the left and elements only on the right for
+    /// as long as both sides have pure cells, stopping once limit elements have been recovered
+    /// if there is one. A decode stopped by the limit then holds about as many elements of
+    /// each side, rather than whichever side the scan of the cells came to first. Decodes that
+    /// complete or get stuck recover the same elements whatever the order, but those that get
+    /// stuck list them in alternating order, so the first of them are balanced too.
+    PeelBalanced {
+        /// Maximum number of elements to recover, if any
+        limit: Option<usize>,
+    },
+}
+
+/// What happened during [IBF::decode_with_strategy](IBF::decode_with_strategy)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeDiagnostics {
+    /// The strategy that was used
+    pub strategy: DecodeStrategy,
+    /// Number of candidate cells examined while peeling
+    pub peel_iterations: usize,
+    /// Number of elements recovered
+    pub recovered: usize,
+    /// True if a bounded strategy stopped with elements left in the IBF
+    pub limit_reached: bool,
+}
+
+/// Iterator over the elements of an IBF as they are peeled, from
+/// [IBF::into_decoder](IBF::into_decoder). It peels the same way as the other decode methods,
+/// but if peeling gets stuck, the [DecodeError](DecodeError) it ends with doesn't repeat the
+/// elements already yielded: its [decoded](DecodeError::decoded) is empty.
+#[derive(Debug, Clone)]
+pub struct Decoder<T, H = DefaultBuildHasher, C = i32, S = Box<[Cell<T, C>]>>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    C: Count,
+    S: CellStorage<T, C>,
+{
+    ibf: IBF<T, H, C, S>,
+    candidates: Vec<usize>,
+    peeled: Peeled,
+    done: bool,
+}
+
+impl<T, H, C, S> Iterator for Decoder<T, H, C, S>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    H: BuildHasher + Clone,
+    C: Count,
+    S: CellStorage<T, C>,
+{
+    type Item = Result<Side<T>, DecodeError<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let ibf = &mut self.ibf;
+        let candidates = &mut self.candidates;
+        if let Some(idx) = ibf.next_pure(|| candidates.pop(), None, &mut self.peeled) {
+            return Some(Ok(ibf.peel_at(idx, candidates, &mut self.peeled)));
+        }
+        self.done = true;
+        if self.ibf.is_empty() {
+            None
+        } else {
+            // The elements were yielded already
+            Some(Err(DecodeError::new(Vec::new(), self.ibf.non_empty)))
+        }
+    }
+}
+
+/// The cells left over when peeling got stuck, from [IBF::stuck_core](IBF::stuck_core)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StuckCore {
+    /// Number of non-empty cells left after peeling
+    pub cells: usize,
+    /// Number of cells in the IBF
+    pub size: usize,
+    /// Lower bound on the number of elements left, from the cell counts. Elements on opposite
+    /// sides cancel out in the counts, so there may be more.
+    pub min_elements: usize,
+}
+
+impl StuckCore {
+    /// Share of the IBF's cells in the core
+    pub fn fraction(&self) -> f64 {
+        self.cells as f64 / self.size as f64
+    }
+}
+
+/// Result of [IBF::decode_validated](IBF::decode_validated)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de>"))]
+pub struct ValidatedDecode<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// The elements that passed validation
+    pub difference: Difference<T>,
+    /// The elements that failed it, likely false decodes or corruption, in the order they were
+    /// recovered
+    pub suspected: Vec<Side<T>>,
+}
+
+impl<T> ValidatedDecode<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// True if every element passed validation
+    pub fn is_clean(&self) -> bool {
+        self.suspected.is_empty()
+    }
+}
+
+/// Result of [IBF::decode_before](IBF::decode_before)
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimedDecode<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// Every difference was recovered before the deadline
+    Complete(Difference<T>),
+    /// The deadline passed first
+    OutOfTime {
+        /// The elements recovered before the deadline, in the order they were recovered
+        recovered: Vec<Side<T>>,
+    },
+}
+
+/// Result of [IBF::decode_up_to](IBF::decode_up_to)
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BoundedDecode<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// Every difference was recovered, and there were no more than the limit
+    Complete(HashSet<Side<T>>),
+    /// Decoding stopped after recovering limit elements, with more still in the IBF
+    TooManyDifferences {
+        /// The limit that was hit
+        limit: usize,
+        /// The elements recovered before stopping
+        recovered: HashSet<Side<T>>,
+    },
+}
+
+impl<T, H, C, S> Neg for IBF<T, H, C, S>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    H: BuildHasher + Clone,
+    C: Count,
+    S: CellStorage<T, C>,
+{
+    type Output = IBF<T, H, C, S>;
+
+    fn neg(self) -> Self::Output {
+        self.negate()
+    }
+}
+
+impl<T, H, C, S> Sub for IBF<T, H, C, S>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    H: BuildHasher + Clone,
+    C: Count,
+    S: CellStorage<T, C>,
+{
+    type Output = Result<IBF<T, H, C, S>, IronRoseError>;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.check_compatible(&rhs)?;
+        self.check_counts(&rhs, C::checked_sub)?;
+        // We own both sides, so subtract in place and move the rhs cells rather than
+        // allocating a new cell array and cloning every element.
+        let (mut cells, mut rhs_cells) = (self.cells, rhs.cells);
+        let mut non_empty = 0;
+        for (l, r) in cells.iter_mut().zip(rhs_cells.iter_mut()) {
+            *l -= core::mem::take(r);
+            if !l.is_empty() {
+                non_empty += 1;
+            }
+        }
+        Ok(Self {
+            cells,
+            non_empty,
+            ..self
+        })
+    }
+}
+
+/// Subtracts a borrowed rhs from an owned lhs, reusing the lhs cells
+impl<T, H, C, S> Sub<&IBF<T, H, C, S>> for IBF<T, H, C, S>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    H: BuildHasher + Clone,
+    C: Count,
+    S: CellStorage<T, C>,
+{
+    type Output = Result<IBF<T, H, C, S>, IronRoseError>;
+
+    fn sub(self, rhs: &IBF<T, H, C, S>) -> Self::Output {
+        self.check_compatible(rhs)?;
+        self.check_counts(rhs, C::checked_sub)?;
+        let mut cells = self.cells;
+        let mut non_empty = 0;
+        for (l, r) in cells.iter_mut().zip(rhs.cells.iter()) {
+            *l -= r;
+            if !l.is_empty() {
+                non_empty += 1;
+            }
+        }
+        Ok(Self {
+            cells,
+            non_empty,
+            ..self
+        })
+    }
+}
+
+/// Subtracts an owned rhs from a borrowed lhs, reusing the rhs cells
+impl<T, H, C, S> Sub<IBF<T, H, C, S>> for &IBF<T, H, C, S>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    H: BuildHasher + Clone,
+    C: Count,
+    S: CellStorage<T, C>,
+{
+    type Output = Result<IBF<T, H, C, S>, IronRoseError>;
+
+    fn sub(self, rhs: IBF<T, H, C, S>) -> Self::Output {
+        self.check_compatible(&rhs)?;
+        self.check_counts(&rhs, C::checked_sub)?;
+        let mut cells = rhs.cells;
+        let mut non_empty = 0;
+        for (l, r) in self.cells.iter().zip(cells.iter_mut()) {
+            *r = l - &*r;
+            if !r.is_empty() {
+                non_empty += 1;
+            }
+        }
+        Ok(IBF {
+            cells,
+            non_empty,
+            ..rhs
+        })
+    }
+}
+
+impl<T, H, C, S> Sub for &IBF<T, H, C, S>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    H: BuildHasher + Clone,
+    C: Count,
+    S: CellStorage<T, C>,
+{
+    type Output = Result<IBF<T, H, C, S>, IronRoseError>;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.check_compatible(rhs)?;
+        // A copy of the lhs cells, in its storage, is subtracted in place
+        self.clone() - rhs
+    }
+}
+
+impl<T, H, C, S> Add for IBF<T, H, C, S>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    H: BuildHasher + Clone,
+    C: Count,
+    S: CellStorage<T, C>,
+{
+    type Output = Result<IBF<T, H, C, S>, IronRoseError>;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        self + &rhs
+    }
+}
+
+/// Adds a borrowed rhs to an owned lhs, reusing the lhs cells
+impl<T, H, C, S> Add<&IBF<T, H, C, S>> for IBF<T, H, C, S>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    H: BuildHasher + Clone,
+    C: Count,
+    S: CellStorage<T, C>,
+{
+    type Output = Result<IBF<T, H, C, S>, IronRoseError>;
+
+    fn add(mut self, rhs: &IBF<T, H, C, S>) -> Self::Output {
+        self.merge(rhs)?;
+        Ok(self)
+    }
+}
+
+/// Adds an owned rhs to a borrowed lhs, reusing the rhs cells
+impl<T, H, C, S> Add<IBF<T, H, C, S>> for &IBF<T, H, C, S>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    H: BuildHasher + Clone,
+    C: Count,
+    S: CellStorage<T, C>,
+{
+    type Output = Result<IBF<T, H, C, S>, IronRoseError>;
+
+    fn add(self, rhs: IBF<T, H, C, S>) -> Self::Output {
+        self.check_compatible(&rhs)?;
+        rhs + self
+    }
+}
+
+impl<T, H, C, S> Add for &IBF<T, H, C, S>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    H: BuildHasher + Clone,
+    C: Count,
+    S: CellStorage<T, C>,
+{
+    type Output = Result<IBF<T, H, C, S>, IronRoseError>;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        self.clone() + rhs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn is_empty_tracks_mutations() {
+        let mut ibf = IBF::new(10);
+        assert!(ibf.is_empty());
+        ibf.encode(1u64);
+        ibf.encode(2u64);
+        assert!(!ibf.is_empty());
+        assert_eq!(
+            ibf.non_empty,
+            ibf.cells.iter().filter(|c| !c.is_empty()).count()
+        );
+
+        let same = ibf.clone();
+        let diff = (&ibf - &same).unwrap();
+        assert!(diff.is_empty());
+        assert_eq!(ibf.decode_cloned().unwrap().len(), 2);
+        assert!(!ibf.is_empty());
+        assert!(diff.decode().unwrap().is_empty());
+    }
+
+    #[test]
+    fn decode_up_to_exact_limit() {
+        let mut ibf = IBF::new(40);
+        for i in 0..5u64 {
+            ibf.encode(i);
+        }
+        match ibf.clone().decode_up_to(5).unwrap() {
+            BoundedDecode::Complete(set) => assert_eq!(set.len(), 5),
+            other => panic!("expected complete decode, got {:?}", other),
+        }
+        match ibf.decode_up_to(4).unwrap() {
+            BoundedDecode::TooManyDifferences { limit, recovered } => {
+                assert_eq!(limit, 4);
+                assert_eq!(recovered.len(), 4);
+            }
+            other => panic!("expected limit to be hit, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_matches_serial() {
+        let (mut left, mut right) = (IBF::new(200), IBF::new(200));
+        for i in 0..1000u64 {
+            left.encode(i);
+            right.encode(i + 20);
+        }
+        let serial = (&left - &right).unwrap();
+        let parallel = left.par_sub(&right).unwrap();
+        assert_eq!(serial.non_empty, parallel.non_empty);
+        assert_eq!(serial.decode().unwrap(), parallel.par_decode().unwrap());
+
+        let params = SketchParams::new(200, 3);
+        let batched = IBF::from_par_iter(params, 0..1000u64);
+        assert_eq!(batched.non_empty, left.non_empty);
+        assert_eq!(batched, left);
+    }
+
+    #[test]
+    fn decode_is_reproducible() {
+        let mut ibf = IBF::new(30);
+        (0..10u64).for_each(|x| ibf.encode(x * 1000));
+        let bytes = ibf.to_bytes();
+        let diff = IBF::<u64>::from_bytes(&bytes)
+            .unwrap()
+            .decode_difference()
+            .unwrap();
+        // Pinned, as the order must not change from one run to the next
+        assert_eq!(
+            diff.left(),
+            &[8000, 2000, 5000, 9000, 6000, 1000, 4000, 7000, 3000, 0]
+        );
+        assert_eq!(
+            diff,
+            IBF::<u64>::from_bytes(&bytes)
+                .unwrap()
+                .decode_difference()
+                .unwrap()
+        );
+
+        let mut ibf = IBF::new(12);
+        (0..12u64).for_each(|x| ibf.encode(x));
+        let bytes = ibf.to_bytes();
+        let err = IBF::<u64>::from_bytes(&bytes)
+            .unwrap()
+            .decode()
+            .unwrap_err();
+        assert_eq!(err.decoded(), &[Side::Left(6)]);
+        let again = IBF::<u64>::from_bytes(&bytes)
+            .unwrap()
+            .decode()
+            .unwrap_err();
+        assert_eq!(format!("{:?}", err), format!("{:?}", again));
+    }
+
+    #[test]
+    fn decode_into_matches_decode() {
+        let (mut left, mut right) = (IBF::new(60), IBF::new(60));
+        for i in 0..100u64 {
+            left.encode(i);
+            right.encode(i + 10);
+        }
+        let diff = (left - right).unwrap();
+        let mut out = Vec::new();
+        diff.clone().decode_into(&mut out).unwrap();
+        assert_eq!(out.len(), 20);
+
+        let mut stuck = IBF::new(4);
+        (0..20u64).for_each(|x| stuck.encode(x));
+        let mut partial = vec![Side::Right(100)];
+        let err = stuck.clone().decode_into(&mut partial).unwrap_err();
+        assert_eq!(err.decoded(), &partial[1..]);
+        assert_eq!(err.decoded(), stuck.clone().decode().unwrap_err().decoded());
+        let streamed = diff.clone().into_decoder().collect::<Result<Vec<_>, _>>();
+        assert_eq!(streamed.unwrap(), out);
+        // A stuck decoder yields what the other decodes recover, then an Err without them
+        let mut streamed = stuck.into_decoder().collect::<Vec<_>>();
+        let last = streamed.pop().unwrap().unwrap_err();
+        assert!(last.decoded().is_empty());
+        assert_eq!(last.remaining_cells(), err.remaining_cells());
+        let streamed = streamed.into_iter().collect::<Result<Vec<_>, _>>();
+        assert_eq!(streamed.unwrap(), err.decoded());
+        let ordered = diff.clone().decode_into_container::<BTreeSet<_>>();
+        assert!(ordered
+            .unwrap()
+            .iter()
+            .eq(out.iter().collect::<BTreeSet<_>>()));
+        assert_eq!(
+            out.into_iter().collect::<HashSet<_>>(),
+            diff.decode().unwrap()
+        );
+        let mut ibf = IBF::new(4);
+        (0..20u64).for_each(|x| ibf.encode(x));
+        assert!(ibf.decode_into_container::<Vec<_>>().is_err());
+    }
+
+    #[test]
+    fn sample_pure_is_bounded_and_distinct() {
+        let mut ibf = IBF::new(100);
+        for i in 0..10u64 {
+            ibf.encode(i);
+        }
+        let sample = ibf.sample_pure(4);
+        assert_eq!(sample.len(), 4);
+        assert_eq!(sample.iter().collect::<HashSet<_>>().len(), 4);
+        let all = ibf.clone().decode().unwrap();
+        assert!(sample.iter().all(|side| all.contains(side)));
+        assert!(ibf.sample_pure(0).is_empty());
+    }
+
+    #[test]
+    fn deserialize_rejects_inconsistent_size() {
+        let mut ibf = IBF::new(4);
+        ibf.encode(1u64);
+        let json = serde_json::to_string(&ibf).unwrap();
+        let back = serde_json::from_str::<IBF<u64>>(&json).unwrap();
+        assert_eq!(back.non_empty, ibf.non_empty);
+
+        let tampered = json.replace("\"size\":4", "\"size\":5");
+        assert!(serde_json::from_str::<IBF<u64>>(&tampered).is_err());
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn arbitrary_ibfs_are_consistent() {
+        use arbitrary::{Arbitrary, Unstructured};
+
+        let bytes = (0..4096u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect::<Vec<_>>();
+        let mut u = Unstructured::new(&bytes);
+        while let Ok(ibf) = IBF::<u64>::arbitrary(&mut u) {
+            let non_empty = ibf.cells.iter().filter(|c| !c.is_empty()).count();
+            assert_eq!(ibf.non_empty, non_empty);
+            assert_eq!(ibf.cells.len(), ibf.size);
+            if u.is_empty() {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn segments_are_checked() {
+        let mut ibf = IBF::new(100);
+        (0..50u64).for_each(|x| ibf.encode(x));
+        assert!(ibf.slice(90..101).is_err());
+        let (a, b) = (ibf.slice(0..50).unwrap(), ibf.slice(50..100).unwrap());
+        assert_eq!(b.range(), 50..100);
+        assert_eq!(IBF::assemble(vec![b.clone(), a.clone()]).unwrap(), ibf);
+        assert!(IBF::assemble(vec![a.clone(), a.clone(), b.clone()]).is_err());
+
+        let mut corrupt = b.clone();
+        let occupied = corrupt.cells.iter().position(|c| !c.is_empty()).unwrap();
+        corrupt.cells[occupied].negate();
+        assert!(!corrupt.verify());
+        assert!(IBF::assemble(vec![a.clone(), corrupt]).is_err());
+
+        let mut other = IBF::new(101);
+        other.encode(1u64);
+        assert!(IBF::assemble(vec![a.clone(), other.slice(50..101).unwrap()]).is_err());
+
+        // A shape corrupted after slicing fails the checksum before anything is allocated
+        let mut huge = a.clone();
+        huge.params = SketchParams::new(usize::MAX, 3);
+        assert_eq!(
+            IBF::assemble(vec![huge, b.clone()]),
+            Err(IronRoseError::SegmentChecksum { start: 0 })
+        );
+        // As does a start that would overflow
+        let mut overflowing = b.clone();
+        overflowing.start = usize::MAX;
+        assert!(IBF::assemble(vec![a.clone(), overflowing]).is_err());
+        assert_eq!(
+            IBF::assemble(vec![b]),
+            Err(IronRoseError::IncompleteSegments { cell: 0 })
+        );
+        assert_eq!(
+            IBF::assemble(vec![a]),
+            Err(IronRoseError::IncompleteSegments { cell: 50 })
+        );
+    }
+
+    #[test]
+    fn serialization_is_canonical() {
+        let mut ibf = IBF::new_with_hash_count(2, 1);
+        ibf.encode(0u64);
+        let cell = serde_json::to_string(&ibf.cells[ibf.cell_index(0, &0)]).unwrap();
+        let empty = r#"{"id_sum":0,"hash_sum":0,"count":0}"#;
+        let cells = if ibf.cell_index(0, &0) == 0 {
+            format!("{},{}", cell, empty)
+        } else {
+            format!("{},{}", empty, cell)
+        };
+        assert_eq!(
+            serde_json::to_string(&ibf).unwrap(),
+            format!(r#"{{"cells":[{}],"hash_count":1,"size":2}}"#, cells)
+        );
+
+        let (mut forwards, mut backwards) = (IBF::new(50), IBF::new(50));
+        (0..100u64).for_each(|x| forwards.encode(x));
+        (0..100u64).rev().for_each(|x| backwards.encode(x));
+        assert_eq!(
+            serde_json::to_string(&forwards).unwrap(),
+            serde_json::to_string(&backwards).unwrap()
+        );
+    }
+
+    #[test]
+    fn checked_subtraction_names_the_mismatch() {
+        use std::collections::hash_map::RandomState;
+
+        let params = SketchParams::new(20, 3);
+        let (left, right) = (
+            IBF::<u64, _>::with_hasher(params, RandomState::new()),
+            IBF::<u64, _>::with_hasher(params, RandomState::new()),
+        );
+        // Plain subtraction can't tell the hashers apart
+        assert!((&left - &right).is_ok());
+        assert!(matches!(
+            left.subtract_checked(&right),
+            Err(IronRoseError::HasherMismatch { .. })
+        ));
+
+        let tagged: IBF<u64> = IBF::with_params(params).with_keyspace("orders");
+        let untagged: IBF<u64> = IBF::with_params(params);
+        assert_eq!(
+            tagged.compatible_with(&untagged.ibf_params()),
+            Err(IronRoseError::KeyspaceMismatch {
+                left: Some("orders".to_string()),
+                right: None
+            })
+        );
+        assert_eq!(
+            untagged.ibf_params().hasher_id(),
+            IBF::<u128>::new(5).ibf_params().hasher_id()
+        );
+    }
+
+    #[test]
+    fn keyspace_survives_the_wire() {
+        let mut ibf = IBF::new(20).with_keyspace("orders");
+        ibf.encode(1u64);
+        let json = serde_json::to_string(&ibf).unwrap();
+        let received: IBF<u64> = serde_json::from_str(&json).unwrap();
+        assert_eq!(received.keyspace(), Some("orders"));
+        assert_eq!((&ibf - &received).unwrap().keyspace(), Some("orders"));
+        assert!((received - IBF::new(20)).is_err());
+
+        // The budget counts the keyspace
+        let bytes = ibf.to_bytes();
+        assert_eq!(ibf.to_bytes_within(bytes.len()).unwrap(), bytes);
+        assert!(ibf.to_bytes_within(bytes.len() - 1).is_err());
+
+        let long = "k".repeat(MAX_KEYSPACE_BYTES + 1);
+        let json = json.replace("orders", &long);
+        assert!(serde_json::from_str::<IBF<u64>>(&json).is_err());
+        let longest = IBF::<u64>::new(20).with_keyspace(&long[1..]);
+        assert_eq!(IBF::from_bytes(&longest.to_bytes()).unwrap(), longest);
+
+        let segments = vec![
+            ibf.slice(0..10).unwrap(),
+            IBF::new(20).slice(10..20).unwrap(),
+        ];
+        assert!(IBF::assemble(segments).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn keyspaces_over_the_wire_limit_are_rejected() {
+        let _ = IBF::<u64>::new(20).with_keyspace(&"k".repeat(MAX_KEYSPACE_BYTES + 1));
+    }
+
+    #[test]
+    fn cell_patch_rejects_other_shapes() {
+        let mut primary = IBF::new(100);
+        primary.encode(1u64);
+        let mut replica = IBF::new(100);
+        let diff = (primary.fingerprint_sketch(10) - replica.fingerprint_sketch(10))
+            .unwrap()
+            .decode()
+            .unwrap();
+        let patch = primary.cell_patch(&diff);
+        assert_eq!(patch.indices().len(), 3);
+        assert!(IBF::<u64>::new(99).apply_cell_patch(&patch).is_err());
+        let mut orders = IBF::<u64>::new(100).with_keyspace("orders");
+        assert_eq!(
+            orders.apply_cell_patch(&patch),
+            Err(IronRoseError::KeyspaceMismatch {
+                left: Some("orders".to_string()),
+                right: None
+            })
+        );
+        assert!(orders.is_empty());
+        replica.apply_cell_patch(&patch).unwrap();
+        assert_eq!(replica.non_empty, 3);
+
+        let users = primary.with_keyspace("users");
+        let patch = users.cell_patch(&diff);
+        assert!(orders.apply_cell_patch(&patch).is_err());
+        assert!(IBF::new(100)
+            .with_keyspace("users")
+            .apply_cell_patch(&patch)
+            .is_ok());
+    }
+
+    #[test]
+    fn subtraction_identities() {
+        let (mut a, mut b, mut c) = (IBF::new(30), IBF::new(30), IBF::new(30));
+        for i in 0..10u64 {
+            a.encode(i);
+            b.encode(i * 2);
+            c.encode(i * 3);
+        }
+        let zero = IBF::new(30);
+        assert!(zero.is_zero());
+        assert!((&a - &a).unwrap().is_zero());
+        assert_eq!((&a - &zero).unwrap(), a);
+        assert_eq!(-(&a - &b).unwrap(), (&b - &a).unwrap());
+        assert_eq!(-(-a.clone()), a);
+        // (a - b) - (c - b) == a - c
+        let ab = (&a - &b).unwrap();
+        let cb = (&c - &b).unwrap();
+        assert_eq!((ab - cb).unwrap(), (&a - &c).unwrap());
+    }
+
+    #[test]
+    fn merged_shards_match_the_whole() {
+        let mut whole = IBF::new(30);
+        let mut shards = [IBF::new(30), IBF::new(30), IBF::new(30)];
+        for i in 0..300u64 {
+            whole.encode(i);
+            shards[(i % 3) as usize].encode(i);
+        }
+        let merged = (&shards[0] + &shards[1]).unwrap();
+        assert_eq!((merged + shards[2].clone()).unwrap(), whole);
+        assert_eq!(
+            (&shards[2] + (shards[0].clone() + &shards[1]).unwrap()).unwrap(),
+            whole
+        );
+
+        let mut sum = IBF::new(30);
+        sum.merge(&whole).unwrap();
+        sum.merge(&whole.clone().negate()).unwrap();
+        assert!(sum.is_empty());
+
+        let tagged = IBF::new(30).with_keyspace("orders");
+        assert!(matches!(
+            &whole + &tagged,
+            Err(IronRoseError::KeyspaceMismatch { .. })
+        ));
+        assert!(matches!(
+            whole + IBF::new(31),
+            Err(IronRoseError::ShapeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn mixed_ownership_subtraction() {
+        let (mut a, mut b) = (IBF::new(30), IBF::new(30));
+        for i in 0..10u64 {
+            a.encode(i);
+            b.encode(i * 2);
+        }
+        let expected = (&a - &b).unwrap();
+        assert_eq!((a.clone() - &b).unwrap(), expected);
+        assert_eq!((&a - b.clone()).unwrap(), expected);
+        assert_eq!((&a - a.clone()).unwrap().non_empty, 0);
+        assert!((a - &IBF::new(31)).is_err());
+    }
+
+    #[test]
+    fn unrolled_encode_matches_dynamic() {
+        let (mut fixed, mut dynamic) = (
+            IBF::new_with_hash_count(64, 4),
+            IBF::new_with_hash_count(64, 4),
+        );
+        for i in 0..40u64 {
+            fixed.encode_k::<4>(i);
+            dynamic.encode(i);
+        }
+        assert_eq!(fixed, dynamic);
+        assert_eq!(fixed.decode().unwrap().len(), 40);
+    }
+
+    #[test]
+    fn low_hash_counts_decode() {
+        for &hash_count in [1, 2].iter() {
+            let (mut left, mut right) = (
+                IBF::new_with_hash_count(400, hash_count),
+                IBF::new_with_hash_count(400, hash_count),
+            );
+            for i in 0..1000u64 {
+                left.encode(i);
+                right.encode(i + 3);
+            }
+            let diff = (left - right).unwrap().decode_difference().unwrap();
+            assert_eq!(diff.len(), 6, "hash_count {}", hash_count);
+        }
+
+        // Both hash functions pick the only cell, so the element is encoded once
+        let mut ibf = IBF::new_with_hash_count(1, 2);
+        ibf.encode(7u64);
+        assert_eq!(ibf.load_histogram()[&1], 1);
+        assert_eq!(ibf.clone().decode().unwrap().len(), 1);
+        ibf.encode(8);
+        assert!(ibf.decode().is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn unrolled_encode_checks_hash_count() {
+        IBF::new(10).encode_k::<4>(1u64);
+    }
+
+    #[test]
+    fn occupancy_estimate_tracks_cardinality() {
+        let mut ibf = IBF::new(2000);
+        assert_eq!(ibf.occupancy_estimate(), 0.0);
+        for x in 0..500u64 {
+            ibf.encode(x);
+        }
+        let estimate = ibf.occupancy_estimate();
+        assert!((estimate - 500.0).abs() < 50.0, "{}", estimate);
+        let mut full = IBF::new(5);
+        (0..100u64).for_each(|x| full.encode(x));
+        assert_eq!(full.occupancy_estimate(), f64::INFINITY);
+    }
+
+    #[test]
+    fn stuck_core_of_a_single_cycle() {
+        let mut ibf = IBF::new(100);
+        (0..10u64).for_each(|x| ibf.encode(x));
+        // Two elements that hash to the same cells form a cycle peeling can't break
+        let cycle = (100u64..)
+            .flat_map(|a| (a + 1..a + 2000).map(move |b| (a, b)))
+            .find(|(a, b)| {
+                let cells = |x| {
+                    let mut cells = ibf.cell_indexes(&x);
+                    cells.sort_unstable();
+                    cells
+                };
+                cells(*a) == cells(*b)
+            })
+            .unwrap();
+        ibf.encode(cycle.0);
+        ibf.encode(cycle.1);
+        let core = ibf.stuck_core().unwrap();
+        assert!(core.cells <= 3);
+        assert_eq!(core.min_elements, 2);
+    }
+
+    #[test]
+    fn apply_difference_rolls_forward() {
+        let (mut left, mut right) = (IBF::new(60), IBF::new(60));
+        for i in 0..100u64 {
+            left.encode(i);
+            right.encode(i + 4);
+        }
+        let diff = (&left - &right).unwrap().decode_difference().unwrap();
+        let mut rolled = left.clone();
+        rolled.apply_difference(&diff, ApplyDirection::ToRight);
+        assert_eq!(rolled, right);
+        assert!((&rolled - &right).unwrap().is_zero());
+        let mut rolled = right.clone();
+        rolled.apply_difference(&diff, ApplyDirection::ToLeft);
+        assert_eq!(rolled, left);
+    }
+
+    #[test]
+    fn strategies_match_dedicated_methods() {
+        let (mut left, mut right) = (IBF::new(60), IBF::new(60));
+        for i in 0..100u64 {
+            left.encode(i);
+            right.encode(i + 5);
+        }
+        let diff = (left - right).unwrap();
+        let (set, diagnostics) = diff
+            .clone()
+            .decode_with_strategy(DecodeStrategy::Peel)
+            .unwrap();
+        assert_eq!(set, diff.decode_cloned().unwrap());
+        assert_eq!(diagnostics.recovered, 10);
+        assert!(!diagnostics.limit_reached);
+
+        let (set, diagnostics) = diff
+            .clone()
+            .decode_with_strategy(DecodeStrategy::PeelUpTo { limit: 10 })
+            .unwrap();
+        assert_eq!(set.len(), 10);
+        assert!(!diagnostics.limit_reached);
+
+        let (set, _) = diff
+            .decode_with_strategy(DecodeStrategy::PeelBalanced { limit: None })
+            .unwrap();
+        assert_eq!(set.len(), 10);
+    }
+
+    #[test]
+    fn balanced_peeling_alternates_sides() {
+        // Far more differences on the left, a scan in cell order mostly finds those first
+        let (mut left, mut right) = (IBF::new(400), IBF::new(400));
+        (0..150u64).for_each(|x| left.encode(x));
+        (1000..1030u64).for_each(|x| right.encode(x));
+        let diff = (left - right).unwrap();
+        let (set, diagnostics) = diff
+            .clone()
+            .decode_with_strategy(DecodeStrategy::PeelBalanced { limit: Some(40) })
+            .unwrap();
+        assert!(diagnostics.limit_reached);
+        let rights = set
+            .iter()
+            .filter(|side| matches!(side, Side::Right(_)))
+            .count();
+        assert!((18..=22).contains(&rights), "{} of 40 on the right", rights);
+
+        let (set, _) = diff
+            .decode_with_strategy(DecodeStrategy::PeelBalanced { limit: None })
+            .unwrap();
+        assert_eq!(set.len(), 180);
+
+        // Stuck decodes recover the same elements whatever the order, but the first of them
+        // are balanced
+        let (mut left, mut right) = (IBF::new(140), IBF::new(140));
+        (0..90u64).for_each(|x| left.encode(x));
+        (1000..1030u64).for_each(|x| right.encode(x));
+        let diff = (left - right).unwrap();
+        let first_rights = |strategy| {
+            let err = diff.clone().decode_with_strategy(strategy).unwrap_err();
+            let mut decoded = err.decoded().to_vec();
+            let first = decoded[..20]
+                .iter()
+                .filter(|side| matches!(side, Side::Right(_)))
+                .count();
+            decoded.sort();
+            (first, decoded)
+        };
+        let (balanced, balanced_set) = first_rights(DecodeStrategy::PeelBalanced { limit: None });
+        let (plain, plain_set) = first_rights(DecodeStrategy::Peel);
+        assert_eq!(balanced_set, plain_set);
+        assert!(balanced > plain, "{} against {}", balanced, plain);
+    }
+
+    #[test]
+    fn failed_decodes_keep_what_was_recovered() {
+        let (mut left, mut right) = (IBF::new(30), IBF::new(30));
+        (0..40u64).for_each(|x| left.encode(x));
+        (1000..1040u64).for_each(|x| right.encode(x));
+        let diff = (left - right).unwrap();
+        let err = diff.clone().decode().unwrap_err();
+        assert!(err.remaining_cells() > 0);
+        assert!(err.decoded().len() < 80);
+        assert_eq!(
+            diff.clone().decode_difference().unwrap_err().into_decoded(),
+            err.clone().into_decoded()
+        );
+        assert_eq!(
+            IronRoseError::from(err.clone()),
+            IronRoseError::DecodeFailed {
+                remaining_cells: err.remaining_cells()
+            }
+        );
+    }
+
+    #[test]
+    fn residual_holds_what_was_not_recovered() {
+        let (mut left, mut right) = (IBF::new(40), IBF::new(40));
+        (0..24u64).for_each(|x| left.encode(x));
+        (100..106u64).for_each(|x| right.encode(x));
+        let diff = (&left - &right).unwrap();
+        let (recovered, residual) = diff.clone().decode_partial();
+        let mut residual = residual.expect("too many differences for 40 cells");
+        assert!(!recovered.is_empty());
+
+        // Putting the recovered elements back gives the original difference
+        for side in recovered {
+            match side {
+                Side::Left(x) => residual.encode(x),
+                Side::Right(x) => residual.remove(x),
+            }
+        }
+        assert_eq!(residual, diff);
+        assert_eq!(
+            (&left - &left).unwrap().decode_partial(),
+            (HashSet::new(), None)
+        );
+    }
+
+    #[test]
+    fn removals_track_a_changing_set() {
+        let (mut live, mut fresh) = (IBF::new(50), IBF::new(50));
+        (0..100u64).for_each(|x| live.encode(x));
+        (0..100u64).step_by(2).for_each(|x| live.remove(x));
+        (1..100u64).step_by(2).for_each(|x| fresh.encode(x));
+        assert_eq!(live, fresh);
+        assert_eq!(live.occupied_cells(), fresh.occupied_cells());
+    }
+
+    #[test]
+    fn wire_format_is_checked() {
+        let mut ibf = IBF::new(10).with_keyspace("orders");
+        (0..5u128).for_each(|x| ibf.encode(x));
+        let bytes = ibf.to_bytes();
+        assert_eq!(IBF::<u128>::from_bytes(&bytes).unwrap(), ibf);
+
+        let mut newer = bytes.clone();
+        newer[0] = crate::WIRE_VERSION + 1;
+        assert_eq!(
+            IBF::<u128>::from_bytes(&newer),
+            Err(IronRoseError::UnsupportedVersion {
+                version: crate::WIRE_VERSION + 1
+            })
+        );
+        assert!(IBF::<u128>::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert!(IBF::<u128>::from_bytes(&longer).is_err());
+        assert_eq!(
+            IBF::<u128>::from_bytes(&wire::resealed(&longer)),
+            Err(IronRoseError::MalformedBytes {
+                reason: "trailing bytes"
+            })
+        );
+
+        // Corruption in transfer fails the checksum before anything is parsed
+        let mut flipped = bytes.clone();
+        flipped[20] ^= 1;
+        assert_eq!(
+            IBF::<u128>::from_bytes(&flipped),
+            Err(IronRoseError::ChecksumMismatch)
+        );
+        assert_eq!(
+            IBF::<u128>::from_bytes(&bytes[..bytes.len() - 4]),
+            Err(IronRoseError::ChecksumMismatch)
+        );
+
+        // The previous version has no checksum, and is still read
+        let old = ibf.to_bytes_as(crate::MIN_WIRE_VERSION).unwrap();
+        assert_eq!(&old[4..], &bytes[4..bytes.len() - 4]);
+        assert_eq!(IBF::<u128>::from_bytes(&old).unwrap(), ibf);
+
+        // A huge claimed size is rejected before anything is allocated for it
+        let mut huge = IBF::<u128>::new(0).to_bytes();
+        huge[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(IBF::<u128>::from_bytes(&wire::resealed(&huge)).is_err());
+
+        // As is a hash count that would place elements in more cells than there are
+        let mut spread = bytes.clone();
+        spread[4..8].copy_from_slice(&11u32.to_le_bytes());
+        assert_eq!(
+            IBF::<u128>::from_bytes(&wire::resealed(&spread)),
+            Err(IronRoseError::HashCountOutOfRange {
+                hash_count: 11,
+                size: 10
+            })
+        );
+    }
+
+    #[test]
+    fn count_widths_decode_and_are_checked_on_the_wire() {
+        let params = SketchParams::new(60, 3);
+        let (mut left, mut right): (IBF<u64, DefaultBuildHasher, i16>, IBF<u64, _, i16>) =
+            (IBF::with_count_width(params), IBF::with_count_width(params));
+        (0..100u64).for_each(|x| left.encode(x));
+        (10..110u64).for_each(|x| right.encode(x));
+        let diff = (&left - &right).unwrap().decode().unwrap();
+        assert_eq!(diff.len(), 20);
+
+        let bytes = left.to_bytes();
+        assert_eq!(bytes.len(), 12 + 60 * (8 + 8 + 2) + 4);
+        assert_eq!(IBF::from_bytes(&bytes), Ok(left.clone()));
+        let wide = left.clone().into_count_width::<i64>().unwrap();
+        assert_eq!(wide.to_bytes().len(), 12 + 60 * (8 + 8 + 8) + 4);
+        assert_eq!(IBF::from_bytes(&wide.to_bytes()), Ok(wide.clone()));
+        let mismatched = IronRoseError::MalformedBytes {
+            reason: "cell count width doesn't match the count type",
+        };
+        assert_eq!(IBF::<u64>::from_bytes(&bytes).unwrap_err(), mismatched);
+        assert_eq!(
+            IBF::<u64, _, i64>::from_bytes(&bytes).unwrap_err(),
+            mismatched
+        );
+        let mut unknown = bytes.clone();
+        unknown[3] |= wire::FLAGS_COUNT_WIDTH;
+        assert_eq!(
+            IBF::<u64, _, i16>::from_bytes(&wire::resealed(&unknown)),
+            Err(IronRoseError::MalformedBytes {
+                reason: "unknown cell count width",
+            })
+        );
+        assert_eq!(
+            left.to_bytes_within(bytes.len()),
+            Ok(bytes.clone()),
+            "the budget check counts 2 byte counts"
+        );
+
+        // Counts that don't fit a narrower width aren't truncated
+        let mut full = IBF::new_with_hash_count(1, 1);
+        (0..=i16::MAX as u64).for_each(|x| full.encode(x));
+        assert_eq!(
+            full.clone().into_count_width::<i16>(),
+            Err(IronRoseError::CountOverflow { cell: 0 })
+        );
+        full.remove(0);
+        let mut narrowed = full.into_count_width::<i16>().unwrap();
+        assert_eq!(narrowed.cells[0].count(), i16::MAX);
+        assert_eq!(
+            narrowed.try_encode(0),
+            Err(IronRoseError::CountOverflow { cell: 0 })
+        );
+        assert_eq!(narrowed.cells[0].count(), i16::MAX);
+    }
+
+    #[test]
+    fn stored_cells_behave_like_boxed_ones() {
+        let params = SketchParams::new(40, 3);
+        let stored = |range: Range<u64>| {
+            let storage = vec![Cell::from_parts(9u64, 9, 9); 40];
+            let mut ibf =
+                IBF::with_storage(params, DefaultBuildHasher::default(), storage).unwrap();
+            range.for_each(|x| ibf.encode(x));
+            ibf
+        };
+        let boxed = |range: Range<u64>| {
+            let mut ibf = IBF::with_params(params);
+            range.for_each(|x| ibf.encode(x));
+            ibf
+        };
+        let (left, right) = (stored(0..30), stored(10..40));
+        assert_eq!(left.to_bytes(), boxed(0..30).to_bytes());
+        let json = serde_json::to_string(&left).unwrap();
+        assert_eq!(json, serde_json::to_string(&boxed(0..30)).unwrap());
+        let back: IBF<u64, DefaultBuildHasher, i32, Vec<Cell<u64>>> =
+            serde_json::from_str(&json).unwrap();
+        assert_eq!(back, left);
+
+        let diff: IBF<u64, _, i32, Vec<Cell<u64>>> = (&left - &right).unwrap();
+        assert_eq!(
+            diff.to_bytes(),
+            (boxed(0..30) - boxed(10..40)).unwrap().to_bytes()
+        );
+        assert_eq!((left - right).unwrap(), diff);
+        let (cells, _) = diff.clone().into_raw_parts();
+        assert_eq!(diff.decode().unwrap().len(), 20);
+        assert_eq!(cells.len(), 40);
+    }
+
+    #[test]
+    fn raw_parts_round_trip() {
+        let mut ibf = IBF::new(30);
+        (0..10u64).for_each(|x| ibf.encode(x));
+        let (cells, params) = ibf.clone().into_raw_parts();
+        // Stored column by column elsewhere, then put back together
+        let (ids, hashes, counts): (Vec<_>, Vec<_>, Vec<_>) = cells
+            .iter()
+            .map(|cell| (*cell.id_sum(), cell.hash_sum(), cell.count()))
+            .fold(
+                Default::default(),
+                |(mut i, mut h, mut c), (id, hash, count)| {
+                    i.push(id);
+                    h.push(hash);
+                    c.push(count);
+                    (i, h, c)
+                },
+            );
+        let cells = (0..params.size())
+            .map(|idx| Cell::from_parts(ids[idx], hashes[idx], counts[idx]))
+            .collect();
+        let back = IBF::from_raw_parts(cells, params).unwrap();
+        assert_eq!(back.occupied_cells(), ibf.occupied_cells());
+        assert_eq!(back.decode().unwrap().len(), 10);
+    }
+
+    #[test]
+    fn is_empty_after_peeling() {
+        let mut left = IBF::new(20);
+        let right = IBF::new(20);
+        left.encode(5u64);
+        let mut diff = (left - right).unwrap();
+        assert!(!diff.is_empty());
+        let pure = *diff.cells.iter().find(|c| c.is_pure(&diff.hasher)).unwrap();
+        diff.peel_cell(pure, &mut Vec::new());
+        assert!(diff.is_empty());
+    }
+
+    #[test]
+    fn custom_hashers_place_and_check_cells() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let params = SketchParams::new(40, 3);
+        let mut default = IBF::with_params(params);
+        default.encode(7u64);
+        assert!(default
+            .cells
+            .iter()
+            .any(|c| c.hash_sum() == element_hash(&7u64)));
+
+        let hasher = BuildHasherDefault::<DefaultHasher>::default();
+        let (mut left, mut right) = (
+            IBF::with_hasher(params, hasher.clone()),
+            IBF::with_hasher(params, hasher),
+        );
+        (0..20u64).for_each(|x| left.encode(x));
+        (5..25u64).for_each(|x| right.encode(x));
+        let mut metro = IBF::with_params(params);
+        (0..20u64).for_each(|x| metro.encode(x));
+        assert_ne!(left.cells, metro.cells);
+
+        let set = (left - right).unwrap().decode().unwrap();
+        let expected = (0..5).map(Side::Left).chain((20..25).map(Side::Right));
+        assert_eq!(set, expected.collect());
+    }
+
+    #[test]
+    fn crafted_cells_are_rejected() {
+        // 10 is placed in cells 4 and 7. Alone in cell 4 it looks pure, but peeling it leaves
+        // its negation pure in cell 7, and peeling that puts it back in cell 4.
+        let mut cells = vec![Cell::default(); 10];
+        cells[4] = Cell::from_parts(10u64, element_hash(&10u64), 1);
+        let forged =
+            IBF::from_raw_parts(cells.into_boxed_slice(), SketchParams::new(10, 3)).unwrap();
+        assert!(forged.clone().decode().is_err());
+        assert!(forged.clone().decode_difference().is_err());
+        assert_eq!(
+            forged.clone().into_decoder().filter(Result::is_ok).count(),
+            10
+        );
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(60);
+        assert!(forged.decode_before(deadline).is_err());
+
+        let mut cells = vec![Cell::default(); 10];
+        cells[3] = Cell::from_parts(1u64, 0, i32::MAX);
+        let huge = IBF::from_raw_parts(cells.into_boxed_slice(), SketchParams::new(10, 3)).unwrap();
+        let mut one = IBF::new(10);
+        let x = (0..).find(|x| one.cell_indexes(x).contains(&3)).unwrap();
+        one.encode(x);
+        let overflow = IronRoseError::CountOverflow { cell: 3 };
+        assert_eq!((&huge - &one.clone().negate()).unwrap_err(), overflow);
+        assert_eq!(huge.clone().merge(&one).unwrap_err(), overflow);
+        assert!((&huge - &one).is_ok());
+        // Encoding saturates instead
+        let mut saturated = huge.clone();
+        (0..100u64).for_each(|x| saturated.encode(x));
+        assert!(saturated.cells.iter().any(|c| c.count() == i32::MAX));
+        assert_eq!(huge.clone().negate().cells[3].count(), -i32::MAX);
+        // and a saturated cell is left unbalanced, so the difference fails to decode rather
+        // than recovering a wrong element
+        let mut unbalanced = huge.clone();
+        unbalanced.encode(x);
+        unbalanced.remove(x);
+        assert_eq!(unbalanced.cells[3].count(), i32::MAX - 1);
+        let err = (&unbalanced - &huge).unwrap().decode().unwrap_err();
+        assert!(err.decoded().is_empty());
+        assert_eq!(err.remaining_cells(), 1);
+    }
+
+    #[test]
+    fn seeded_hashers_key_placement() {
+        use crate::SeededHasher;
+
+        let params = SketchParams::new(40, 3);
+        let (alice, mallory) = (SeededHasher::new([1; 16]), SeededHasher::new([2; 16]));
+        let mut left = IBF::with_hasher(params, alice);
+        let mut right = IBF::with_hasher(params, alice);
+        (0..20u64).for_each(|x| left.encode(x));
+        (5..25u64).for_each(|x| right.encode(x));
+        let mut other = IBF::with_hasher(params, mallory);
+        (0..20u64).for_each(|x| other.encode(x));
+        assert_ne!(left.cells, other.cells);
+
+        let right = IBF::<u64>::from_bytes(&right.to_bytes())
+            .unwrap()
+            .into_hasher(alice);
+        let set = (left - right).unwrap().decode().unwrap();
+        let expected = (0..5).map(Side::Left).chain((20..25).map(Side::Right));
+        assert_eq!(set, expected.collect());
+    }
+
+    #[test]
+    fn maybe_contains_false_positives_match_estimate() {
+        let mut ibf = IBF::new(1000);
+        (0..300u64).for_each(|x| ibf.encode(x));
+        ibf.encode(500);
+        ibf.remove(500);
+        assert!((0..300u64).all(|x| ibf.maybe_contains(&x)));
+
+        // λ = 0.9
+        let crowded = (1.0 - (-0.9f64).exp() - 0.9 * (-0.9f64).exp()).powi(3);
+        let trials = 20_000;
+        let false_positives = (1u64 << 40..(1 << 40) + trials)
+            .filter(|x| ibf.maybe_contains(x))
+            .count() as f64
+            / trials as f64;
+        assert!(
+            (false_positives - crowded).abs() < 0.005,
+            "{} vs {}",
+            false_positives,
+            crowded
+        );
+        assert!(!IBF::<u64>::new(10).maybe_contains(&1));
+    }
+
+    #[cfg(feature = "gpu")]
+    #[test]
+    fn gpu_matches_cpu() {
+        let gpu = match crate::GpuContext::new() {
+            Ok(gpu) => gpu,
+            // Nothing to compare against on machines without a GPU
+            Err(_) => return,
+        };
+        let (mut left, mut right) = (IBF::new(1000), IBF::new(1000));
+        for i in 0..5000u64 {
+            left.encode(i);
+            right.encode(i + 100);
+        }
+        let gpu_diff = left.gpu_sub(&right, &gpu).unwrap();
+        let cpu_diff = (&left - &right).unwrap();
+        assert_eq!(gpu_diff.clone().into_ibf(), cpu_diff);
+        assert_eq!(gpu_diff.decode().unwrap(), cpu_diff.decode().unwrap());
+
+        let narrow = IBF::<u16>::new(10);
+        assert!(narrow.gpu_sub(&narrow, &gpu).is_err());
+
+        // Counts that would overflow fail like on the CPU
+        let mut cells = vec![Cell::default(); 10];
+        cells[3] = Cell::from_parts(1u64, 0, i32::MAX);
+        let huge = IBF::from_raw_parts(cells.into_boxed_slice(), SketchParams::new(10, 3)).unwrap();
+        let mut one = IBF::new(10);
+        let x = (0..).find(|x| one.cell_indexes(x).contains(&3)).unwrap();
+        one.encode(x);
+        let one = one.negate();
+        assert_eq!(
+            huge.gpu_sub(&one, &gpu).unwrap_err(),
+            (&huge - &one).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn colliding_hash_functions_use_a_cell_once() {
+        let mut ibf = IBF::new(10);
+        // 10 is placed in cells 4, 7 and 7, and 25 in cell 4 three times over
+        assert_eq!(
+            (0..3)
+                .map(|i| ibf.cell_index(i, &10u64))
+                .collect::<Vec<_>>(),
+            vec![4, 7, 7]
+        );
+        assert_eq!(
+            (0..3)
+                .map(|i| ibf.cell_index(i, &25u64))
+                .collect::<Vec<_>>(),
+            vec![4, 4, 4]
+        );
+
+        ibf.encode(10u64);
+        assert!(ibf.cells[7].is_pure(&ibf.hasher));
+        assert_eq!(ibf.occupied_cells(), 2);
+        let mut fixed = IBF::new(10);
+        fixed.encode_k::<3>(10u64);
+        assert_eq!(fixed, ibf);
+
+        let mut lone = IBF::new(10);
+        lone.encode(25u64);
+        assert_eq!(lone.occupied_cells(), 1);
+        assert_eq!(
+            lone.clone()
+                .decode()
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![Side::Left(25)]
+        );
+        lone.remove(25);
+        assert!(lone.is_empty());
+
+        let mut right = IBF::new(10);
+        right.encode(25u64);
+        let set = (ibf - right).unwrap().decode().unwrap();
+        assert_eq!(
+            set,
+            [Side::Left(10), Side::Right(25)].iter().copied().collect()
+        );
+    }
+
+    #[test]
+    fn validation_sets_suspects_aside() {
+        let mut ibf = IBF::new(60);
+        (0..10u64).for_each(|x| ibf.encode(x));
+        ibf.remove(1_000);
+        let decoded = ibf.clone().decode_validated(|x| x % 2 == 0).unwrap();
+        assert_eq!(decoded.difference.len(), 6);
+        assert_eq!(decoded.suspected.len(), 5);
+        assert!(!decoded.is_clean());
+        assert!(decoded.suspected.contains(&Side::Left(3)));
+        assert!(ibf.clone().decode_validated(|_| true).unwrap().is_clean());
+
+        // Suspects count among the elements recovered before a decode got stuck
+        let mut stuck = IBF::new(3);
+        (0..30u64).for_each(|x| stuck.encode(x));
+        let expected = stuck
+            .clone()
+            .decode_difference()
+            .unwrap_err()
+            .decoded()
+            .len();
+        let err = stuck.decode_validated(|_| false).unwrap_err();
+        assert_eq!(err.decoded().len(), expected);
+    }
 }
@@ -0,0 +1,149 @@
+use crate::WireElement;
+use alloc::vec::Vec;
+use core::ops::{BitXor, BitXorAssign};
+use serde::{Deserialize, Serialize};
+
+/// Start of the Twitter snowflake epoch, in Unix milliseconds
+pub const TWITTER_EPOCH_MILLIS: u64 = 1_288_834_974_657;
+
+/// Start of the KSUID epoch, in Unix seconds
+pub const KSUID_EPOCH_SECONDS: u64 = 1_400_000_000;
+
+/// Unix milliseconds at which a snowflake style id (41 bit timestamp above 22 bits of worker and
+/// sequence) was minted, given the epoch of its generator such as
+/// [TWITTER_EPOCH_MILLIS](TWITTER_EPOCH_MILLIS). Snowflakes are plain `i64`s and can be encoded
+/// as they are, this is for picking strata by [Recency](crate::Recency).
+/// ```rust
+/// use iron_rose::{snowflake_millis, TWITTER_EPOCH_MILLIS};
+///
+/// let id = 1_541_815_603_606_036_480;
+/// assert_eq!(snowflake_millis(id, TWITTER_EPOCH_MILLIS), 1_656_432_460_105);
+/// ```
+pub fn snowflake_millis(id: i64, epoch_millis: u64) -> u64 {
+    ((id as u64) >> 22) + epoch_millis
+}
+
+/// A [KSUID](https://github.com/segmentio/ksuid) as an IBF element: its 20 bytes as they are
+/// written, a big endian 32 bit timestamp followed by 16 bytes of payload. Order, hashing and the
+/// [wire format](crate::IBF::to_bytes) all use those bytes unchanged, so every peer agrees on
+/// them whatever its platform.
+/// ```rust
+/// use iron_rose::{Ksuid, Side, IBF};
+///
+/// let (a, b) = (Ksuid::from_bytes([1; 20]), Ksuid::from_bytes([2; 20]));
+/// let (mut left, mut right) = (IBF::new(10), IBF::new(10));
+/// left.encode(a);
+/// right.encode(b);
+/// let diff = (left - right).unwrap().decode().unwrap();
+/// assert!(diff.contains(&Side::Left(a)));
+/// assert_eq!(a.timestamp(), 0x01010101);
+/// ```
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Ksuid([u8; 20]);
+
+impl Ksuid {
+    /// The KSUID with these 20 bytes
+    pub const fn from_bytes(bytes: [u8; 20]) -> Self {
+        Self(bytes)
+    }
+
+    /// The KSUID's 20 bytes
+    pub const fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    /// Seconds since [KSUID_EPOCH_SECONDS](KSUID_EPOCH_SECONDS) at which it was minted
+    pub fn timestamp(&self) -> u32 {
+        u32::from_be_bytes([self.0[0], self.0[1], self.0[2], self.0[3]])
+    }
+
+    /// Unix seconds at which it was minted, for picking strata by [Recency](crate::Recency)
+    pub fn unix_seconds(&self) -> u64 {
+        self.timestamp() as u64 + KSUID_EPOCH_SECONDS
+    }
+}
+
+impl BitXor for Ksuid {
+    type Output = Ksuid;
+
+    fn bitxor(mut self, rhs: Self) -> Self::Output {
+        self ^= rhs;
+        self
+    }
+}
+
+impl BitXorAssign for Ksuid {
+    fn bitxor_assign(&mut self, rhs: Self) {
+        for (l, r) in self.0.iter_mut().zip(rhs.0.iter()) {
+            *l ^= r;
+        }
+    }
+}
+
+impl WireElement for Ksuid {
+    const WIDTH: usize = 20;
+
+    fn write_le(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.0);
+    }
+
+    fn read_le(bytes: &[u8]) -> Self {
+        let mut id = [0; 20];
+        id.copy_from_slice(bytes);
+        Self(id)
+    }
+}
+
+/// A ULID as an IBF element: the u128 whose big endian bytes are the ULID's 16 bytes, so the
+/// 48 bit millisecond timestamp is in the top bits. This is the ulid crate's own conversion.
+/// ```rust
+/// use iron_rose::{element_ulid, ulid_element, ulid_millis};
+/// use ulid::Ulid;
+///
+/// let id = Ulid::from_parts(1_656_432_467_577, 42);
+/// let element = ulid_element(id);
+/// assert_eq!(ulid_millis(&element), 1_656_432_467_577);
+/// assert_eq!(element_ulid(element), id);
+/// ```
+#[cfg(feature = "ulid")]
+pub fn ulid_element(id: ulid::Ulid) -> u128 {
+    id.into()
+}
+
+/// The ULID a decoded element stands for, undoing [ulid_element](ulid_element)
+#[cfg(feature = "ulid")]
+pub fn element_ulid(element: u128) -> ulid::Ulid {
+    element.into()
+}
+
+/// Unix milliseconds at which the ULID behind element was minted, for picking strata by
+/// [Recency](crate::Recency)
+#[cfg(feature = "ulid")]
+pub fn ulid_millis(element: &u128) -> u64 {
+    (element >> 80) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Side, IBF};
+
+    #[test]
+    fn ksuids_round_trip() {
+        let mut bytes = [0u8; 20];
+        bytes[..4].copy_from_slice(&107_608_047u32.to_be_bytes());
+        bytes[19] = 7;
+        let id = Ksuid::from_bytes(bytes);
+        assert_eq!(id.unix_seconds(), 1_507_608_047);
+
+        let mut ibf = IBF::new(10);
+        ibf.encode(id);
+        let back = IBF::<Ksuid>::from_bytes(&ibf.to_bytes()).unwrap();
+        assert_eq!(
+            back.decode().unwrap().into_iter().next(),
+            Some(Side::Left(id))
+        );
+    }
+}
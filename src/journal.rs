@@ -0,0 +1,189 @@
+use crate::Difference;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fmt::Debug,
+    ops::{BitXor, BitXorAssign},
+    time::SystemTime,
+};
+
+/// What a single reconciliation round changed in the local set
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    epoch: u64,
+    recorded_at: SystemTime,
+    added: Vec<T>,
+    removed: Vec<T>,
+}
+
+impl<T> JournalEntry<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// Epoch (round number) the entry was recorded for
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// When the entry was recorded
+    pub fn recorded_at(&self) -> SystemTime {
+        self.recorded_at
+    }
+
+    /// Elements the round added to the local set
+    pub fn added(&self) -> &[T] {
+        &self.added
+    }
+
+    /// Elements the round removed from the local set
+    pub fn removed(&self) -> &[T] {
+        &self.removed
+    }
+}
+
+/// Audit log of the changes reconciliation rounds applied to a local set, so operators can see
+/// exactly which ids a round added or removed, and undo a bad round.
+/// ```rust
+/// use iron_rose::{SyncJournal, IBF};
+/// use std::collections::HashSet;
+///
+/// let mut local_set = (0..10u64).collect::<HashSet<_>>();
+/// let (mut local, mut remote) = (IBF::new(20), IBF::new(20));
+/// local_set.iter().for_each(|x| local.encode(*x));
+/// (0..11u64).for_each(|x| remote.encode(x));
+///
+/// let mut journal = SyncJournal::new();
+/// let diff = (local - remote).unwrap().decode_difference().unwrap();
+/// journal.record_difference(1, &diff);
+/// journal.replay_into(&mut local_set);
+/// assert!(local_set.contains(&10));
+///
+/// journal.rollback_last(&mut local_set);
+/// assert!(!local_set.contains(&10));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncJournal<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    entries: Vec<JournalEntry<T>>,
+}
+
+impl<T> SyncJournal<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// Empty journal
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Records a round that added and removed the given elements from the local set
+    pub fn record(&mut self, epoch: u64, added: Vec<T>, removed: Vec<T>) {
+        self.entries.push(JournalEntry {
+            epoch,
+            recorded_at: SystemTime::now(),
+            added,
+            removed,
+        });
+    }
+
+    /// Records a round that applied diff to the local set, where the local IBF was the "Left"
+    /// side of the subtraction. Elements only on the "Right" side are the ones the round pulled
+    /// in, elements only on the "Left" side are left alone as they belong to the remote's half
+    /// of the exchange.
+    pub fn record_difference(&mut self, epoch: u64, diff: &Difference<T>) {
+        self.record(epoch, diff.right().to_vec(), Vec::new());
+    }
+
+    /// All recorded entries, oldest first
+    pub fn entries(&self) -> &[JournalEntry<T>] {
+        &self.entries
+    }
+
+    /// Applies every entry, oldest first, to set
+    pub fn replay_into(&self, set: &mut HashSet<T>) {
+        for entry in self.entries.iter() {
+            set.extend(entry.added.iter().cloned());
+            for x in entry.removed.iter() {
+                set.remove(x);
+            }
+        }
+    }
+
+    /// Undoes the newest entry on set, and removes it from the journal
+    pub fn rollback_last(&mut self, set: &mut HashSet<T>) -> Option<JournalEntry<T>> {
+        let entry = self.entries.pop()?;
+        for x in entry.added.iter() {
+            set.remove(x);
+        }
+        set.extend(entry.removed.iter().cloned());
+        Some(entry)
+    }
+
+    /// Undoes, newest first, every entry recorded after epoch, returning them
+    pub fn rollback_to(&mut self, epoch: u64, set: &mut HashSet<T>) -> Vec<JournalEntry<T>> {
+        let mut undone = Vec::new();
+        while self.entries.last().is_some_and(|e| e.epoch > epoch) {
+            undone.extend(self.rollback_last(set));
+        }
+        undone
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rollback_to_epoch() {
+        let mut set = HashSet::new();
+        let mut journal = SyncJournal::new();
+        journal.record(1, vec![1u64, 2], vec![]);
+        journal.record(2, vec![3], vec![1]);
+        journal.record(3, vec![4], vec![]);
+        journal.replay_into(&mut set);
+        assert_eq!(set, vec![2, 3, 4].into_iter().collect());
+
+        let undone = journal.rollback_to(1, &mut set);
+        assert_eq!(
+            undone.iter().map(|e| e.epoch()).collect::<Vec<_>>(),
+            vec![3, 2]
+        );
+        assert_eq!(set, vec![1, 2].into_iter().collect());
+        assert_eq!(journal.entries().len(), 1);
+    }
+}
@@ -0,0 +1,297 @@
+use crate::{IronRoseError, Namespaced, Side, IBF};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    ops::{BitXor, BitXorAssign, Sub},
+};
+
+/// A difference found by a [KeyedIBF](KeyedIBF)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum KeyedSide<K> {
+    /// The key was on the "Left" side and missing in the "Right" side
+    Left(K),
+    /// The key was on the "Right" side and missing in the "Left" side
+    Right(K),
+    /// The key is on both sides, with different values
+    Changed(K),
+}
+
+impl<K> KeyedSide<K> {
+    /// The key that differs
+    pub fn key(&self) -> &K {
+        match self {
+            KeyedSide::Left(key) | KeyedSide::Right(key) | KeyedSide::Changed(key) => key,
+        }
+    }
+}
+
+/// A difference decoded by [decode_values](KeyedIBF::decode_values), with the value encoded
+/// for the key on each side it was found on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyedDifference<K, V> {
+    /// The key and which side it differs on
+    pub side: KeyedSide<K>,
+    /// The value on the "Left" side, if the key is there
+    pub left: Option<V>,
+    /// The value on the "Right" side, if the key is there
+    pub right: Option<V>,
+}
+
+impl<K, V: Ord> KeyedDifference<K, V> {
+    /// The larger of the two values, so a [Changed](KeyedSide::Changed) key ranks by its highest
+    /// priority or newest version
+    pub fn priority(&self) -> Option<&V> {
+        self.left.as_ref().max(self.right.as_ref())
+    }
+}
+
+/// A sketch of key value entries, such as rows and a digest of their payload, that tells keys
+/// missing from one side apart from keys whose value changed. Each entry is encoded as a
+/// [Namespaced](Namespaced) pair of key and value digest, so a changed entry decodes as the old
+/// pair on one side and the new pair on the other, which are then matched up by key.
+///
+/// A changed entry takes up two differences, so size the IBF for twice the expected number of
+/// changed rows.
+///
+/// The value can also be a small priority or version number instead of a digest, such as a
+/// `u32`, so the differences can be fetched most important first with
+/// [decode_by_priority](KeyedIBF::decode_by_priority).
+/// ```rust
+/// use iron_rose::{KeyedIBF, KeyedSide};
+///
+/// let (mut left, mut right) = (KeyedIBF::new(40), KeyedIBF::new(40));
+/// left.encode(1u64, 0xaau64);
+/// left.encode(2, 0xbb);
+/// right.encode(2, 0xcc);
+/// right.encode(3, 0xdd);
+/// let diff = (left - right).unwrap().decode().unwrap();
+/// assert!(diff.contains(&KeyedSide::Left(1)));
+/// assert!(diff.contains(&KeyedSide::Changed(2)));
+/// assert!(diff.contains(&KeyedSide::Right(3)));
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyedIBF<K, V>
+where
+    K: Clone
+        + std::hash::Hash
+        + BitXor<Output = K>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    V: Clone
+        + std::hash::Hash
+        + BitXor<Output = V>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    ibf: IBF<Namespaced<K, V>>,
+}
+
+impl<K, V> KeyedIBF<K, V>
+where
+    K: Clone
+        + std::hash::Hash
+        + BitXor<Output = K>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    V: Clone
+        + std::hash::Hash
+        + BitXor<Output = V>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// New keyed IBF with size buckets and the default hash_count of 3
+    pub fn new(size: usize) -> Self {
+        Self::new_with_hash_count(size, 3)
+    }
+
+    /// New keyed IBF with size buckets and a settable hash_count
+    pub fn new_with_hash_count(size: usize, hash_count: usize) -> Self {
+        Self {
+            ibf: IBF::new_with_hash_count(size, hash_count),
+        }
+    }
+
+    /// Encodes the entry for key, with a digest of its value
+    pub fn encode(&mut self, key: K, value_digest: V) {
+        self.ibf.encode(Namespaced {
+            namespace: key,
+            id: value_digest,
+        })
+    }
+
+    /// Undoes an [encode](KeyedIBF::encode) of the same key and value digest, such as before
+    /// encoding the key again with its new value
+    pub fn remove(&mut self, key: K, value_digest: V) {
+        self.ibf.remove(Namespaced {
+            namespace: key,
+            id: value_digest,
+        })
+    }
+
+    /// Decodes the differences by key. A key that decodes on both sides is
+    /// [Changed](KeyedSide::Changed).
+    pub fn decode(self) -> Result<HashSet<KeyedSide<K>>, IronRoseError> {
+        Ok(self
+            .decode_values()?
+            .into_iter()
+            .map(|difference| difference.side)
+            .collect())
+    }
+
+    /// Decodes the differences by key like [decode](KeyedIBF::decode), along with the value
+    /// each side encoded for the key
+    pub fn decode_values(self) -> Result<Vec<KeyedDifference<K, V>>, IronRoseError> {
+        let mut values: HashMap<K, (Option<V>, Option<V>)> = HashMap::new();
+        for side in self.ibf.decode()? {
+            match side {
+                Side::Left(entry) => values.entry(entry.namespace).or_default().0 = Some(entry.id),
+                Side::Right(entry) => values.entry(entry.namespace).or_default().1 = Some(entry.id),
+            }
+        }
+        Ok(values
+            .into_iter()
+            .map(|(key, (left, right))| {
+                let side = match (&left, &right) {
+                    (Some(_), Some(_)) => KeyedSide::Changed(key),
+                    (Some(_), None) => KeyedSide::Left(key),
+                    _ => KeyedSide::Right(key),
+                };
+                KeyedDifference { side, left, right }
+            })
+            .collect())
+    }
+
+    /// Decodes the differences like [decode_values](KeyedIBF::decode_values), highest
+    /// [priority](KeyedDifference::priority) first, for when the value is a priority or version
+    /// rather than a digest. A sync engine short on bandwidth can then fetch the entries that
+    /// matter most, or the most recent versions, and leave the rest for a later round. Entries of
+    /// equal priority come in no particular order.
+    /// ```rust
+    /// use iron_rose::{KeyedIBF, KeyedSide};
+    ///
+    /// let (mut local, mut remote) = (KeyedIBF::new(40), KeyedIBF::new(40));
+    /// local.encode(1u64, 5u32);
+    /// remote.encode(2, 9);
+    /// remote.encode(3, 1);
+    /// // Key 4 was bumped from version 2 to 7 remotely
+    /// local.encode(4, 2);
+    /// remote.encode(4, 7);
+    /// let ranked = (local - remote).unwrap().decode_by_priority().unwrap();
+    /// let keys: Vec<_> = ranked.iter().map(|difference| difference.side).collect();
+    /// assert_eq!(
+    ///     keys,
+    ///     [
+    ///         KeyedSide::Right(2),
+    ///         KeyedSide::Changed(4),
+    ///         KeyedSide::Left(1),
+    ///         KeyedSide::Right(3)
+    ///     ]
+    /// );
+    /// assert_eq!(ranked[1].right, Some(7));
+    /// ```
+    pub fn decode_by_priority(self) -> Result<Vec<KeyedDifference<K, V>>, IronRoseError>
+    where
+        V: Ord,
+    {
+        let mut differences = self.decode_values()?;
+        differences.sort_by(|a, b| b.priority().cmp(&a.priority()));
+        Ok(differences)
+    }
+
+    /// The underlying IBF of key and value digest pairs
+    pub fn inner(&self) -> &IBF<Namespaced<K, V>> {
+        &self.ibf
+    }
+}
+
+impl<K, V> Sub for KeyedIBF<K, V>
+where
+    K: Clone
+        + std::hash::Hash
+        + BitXor<Output = K>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    V: Clone
+        + std::hash::Hash
+        + BitXor<Output = V>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    type Output = Result<KeyedIBF<K, V>, IronRoseError>;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Ok(Self {
+            ibf: (self.ibf - rhs.ibf)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updates_decode_as_changed() {
+        let (mut left, mut right) = (KeyedIBF::new(60), KeyedIBF::new(60));
+        for key in 0..100u64 {
+            left.encode(key, key * 7);
+            right.encode(key, key * 7);
+        }
+        right.remove(10, 70);
+        right.encode(10, 71);
+        right.remove(20, 140);
+        left.encode(200, 1);
+
+        let diff = (left - right).unwrap().decode().unwrap();
+        let expected = [
+            KeyedSide::Changed(10),
+            KeyedSide::Left(20),
+            KeyedSide::Left(200),
+        ];
+        assert_eq!(diff, expected.iter().copied().collect());
+    }
+
+    #[test]
+    fn priorities_rank_differences() {
+        let (mut left, mut right) = (KeyedIBF::new(80), KeyedIBF::new(80));
+        for key in 0..200u64 {
+            left.encode(key, 0u8);
+            right.encode(key, 0u8);
+        }
+        for key in 0..10u64 {
+            right.remove(key, 0);
+            right.encode(key, key as u8 + 1);
+        }
+        left.encode(500, 3);
+
+        let ranked = (left - right).unwrap().decode_by_priority().unwrap();
+        assert_eq!(ranked.len(), 11);
+        assert_eq!(ranked[0].side, KeyedSide::Changed(9));
+        assert_eq!(ranked[0].left, Some(0));
+        assert_eq!(ranked[0].right, Some(10));
+        let priorities: Vec<_> = ranked.iter().map(|d| *d.priority().unwrap()).collect();
+        assert!(priorities.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert!(ranked
+            .iter()
+            .any(|d| d.side == KeyedSide::Left(500) && d.right.is_none()));
+    }
+}
@@ -0,0 +1,359 @@
+//! Soft-deprecated compatibility with v0 sketches: the element placement and serde formats of
+//! this release, frozen so that fleets can keep interoperating while their nodes move to newer
+//! hashing, placement or formats one at a time. Nothing here changes when the defaults do, and
+//! it will be removed once v0 is no longer in use.
+//!
+//! - [V0Ibf](V0Ibf) is an IBF that places elements and checks purity with the v0 hasher,
+//!   whatever [DefaultBuildHasher](crate::DefaultBuildHasher) becomes.
+//! - [v0_placement](v0_placement) is the v0 placement on its own, to check a port against.
+//! - [ibf_serde](ibf_serde) and [strata_serde](strata_serde) read and write the v0 serde
+//!   formats, for use with `#[serde(with = "...")]`.
+//! - [convert](convert) moves cells between IBFs with different hashers, which is only possible
+//!   while they place elements the same way. Otherwise the sketch has to be rebuilt from its
+//!   elements.
+//!
+//! ```rust
+//! use iron_rose::legacy::{self, V0Ibf};
+//! use iron_rose::{SketchParams, IBF};
+//!
+//! let mut old = V0Ibf::with_hasher(SketchParams::new(20, 3), Default::default());
+//! old.encode(7u64);
+//! let new: IBF<u64> = legacy::convert(old.clone()).unwrap();
+//! assert_eq!(new.ibf_params().hasher_id(), old.ibf_params().hasher_id());
+//! ```
+use crate::ibf::{check_hash_count, check_keyspace, hasher_id};
+use crate::{Cell, IronRoseError, MetroHasher, SketchParams, StrataEstimator, IBF};
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::{
+    fmt::Debug,
+    hash::{BuildHasher, BuildHasherDefault, Hash, Hasher},
+    ops::{BitXor, BitXorAssign},
+};
+use serde::{Deserialize, Serialize};
+
+/// The v0 hasher: MetroHash with the default seed, as shipped
+pub type V0BuildHasher = BuildHasherDefault<MetroHasher>;
+
+/// An IBF whose placement and purity checks are pinned to v0
+pub type V0Ibf<T> = IBF<T, V0BuildHasher>;
+
+/// The cells v0 places element in, one per hash function, with a cell picked by more than one
+/// of them used once: cell i is the v0 hash of the element followed by i as a usize, modulo the
+/// size.
+/// ```rust
+/// use iron_rose::legacy::v0_placement;
+/// use iron_rose::SketchParams;
+///
+/// let cells = v0_placement(&7u64, SketchParams::new(20, 3));
+/// assert!(!cells.is_empty() && cells.len() <= 3);
+/// assert!(cells.iter().all(|&cell| cell < 20));
+/// ```
+pub fn v0_placement<T: Hash + ?Sized>(element: &T, params: SketchParams) -> Vec<usize> {
+    let mut cells = Vec::with_capacity(params.hash_count());
+    for i in 0..params.hash_count() {
+        let mut hasher = MetroHasher::default();
+        element.hash(&mut hasher);
+        i.hash(&mut hasher);
+        let cell = (hasher.finish() % params.size() as u64) as usize;
+        if !cells.contains(&cell) {
+            cells.push(cell);
+        }
+    }
+    cells
+}
+
+/// Moves an IBF's cells into one with another hasher, keeping its shape and keyspace. Fails
+/// with [HasherMismatch](IronRoseError::HasherMismatch) unless both hashers place elements the
+/// same way, as cells can't be moved between placements: rebuild the sketch from its elements
+/// instead.
+pub fn convert<T, H, H2>(ibf: IBF<T, H>) -> Result<IBF<T, H2>, IronRoseError>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    H: BuildHasher + Clone,
+    H2: BuildHasher + Clone + Default,
+{
+    let hasher = H2::default();
+    let (left, right) = (hasher_id(ibf.hasher()), hasher_id(&hasher));
+    if left != right {
+        return Err(IronRoseError::HasherMismatch { left, right });
+    }
+    let keyspace = ibf.keyspace().map(Into::into);
+    let (cells, params) = ibf.into_raw_parts();
+    Ok(IBF::from_cells(
+        cells,
+        params.hash_count(),
+        params.size(),
+        keyspace,
+        hasher,
+    ))
+}
+
+/// The v0 serde format of a cell
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Cell")]
+struct V0Cell<T> {
+    id_sum: T,
+    hash_sum: u64,
+    count: i32,
+}
+
+/// The v0 serde format of an IBF
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "IBF")]
+struct V0Sketch<T> {
+    cells: Vec<V0Cell<T>>,
+    hash_count: usize,
+    size: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    keyspace: Option<String>,
+}
+
+impl<T> V0Sketch<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    fn of<H: BuildHasher + Clone>(ibf: &IBF<T, H>) -> Self {
+        Self {
+            cells: ibf
+                .cells()
+                .iter()
+                .map(|cell| V0Cell {
+                    id_sum: cell.id_sum().clone(),
+                    hash_sum: cell.hash_sum(),
+                    count: cell.count(),
+                })
+                .collect(),
+            hash_count: ibf.params().hash_count(),
+            size: ibf.params().size(),
+            keyspace: ibf.keyspace().map(Into::into),
+        }
+    }
+
+    fn into_ibf<H: BuildHasher + Clone + Default>(self) -> Result<IBF<T, H>, IronRoseError> {
+        if self.cells.len() != self.size {
+            return Err(IronRoseError::CellCountMismatch {
+                size: self.size,
+                cells: self.cells.len(),
+            });
+        }
+        check_hash_count(self.hash_count, self.size)?;
+        if let Some(keyspace) = self.keyspace.as_deref() {
+            check_keyspace(keyspace)?;
+        }
+        let cells = self
+            .cells
+            .into_iter()
+            .map(|cell| Cell::from_parts(cell.id_sum, cell.hash_sum, cell.count))
+            .collect::<Box<[_]>>();
+        Ok(IBF::from_cells(
+            cells,
+            self.hash_count,
+            self.size,
+            self.keyspace.map(Into::into),
+            H::default(),
+        ))
+    }
+}
+
+/// Reads and writes an [IBF](IBF) in the v0 serde format, for fields marked
+/// `#[serde(with = "iron_rose::legacy::ibf_serde")]`
+/// ```rust
+/// use iron_rose::legacy::V0Ibf;
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct SyncRequest {
+///     #[serde(with = "iron_rose::legacy::ibf_serde")]
+///     sketch: V0Ibf<u64>,
+/// }
+///
+/// let mut sketch = V0Ibf::with_hasher(iron_rose::SketchParams::new(10, 3), Default::default());
+/// sketch.encode(1);
+/// let json = serde_json::to_string(&SyncRequest { sketch: sketch.clone() }).unwrap();
+/// let back: SyncRequest = serde_json::from_str(&json).unwrap();
+/// assert_eq!(back.sketch, sketch);
+/// ```
+pub mod ibf_serde {
+    use super::V0Sketch;
+    use crate::IBF;
+    use core::{
+        fmt::Debug,
+        hash::BuildHasher,
+        ops::{BitXor, BitXorAssign},
+    };
+    use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+    /// Writes ibf in the v0 format
+    pub fn serialize<T, H, S>(ibf: &IBF<T, H>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Clone
+            + core::hash::Hash
+            + BitXor<Output = T>
+            + BitXorAssign
+            + Default
+            + PartialEq
+            + Eq
+            + Debug
+            + Serialize,
+        H: BuildHasher + Clone,
+        S: Serializer,
+    {
+        V0Sketch::of(ibf).serialize(serializer)
+    }
+
+    /// Reads an IBF in the v0 format, failing if its cells don't match its size
+    pub fn deserialize<'de, T, H, D>(deserializer: D) -> Result<IBF<T, H>, D::Error>
+    where
+        T: Clone
+            + core::hash::Hash
+            + BitXor<Output = T>
+            + BitXorAssign
+            + Default
+            + PartialEq
+            + Eq
+            + Debug
+            + Deserialize<'de>,
+        H: BuildHasher + Clone + Default,
+        D: Deserializer<'de>,
+    {
+        V0Sketch::deserialize(deserializer)?
+            .into_ibf()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// The v0 serde format of a strata estimator
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "StrataEstimator")]
+struct V0Strata {
+    ibfs: Vec<V0Sketch<u64>>,
+}
+
+/// Reads and writes a [StrataEstimator](StrataEstimator) in the v0 serde format, for fields
+/// marked `#[serde(with = "iron_rose::legacy::strata_serde")]`. Duplicate detection is off in
+/// estimators read back.
+pub mod strata_serde {
+    use super::V0Strata;
+    use crate::StrataEstimator;
+    use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+    /// Writes estimator in the v0 format
+    pub fn serialize<S: Serializer>(
+        estimator: &StrataEstimator,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        V0Strata::of(estimator).serialize(serializer)
+    }
+
+    /// Reads an estimator in the v0 format, failing if any stratum's cells don't match its size
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<StrataEstimator, D::Error> {
+        V0Strata::deserialize(deserializer)?
+            .into_estimator()
+            .map_err(D::Error::custom)
+    }
+}
+
+impl V0Strata {
+    fn of(estimator: &StrataEstimator) -> Self {
+        Self {
+            ibfs: estimator.strata_ibfs().map(V0Sketch::of).collect(),
+        }
+    }
+
+    fn into_estimator(self) -> Result<StrataEstimator, IronRoseError> {
+        let ibfs = self
+            .ibfs
+            .into_iter()
+            .map(V0Sketch::into_ibf)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(StrataEstimator::from_strata(ibfs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DefaultBuildHasher, Side};
+
+    #[derive(Serialize, Deserialize)]
+    struct Message {
+        #[serde(with = "ibf_serde")]
+        sketch: V0Ibf<u64>,
+        #[serde(with = "strata_serde")]
+        estimator: StrataEstimator,
+    }
+
+    #[test]
+    fn v0_is_pinned() {
+        let params = SketchParams::new(13, 4);
+        let sketch = V0Ibf::with_hasher(params, V0BuildHasher::default());
+        for element in 0..500u64 {
+            assert_eq!(
+                v0_placement(&element, params),
+                sketch.cell_indexes(&element)
+            );
+        }
+        // Placements as shipped, on 64 bit little endian platforms
+        let shipped = SketchParams::new(1000, 3);
+        assert_eq!(v0_placement(&0u64, shipped), vec![350, 607, 165]);
+        assert_eq!(v0_placement(&1u128, shipped), vec![536, 506, 425]);
+        assert_eq!(v0_placement("alice", shipped), vec![537, 662, 303]);
+
+        let params = SketchParams::new(40, 4);
+        let mut sketch = V0Ibf::with_hasher(params, V0BuildHasher::default());
+        (0..5u64).for_each(|element| sketch.encode(element));
+
+        let mut estimator = StrataEstimator::default();
+        (0..100).for_each(|i| estimator.encode(i));
+        let message = Message {
+            sketch: sketch.clone().with_keyspace("orders"),
+            estimator: estimator.clone(),
+        };
+        let json = serde_json::to_string(&message).unwrap();
+        // Today's formats are v0
+        let today = format!(
+            r#"{{"sketch":{},"estimator":{}}}"#,
+            serde_json::to_string(&message.sketch).unwrap(),
+            serde_json::to_string(&estimator).unwrap()
+        );
+        assert_eq!(json, today);
+        let back: Message = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.sketch, message.sketch);
+        assert_eq!(back.estimator.estimate(&estimator).unwrap().value(), 0);
+
+        let current: IBF<u64, DefaultBuildHasher> = convert(sketch.clone()).unwrap();
+        let mut other = IBF::with_params(params);
+        other.encode(1000u64);
+        let diff = (current - other.clone()).unwrap();
+        assert_eq!(diff.decode().unwrap().len(), 6);
+        let foreign = IBF::<u64, std::collections::hash_map::RandomState>::with_hasher(
+            params,
+            Default::default(),
+        );
+        assert!(matches!(
+            convert::<_, _, V0BuildHasher>(foreign),
+            Err(IronRoseError::HasherMismatch { .. })
+        ));
+        assert!((&convert::<_, _, V0BuildHasher>(other).unwrap() - &sketch)
+            .unwrap()
+            .decode()
+            .unwrap()
+            .contains(&Side::Left(1000)));
+    }
+}
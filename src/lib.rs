@@ -25,11 +25,11 @@
 //! #    remote_estimator.encode(*x);
 //! # }
 //! // Retreive Remote Estimator in some way
-//! let ibf_size = estimator
+//! let expected = estimator
 //!     .estimate_differences(&remote_estimator)
 //!     .expect("estimators should be same shape");
-//! let mut local = IBF::new(ibf_size);
-//! # let mut remote = IBF::new(ibf_size);
+//! let mut local = IBF::for_expected_differences(expected);
+//! # let mut remote = IBF::for_expected_differences(expected);
 //! for x in ids_from_database.iter() {
 //!     local.encode(**x);
 //! }
@@ -41,7 +41,29 @@
 //! let differences = diff
 //!     .decode()
 //!     .expect("Successfully decoded because IBFs were large enough");
+//! ```
 //!
+//! ## Thread Safety
+//!
+//! [IBF](IBF), [Side](Side) and [StrataEstimator](StrataEstimator) are all `Send + Sync`
+//! whenever the element type is, so they can be moved between threads or shared behind an
+//! `Arc` (for example across tokio tasks).
+//!
+//! ## Untrusted input
+//!
+//! Reading, combining and decoding sketches received from a peer never panics, see
+//! [untrusted](untrusted) for exactly which functions that covers.
+//!
+//! ## no_std
+//!
+//! With `default-features = false` the crate is `no_std` and only needs `alloc`, for syncing
+//! from embedded devices. [IBF](IBF), [StrataEstimator](StrataEstimator), the
+//! [wire format](IBF::to_bytes) and serde support remain. As there is no `HashSet`, decode with
+//! [decode_into](IBF::decode_into) or [decode_difference](IBF::decode_difference), which return
+//! the same elements as a server's [decode](IBF::decode). Everything else, including sizing
+//! with floating point math, needs the `std` feature, which is on by default.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+#![forbid(unsafe_code)]
 #![warn(
     missing_docs,
     rust_2018_idioms,
@@ -50,16 +72,176 @@
 )]
 #![allow(clippy::type_complexity)]
 
+extern crate alloc;
+
+mod adaptive;
+#[cfg(feature = "std")]
+mod archive;
+#[cfg(feature = "std")]
+mod audit;
+#[cfg(feature = "roaring")]
+mod bitmap;
+#[cfg(feature = "std")]
+mod builder;
+mod capabilities;
 mod cell;
+mod count;
+mod dense;
+mod difference;
+#[cfg(feature = "std")]
+mod digest;
+mod error;
+#[cfg(feature = "std")]
+mod feed;
+#[cfg(feature = "fs")]
+mod fs;
+#[cfg(feature = "gpu")]
+mod gpu;
 mod ibf;
+mod ids;
+#[cfg(feature = "std")]
+mod journal;
+#[cfg(feature = "std")]
+mod keyed;
+#[cfg(feature = "legacy-v0")]
+pub mod legacy;
+mod metro;
+mod min_wise;
+#[cfg(feature = "std")]
+mod multiparty;
+#[cfg(feature = "std")]
+mod namespaced;
+mod params;
+#[cfg(feature = "std")]
+mod policy;
+pub mod profiles;
+#[cfg(feature = "std")]
+mod rateless;
+#[cfg(feature = "std")]
+mod rebuild;
+#[cfg(feature = "std")]
+pub mod reconcile;
+#[cfg(feature = "replay")]
+mod replay;
+mod sequenced;
+#[cfg(feature = "std")]
+mod set_digest;
+#[cfg(feature = "std")]
+mod shard;
+mod siphash;
+mod snapshot;
+#[cfg(feature = "std")]
+mod spill;
+mod storage;
 mod strata_estimator;
+#[cfg(feature = "std")]
+mod stream;
+#[cfg(feature = "testkit")]
+pub mod testkit;
+#[cfg(feature = "std")]
+pub mod theory;
+#[cfg(feature = "std")]
+mod tiered;
+#[cfg(feature = "std")]
+pub mod untrusted;
+#[cfg(feature = "std")]
+mod view;
+mod wire;
 
-pub use crate::cell::Side;
-pub use ibf::IBF;
-pub use strata_estimator::StrataEstimator;
+pub use crate::cell::{Cell, Side};
+pub use crate::count::Count;
+pub use adaptive::AdaptiveStrataEstimator;
+#[cfg(feature = "std")]
+pub use archive::{SketchArchive, SketchMetadata};
+#[cfg(feature = "std")]
+pub use audit::{audit_collisions, audit_collisions_with_hasher, CollisionAudit};
+#[cfg(feature = "std")]
+pub use builder::{IbfBuilder, DEFAULT_FAILURE_PROBABILITY};
+pub use capabilities::{capabilities, Capabilities};
+pub use dense::DenseIbf;
+#[cfg(feature = "std")]
+pub use difference::DifferenceIndex;
+pub use difference::{Difference, DifferenceIntoIter, DifferenceIter, SortedRuns};
+#[cfg(feature = "std")]
+pub use digest::{DigestDifference, DigestIBF, IbfElement};
+pub use error::{DecodeError, ErrorKind, IronRoseError};
+#[cfg(feature = "std")]
+pub use feed::{AppliedEvents, ApplyChange, ChangeEvent, FeedCursor};
+#[cfg(feature = "fs")]
+pub use fs::{ContentHash, DirectorySketch, FileDifference};
+#[cfg(feature = "gpu")]
+pub use gpu::GpuContext;
+#[cfg(feature = "gpu")]
+pub use ibf::GpuDifference;
+pub use ibf::{
+    element_hash, ApplyDirection, CellPatch, DecodeDiagnostics, DecodeStrategy, Decoder,
+    DefaultBuildHasher, IbfSegment, StuckCore, ValidatedDecode, IBF,
+};
+#[cfg(feature = "std")]
+pub use ibf::{BoundedDecode, TimedDecode};
+#[cfg(feature = "ulid")]
+pub use ids::{element_ulid, ulid_element, ulid_millis};
+pub use ids::{snowflake_millis, Ksuid, KSUID_EPOCH_SECONDS, TWITTER_EPOCH_MILLIS};
+#[cfg(feature = "std")]
+pub use journal::{JournalEntry, SyncJournal};
+#[cfg(feature = "std")]
+pub use keyed::{KeyedDifference, KeyedIBF, KeyedSide};
+pub use metro::MetroHasher;
+pub use min_wise::{HybridEstimator, MinWiseEstimator};
+#[cfg(feature = "std")]
+pub use multiparty::{Missing, MultiPartyDiff, PeerId};
+#[cfg(feature = "std")]
+pub use namespaced::{Namespaced, NamespacedIBF};
+pub use params::{IbfParams, ParamMismatch, SketchBytes, SketchKind, SketchParams};
+#[cfg(feature = "std")]
+pub use policy::{GrowthPolicy, RetryBudget};
+#[cfg(feature = "std")]
+pub use rateless::{RatelessDecoder, RatelessEncoder};
+#[cfg(feature = "std")]
+pub use rebuild::{RebuildReason, RebuildScheduler, RebuildThresholds};
+#[cfg(feature = "replay")]
+pub use replay::{PlacementMismatch, PlacementTrace};
+pub use sequenced::{SequenceWatermark, Sequenced};
+#[cfg(feature = "std")]
+pub use set_digest::{verify_reconciled, IncrementalSetHash, SetDigest, SetRoot};
+#[cfg(feature = "std")]
+pub use shard::{jump_consistent_hash, ShardTopology};
+pub use siphash::{SeededHasher, SipHasher};
+#[cfg(feature = "std")]
+pub use spill::SpillingEstimatorBuilder;
+pub use storage::CellStorage;
+#[cfg(feature = "std")]
+pub use strata_estimator::StratumLoad;
+pub use strata_estimator::{
+    Estimate, EstimateTrace, OverlappingEstimate, Recency, StrataEstimator, StrataEstimatorConfig,
+    StratumSelector, StratumTrace, TrailingZeros,
+};
+#[cfg(feature = "std")]
+pub use stream::read_message;
+#[cfg(feature = "std")]
+pub use tiered::TieredIBF;
+#[cfg(feature = "std")]
+pub use view::{ViewCache, ViewFilter};
+pub use wire::{MessageKind, WireElement, MIN_WIRE_VERSION, WIRE_VERSION};
 
 #[cfg(test)]
 mod tests {
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn public_types_are_send_sync() {
+        use super::{Side, StrataEstimator, IBF};
+
+        assert_send_sync::<IBF<u128>>();
+        assert_send_sync::<IBF<u64>>();
+        assert_send_sync::<Side<u128>>();
+        assert_send_sync::<super::Difference<u128>>();
+        assert_send_sync::<StrataEstimator>();
+        assert_send_sync::<super::SetDigest>();
+        assert_send_sync::<super::SetRoot>();
+        assert_send_sync::<super::IncrementalSetHash>();
+    }
+
     #[test]
     fn it_works() {
         use super::{Side, StrataEstimator, IBF};
@@ -86,8 +268,8 @@ mod tests {
         let ibf_size = estimator
             .estimate_differences(&remote_estimator)
             .expect("estimators should be same shape");
-        let mut local = IBF::new(ibf_size * 3);
-        let mut remote = IBF::new(ibf_size * 3);
+        let mut local = IBF::for_expected_differences(ibf_size);
+        let mut remote = IBF::for_expected_differences(ibf_size);
         for x in core.iter().chain(local_ids.iter()) {
             local.encode(*x);
         }
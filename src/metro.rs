@@ -0,0 +1,204 @@
+//! Pure Rust port of the MetroHash variant the crate has always hashed with,
+//! `metrohash64crc_1`, as built into fasthash. Hashes match it bit for bit, so sketches stay
+//! compatible with ones built by earlier releases, and no C toolchain is needed.
+use alloc::vec::Vec;
+use core::hash::Hasher;
+
+const K0: u64 = 0xC83A91E1;
+const K1: u64 = 0x8648DBDB;
+const K2: u64 = 0x7BDEC03B;
+const K3: u64 = 0x2F5870A5;
+
+/// Reflected CRC-32C (Castagnoli) polynomial, as used by the SSE 4.2 `crc32` instruction
+const CRC32C_POLY: u32 = 0x82F6_3B78;
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ CRC32C_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Same as `_mm_crc32_u64`: folds the 8 bytes of value into the low 32 bits of crc, without the
+/// usual pre and post inversion
+fn crc32_u64(crc: u64, value: u64) -> u64 {
+    let mut crc = crc as u32;
+    for byte in value.to_le_bytes().iter() {
+        crc = (crc >> 8) ^ CRC32C_TABLE[((crc ^ *byte as u32) & 0xff) as usize];
+    }
+    crc as u64
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(word)
+}
+
+/// MetroHash64 with CRC, variant 1, of bytes
+pub(crate) fn metrohash64crc_1(bytes: &[u8], seed: u32) -> u64 {
+    let mut rest = bytes;
+    let mut hash = ((seed as u64).wrapping_add(K2))
+        .wrapping_mul(K0)
+        .wrapping_add(bytes.len() as u64);
+
+    if rest.len() >= 32 {
+        let mut v = [hash; 4];
+        while rest.len() >= 32 {
+            for (i, lane) in v.iter_mut().enumerate() {
+                *lane ^= crc32_u64(*lane, read_u64(&rest[i * 8..]));
+            }
+            rest = &rest[32..];
+        }
+        v[2] ^= (v[0].wrapping_add(v[3]).wrapping_mul(K0).wrapping_add(v[1]))
+            .rotate_right(33)
+            .wrapping_mul(K1);
+        v[3] ^= (v[1].wrapping_add(v[2]).wrapping_mul(K1).wrapping_add(v[0]))
+            .rotate_right(33)
+            .wrapping_mul(K0);
+        v[0] ^= (v[0].wrapping_add(v[2]).wrapping_mul(K0).wrapping_add(v[3]))
+            .rotate_right(33)
+            .wrapping_mul(K1);
+        v[1] ^= (v[1].wrapping_add(v[3]).wrapping_mul(K1).wrapping_add(v[2]))
+            .rotate_right(33)
+            .wrapping_mul(K0);
+        hash = hash.wrapping_add(v[0] ^ v[1]);
+    }
+
+    if rest.len() >= 16 {
+        let mut v0 = hash.wrapping_add(read_u64(rest).wrapping_mul(K0));
+        v0 = v0.rotate_right(33).wrapping_mul(K1);
+        let mut v1 = hash.wrapping_add(read_u64(&rest[8..]).wrapping_mul(K1));
+        v1 = v1.rotate_right(33).wrapping_mul(K2);
+        v0 ^= v0.wrapping_mul(K0).rotate_right(35).wrapping_add(v1);
+        v1 ^= v1.wrapping_mul(K3).rotate_right(35).wrapping_add(v0);
+        hash = hash.wrapping_add(v1);
+        rest = &rest[16..];
+    }
+
+    if rest.len() >= 8 {
+        hash = hash.wrapping_add(read_u64(rest).wrapping_mul(K3));
+        hash ^= hash.rotate_right(33).wrapping_mul(K1);
+        rest = &rest[8..];
+    }
+
+    if rest.len() >= 4 {
+        let word = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]);
+        hash ^= crc32_u64(hash, word as u64);
+        hash ^= hash.rotate_right(15).wrapping_mul(K1);
+        rest = &rest[4..];
+    }
+
+    if rest.len() >= 2 {
+        let word = u16::from_le_bytes([rest[0], rest[1]]);
+        hash ^= crc32_u64(hash, word as u64);
+        hash ^= hash.rotate_right(13).wrapping_mul(K1);
+        rest = &rest[2..];
+    }
+
+    if let Some(byte) = rest.first() {
+        hash ^= crc32_u64(hash, *byte as u64);
+        hash ^= hash.rotate_right(25).wrapping_mul(K1);
+    }
+
+    hash ^= hash.rotate_right(33);
+    hash = hash.wrapping_mul(K0);
+    hash ^= hash.rotate_right(33);
+    hash
+}
+
+/// [Hasher](Hasher) over [metrohash64crc_1](metrohash64crc_1) with a seed of 0. Like fasthash's,
+/// it buffers everything written and hashes it all at once in finish, which is what makes
+/// hashes independent of how the bytes were split across writes.
+#[derive(Debug, Clone, Default)]
+pub struct MetroHasher {
+    bytes: Vec<u8>,
+}
+
+impl MetroHasher {
+    /// Hash of everything written so far with the given seed, [finish](Hasher::finish) being
+    /// seed 0
+    #[cfg(feature = "std")]
+    pub(crate) fn finish_seeded(&self, seed: u32) -> u64 {
+        metrohash64crc_1(&self.bytes, seed)
+    }
+}
+
+impl Hasher for MetroHasher {
+    fn finish(&self) -> u64 {
+        metrohash64crc_1(&self.bytes, 0)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::hash::Hash;
+
+    /// Outputs of fasthash 0.4's MetroHasher, which this replaces
+    #[test]
+    fn matches_fasthash_vectors() {
+        let bytes: Vec<u8> = (0..200u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let by_len = [
+            (0, 0xb9b61f89292d08a3),
+            (1, 0xc151039ff4308687),
+            (2, 0xb60d8f71fa86e9d3),
+            (3, 0x15ccc6af1cfe749d),
+            (4, 0x5b7290c4e6be9067),
+            (5, 0x56ba32822cec05bd),
+            (7, 0x1f008334acc6155f),
+            (8, 0xb41ce67b1f23e884),
+            (9, 0xf1c7ef4210ff9a69),
+            (15, 0x8a1e2e9065fa3d1d),
+            (16, 0x69bc93c8917608e9),
+            (17, 0x30b3c8b1dadf741e),
+            (31, 0x0e46495291b8b20d),
+            (32, 0xc5006a33227177fa),
+            (33, 0xe76c7db7d34b2509),
+            (63, 0x9d3bbab3bb10d473),
+            (64, 0xd8302b37afb38a4f),
+            (100, 0x86711fd0a434e843),
+            (199, 0x5307ff4cebd7dbd8),
+        ];
+        for (len, expected) in by_len.iter() {
+            assert_eq!(
+                metrohash64crc_1(&bytes[..*len], 0),
+                *expected,
+                "len {}",
+                len
+            );
+        }
+
+        let elements = [
+            (0u128, 0x1ac8a1fff8526ce6),
+            (1, 0xb1c5113ef8b8b874),
+            (42, 0xa3896ea58a7522ee),
+            (u64::MAX as u128, 0x38682feeb1fb73b5),
+            (u128::MAX, 0x2fa5a3419ebfd5bb),
+        ];
+        for (element, expected) in elements.iter() {
+            let mut hasher = MetroHasher::default();
+            element.hash(&mut hasher);
+            assert_eq!(hasher.finish(), *expected, "element {}", element);
+        }
+    }
+}
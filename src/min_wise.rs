@@ -0,0 +1,263 @@
+use alloc::collections::BTreeSet;
+use core::hash::Hash;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    element_hash, profiles, Estimate, IronRoseError, StrataEstimator, StrataEstimatorConfig,
+};
+
+/// Min-wise estimator, a bottom-k sketch of a set: the `capacity` smallest
+/// [element hashes](crate::element_hash) seen, along with the number of elements. The
+/// smallest hashes of the union of two sets are a uniform sample of it, and the share of that
+/// sample found in both sketches estimates the sets' Jaccard similarity, and so their
+/// differences.
+///
+/// Unlike a [StrataEstimator](StrataEstimator) the error is relative to the size of the sets,
+/// so it's accurate for differences that are a large share of the sets and useless for a
+/// handful of differences between large sets. While both sets have at most capacity elements
+/// the sketches hold them whole, and the estimate is exact.
+/// ```rust
+/// use iron_rose::{Estimate, MinWiseEstimator};
+///
+/// let (mut mw1, mut mw2) = (MinWiseEstimator::new(256), MinWiseEstimator::new(256));
+/// for i in 0..100_000 {
+///     mw1.encode(i);
+///     mw2.encode(i + 50_000);
+/// }
+/// let estimate = mw1.estimate(&mw2).unwrap();
+/// assert!(!estimate.is_exact());
+/// assert!(estimate.value() > 70_000 && estimate.value() < 130_000);
+/// ```
+///
+/// Elements encoded more than once are counted more than once, which biases the estimate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MinWiseEstimator {
+    capacity: usize,
+    hashes: BTreeSet<u64>,
+    count: u64,
+}
+
+impl Default for MinWiseEstimator {
+    fn default() -> Self {
+        Self::new(profiles::MIN_WISE_HASHES)
+    }
+}
+
+impl MinWiseEstimator {
+    /// Returns an empty estimator keeping up to capacity hashes. Panics if capacity is 0.
+    pub fn new(capacity: usize) -> Self {
+        assert!(
+            capacity > 0,
+            "a min-wise estimator needs to keep at least one hash"
+        );
+        Self {
+            capacity,
+            hashes: BTreeSet::new(),
+            count: 0,
+        }
+    }
+
+    /// Number of hashes kept
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of elements encoded
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    /// True if no element was encoded
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Encodes an element into the estimator
+    pub fn encode<T: Hash>(&mut self, element: T) {
+        self.encode_prehashed(element_hash(&element))
+    }
+
+    /// Encodes an element by its [element_hash](crate::element_hash) alone
+    pub fn encode_prehashed(&mut self, hash: u64) {
+        self.count += 1;
+        self.hashes.insert(hash);
+        if self.hashes.len() > self.capacity {
+            let largest = *self.hashes.iter().next_back().expect("over capacity");
+            self.hashes.remove(&largest);
+        }
+    }
+
+    /// True if every element's hash was kept
+    fn is_complete(&self) -> bool {
+        self.count <= self.capacity as u64
+    }
+
+    /// Estimates the number of differences between the sets encoded into the two estimators,
+    /// failing unless both keep the same number of hashes
+    pub fn estimate(&self, other: &MinWiseEstimator) -> Result<Estimate, IronRoseError> {
+        if self.capacity != other.capacity {
+            return Err(IronRoseError::MinWiseSizeMismatch {
+                left: self.capacity,
+                right: other.capacity,
+            });
+        }
+        if self.is_complete() && other.is_complete() {
+            let differences = self.hashes.symmetric_difference(&other.hashes).count();
+            return Ok(Estimate::Exact(differences));
+        }
+
+        // The smallest hashes of the union, and how many of them both sets have
+        let mut sample = self.hashes.union(&other.hashes).take(self.capacity);
+        let (mut taken, mut shared) = (0usize, 0usize);
+        for hash in &mut sample {
+            taken += 1;
+            if self.hashes.contains(hash) && other.hashes.contains(hash) {
+                shared += 1;
+            }
+        }
+        let jaccard = shared as f64 / taken.max(1) as f64;
+        // |A ∆ B| = |A ∪ B| (1 - J) and |A ∪ B| = (|A| + |B|) / (1 + J)
+        let total = (self.count + other.count) as f64;
+        let differences = total * (1.0 - jaccard) / (1.0 + jaccard);
+        Ok(Estimate::Approximate((differences + 0.5) as usize))
+    }
+
+    /// Same as [StrataEstimator::estimate_differences](StrataEstimator::estimate_differences):
+    /// the estimate, doubled to size an IBF with
+    pub fn estimate_differences(&self, other: &MinWiseEstimator) -> Result<usize, IronRoseError> {
+        Ok(self.estimate(other)?.value() * 2)
+    }
+}
+
+/// Strata and min-wise estimators combined, as proposed alongside the strata estimator (Eppstein
+/// et al.), for tighter estimates with less data than a [StrataEstimator](StrataEstimator) of
+/// the same depth.
+///
+/// Elements with fewer trailing zeros in their hash than there are strata go into the strata
+/// as usual, the rest, a small sample of the set, go into a [MinWiseEstimator](MinWiseEstimator)
+/// in place of the deepest strata. Small differences decode from the strata exactly, with the
+/// differences in the sample counted by the min-wise estimator, exactly too while the sample
+/// fits in it. When a stratum fails, the count is extrapolated from the min-wise estimate and
+/// the strata above the failed one, which is less noisy than extrapolating from the few
+/// elements that deep strata decode.
+/// ```rust
+/// use iron_rose::{Estimate, HybridEstimator};
+///
+/// let (mut he1, mut he2) = (HybridEstimator::default(), HybridEstimator::default());
+/// for i in 0..1000 {
+///     he1.encode(i);
+///     he2.encode(i + 25);
+/// }
+/// assert_eq!(he1.estimate(&he2), Ok(Estimate::Exact(50)));
+/// assert_eq!(he1.estimate_differences(&he2), Ok(100));
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HybridEstimator {
+    strata: StrataEstimator,
+    min_wise: MinWiseEstimator,
+}
+
+impl Default for HybridEstimator {
+    fn default() -> Self {
+        Self::new(
+            StrataEstimatorConfig::new(profiles::HYBRID_STRATA, profiles::STRATUM),
+            profiles::MIN_WISE_HASHES,
+        )
+    }
+}
+
+impl HybridEstimator {
+    /// Returns an empty estimator with the given strata, and a min-wise estimator keeping up to
+    /// min_wise_hashes hashes for the elements that are too deep for them. Elements with at
+    /// least as many trailing zeros as there are strata, one in 2^strata, go to the min-wise
+    /// estimator, so it holds them all exactly for up to `min_wise_hashes * 2^strata`
+    /// elements.
+    pub fn new(strata: StrataEstimatorConfig, min_wise_hashes: usize) -> Self {
+        Self {
+            strata: StrataEstimator::with_config(strata),
+            min_wise: MinWiseEstimator::new(min_wise_hashes),
+        }
+    }
+
+    /// Encodes an element into the estimator
+    pub fn encode<T: Hash>(&mut self, element: T) {
+        let hash = element_hash(&element);
+        if hash.trailing_zeros() as usize >= self.strata.strata() {
+            self.min_wise.encode_prehashed(hash);
+        } else {
+            self.strata.encode_prehashed(hash);
+        }
+    }
+
+    /// Estimates the number of differences between the sets encoded into the two estimators,
+    /// failing unless both are the same shape
+    pub fn estimate(&self, other: &HybridEstimator) -> Result<Estimate, IronRoseError> {
+        let (strata, trace) = self.strata.estimate_traced(&other.strata)?;
+        let sampled = self.min_wise.estimate(&other.min_wise)?;
+        let decoded: usize = trace
+            .strata
+            .iter()
+            .filter_map(|stratum| stratum.recovered)
+            .sum();
+        let count = decoded.saturating_add(sampled.value());
+        match trace.extrapolated_at {
+            None if strata.is_exact() && sampled.is_exact() => Ok(Estimate::Exact(count)),
+            None => Ok(Estimate::Approximate(count)),
+            // Strata above the failed one and the sample hold 2^-(i+1) of the elements
+            Some(failed) => Ok(Estimate::Approximate(
+                count.saturating_mul(2_usize.saturating_pow(failed as u32 + 1)),
+            )),
+        }
+    }
+
+    /// Same as [StrataEstimator::estimate_differences](StrataEstimator::estimate_differences):
+    /// the estimate, doubled to size an IBF with
+    pub fn estimate_differences(&self, other: &HybridEstimator) -> Result<usize, IronRoseError> {
+        Ok(self.estimate(other)?.value() * 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_sets_are_exact() {
+        let (mut mw1, mut mw2) = (MinWiseEstimator::new(64), MinWiseEstimator::new(64));
+        for i in 0..60u64 {
+            mw1.encode(i);
+            mw2.encode(i + 10);
+        }
+        assert_eq!(mw1.estimate(&mw2), Ok(Estimate::Exact(20)));
+        assert_eq!(mw1.hashes.len(), 60);
+        (1000..1005u64).for_each(|i| mw1.encode(i));
+        assert_eq!(mw1.hashes.len(), 64);
+        assert!(!mw1.estimate(&mw2).unwrap().is_exact());
+        assert_eq!(
+            mw1.estimate(&MinWiseEstimator::new(32)),
+            Err(IronRoseError::MinWiseSizeMismatch {
+                left: 64,
+                right: 32
+            })
+        );
+    }
+
+    #[test]
+    fn hybrid_extrapolates_large_differences() {
+        let (mut he1, mut he2) = (HybridEstimator::default(), HybridEstimator::default());
+        for i in 0..200_000u64 {
+            he1.encode(i);
+            he2.encode(i + 20_000);
+        }
+        let estimate = he1.estimate(&he2).unwrap();
+        assert!(!estimate.is_exact());
+        let value = estimate.value();
+        assert!(value > 30_000 && value < 50_000, "{}", value);
+        assert!(he1
+            .estimate(&HybridEstimator::new(
+                StrataEstimatorConfig::default(),
+                1024
+            ))
+            .is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use crate::{IronRoseError, Side, IBF};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    ops::{BitXor, BitXorAssign},
+};
+
+/// Index of a peer in a [MultiPartyDiff](MultiPartyDiff), in the order their IBFs were given
+pub type PeerId = usize;
+
+/// An element that some peers of a [MultiPartyDiff](MultiPartyDiff) hold and others don't
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Missing<T> {
+    /// The element
+    pub element: T,
+    /// Peers that don't hold the element, in increasing order. Every other peer does.
+    pub peers: Vec<PeerId>,
+}
+
+/// Reconciles the same set across N replicas at once. Given one same-shaped IBF per peer, it
+/// works out which peers miss which elements with N - 1 subtractions and decodes, all against
+/// one hub peer, instead of one for each of the N (N - 1) / 2 pairs.
+///
+/// An element decoded against the hub tells whether the hub holds it, and then each other peer
+/// holds it exactly when its difference with the hub doesn't include it. Each IBF has to be
+/// large enough for the peer's difference with the hub, so the hub is best picked as the most
+/// up to date replica.
+/// ```rust
+/// use iron_rose::{Missing, MultiPartyDiff, IBF};
+///
+/// let mut peers = vec![IBF::new(40), IBF::new(40), IBF::new(40)];
+/// for x in 0..100u64 {
+///     peers.iter_mut().for_each(|ibf| ibf.encode(x));
+/// }
+/// peers[0].encode(100);
+/// peers[1].encode(100);
+/// peers[2].encode(200);
+/// let report = MultiPartyDiff::new(peers).decode().unwrap();
+/// assert_eq!(report.len(), 2);
+/// assert!(report.contains(&Missing { element: 100, peers: vec![2] }));
+/// assert!(report.contains(&Missing { element: 200, peers: vec![0, 1] }));
+/// ```
+#[derive(Debug, Clone)]
+pub struct MultiPartyDiff<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    peers: Vec<IBF<T>>,
+    hub: PeerId,
+}
+
+impl<T> MultiPartyDiff<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// Reconciliation of the peers' IBFs, with peer 0 as the hub
+    pub fn new(peers: Vec<IBF<T>>) -> Self {
+        Self { peers, hub: 0 }
+    }
+
+    /// Subtracts every other peer from hub instead of peer 0. Panics unless hub is one of the
+    /// peers.
+    pub fn hub(mut self, hub: PeerId) -> Self {
+        assert!(hub < self.peers.len(), "hub {} is not a peer", hub);
+        self.hub = hub;
+        self
+    }
+
+    /// Number of peers
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// True if there are no peers
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Every element some peers are missing, with those peers. Fails like subtraction if the
+    /// IBFs aren't all the same shape, or with
+    /// [DecodeFailed](IronRoseError::DecodeFailed) if a peer's difference with the hub doesn't
+    /// decode.
+    pub fn decode(self) -> Result<Vec<Missing<T>>, IronRoseError> {
+        let n = self.peers.len();
+        // Per element, whether the hub holds it and which peers' differences with the hub have it
+        let mut seen: HashMap<T, (bool, Vec<PeerId>)> = HashMap::new();
+        let mut order = Vec::new();
+        let hub = match self.peers.get(self.hub) {
+            Some(hub) => hub,
+            None => return Ok(Vec::new()),
+        };
+        for (peer, ibf) in self.peers.iter().enumerate() {
+            if peer == self.hub {
+                continue;
+            }
+            for side in (hub - ibf)?.decode_difference()? {
+                let (element, at_hub) = match side {
+                    Side::Left(element) => (element, true),
+                    Side::Right(element) => (element, false),
+                };
+                let entry = seen.entry(element.clone()).or_insert_with(|| {
+                    order.push(element);
+                    (at_hub, Vec::new())
+                });
+                entry.1.push(peer);
+            }
+        }
+        Ok(order
+            .into_iter()
+            .map(|element| {
+                let (at_hub, differing) = seen.remove(&element).expect("recorded with the order");
+                // A peer agrees with the hub on the element unless its difference with the hub
+                // decoded it
+                let peers = (0..n)
+                    .filter(|peer| at_hub == differing.contains(peer))
+                    .collect();
+                Missing { element, peers }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn matches_pairwise_membership() {
+        let sets: Vec<HashSet<u64>> = vec![
+            (0..300).collect(),
+            (10..300).chain(1000..1005).collect(),
+            (0..290).chain(1000..1003).collect(),
+            (5..305).collect(),
+        ];
+        let peers: Vec<IBF<u64>> = sets
+            .iter()
+            .map(|set| {
+                let mut ibf = IBF::new(80);
+                set.iter().for_each(|x| ibf.encode(*x));
+                ibf
+            })
+            .collect();
+
+        let everything: HashSet<u64> = sets.iter().flatten().copied().collect();
+        let expected: HashSet<(u64, Vec<PeerId>)> = everything
+            .into_iter()
+            .map(|x| (x, (0..4).filter(|&p| !sets[p].contains(&x)).collect()))
+            .filter(|(_, missing): &(u64, Vec<PeerId>)| !missing.is_empty())
+            .collect();
+
+        for hub in 0..4 {
+            let report = MultiPartyDiff::new(peers.clone())
+                .hub(hub)
+                .decode()
+                .unwrap();
+            let found: HashSet<_> = report.into_iter().map(|m| (m.element, m.peers)).collect();
+            assert_eq!(found, expected, "hub {}", hub);
+        }
+
+        assert!(MultiPartyDiff::<u64>::new(vec![])
+            .decode()
+            .unwrap()
+            .is_empty());
+        let mismatched = vec![IBF::new(80), IBF::new(40)];
+        assert!(MultiPartyDiff::<u64>::new(mismatched).decode().is_err());
+    }
+}
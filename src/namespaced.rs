@@ -0,0 +1,229 @@
+use crate::{IronRoseError, Side, IBF};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    hash::Hash,
+    ops::{BitXor, BitXorAssign, Sub},
+};
+
+/// An element tagged with the namespace (tenant, table, ...) it belongs to. Both the namespace
+/// and the id take part in the element hash, so the same id in two namespaces are two distinct
+/// elements of the sketch.
+///
+/// As both halves are XORed field by field, this also works as a two part composite key without
+/// any bit packing, converting to and from tuples.
+/// ```rust
+/// use iron_rose::{Namespaced, Side, IBF};
+///
+/// let (mut left, mut right) = (IBF::new(20), IBF::new(20));
+/// left.encode(Namespaced::from((7u32, 42u128)));
+/// right.encode((7u32, 43u128).into());
+/// let diff = (left - right).unwrap().decode().unwrap();
+/// assert!(diff.contains(&Side::Left((7, 42).into())));
+/// assert_eq!(Namespaced::from((7u32, 43u128)).into_tuple(), (7, 43));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Namespaced<N, T> {
+    /// Namespace the id belongs to
+    pub namespace: N,
+    /// The id itself
+    pub id: T,
+}
+
+impl<N, T> Namespaced<N, T> {
+    /// Splits back into a (namespace, id) tuple
+    pub fn into_tuple(self) -> (N, T) {
+        (self.namespace, self.id)
+    }
+}
+
+impl<N, T> From<(N, T)> for Namespaced<N, T> {
+    fn from((namespace, id): (N, T)) -> Self {
+        Self { namespace, id }
+    }
+}
+
+impl<N, T> BitXor for Namespaced<N, T>
+where
+    N: BitXor<Output = N>,
+    T: BitXor<Output = T>,
+{
+    type Output = Self;
+
+    fn bitxor(self, rhs: Self) -> Self::Output {
+        Self {
+            namespace: self.namespace ^ rhs.namespace,
+            id: self.id ^ rhs.id,
+        }
+    }
+}
+
+impl<N, T> BitXorAssign for Namespaced<N, T>
+where
+    N: BitXorAssign,
+    T: BitXorAssign,
+{
+    fn bitxor_assign(&mut self, rhs: Self) {
+        self.namespace ^= rhs.namespace;
+        self.id ^= rhs.id;
+    }
+}
+
+/// A single sketch covering ids from many namespaces, decoding back into per namespace
+/// differences.
+/// ```rust
+/// use iron_rose::{NamespacedIBF, Side};
+///
+/// let mut left = NamespacedIBF::new(40);
+/// let mut right = NamespacedIBF::new(40);
+/// left.encode_in(1u64, 10u128);
+/// left.encode_in(2u64, 10u128);
+/// right.encode_in(1u64, 10u128);
+/// let diff = (left - right).expect("same shape");
+/// let by_namespace = diff.decode().expect("small difference");
+/// assert!(by_namespace[&2].contains(&Side::Left(10)));
+/// assert!(!by_namespace.contains_key(&1));
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NamespacedIBF<N, T>
+where
+    N: Clone
+        + std::hash::Hash
+        + BitXor<Output = N>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    ibf: IBF<Namespaced<N, T>>,
+}
+
+impl<N, T> NamespacedIBF<N, T>
+where
+    N: Clone
+        + std::hash::Hash
+        + BitXor<Output = N>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// New namespaced IBF with size buckets and the default hash_count of 3
+    pub fn new(size: usize) -> Self {
+        Self::new_with_hash_count(size, 3)
+    }
+
+    /// New namespaced IBF with size buckets and a settable hash_count
+    pub fn new_with_hash_count(size: usize, hash_count: usize) -> Self {
+        Self {
+            ibf: IBF::new_with_hash_count(size, hash_count),
+        }
+    }
+
+    /// Encodes id as a member of namespace
+    pub fn encode_in(&mut self, namespace: N, id: T) {
+        self.ibf.encode(Namespaced { namespace, id })
+    }
+
+    /// Decodes the differences, grouped by the namespace they belong to. Namespaces without any
+    /// differences are absent from the map.
+    pub fn decode(self) -> Result<HashMap<N, HashSet<Side<T>>>, IronRoseError> {
+        let mut by_namespace: HashMap<N, HashSet<Side<T>>> = HashMap::new();
+        for side in self.ibf.decode()? {
+            let (namespace, side) = match side {
+                Side::Left(elm) => (elm.namespace, Side::Left(elm.id)),
+                Side::Right(elm) => (elm.namespace, Side::Right(elm.id)),
+            };
+            by_namespace.entry(namespace).or_default().insert(side);
+        }
+        Ok(by_namespace)
+    }
+
+    /// Decodes the differences of a single namespace. The whole sketch still has to be peeled,
+    /// so this fails whenever [decode](NamespacedIBF::decode) would.
+    pub fn decode_in(self, namespace: &N) -> Result<HashSet<Side<T>>, IronRoseError> {
+        Ok(self.decode()?.remove(namespace).unwrap_or_default())
+    }
+
+    /// The underlying IBF of namespaced elements
+    pub fn inner(&self) -> &IBF<Namespaced<N, T>> {
+        &self.ibf
+    }
+}
+
+impl<N, T> Sub for NamespacedIBF<N, T>
+where
+    N: Clone
+        + std::hash::Hash
+        + BitXor<Output = N>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    type Output = Result<NamespacedIBF<N, T>, IronRoseError>;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Ok(Self {
+            ibf: (self.ibf - rhs.ibf)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_id_different_namespaces() {
+        let mut left = NamespacedIBF::new(60);
+        let mut right = NamespacedIBF::new(60);
+        for ns in 0..3u32 {
+            for id in 0..20u64 {
+                left.encode_in(ns, id);
+                right.encode_in(ns, id);
+            }
+        }
+        left.encode_in(0, 100);
+        right.encode_in(2, 100);
+
+        let diff = (left - right).unwrap();
+        let tenant_two = diff.clone().decode_in(&2).unwrap();
+        assert_eq!(
+            tenant_two.into_iter().collect::<Vec<_>>(),
+            vec![Side::Right(100)]
+        );
+
+        let all = diff.decode().unwrap();
+        assert_eq!(all.len(), 2);
+        assert!(all[&0].contains(&Side::Left(100)));
+    }
+}
@@ -0,0 +1,573 @@
+use crate::ibf::hasher_id;
+use crate::{DefaultBuildHasher, IronRoseError};
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use serde::{Deserialize, Serialize};
+
+/// The shape of an IBF, two IBFs can only be subtracted from each other when their params are
+/// equal. Small and serializable on its own, so peers can compare configurations before sending
+/// whole sketches.
+/// ```rust
+/// use iron_rose::{SketchParams, IBF};
+///
+/// let params = SketchParams::new(100, 3);
+/// let ibf: IBF<u64> = IBF::with_params(params);
+/// assert_eq!(ibf.params(), params);
+/// assert_eq!(ibf.params().cache_key(), SketchParams::new(100, 3).cache_key());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SketchParams {
+    size: usize,
+    hash_count: usize,
+    #[serde(default)]
+    max_wire_bytes: Option<usize>,
+}
+
+/// Bytes of the [binary wire format](crate::IBF::to_bytes) ahead of an IBF's cells: version,
+/// kind, element width and flags, then hash_count and size as u32s
+const BINARY_IBF_HEADER: usize = 4 + 4 + 4;
+
+/// Bytes of the CRC32 the [binary wire format](crate::IBF::to_bytes) ends with
+const BINARY_FOOTER: usize = 4;
+
+/// Kind of sketch to size with [estimated_bytes](SketchParams::estimated_bytes)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SketchKind {
+    /// An IBF with the params, of elements element_width bytes wide
+    Ibf {
+        /// Width of an element in bytes, see [ibf](SketchKind::ibf)
+        element_width: usize,
+    },
+    /// A [StrataEstimator](crate::StrataEstimator) with strata IBFs of the params
+    StrataEstimator {
+        /// Number of strata
+        strata: usize,
+    },
+}
+
+impl SketchKind {
+    /// An IBF of `T`
+    pub const fn ibf<T>() -> Self {
+        SketchKind::Ibf {
+            element_width: core::mem::size_of::<T>(),
+        }
+    }
+}
+
+/// Size of a sketch in memory and on the wire, from
+/// [estimated_bytes](SketchParams::estimated_bytes)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SketchBytes {
+    /// Bytes taken by the cells in memory
+    pub memory: usize,
+    /// Bytes in the [binary wire format](crate::IBF::to_bytes), without a keyspace
+    pub wire: usize,
+}
+
+impl SketchParams {
+    /// Params for an IBF with size cells, each element encoded into hash_count of them
+    pub const fn new(size: usize, hash_count: usize) -> Self {
+        Self {
+            size,
+            hash_count,
+            max_wire_bytes: None,
+        }
+    }
+
+    /// Sets a budget on how large, in bytes, a sketch built from these params may be on the
+    /// wire. [IBF::try_with_params](crate::IBF::try_with_params) refuses to build anything
+    /// bigger, and [IBF::to_bytes_within](crate::IBF::to_bytes_within) to serialize it, so a
+    /// badly overestimated difference can't turn into a multi gigabyte message.
+    pub const fn with_max_wire_bytes(mut self, max_wire_bytes: usize) -> Self {
+        self.max_wire_bytes = Some(max_wire_bytes);
+        self
+    }
+
+    /// The wire size budget, if any
+    pub const fn max_wire_bytes(&self) -> Option<usize> {
+        self.max_wire_bytes
+    }
+
+    /// Every parameter that differs from other's, empty when they are equal
+    /// ```rust
+    /// use iron_rose::{ParamMismatch, SketchParams};
+    ///
+    /// let ours = SketchParams::new(100, 3);
+    /// let theirs = SketchParams::new(100, 4).with_max_wire_bytes(4096);
+    /// assert_eq!(
+    ///     ours.diff(&theirs),
+    ///     [
+    ///         ParamMismatch::HashCount { left: 3, right: 4 },
+    ///         ParamMismatch::MaxWireBytes { left: None, right: Some(4096) },
+    ///     ]
+    /// );
+    /// ```
+    pub fn diff(&self, other: &SketchParams) -> Vec<ParamMismatch> {
+        let mut diff = Vec::new();
+        if self.size != other.size {
+            diff.push(ParamMismatch::Size {
+                left: self.size,
+                right: other.size,
+            });
+        }
+        if self.hash_count != other.hash_count {
+            diff.push(ParamMismatch::HashCount {
+                left: self.hash_count,
+                right: other.hash_count,
+            });
+        }
+        if self.max_wire_bytes != other.max_wire_bytes {
+            diff.push(ParamMismatch::MaxWireBytes {
+                left: self.max_wire_bytes,
+                right: other.max_wire_bytes,
+            });
+        }
+        diff
+    }
+
+    /// Size in bytes of an IBF of `T` with these params in the
+    /// [binary wire format](crate::IBF::to_bytes), without a keyspace, as worked out by
+    /// [estimated_bytes](SketchParams::estimated_bytes)
+    pub fn wire_bytes<T>(&self) -> usize {
+        self.estimated_bytes(SketchKind::ibf::<T>()).wire
+    }
+
+    /// Size in bytes of the cells of an IBF of `T` with these params in memory, as worked out by
+    /// [estimated_bytes](SketchParams::estimated_bytes)
+    pub fn memory_bytes<T>(&self) -> usize {
+        self.estimated_bytes(SketchKind::ibf::<T>()).memory
+    }
+
+    /// Memory and wire sizes of a sketch of the given kind with these params, worked out without
+    /// allocating anything, for capacity planning and admission control. Memory counts the
+    /// cells, laid out as a [Cell](crate::Cell) of an element of that width would be, while the
+    /// wire size is exact for the binary format. [wire_bytes](SketchParams::wire_bytes) and
+    /// [memory_bytes](SketchParams::memory_bytes) are shorthands for an IBF's sizes.
+    /// ```rust
+    /// use iron_rose::{SketchBytes, SketchKind, SketchParams, IBF};
+    ///
+    /// let params = SketchParams::new(1000, 3);
+    /// let bytes = params.estimated_bytes(SketchKind::ibf::<u128>());
+    /// assert_eq!(bytes, SketchBytes { memory: 32_000, wire: 28_016 });
+    /// assert_eq!(IBF::<u128>::with_params(params).to_bytes().len(), bytes.wire);
+    ///
+    /// let estimator = SketchParams::new(80, 3).estimated_bytes(SketchKind::StrataEstimator { strata: 32 });
+    /// assert_eq!(estimator.wire, 16 + 32 * 80 * 20 + 4);
+    /// ```
+    pub fn estimated_bytes(&self, kind: SketchKind) -> SketchBytes {
+        let (ibfs, width) = match kind {
+            SketchKind::Ibf { element_width } => (1, element_width),
+            SketchKind::StrataEstimator { strata } => (strata, core::mem::size_of::<u64>()),
+        };
+        let cells = self.size.saturating_mul(ibfs);
+        // Cells are aligned to their 64 bit hash sum, or to the element if that's wider
+        let memory_cell = (width + 8 + 4).div_ceil(8) * 8;
+        let header = match kind {
+            SketchKind::Ibf { .. } => BINARY_IBF_HEADER,
+            // The number of strata goes after the header, and the shape is written once
+            SketchKind::StrataEstimator { .. } => BINARY_IBF_HEADER + 4,
+        };
+        SketchBytes {
+            memory: cells.saturating_mul(memory_cell),
+            wire: cells
+                .saturating_mul(width + 8 + 4)
+                .saturating_add(header + BINARY_FOOTER),
+        }
+    }
+
+    /// Returns the would-be [wire size](SketchParams::wire_bytes) of an IBF of `T`, or an Err
+    /// with that size if it exceeds the [budget](SketchParams::with_max_wire_bytes).
+    /// ```rust
+    /// use iron_rose::SketchParams;
+    ///
+    /// let params = SketchParams::new(1_000_000, 3).with_max_wire_bytes(64 * 1024);
+    /// assert!(params.check_wire_budget::<u128>().is_err());
+    /// ```
+    pub fn check_wire_budget<T>(&self) -> Result<usize, IronRoseError> {
+        let bytes = self.wire_bytes::<T>();
+        match self.max_wire_bytes {
+            Some(max) if bytes > max => Err(IronRoseError::OverWireBudget { bytes, max }),
+            _ => Ok(bytes),
+        }
+    }
+
+    /// Like [new](SketchParams::new), but panics unless `0 < hash_count <= size`. As a const fn,
+    /// using it to define a constant turns a misconfiguration into a compile error.
+    /// ```compile_fail
+    /// use iron_rose::SketchParams;
+    ///
+    /// const BROKEN: SketchParams = SketchParams::checked(2, 3);
+    /// # let _ = BROKEN;
+    /// ```
+    pub const fn checked(size: usize, hash_count: usize) -> Self {
+        assert!(hash_count > 0, "hash_count must be at least 1");
+        assert!(hash_count <= size, "hash_count can't be larger than size");
+        Self::new(size, hash_count)
+    }
+
+    /// Number of cells
+    pub const fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of cells each element is encoded into
+    pub const fn hash_count(&self) -> usize {
+        self.hash_count
+    }
+
+    /// A 64 bit key identifying this configuration, suitable for cache keys and quick
+    /// comparisons during a handshake. The key is computed with FNV-1a over a fixed little
+    /// endian layout, so it is stable across platforms and crate versions. Only the shape is
+    /// included, not the wire budget.
+    pub fn cache_key(&self) -> u64 {
+        const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        let mut bytes = b"iron_rose/params/v1".to_vec();
+        bytes.extend_from_slice(&(self.size as u64).to_le_bytes());
+        bytes.extend_from_slice(&(self.hash_count as u64).to_le_bytes());
+        bytes
+            .iter()
+            .fold(OFFSET, |hash, b| (hash ^ *b as u64).wrapping_mul(PRIME))
+    }
+}
+
+/// Everything two peers' IBFs have to agree on to be subtracted, from
+/// [IBF::ibf_params](crate::IBF::ibf_params): the [shape](SketchParams), an id of the hasher
+/// placing elements in cells, the width in bytes of the elements and the
+/// [keyspace](crate::IBF::with_keyspace). Serializable on its own, so a protocol can send it
+/// ahead of the cells and settle on a shape first.
+/// ```rust
+/// use iron_rose::{IbfParams, IBF};
+///
+/// let ibf: IBF<u64> = IBF::new(100).with_keyspace("orders");
+/// let params = ibf.ibf_params();
+/// assert_eq!(params.size(), 100);
+/// assert_eq!(params.element_width(), 8);
+/// let json = serde_json::to_string(&params).unwrap();
+/// assert_eq!(serde_json::from_str::<IbfParams>(&json).unwrap(), params);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct IbfParams {
+    size: usize,
+    hash_count: usize,
+    hasher_id: u64,
+    element_width: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    keyspace: Option<String>,
+}
+
+impl IbfParams {
+    /// Params of an IBF of the given shape, hasher id, element width and keyspace
+    pub fn new(
+        sketch: SketchParams,
+        hasher_id: u64,
+        element_width: usize,
+        keyspace: Option<&str>,
+    ) -> Self {
+        Self {
+            size: sketch.size(),
+            hash_count: sketch.hash_count(),
+            hasher_id,
+            element_width,
+            keyspace: keyspace.map(str::to_string),
+        }
+    }
+
+    /// Number of cells
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of cells each element is encoded into
+    pub fn hash_count(&self) -> usize {
+        self.hash_count
+    }
+
+    /// The shape alone
+    pub fn sketch_params(&self) -> SketchParams {
+        SketchParams::new(self.size, self.hash_count)
+    }
+
+    /// Hash of a fixed probe value under the IBF's hasher, equal for hashers that place elements
+    /// the same way
+    pub fn hasher_id(&self) -> u64 {
+        self.hasher_id
+    }
+
+    /// Size in bytes of an element
+    pub fn element_width(&self) -> usize {
+        self.element_width
+    }
+
+    /// The keyspace, if any
+    pub fn keyspace(&self) -> Option<&str> {
+        self.keyspace.as_deref()
+    }
+
+    /// Every parameter that differs from other's, where
+    /// [check_compatible](IbfParams::check_compatible) only names the first. Empty when IBFs of
+    /// the two params can be subtracted.
+    /// ```rust
+    /// use iron_rose::{ParamMismatch, SeededHasher, SketchParams, IBF};
+    ///
+    /// let local: IBF<u64> = IBF::new(100);
+    /// let remote: IBF<u64, _> =
+    ///     IBF::with_hasher(SketchParams::new(120, 3), SeededHasher::new([7; 16]));
+    /// let diff = local.ibf_params().diff(&remote.ibf_params());
+    /// assert_eq!(diff[0], ParamMismatch::Size { left: 100, right: 120 });
+    /// assert!(matches!(
+    ///     diff[1],
+    ///     ParamMismatch::Hasher { left_default: true, right_default: false, .. }
+    /// ));
+    /// ```
+    pub fn diff(&self, other: &IbfParams) -> Vec<ParamMismatch> {
+        let mut diff = self.sketch_params().diff(&other.sketch_params());
+        if self.element_width != other.element_width {
+            diff.push(ParamMismatch::ElementWidth {
+                left: self.element_width,
+                right: other.element_width,
+            });
+        }
+        if self.hasher_id != other.hasher_id {
+            diff.push(ParamMismatch::hasher(self.hasher_id, other.hasher_id));
+        }
+        if self.keyspace != other.keyspace {
+            diff.push(ParamMismatch::Keyspace {
+                left: self.keyspace.clone(),
+                right: other.keyspace.clone(),
+            });
+        }
+        diff
+    }
+
+    /// Fails, naming the first parameter that differs, unless IBFs of the two params can be
+    /// subtracted: [ShapeMismatch](IronRoseError::ShapeMismatch),
+    /// [ElementWidthMismatch](IronRoseError::ElementWidthMismatch),
+    /// [HasherMismatch](IronRoseError::HasherMismatch) or
+    /// [KeyspaceMismatch](IronRoseError::KeyspaceMismatch), in that order
+    pub fn check_compatible(&self, other: &IbfParams) -> Result<(), IronRoseError> {
+        if self.sketch_params() != other.sketch_params() {
+            return Err(IronRoseError::ShapeMismatch {
+                left: self.sketch_params(),
+                right: other.sketch_params(),
+            });
+        }
+        if self.element_width != other.element_width {
+            return Err(IronRoseError::ElementWidthMismatch {
+                left: self.element_width,
+                right: other.element_width,
+            });
+        }
+        if self.hasher_id != other.hasher_id {
+            return Err(IronRoseError::HasherMismatch {
+                left: self.hasher_id,
+                right: other.hasher_id,
+            });
+        }
+        if self.keyspace != other.keyspace {
+            return Err(IronRoseError::KeyspaceMismatch {
+                left: self.keyspace.clone(),
+                right: other.keyspace.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// One parameter two sketches disagree on, from [SketchParams::diff](SketchParams::diff),
+/// [IbfParams::diff](IbfParams::diff) or [IronRoseError::param_diff](IronRoseError::param_diff).
+/// Serializable, so both sides of a failed handshake can be logged and compared by tools.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ParamMismatch {
+    /// Number of cells
+    Size {
+        /// Left hand side
+        left: usize,
+        /// Right hand side
+        right: usize,
+    },
+    /// Number of cells each element is encoded into
+    HashCount {
+        /// Left hand side
+        left: usize,
+        /// Right hand side
+        right: usize,
+    },
+    /// [Wire size budget](SketchParams::with_max_wire_bytes)
+    MaxWireBytes {
+        /// Left hand side
+        left: Option<usize>,
+        /// Right hand side
+        right: Option<usize>,
+    },
+    /// Number of strata of a [StrataEstimator](crate::StrataEstimator)
+    Strata {
+        /// Left hand side
+        left: usize,
+        /// Right hand side
+        right: usize,
+    },
+    /// Size in bytes of an element
+    ElementWidth {
+        /// Left hand side
+        left: usize,
+        /// Right hand side
+        right: usize,
+    },
+    /// [Hasher id](IbfParams::hasher_id), with which side, if any, uses the
+    /// [default hasher](DefaultBuildHasher). When only one side does, the other was built
+    /// [with](crate::IBF::with_hasher) a custom or keyed hasher, often the side to look at.
+    Hasher {
+        /// Left hand side
+        left: u64,
+        /// Right hand side
+        right: u64,
+        /// True if the left hand side uses the default hasher
+        left_default: bool,
+        /// True if the right hand side uses the default hasher
+        right_default: bool,
+    },
+    /// [Keyspace](crate::IBF::with_keyspace)
+    Keyspace {
+        /// Left hand side
+        left: Option<String>,
+        /// Right hand side
+        right: Option<String>,
+    },
+}
+
+impl ParamMismatch {
+    /// A [Hasher](ParamMismatch::Hasher) mismatch between the two hasher ids
+    pub fn hasher(left: u64, right: u64) -> Self {
+        ParamMismatch::Hasher {
+            left,
+            right,
+            left_default: is_default_hasher(left),
+            right_default: is_default_hasher(right),
+        }
+    }
+}
+
+/// True if id is the [hasher id](IbfParams::hasher_id) of the [default hasher](DefaultBuildHasher)
+pub(crate) fn is_default_hasher(id: u64) -> bool {
+    id == hasher_id(&DefaultBuildHasher::default())
+}
+
+/// Random, valid shapes for fuzzing: up to 4096 cells and 0 < hash_count <= min(size, 8)
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for SketchParams {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let size = u.int_in_range(1..=4096)?;
+        let hash_count = u.int_in_range(1..=size.min(8))?;
+        Ok(Self::new(size, hash_count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Cell;
+
+    #[test]
+    fn estimated_bytes_match_real_sketches() {
+        use crate::{StrataEstimator, StrataEstimatorConfig, IBF};
+
+        for &size in [1, 80, 1000].iter() {
+            let params = SketchParams::new(size, 1);
+            let bytes = params.estimated_bytes(SketchKind::ibf::<u64>());
+            assert_eq!(bytes.memory, size * core::mem::size_of::<Cell<u64>>());
+            assert_eq!(bytes.wire, IBF::<u64>::with_params(params).to_bytes().len());
+            assert_eq!(params.memory_bytes::<u64>(), bytes.memory);
+            assert_eq!(params.wire_bytes::<u64>(), bytes.wire);
+            let bytes = params.estimated_bytes(SketchKind::ibf::<u128>());
+            assert_eq!(bytes.memory, size * core::mem::size_of::<Cell<u128>>());
+            assert_eq!(
+                bytes.wire,
+                IBF::<u128>::with_params(params).to_bytes().len()
+            );
+
+            let estimator = StrataEstimator::with_config(StrataEstimatorConfig::new(16, params));
+            let bytes = params.estimated_bytes(SketchKind::StrataEstimator { strata: 16 });
+            assert_eq!(bytes.wire, estimator.to_bytes().len());
+            assert_eq!(bytes.memory, 16 * params.memory_bytes::<u64>());
+        }
+        assert_eq!(
+            SketchParams::new(10, 3)
+                .estimated_bytes(SketchKind::ibf::<[u8; 32]>())
+                .memory,
+            480
+        );
+    }
+
+    #[test]
+    fn wire_budget() {
+        let params = SketchParams::new(100, 3);
+        assert_eq!(
+            params.wire_bytes::<u128>(),
+            100 * 28 + BINARY_IBF_HEADER + BINARY_FOOTER
+        );
+        assert!(params.check_wire_budget::<u128>().is_ok());
+        let err = params
+            .with_max_wire_bytes(1000)
+            .check_wire_budget::<u128>()
+            .unwrap_err();
+        assert_eq!(
+            err,
+            IronRoseError::OverWireBudget {
+                bytes: 2816,
+                max: 1000
+            }
+        );
+        assert_eq!(
+            params.with_max_wire_bytes(1000).cache_key(),
+            params.cache_key()
+        );
+    }
+
+    #[test]
+    fn mismatches_are_diagnosed() {
+        use crate::{SeededHasher, StrataEstimator, StrataEstimatorConfig, IBF};
+
+        let small =
+            StrataEstimator::with_config(StrataEstimatorConfig::new(8, SketchParams::new(40, 3)));
+        let err = StrataEstimator::default()
+            .estimate_differences(&small)
+            .unwrap_err();
+        let diff = err.param_diff();
+        assert!(matches!(diff[0], ParamMismatch::Strata { right: 8, .. }));
+        assert_eq!(
+            diff[1],
+            ParamMismatch::Size {
+                left: 80,
+                right: 40
+            }
+        );
+
+        let local: IBF<u64> = IBF::new(10);
+        let keyed: IBF<u64, _> = IBF::with_hasher(local.params(), SeededHasher::new([1; 16]));
+        let err = local.compatible_with(&keyed.ibf_params()).unwrap_err();
+        assert_eq!(
+            err.param_diff(),
+            [ParamMismatch::hasher(
+                local.ibf_params().hasher_id(),
+                keyed.ibf_params().hasher_id()
+            )]
+        );
+        assert!(err.to_string().contains("(default) and"));
+        assert!(err.to_string().ends_with("(custom)"));
+        assert!(IronRoseError::LikelyDuplicate.param_diff().is_empty());
+    }
+
+    #[test]
+    fn cache_key_is_stable() {
+        // Pinned so that accidental changes to the key derivation are caught.
+        assert_eq!(SketchParams::new(80, 3).cache_key(), 0xafca_68b2_9bf7_a875);
+        assert_ne!(
+            SketchParams::new(80, 3).cache_key(),
+            SketchParams::new(80, 4).cache_key()
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+
+/// How to grow an IBF after it failed to decode
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum GrowthPolicy {
+    /// Multiply the size by factor each attempt
+    Geometric {
+        /// Growth factor, values of 1.0 or less are treated as doubling
+        factor: f64,
+    },
+    /// Add a fixed number of cells each attempt
+    Additive {
+        /// Cells added per attempt
+        cells: usize,
+    },
+    /// Size from a fresh estimate of the differences (e.g. from a newly exchanged
+    /// [StrataEstimator](crate::StrataEstimator)) times multiplier, falling back to doubling
+    /// when no estimate is available. Never shrinks.
+    EstimatorReconsult {
+        /// Cells per estimated difference
+        multiplier: f64,
+    },
+}
+
+impl Default for GrowthPolicy {
+    fn default() -> Self {
+        GrowthPolicy::Geometric { factor: 2.0 }
+    }
+}
+
+impl GrowthPolicy {
+    /// Size to use after an IBF of current cells failed to decode. Always larger than current.
+    /// ```rust
+    /// use iron_rose::GrowthPolicy;
+    ///
+    /// assert_eq!(GrowthPolicy::Geometric { factor: 2.0 }.next_size(100, None), 200);
+    /// assert_eq!(GrowthPolicy::Additive { cells: 50 }.next_size(100, None), 150);
+    /// let reconsult = GrowthPolicy::EstimatorReconsult { multiplier: 1.5 };
+    /// assert_eq!(reconsult.next_size(100, Some(400)), 600);
+    /// ```
+    pub fn next_size(&self, current: usize, estimate: Option<usize>) -> usize {
+        let next = match *self {
+            GrowthPolicy::Geometric { factor } if factor > 1.0 => {
+                (current as f64 * factor).ceil() as usize
+            }
+            GrowthPolicy::Geometric { .. } => current.saturating_mul(2),
+            GrowthPolicy::Additive { cells } => current.saturating_add(cells),
+            GrowthPolicy::EstimatorReconsult { multiplier } => match estimate {
+                Some(estimate) => (estimate as f64 * multiplier).ceil() as usize,
+                None => current.saturating_mul(2),
+            },
+        };
+        next.max(current.saturating_add(1))
+    }
+}
+
+/// Limits on how hard to retry a reconciliation whose IBF failed to decode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryBudget {
+    /// Total number of attempts, including the first
+    pub max_attempts: usize,
+    /// Largest IBF, in cells, that may be built
+    pub max_cells: usize,
+}
+
+impl Default for RetryBudget {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            max_cells: 1 << 24,
+        }
+    }
+}
+
+impl RetryBudget {
+    /// True if attempt number `attempt` (starting at 1) with an IBF of size cells is within
+    /// budget
+    pub fn allows(&self, attempt: usize, size: usize) -> bool {
+        attempt <= self.max_attempts && size <= self.max_cells
+    }
+
+    /// The sizes each attempt would use, starting at initial and growing by policy without
+    /// fresh estimates, stopping once the budget is exhausted.
+    /// ```rust
+    /// use iron_rose::{GrowthPolicy, RetryBudget};
+    ///
+    /// let budget = RetryBudget { max_attempts: 4, max_cells: 500 };
+    /// let sizes = budget.sizes(GrowthPolicy::default(), 100).collect::<Vec<_>>();
+    /// assert_eq!(sizes, vec![100, 200, 400]);
+    /// ```
+    pub fn sizes(&self, policy: GrowthPolicy, initial: usize) -> impl Iterator<Item = usize> {
+        let budget = *self;
+        std::iter::successors(Some(initial), move |size| {
+            Some(policy.next_size(*size, None))
+        })
+        .enumerate()
+        .take_while(move |(i, size)| budget.allows(i + 1, *size))
+        .map(|(_, size)| size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn always_grows() {
+        let policies = [
+            GrowthPolicy::Geometric { factor: 0.5 },
+            GrowthPolicy::Additive { cells: 0 },
+            GrowthPolicy::EstimatorReconsult { multiplier: 1.5 },
+        ];
+        for policy in policies.iter() {
+            assert!(policy.next_size(100, Some(1)) > 100, "{:?}", policy);
+            assert!(policy.next_size(0, None) > 0, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn budget_limits_attempts() {
+        let budget = RetryBudget {
+            max_attempts: 3,
+            max_cells: usize::MAX,
+        };
+        let sizes = budget
+            .sizes(GrowthPolicy::Additive { cells: 10 }, 10)
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![10, 20, 30]);
+    }
+}
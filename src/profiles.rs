@@ -0,0 +1,93 @@
+//! Predefined, compile-time checked configurations.
+//!
+//! Every profile is a `const` built with checked constructors, so a bad edit to one of them (or
+//! to a profile defined the same way in an application) fails the build rather than at runtime
+//! on a device.
+//! ```rust
+//! use iron_rose::{profiles, SketchParams};
+//!
+//! // An application profile, checked at compile time
+//! const ORDERS: SketchParams = SketchParams::checked(4096, 4);
+//! assert_eq!(profiles::STRATUM.size(), 80);
+//! # let _ = ORDERS;
+//! ```
+use crate::{DefaultBuildHasher, SeededHasher, SketchParams, StrataEstimatorConfig};
+use core::time::Duration;
+
+/// Largest useful number of strata in a [StrataEstimator](crate::StrataEstimator): elements are
+/// assigned to strata by the trailing zeros of a 64 bit hash.
+pub const MAX_STRATA: usize = 64;
+
+/// Panics unless `0 < strata <= MAX_STRATA`, in a const context this is a compile error.
+/// ```compile_fail
+/// use iron_rose::profiles::checked_strata;
+///
+/// const TOO_DEEP: usize = checked_strata(65);
+/// # let _ = TOO_DEEP;
+/// ```
+pub const fn checked_strata(strata: usize) -> usize {
+    assert!(strata > 0, "a strata estimator needs at least one stratum");
+    assert!(strata <= MAX_STRATA, "strata can't exceed MAX_STRATA");
+    strata
+}
+
+/// Shape of each stratum IBF in a [StrataEstimator](crate::StrataEstimator)
+pub const STRATUM: SketchParams = SketchParams::checked(80, 3);
+
+/// Shape for the strata of a [StrataEstimator](crate::StrataEstimator) where memory is tight, a
+/// quarter of the cells of [STRATUM](STRATUM) with 2 hash functions. Each stratum decodes fewer
+/// differences, so estimates are extrapolated sooner and are noisier, see
+/// [theory](crate::theory) for the low hash count tradeoffs.
+pub const COMPACT_STRATUM: SketchParams = SketchParams::checked(20, 2);
+
+/// Number of strata in a default [StrataEstimator](crate::StrataEstimator)
+pub const DEFAULT_STRATA: usize = checked_strata(32);
+
+/// Number of strata in a default [HybridEstimator](crate::HybridEstimator), the deeper ones are
+/// replaced by its min-wise estimator
+pub const HYBRID_STRATA: usize = checked_strata(16);
+
+/// Number of strata an [AdaptiveStrataEstimator](crate::AdaptiveStrataEstimator) starts with,
+/// deeper ones are added as the set grows
+pub const ADAPTIVE_STRATA: usize = checked_strata(16);
+
+/// Number of hashes a default [MinWiseEstimator](crate::MinWiseEstimator) keeps, 8 KiB of them
+pub const MIN_WISE_HASHES: usize = 1024;
+
+/// Estimator for peers that reconcile every second or so and usually differ by a handful of
+/// elements: 4 strata of 10 cells, under a KiB on the wire. It estimates a few dozen differences
+/// exactly. Beyond that its last stratum fails with nothing decoded above it, and the estimate is
+/// only a lower bound of about 150, so a session sized from it after the
+/// [speculative IBF](REALTIME_IBF) failed may still have to grow a few times.
+pub const REALTIME_ESTIMATOR: StrataEstimatorConfig =
+    StrataEstimatorConfig::new(checked_strata(4), SketchParams::checked(10, 2));
+
+/// IBF sent right away by a [realtime](crate::reconcile::SyncSession::realtime) session, before
+/// any estimate: 24 cells, about half a KiB for u64 elements, which decode up to 5 differences
+/// nearly every time
+pub const REALTIME_IBF: SketchParams = SketchParams::checked(24, 3);
+
+/// How long a [DiffCache](crate::reconcile::DiffCache) of realtime peers keeps a difference. A
+/// peer whose set hasn't changed asks again at the same epoch every round, so entries are kept
+/// for a minute of rounds rather than decoded again.
+pub const REALTIME_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Hasher for IBFs exchanged between peers that trust each other: MetroHash, the default. It
+/// hashes the same on every platform and release, so it's what any peer builds with unless told
+/// otherwise.
+///
+/// `benches/hasher_bench.rs` compares it with the other hashers IBFs can be built
+/// [with](crate::IBF::with_hasher). MurmurHash3 and xxHash64 encode about twice as fast, like
+/// SipHash, and are as portable, so peers that agree on one of them can use it through
+/// [with_hasher](crate::IBF::with_hasher), though neither ships with this crate. FNV and FxHash
+/// encode several times faster, but mix small integers poorly, and cells placed by them should
+/// be checked against the [theory](crate::theory) failure rates before use. Std's `RandomState`
+/// is keyed differently in every process, so peers built with it can't subtract each other's
+/// sketches. The hasher makes no measurable difference to estimator ingest, whose strata always
+/// use MetroHash.
+pub type TrustedHasher = DefaultBuildHasher;
+
+/// Hasher for IBFs exchanged with peers that might craft elements or cells: keyed SipHash-2-4.
+/// In the hasher benchmarks it encodes no slower than [TrustedHasher](TrustedHasher), so speed
+/// is no reason to go without it.
+pub type UntrustedHasher = SeededHasher;
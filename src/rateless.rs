@@ -0,0 +1,310 @@
+use crate::{Cell, DecodeError, DefaultBuildHasher, Side};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashSet},
+    fmt::Debug,
+    hash::BuildHasher,
+    ops::{BitXor, BitXorAssign},
+};
+
+/// Sequence of cell indexes an element is coded into, as in Rateless IBLT (Yang et al.): every
+/// element is in cell 0, and the chance of being in cell i falls off as roughly 1 / (1 + i / 2),
+/// so however many cells have been sent, the first ones are dense enough to peel.
+#[derive(Debug, Clone, Copy)]
+struct Mapping {
+    prng: u64,
+    index: u64,
+}
+
+impl Mapping {
+    fn new(hash: u64) -> Self {
+        Self {
+            prng: hash,
+            index: 0,
+        }
+    }
+
+    fn next_index(&mut self) -> u64 {
+        let r = self.prng.wrapping_mul(0xda94_2042_e4dd_58b5);
+        self.prng = r;
+        let gap = (self.index as f64 + 1.5) * ((1u64 << 32) as f64 / (r as f64 + 1.0).sqrt() - 1.0);
+        self.index += (gap.ceil() as u64).max(1);
+        self.index
+    }
+}
+
+/// Elements queued by the next cell index they are coded into
+#[derive(Debug, Clone)]
+struct Window<E> {
+    entries: Vec<(E, Mapping)>,
+    queue: BinaryHeap<Reverse<(u64, usize)>>,
+}
+
+impl<E> Window<E> {
+    fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            queue: BinaryHeap::new(),
+        }
+    }
+
+    fn push(&mut self, element: E, mapping: Mapping) {
+        self.queue
+            .push(Reverse((mapping.index, self.entries.len())));
+        self.entries.push((element, mapping));
+    }
+
+    /// Calls f with every element coded into cell index, moving each on to its next index
+    fn drain_index<F: FnMut(&E)>(&mut self, index: u64, mut f: F) {
+        while let Some(&Reverse((next, entry))) = self.queue.peek() {
+            if next != index {
+                break;
+            }
+            self.queue.pop();
+            let (element, mapping) = &mut self.entries[entry];
+            f(element);
+            self.queue.push(Reverse((mapping.next_index(), entry)));
+        }
+    }
+}
+
+/// Infinite stream of coded cells for a set, the sending half of rateless reconciliation. No
+/// estimate of the difference is needed up front: the peer feeds cells to a
+/// [RatelessDecoder](RatelessDecoder) until it decodes, and the sender stops when told to.
+/// Differences of d elements take around 1.35 d to 2 d cells.
+/// ```rust
+/// use iron_rose::{RatelessDecoder, RatelessEncoder, Side};
+///
+/// let remote = RatelessEncoder::new((0..1000u64).filter(|&x| x != 7));
+/// let mut decoder = RatelessDecoder::new(0..1000u64);
+/// for cell in remote {
+///     decoder.add_cell(cell);
+///     if decoder.is_decoded() {
+///         break;
+///     }
+/// }
+/// let set = decoder.decode().unwrap();
+/// assert_eq!(set.into_iter().collect::<Vec<_>>(), vec![Side::Left(7)]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RatelessEncoder<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    window: Window<T>,
+    next: u64,
+    hasher: DefaultBuildHasher,
+}
+
+impl<T> RatelessEncoder<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// Encoder of the set of elements, starting at cell 0
+    pub fn new<I: IntoIterator<Item = T>>(elements: I) -> Self {
+        let hasher = DefaultBuildHasher::default();
+        let mut window = Window::new();
+        for element in elements {
+            let mapping = Mapping::new(hasher.hash_one(&element));
+            window.push(element, mapping);
+        }
+        Self {
+            window,
+            next: 0,
+            hasher,
+        }
+    }
+}
+
+impl<T> Iterator for RatelessEncoder<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    type Item = Cell<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut cell = Cell::default();
+        let hasher = &self.hasher;
+        self.window
+            .drain_index(self.next, |element| cell.encode(element.clone(), hasher));
+        self.next += 1;
+        Some(cell)
+    }
+}
+
+/// Receiving half of rateless reconciliation: subtracts the peer's coded cells, in order, from
+/// those of the local set and peels as they arrive. Elements only in the local set decode as
+/// [Left](Side::Left), those only in the peer's as [Right](Side::Right), as with
+/// `local - remote`. See [RatelessEncoder](RatelessEncoder).
+#[derive(Debug, Clone)]
+pub struct RatelessDecoder<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    local: RatelessEncoder<T>,
+    /// Elements recovered so far, to take out of cells that are still to come
+    recovered: Window<Side<T>>,
+    cells: Vec<Cell<T>>,
+    decoded: Vec<Side<T>>,
+    hasher: DefaultBuildHasher,
+}
+
+impl<T> RatelessDecoder<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// Decoder against the local set of elements
+    pub fn new<I: IntoIterator<Item = T>>(local: I) -> Self {
+        Self {
+            local: RatelessEncoder::new(local),
+            recovered: Window::new(),
+            cells: Vec::new(),
+            decoded: Vec::new(),
+            hasher: DefaultBuildHasher::default(),
+        }
+    }
+
+    /// Adds the peer's next coded cell, peeling whatever that makes decodable
+    pub fn add_cell(&mut self, remote: Cell<T>) {
+        let index = self.cells.len();
+        let local = self.local.next().expect("Encoders never run out of cells");
+        let mut cell = local - remote;
+        let hasher = &self.hasher;
+        self.recovered
+            .drain_index(index as u64, |side| take_out(&mut cell, side, hasher));
+        self.cells.push(cell);
+        self.peel(vec![index]);
+    }
+
+    /// Number of the peer's cells added so far
+    pub fn cells_received(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// True once every difference has been recovered. Every element is coded into the first
+    /// cell, so that is when it becomes empty.
+    pub fn is_decoded(&self) -> bool {
+        self.cells.first().is_some_and(|cell| cell.is_empty())
+    }
+
+    /// The differences, once [decoded](RatelessDecoder::is_decoded). Until then the Err holds
+    /// those recovered so far, and more cells are needed.
+    pub fn decode(&self) -> Result<HashSet<Side<T>>, DecodeError<T>> {
+        if self.is_decoded() {
+            Ok(self.decoded.iter().cloned().collect())
+        } else {
+            let remaining = self.cells.iter().filter(|cell| !cell.is_empty()).count();
+            Err(DecodeError::new(self.decoded.clone(), remaining.max(1)))
+        }
+    }
+
+    /// Peels pure cells, starting from candidates, out of every received cell they're coded
+    /// into, and queues them to be taken out of the cells still to come
+    fn peel(&mut self, mut candidates: Vec<usize>) {
+        while let Some(idx) = candidates.pop() {
+            let side = match self.cells[idx].decode(&self.hasher) {
+                Ok(side) => side,
+                Err(_) => continue,
+            };
+            let mut mapping = Mapping::new(self.hasher.hash_one(&*side));
+            let mut at = 0;
+            while (at as usize) < self.cells.len() {
+                let cell = &mut self.cells[at as usize];
+                take_out(cell, &side, &self.hasher);
+                if cell.is_pure(&self.hasher) {
+                    candidates.push(at as usize);
+                }
+                at = mapping.next_index();
+            }
+            self.recovered.push(side.clone(), mapping);
+            self.decoded.push(side);
+        }
+    }
+}
+
+/// Removes a recovered element from a cell of the `local - remote` difference
+fn take_out<T, H>(cell: &mut Cell<T>, side: &Side<T>, hasher: &H)
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    H: BuildHasher,
+{
+    match side {
+        Side::Left(element) => cell.unencode(element.clone(), hasher),
+        Side::Right(element) => cell.encode(element.clone(), hasher),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streams_until_decoded() {
+        let local: HashSet<u64> = (0..5000).filter(|x| x % 250 != 0).collect();
+        let remote: HashSet<u64> = (10..5010).collect();
+        let mut decoder = RatelessDecoder::new(local.iter().copied());
+        let mut cells = RatelessEncoder::new(remote.iter().copied());
+        while !decoder.is_decoded() {
+            assert!(decoder.decode().is_err());
+            decoder.add_cell(cells.next().unwrap());
+        }
+
+        let expected: HashSet<_> = local
+            .difference(&remote)
+            .map(|&x| Side::Left(x))
+            .chain(remote.difference(&local).map(|&x| Side::Right(x)))
+            .collect();
+        assert_eq!(expected.len(), 38);
+        assert_eq!(decoder.decode().unwrap(), expected);
+        assert!(decoder.cells_received() < 3 * expected.len());
+    }
+
+    #[test]
+    fn equal_sets_decode_from_one_cell() {
+        let mut decoder = RatelessDecoder::new(0..100u64);
+        decoder.add_cell(RatelessEncoder::new(0..100u64).next().unwrap());
+        assert!(decoder.decode().unwrap().is_empty());
+    }
+}
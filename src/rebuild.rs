@@ -0,0 +1,227 @@
+use crate::{IronRoseError, IBF};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Debug,
+    ops::{BitXor, BitXorAssign},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// How much drift a long lived sketch may accumulate before a
+/// [RebuildScheduler](RebuildScheduler) advises rebuilding it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RebuildThresholds {
+    /// Removals since the last rebuild. Removing an element that was never encoded corrupts the
+    /// sketch in a way only a rebuild fixes, so every removal is a small risk.
+    pub max_removals: usize,
+    /// Failed integrity checks (such as [verify_reconciled](crate::verify_reconciled)) since the
+    /// last rebuild
+    pub max_integrity_failures: usize,
+    /// Age of the sketch, if it should be rebuilt periodically regardless of drift
+    pub max_age: Option<Duration>,
+}
+
+impl Default for RebuildThresholds {
+    fn default() -> Self {
+        Self {
+            max_removals: 100_000,
+            max_integrity_failures: 1,
+            max_age: None,
+        }
+    }
+}
+
+/// Why a [RebuildScheduler](RebuildScheduler) advises a rebuild
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebuildReason {
+    /// Too many removals since the last rebuild
+    Removals(usize),
+    /// Too many failed integrity checks since the last rebuild
+    IntegrityFailures(usize),
+    /// The sketch is older than the maximum age
+    Age(Duration),
+}
+
+/// Tracks drift on a long lived IBF and signals when it should be rebuilt from the source of
+/// truth. Every rebuild starts a new generation. Counters are atomic, so a scheduler can be
+/// shared between the writers recording drift and the task doing the rebuilds.
+/// ```rust
+/// use std::sync::{Arc, RwLock};
+/// use iron_rose::{IronRoseError, RebuildReason, RebuildScheduler, RebuildThresholds, IBF};
+///
+/// let thresholds = RebuildThresholds { max_removals: 2, ..Default::default() };
+/// let scheduler = Arc::new(RebuildScheduler::new(thresholds));
+/// let live = Arc::new(RwLock::new(IBF::<u64>::new(20)));
+/// scheduler.record_removals(3);
+/// assert_eq!(scheduler.rebuild_reason(), Some(RebuildReason::Removals(3)));
+///
+/// let handle = scheduler.rebuild_in_background(live.clone(), || {
+///     let mut fresh = IBF::new(20);
+///     fresh.encode(1u64);
+///     Ok::<_, IronRoseError>(fresh)
+/// });
+/// assert_eq!(handle.join().unwrap(), Ok(1));
+/// assert_eq!(scheduler.rebuild_reason(), None);
+/// assert!(!live.read().unwrap().is_empty());
+/// ```
+#[derive(Debug)]
+pub struct RebuildScheduler {
+    thresholds: RebuildThresholds,
+    generation: AtomicU64,
+    removals: AtomicUsize,
+    integrity_failures: AtomicUsize,
+    built_at: Mutex<Instant>,
+}
+
+impl RebuildScheduler {
+    /// New scheduler for a sketch that was just built, as generation 0
+    pub fn new(thresholds: RebuildThresholds) -> Self {
+        Self {
+            thresholds,
+            generation: AtomicU64::new(0),
+            removals: AtomicUsize::new(0),
+            integrity_failures: AtomicUsize::new(0),
+            built_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Records a removal from the sketch
+    pub fn record_removal(&self) {
+        self.record_removals(1)
+    }
+
+    /// Records n removals from the sketch
+    pub fn record_removals(&self, n: usize) {
+        self.removals.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Records a failed integrity check of the sketch
+    pub fn record_integrity_failure(&self) {
+        self.integrity_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of rebuilds so far
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Why the sketch should be rebuilt, or None if it's fine as is
+    pub fn rebuild_reason(&self) -> Option<RebuildReason> {
+        let failures = self.integrity_failures.load(Ordering::Relaxed);
+        if failures >= self.thresholds.max_integrity_failures.max(1) {
+            return Some(RebuildReason::IntegrityFailures(failures));
+        }
+        let removals = self.removals.load(Ordering::Relaxed);
+        if removals > self.thresholds.max_removals {
+            return Some(RebuildReason::Removals(removals));
+        }
+        let age = self.built_at.lock().expect("poisoned").elapsed();
+        match self.thresholds.max_age {
+            Some(max_age) if age > max_age => Some(RebuildReason::Age(age)),
+            _ => None,
+        }
+    }
+
+    /// True if the sketch should be rebuilt
+    pub fn should_rebuild(&self) -> bool {
+        self.rebuild_reason().is_some()
+    }
+
+    /// Marks a rebuild as done, resetting the drift counters and starting a new generation,
+    /// which is returned
+    pub fn rebuilt(&self) -> u64 {
+        self.removals.store(0, Ordering::Relaxed);
+        self.integrity_failures.store(0, Ordering::Relaxed);
+        *self.built_at.lock().expect("poisoned") = Instant::now();
+        self.generation.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    /// Runs build on a background thread and swaps its result into live, so readers only ever
+    /// see the old or the new sketch. The handle yields the new generation, or the error from
+    /// build, in which case live is untouched. A poisoned live lock is reported as
+    /// [LockPoisoned](IronRoseError::LockPoisoned).
+    ///
+    /// Drift recorded while build runs is discarded with the rest when the swap happens, so
+    /// build should read the source of truth as late as it can.
+    pub fn rebuild_in_background<T, F, E>(
+        self: &Arc<Self>,
+        live: Arc<RwLock<IBF<T>>>,
+        build: F,
+    ) -> JoinHandle<Result<u64, E>>
+    where
+        T: Clone
+            + std::hash::Hash
+            + BitXor<Output = T>
+            + BitXorAssign
+            + Default
+            + PartialEq
+            + Eq
+            + Debug
+            + Send
+            + Sync
+            + 'static,
+        F: FnOnce() -> Result<IBF<T>, E> + Send + 'static,
+        E: From<IronRoseError> + Send + 'static,
+    {
+        let scheduler = Arc::clone(self);
+        thread::spawn(move || {
+            let fresh = build()?;
+            let mut live = live.write().map_err(|_| IronRoseError::LockPoisoned)?;
+            *live = fresh;
+            Ok(scheduler.rebuilt())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SketchParams;
+
+    #[test]
+    fn reasons_and_generations() {
+        let scheduler = RebuildScheduler::new(RebuildThresholds {
+            max_removals: 10,
+            max_integrity_failures: 2,
+            max_age: None,
+        });
+        assert!(!scheduler.should_rebuild());
+        scheduler.record_removals(10);
+        scheduler.record_integrity_failure();
+        assert!(!scheduler.should_rebuild());
+        scheduler.record_removal();
+        assert_eq!(
+            scheduler.rebuild_reason(),
+            Some(RebuildReason::Removals(11))
+        );
+        scheduler.record_integrity_failure();
+        assert_eq!(
+            scheduler.rebuild_reason(),
+            Some(RebuildReason::IntegrityFailures(2))
+        );
+        assert_eq!(scheduler.rebuilt(), 1);
+        assert_eq!(scheduler.generation(), 1);
+        assert!(!scheduler.should_rebuild());
+    }
+
+    #[test]
+    fn failed_build_leaves_live_sketch() {
+        let scheduler = Arc::new(RebuildScheduler::new(Default::default()));
+        let mut sketch = IBF::new(10);
+        sketch.encode(7u64);
+        let live = Arc::new(RwLock::new(sketch.clone()));
+        let over_budget = SketchParams::new(1000, 3).with_max_wire_bytes(100);
+        let handle = scheduler
+            .rebuild_in_background(live.clone(), move || IBF::try_with_params(over_budget));
+        assert!(matches!(
+            handle.join().unwrap(),
+            Err(IronRoseError::OverWireBudget { .. })
+        ));
+        assert_eq!(*live.read().unwrap(), sketch);
+        assert_eq!(scheduler.generation(), 0);
+    }
+}
@@ -0,0 +1,2071 @@
+//! A reconciliation session, walking one peer through the protocol: exchange
+//! [StrataEstimators](crate::StrataEstimator), size and exchange [IBFs](crate::IBF), then
+//! subtract and decode.
+//!
+//! Each step consumes the session and returns it in its next state, so calling steps out of
+//! order (sending an IBF before the estimators were exchanged, say) doesn't compile. Transport
+//! is up to the application: each state exposes what has to be sent to the peer.
+//! ```rust
+//! use iron_rose::reconcile::Session;
+//!
+//! let alice = Session::new((0..1000u64).collect());
+//! let bob = Session::new((4..1004u64).collect());
+//!
+//! // Swap estimators
+//! let (alice_estimator, bob_estimator) = (alice.estimator().clone(), bob.estimator().clone());
+//! let alice = alice.on_estimator(&bob_estimator).unwrap().proceed().unwrap();
+//! let bob = bob.on_estimator(&alice_estimator).unwrap().proceed().unwrap();
+//!
+//! // Swap IBFs, which both sides sized the same from the same pair of estimators
+//! let alice_ibf = alice.ibf().clone();
+//! let alice = alice.on_ibf(bob.ibf()).unwrap();
+//! let bob = bob.on_ibf(&alice_ibf).unwrap();
+//!
+//! assert_eq!(alice.difference().left().len(), 4);
+//! assert_eq!(bob.difference().right().len(), 4);
+//! ```
+//! Both peers size their IBF from the same pair of estimators, but they don't have to agree: a
+//! peer may run a different release, or size from a different estimator. Exchanging
+//! [proposals](Session::proposal) before the IBFs settles it, both sides then use the larger of
+//! the two shapes, see [on_proposal](Session::on_proposal).
+//!
+//! Applications that would rather move messages than session states around can use a
+//! [SyncSession](SyncSession), which also retries with larger IBFs when decoding fails. With the
+//! `tokio` feature, [sync_over](sync_over) runs one over any async stream.
+//! [reconcile_with_deadline](reconcile_with_deadline) runs one to a deadline, for jobs bound by
+//! an SLA.
+//!
+//! A hub reconciling with many peers at once can use a [Reconciler](Reconciler) instead, which
+//! shares one estimator and IBF between all of them.
+//!
+//! Skipping the estimator exchange is a compile error:
+//! ```compile_fail
+//! use iron_rose::{reconcile::Session, IBF};
+//!
+//! let alice = Session::new(vec![1u64]);
+//! alice.on_ibf(&IBF::new(10));
+//! ```
+use crate::ibf::check_hash_count;
+use crate::{
+    capabilities, profiles, Capabilities, Difference, Estimate, GrowthPolicy, IronRoseError,
+    RetryBudget, Side, SketchKind, SketchParams, StrataEstimator, StrataEstimatorConfig,
+    TimedDecode, WireElement, IBF,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fmt::Debug,
+    hash::Hash,
+    ops::{BitXor, BitXorAssign},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Smallest IBF a session will build, so that nearly identical sets still get a few cells to
+/// decode into
+const MIN_CELLS: usize = 16;
+
+/// Hash count of the IBFs a session builds
+const HASH_COUNT: usize = 3;
+
+/// Waiting for the peer's estimator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AwaitingEstimator;
+
+/// Waiting for the peer's IBF, holding the local one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AwaitingIbf<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    ibf: IBF<T>,
+}
+
+/// Reconciled, holding the decoded differences, local elements on the left
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Complete<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    difference: Difference<T>,
+}
+
+/// What a [Session](Session) does after the estimators are exchanged. More outcomes may be
+/// added, so matches outside the crate need a wildcard arm.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum EstimateOutcome<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// Go on to exchange IBFs
+    Proceed(Session<T, AwaitingIbf<T>>),
+    /// The sets are too far apart to reconcile within the session's maximum
+    TooDivergent {
+        /// The estimated differences
+        estimate: Estimate,
+    },
+}
+
+impl<T> EstimateOutcome<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// The session, if it can proceed
+    pub fn proceed(self) -> Option<Session<T, AwaitingIbf<T>>> {
+        match self {
+            EstimateOutcome::Proceed(session) => Some(session),
+            EstimateOutcome::TooDivergent { .. } => None,
+        }
+    }
+}
+
+/// What going on from the estimators would cost, from [dry_run](Session::dry_run)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DryRun {
+    /// The estimated differences
+    pub estimate: Estimate,
+    /// Shape of the IBF the session would build
+    pub params: SketchParams,
+    /// Size of the IBF message to the peer, see
+    /// [estimated_bytes](SketchParams::estimated_bytes)
+    pub message_bytes: usize,
+    /// Memory taken by the IBF's cells
+    pub memory_bytes: usize,
+    /// True if [on_estimator](Session::on_estimator) would end the session as
+    /// [TooDivergent](EstimateOutcome::TooDivergent)
+    pub too_divergent: bool,
+}
+
+/// One peer's side of a reconciliation, in protocol state S
+#[derive(Debug, Clone)]
+pub struct Session<T, S>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    elements: Vec<T>,
+    estimator: StrataEstimator,
+    max_reconcilable_diff: Option<usize>,
+    state: S,
+}
+
+impl<T> Session<T, AwaitingEstimator>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// Starts a session over the local set, encoding it into an estimator
+    pub fn new(elements: Vec<T>) -> Self {
+        let mut estimator = StrataEstimator::default();
+        for element in elements.iter() {
+            estimator.encode(element);
+        }
+        Self {
+            elements,
+            estimator,
+            max_reconcilable_diff: None,
+            state: AwaitingEstimator,
+        }
+    }
+
+    /// Gives up on reconciling once the estimators show more than max differences, rather than
+    /// building an IBF big enough to blow memory or bandwidth budgets. See
+    /// [on_estimator](Session::on_estimator).
+    pub fn with_max_reconcilable_diff(mut self, max: usize) -> Self {
+        self.max_reconcilable_diff = Some(max);
+        self
+    }
+
+    /// The local estimator, to send to the peer
+    pub fn estimator(&self) -> &StrataEstimator {
+        &self.estimator
+    }
+
+    /// Works out what taking the peer's estimator would lead to, without building the IBF or
+    /// leaving this state, so a scheduler can decide when, or whether, to run the expensive part
+    /// of the session.
+    /// ```rust
+    /// use iron_rose::reconcile::Session;
+    ///
+    /// let alice = Session::new((0..1000u64).collect()).with_max_reconcilable_diff(100);
+    /// let bob = Session::new((10..1010u64).collect());
+    /// let plan = alice.dry_run(bob.estimator()).unwrap();
+    /// assert_eq!(plan.estimate.value(), 20);
+    /// assert!(!plan.too_divergent);
+    /// assert!(plan.message_bytes > plan.params.size() * 20);
+    /// let alice = alice.on_estimator(bob.estimator()).unwrap().proceed().unwrap();
+    /// assert_eq!(alice.ibf().params(), plan.params);
+    /// ```
+    pub fn dry_run(&self, remote: &StrataEstimator) -> Result<DryRun, IronRoseError> {
+        let estimate = self.estimator.estimate(remote)?;
+        let params = params_for(estimate);
+        let bytes = params.estimated_bytes(SketchKind::ibf::<T>());
+        Ok(DryRun {
+            estimate,
+            params,
+            message_bytes: bytes.wire,
+            memory_bytes: bytes.memory,
+            too_divergent: self.too_divergent(estimate),
+        })
+    }
+
+    /// Takes the peer's estimator, and builds the local IBF sized for the estimated differences.
+    /// If the estimate is over the [maximum](Session::with_max_reconcilable_diff) the session
+    /// ends with [TooDivergent](EstimateOutcome::TooDivergent) instead, without building
+    /// anything.
+    /// ```rust
+    /// use iron_rose::reconcile::{EstimateOutcome, Session};
+    ///
+    /// let alice = Session::new((0..1000u64).collect()).with_max_reconcilable_diff(10);
+    /// let bob = Session::new((500..1500u64).collect());
+    /// match alice.on_estimator(bob.estimator()).unwrap() {
+    ///     EstimateOutcome::TooDivergent { estimate } => assert!(estimate.value() > 10),
+    ///     _ => unreachable!(),
+    /// }
+    /// ```
+    pub fn on_estimator(
+        self,
+        remote: &StrataEstimator,
+    ) -> Result<EstimateOutcome<T>, IronRoseError> {
+        let estimate = self.estimator.estimate(remote)?;
+        if self.too_divergent(estimate) {
+            return Ok(EstimateOutcome::TooDivergent { estimate });
+        }
+        let ibf = encode_all(&self.elements, params_for(estimate));
+        Ok(EstimateOutcome::Proceed(Session {
+            elements: self.elements,
+            estimator: self.estimator,
+            max_reconcilable_diff: self.max_reconcilable_diff,
+            state: AwaitingIbf { ibf },
+        }))
+    }
+
+    fn too_divergent(&self, estimate: Estimate) -> bool {
+        self.max_reconcilable_diff
+            .is_some_and(|max| estimate.value() > max)
+    }
+}
+
+impl<T> Session<T, AwaitingIbf<T>>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// The local IBF, to send to the peer
+    pub fn ibf(&self) -> &IBF<T> {
+        &self.state.ibf
+    }
+
+    /// The shape of the local IBF, to send to the peer as this side's proposal
+    pub fn proposal(&self) -> SketchParams {
+        self.state.ibf.params()
+    }
+
+    /// Takes the peer's [proposal](Session::proposal) and settles on the larger of the two
+    /// shapes, by size and then hash_count, rebuilding the local IBF if the peer's is the
+    /// larger one. Both peers settle on the same shape whichever order the proposals cross in,
+    /// so their IBFs can be subtracted.
+    ///
+    /// The proposal comes from the peer, so it's checked before anything is built: a hash_count
+    /// of 0 or over the size fails with [HashCountOutOfRange](IronRoseError::HashCountOutOfRange),
+    /// and a size over what the [maximum](Session::with_max_reconcilable_diff) number of
+    /// differences calls for, or over the default [RetryBudget](RetryBudget) without one, with
+    /// [OverCellBudget](IronRoseError::OverCellBudget).
+    /// ```rust
+    /// use iron_rose::{reconcile::Session, IronRoseError, SketchParams};
+    ///
+    /// let alice = Session::new((0..1000u64).collect());
+    /// let bob = Session::new((4..1004u64).collect());
+    /// let (alice_estimator, bob_estimator) = (alice.estimator().clone(), bob.estimator().clone());
+    /// let alice = alice.on_estimator(&bob_estimator).unwrap().proceed().unwrap();
+    /// let bob = bob.on_estimator(&alice_estimator).unwrap().proceed().unwrap();
+    ///
+    /// // Bob wants more headroom than Alice
+    /// let bob = bob.on_proposal(SketchParams::new(100, 3)).unwrap();
+    /// let (alice_proposal, bob_proposal) = (alice.proposal(), bob.proposal());
+    /// let alice = alice.on_proposal(bob_proposal).unwrap();
+    /// let bob = bob.on_proposal(alice_proposal).unwrap();
+    /// assert_eq!(alice.proposal(), SketchParams::new(100, 3));
+    ///
+    /// let alice = alice.on_ibf(bob.ibf()).unwrap();
+    /// assert_eq!(alice.difference().left().len(), 4);
+    /// ```
+    pub fn on_proposal(mut self, remote: SketchParams) -> Result<Self, IronRoseError> {
+        check_hash_count(remote.hash_count(), remote.size())?;
+        let max = match self.max_reconcilable_diff {
+            Some(max) => params_for(Estimate::Approximate(max)).size(),
+            None => RetryBudget::default().max_cells,
+        };
+        if remote.size() > max {
+            return Err(IronRoseError::OverCellBudget {
+                cells: remote.size(),
+                max,
+            });
+        }
+        let local = self.proposal();
+        let key = |params: SketchParams| (params.size(), params.hash_count());
+        if key(remote) > key(local) {
+            self.state.ibf = encode_all(&self.elements, remote);
+        }
+        Ok(self)
+    }
+
+    /// Takes the peer's IBF, subtracting it from the local one and decoding the differences
+    pub fn on_ibf(self, remote: &IBF<T>) -> Result<Session<T, Complete<T>>, IronRoseError> {
+        let difference = (&self.state.ibf - remote)?.decode_difference()?;
+        Ok(Session {
+            elements: self.elements,
+            estimator: self.estimator,
+            max_reconcilable_diff: self.max_reconcilable_diff,
+            state: Complete { difference },
+        })
+    }
+}
+
+impl<T> Session<T, Complete<T>>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// The differences, with local only elements on the left and remote only on the right
+    pub fn difference(&self) -> &Difference<T> {
+        &self.state.difference
+    }
+
+    /// Ends the session, returning the differences
+    pub fn into_difference(self) -> Difference<T> {
+        self.state.difference
+    }
+}
+
+/// Shape of the IBF to build for an estimate, with the same overhead as
+/// [estimate_differences](StrataEstimator::estimate_differences)
+fn params_for(estimate: Estimate) -> SketchParams {
+    SketchParams::new(
+        estimate.value().saturating_mul(2).max(MIN_CELLS),
+        HASH_COUNT,
+    )
+}
+
+/// An IBF of the given shape holding every element
+fn encode_all<T>(elements: &[T], params: SketchParams) -> IBF<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    let mut ibf = IBF::with_params(params);
+    for element in elements.iter() {
+        ibf.encode(element.clone());
+    }
+    ibf
+}
+
+/// First message of a [SyncSession](SyncSession): the sender's estimator
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EstimatorMsg {
+    /// The sender's estimator
+    pub estimator: StrataEstimator,
+}
+
+impl EstimatorMsg {
+    /// The message in the binary wire format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.estimator.to_bytes()
+    }
+
+    /// The message in an older version of the binary wire format, such as the
+    /// [wire_version](SyncSession::wire_version) agreed with the peer
+    pub fn to_bytes_as(&self, version: u8) -> Result<Vec<u8>, IronRoseError> {
+        self.estimator.to_bytes_as(version)
+    }
+
+    /// Reads a message written by [to_bytes](EstimatorMsg::to_bytes) or
+    /// [to_bytes_as](EstimatorMsg::to_bytes_as)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, IronRoseError> {
+        Ok(Self {
+            estimator: StrataEstimator::from_bytes(bytes)?,
+        })
+    }
+}
+
+/// The sender's IBF for one attempt of a [SyncSession](SyncSession)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de>"))]
+pub struct IbfMsg<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// Attempt the IBF was built for, starting at 1 and counting up with every retry
+    pub attempt: usize,
+    /// The sender's IBF
+    pub ibf: IBF<T>,
+}
+
+impl<T> IbfMsg<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + WireElement,
+{
+    /// The message in the binary wire format: the attempt as a little endian u32, then the IBF
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = (self.attempt as u32).to_le_bytes().to_vec();
+        out.extend(self.ibf.to_bytes());
+        out
+    }
+
+    /// The message in an older version of the binary wire format, such as the
+    /// [wire_version](SyncSession::wire_version) agreed with the peer
+    pub fn to_bytes_as(&self, version: u8) -> Result<Vec<u8>, IronRoseError> {
+        let mut out = (self.attempt as u32).to_le_bytes().to_vec();
+        out.extend(self.ibf.to_bytes_as(version)?);
+        Ok(out)
+    }
+
+    /// Reads a message written by [to_bytes](IbfMsg::to_bytes) or
+    /// [to_bytes_as](IbfMsg::to_bytes_as)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, IronRoseError> {
+        if bytes.len() < 4 {
+            return Err(IronRoseError::MalformedBytes {
+                reason: "missing attempt",
+            });
+        }
+        let (attempt, ibf) = bytes.split_at(4);
+        Ok(Self {
+            attempt: u32::from_le_bytes(attempt.try_into().expect("4 bytes")) as usize,
+            ibf: IBF::from_bytes(ibf)?,
+        })
+    }
+}
+
+/// Which estimator and first IBF a [SyncSession](SyncSession) uses. Peers have to run the same
+/// profile for their estimators to be comparable, which they check with a [Hello](Hello).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "profile")]
+#[non_exhaustive]
+pub enum SessionProfile {
+    /// A session started with [new](SyncSession::new)
+    Standard,
+    /// A session started with [realtime](SyncSession::realtime)
+    Realtime,
+    /// A profile of a newer release, which this one doesn't run
+    #[serde(other)]
+    Unknown,
+}
+
+/// Message a [SyncSession](SyncSession) sends before anything else, so peers running different
+/// releases or builds, as during a rolling upgrade, can check that they can reconcile and agree
+/// on how: build it with [hello](SyncSession::hello) and hand the peer's to
+/// [on_hello](SyncSession::on_hello).
+///
+/// It serializes with serde only, since it has to be readable whatever wire versions the peers
+/// support. Fields a newer peer adds are ignored, and profiles it adds read as
+/// [Unknown](SessionProfile::Unknown).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    /// The sender's build: the [wire versions](Capabilities::common_wire_version) it reads and
+    /// writes, and features such as keyed or rateless sketches
+    pub capabilities: Capabilities,
+    /// Profiles the sender runs sessions in
+    pub profiles: Vec<SessionProfile>,
+    /// Largest message the sender takes, in bytes of the binary wire format
+    pub max_sketch_bytes: usize,
+}
+
+/// What a [SyncSession](SyncSession) does with the peer's IBF. More outcomes may be added, so
+/// matches outside the crate need a wildcard arm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SyncOutcome<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// Reconciled, with local only elements on the left and remote only on the right
+    Done(Difference<T>),
+    /// The IBFs were too small to decode: send the peer this larger IBF and wait for its own
+    Retry(IbfMsg<T>),
+    /// The [deadline](SyncSession::with_deadline) passed while decoding, or before a retry
+    Expired {
+        /// The differences recovered before the deadline, local only elements on the left
+        recovered: Vec<Side<T>>,
+    },
+}
+
+/// One peer's side of a reconciliation, driven by messages rather than by typed states like a
+/// [Session](Session), so applications only have to move messages between peers: send
+/// [start](SyncSession::start), answer the peer's estimator with
+/// [on_estimator](SyncSession::on_estimator), and feed every IBF the peer sends to
+/// [on_ibf](SyncSession::on_ibf) until it's done. Every message serializes with serde or in the
+/// binary wire format. Peers that may run different releases exchange a [Hello](Hello) first.
+///
+/// Both peers run the same steps. Peeling succeeds or fails alike on both sides, as their
+/// differences are the same up to sides, so when one side has to retry so does the other, and
+/// both grow their IBFs the same way. Growth follows a [GrowthPolicy](crate::GrowthPolicy),
+/// doubling by default, within a [RetryBudget](crate::RetryBudget).
+/// ```rust
+/// use iron_rose::reconcile::{EstimatorMsg, IbfMsg, SyncOutcome, SyncSession};
+///
+/// let mut alice = SyncSession::new((0..1000u64).collect());
+/// let mut bob = SyncSession::new((3..1003u64).collect());
+///
+/// // Bytes on the wire, in whatever transport the application uses
+/// let (alice_estimator, bob_estimator) = (alice.start().to_bytes(), bob.start().to_bytes());
+/// let mut to_bob = alice
+///     .on_estimator(&EstimatorMsg::from_bytes(&bob_estimator).unwrap())
+///     .unwrap()
+///     .to_bytes();
+/// let mut to_alice = bob
+///     .on_estimator(&EstimatorMsg::from_bytes(&alice_estimator).unwrap())
+///     .unwrap()
+///     .to_bytes();
+/// let difference = loop {
+///     let from_bob = IbfMsg::from_bytes(&to_alice).unwrap();
+///     let from_alice = IbfMsg::from_bytes(&to_bob).unwrap();
+///     match (alice.on_ibf(&from_bob).unwrap(), bob.on_ibf(&from_alice).unwrap()) {
+///         (SyncOutcome::Done(difference), SyncOutcome::Done(_)) => break difference,
+///         (SyncOutcome::Retry(a), SyncOutcome::Retry(b)) => {
+///             to_bob = a.to_bytes();
+///             to_alice = b.to_bytes();
+///         }
+///         _ => unreachable!("peers retry together"),
+///     }
+/// };
+/// let mut missing_from_bob = difference.left().to_vec();
+/// missing_from_bob.sort();
+/// assert_eq!(missing_from_bob, vec![0, 1, 2]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SyncSession<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    elements: Vec<T>,
+    estimator: StrataEstimator,
+    profile: SessionProfile,
+    growth: GrowthPolicy,
+    budget: RetryBudget,
+    max_sketch_bytes: Option<usize>,
+    wire_version: u8,
+    estimate: Option<usize>,
+    attempt: usize,
+    ibf: Option<IBF<T>>,
+    deadline: Option<Instant>,
+    started: Instant,
+    bytes_sent: usize,
+    bytes_received: usize,
+    finished: Option<(usize, Duration)>,
+}
+
+impl<T> SyncSession<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// Starts a session over the local set, encoding it into an estimator
+    pub fn new(elements: Vec<T>) -> Self {
+        Self::with_estimator(
+            elements,
+            StrataEstimatorConfig::default(),
+            SessionProfile::Standard,
+        )
+    }
+
+    /// Starts a session for peers that reconcile every second or so and usually differ by 0 to 5
+    /// elements, with the [realtime](profiles::REALTIME_ESTIMATOR) estimator. Rather than
+    /// exchanging estimators first, both peers [speculate](SyncSession::speculate) with a
+    /// [small IBF](profiles::REALTIME_IBF), which reconciles in one round trip of messages
+    /// under a KiB each when the sets are as close as expected, and grows by the session's
+    /// [GrowthPolicy](crate::GrowthPolicy) when they aren't. Both peers have to be realtime
+    /// sessions for their estimators to be comparable.
+    /// ```rust
+    /// use iron_rose::reconcile::{SyncOutcome, SyncSession};
+    ///
+    /// let mut alice = SyncSession::realtime((0..1000u64).collect());
+    /// let mut bob = SyncSession::realtime((2..1001u64).collect());
+    /// let (to_bob, to_alice) = (alice.speculate(), bob.speculate());
+    /// assert!(to_bob.to_bytes().len() < 1024);
+    /// assert!(alice.start().to_bytes().len() < 1024);
+    ///
+    /// match alice.on_ibf(&to_alice).unwrap() {
+    ///     SyncOutcome::Done(difference) => assert_eq!(difference.len(), 3),
+    ///     _ => unreachable!("3 differences fit the speculative IBF"),
+    /// }
+    /// ```
+    pub fn realtime(elements: Vec<T>) -> Self {
+        Self::with_estimator(
+            elements,
+            profiles::REALTIME_ESTIMATOR,
+            SessionProfile::Realtime,
+        )
+    }
+
+    fn with_estimator(
+        elements: Vec<T>,
+        config: StrataEstimatorConfig,
+        profile: SessionProfile,
+    ) -> Self {
+        let mut estimator = StrataEstimator::with_config(config);
+        for element in elements.iter() {
+            estimator.encode(element);
+        }
+        Self {
+            elements,
+            estimator,
+            profile,
+            growth: GrowthPolicy::default(),
+            budget: RetryBudget::default(),
+            max_sketch_bytes: None,
+            wire_version: crate::WIRE_VERSION,
+            estimate: None,
+            attempt: 0,
+            ibf: None,
+            deadline: None,
+            started: Instant::now(),
+            bytes_sent: 0,
+            bytes_received: 0,
+            finished: None,
+        }
+    }
+
+    /// Grows IBFs that failed to decode by policy instead of doubling them. Both peers have to
+    /// use the same policy.
+    pub fn with_growth(mut self, policy: GrowthPolicy) -> Self {
+        self.growth = policy;
+        self
+    }
+
+    /// Gives up once a retry would go over budget, see [on_ibf](SyncSession::on_ibf)
+    pub fn with_budget(mut self, budget: RetryBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Advertises bytes in the [Hello](SyncSession::hello) as the largest message this side
+    /// takes, instead of the size of the largest IBF the budget allows. The application still
+    /// has to enforce it when reading messages.
+    pub fn with_max_sketch_bytes(mut self, bytes: usize) -> Self {
+        self.max_sketch_bytes = Some(bytes);
+        self
+    }
+
+    /// Stops decoding once deadline has passed, and doesn't retry after it, ending the session
+    /// with [Expired](SyncOutcome::Expired) instead
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// The local estimator, to send to the peer
+    pub fn start(&self) -> EstimatorMsg {
+        EstimatorMsg {
+            estimator: self.estimator.clone(),
+        }
+    }
+
+    /// The local [Hello](Hello), to send to the peer before the estimator
+    pub fn hello(&self) -> Hello {
+        let max_sketch_bytes = self.max_sketch_bytes.unwrap_or_else(|| {
+            let largest_ibf = SketchParams::new(self.budget.max_cells, HASH_COUNT);
+            ibf_msg_bytes::<T>(largest_ibf).max(estimator_msg_bytes(&self.estimator))
+        });
+        Hello {
+            capabilities: capabilities(),
+            profiles: vec![self.profile],
+            max_sketch_bytes,
+        }
+    }
+
+    /// Takes the peer's [Hello](Hello), settling the newest wire version both sides read, to
+    /// write messages in from then on, see [wire_version](SyncSession::wire_version), and
+    /// shrinking the budget so that no IBF is larger than either side takes. Fails with
+    /// [IncompatiblePeer](IronRoseError::IncompatiblePeer) if the peers share no wire version
+    /// or the peer doesn't run this session's profile, and with
+    /// [OverWireBudget](IronRoseError::OverWireBudget) if even the local estimator is larger
+    /// than the peer takes.
+    /// ```rust
+    /// use iron_rose::reconcile::SyncSession;
+    ///
+    /// let mut alice = SyncSession::new((0..1000u64).collect());
+    /// let mut bob = SyncSession::new((3..1003u64).collect()).with_max_sketch_bytes(64 * 1024);
+    /// let (to_bob, to_alice) = (alice.hello(), bob.hello());
+    /// alice.on_hello(&to_alice).unwrap();
+    /// bob.on_hello(&to_bob).unwrap();
+    /// assert_eq!(alice.wire_version(), iron_rose::WIRE_VERSION);
+    ///
+    /// // Messages go out in the agreed version
+    /// let estimator = alice.start().to_bytes_as(alice.wire_version()).unwrap();
+    /// assert!(estimator.len() <= to_alice.max_sketch_bytes);
+    ///
+    /// // A realtime peer's estimators can't be compared with this one's
+    /// let realtime = SyncSession::realtime((0..1000u64).collect());
+    /// assert!(alice.on_hello(&realtime.hello()).is_err());
+    /// ```
+    pub fn on_hello(&mut self, hello: &Hello) -> Result<(), IronRoseError> {
+        let version = capabilities()
+            .common_wire_version(&hello.capabilities)
+            .ok_or(IronRoseError::IncompatiblePeer {
+                reason: "no wire version in common",
+            })?;
+        if !hello.profiles.contains(&self.profile) {
+            return Err(IronRoseError::IncompatiblePeer {
+                reason: "the peer doesn't run this session's profile",
+            });
+        }
+        let estimator = estimator_msg_bytes(&self.estimator);
+        if estimator > hello.max_sketch_bytes {
+            return Err(IronRoseError::OverWireBudget {
+                bytes: estimator,
+                max: hello.max_sketch_bytes,
+            });
+        }
+        let max_bytes = match self.max_sketch_bytes {
+            Some(local) => local.min(hello.max_sketch_bytes),
+            None => hello.max_sketch_bytes,
+        };
+        self.wire_version = version;
+        self.budget.max_cells = self.budget.max_cells.min(max_cells_within::<T>(max_bytes));
+        Ok(())
+    }
+
+    /// The wire version to write messages to the peer in: the newest both sides read once the
+    /// peer's [Hello](SyncSession::on_hello) came in, [WIRE_VERSION](crate::WIRE_VERSION)
+    /// before
+    pub fn wire_version(&self) -> u8 {
+        self.wire_version
+    }
+
+    /// The attempt the local IBF was built for, 0 before there is one
+    pub fn attempt(&self) -> usize {
+        self.attempt
+    }
+
+    /// The estimated number of differences, once the peer's estimator came in
+    pub fn estimate(&self) -> Option<usize> {
+        self.estimate
+    }
+
+    /// Takes the peer's estimator, and builds the local IBF for the first attempt, sized for the
+    /// estimated differences but no larger than the budget allows, to send to the peer
+    pub fn on_estimator(&mut self, msg: &EstimatorMsg) -> Result<IbfMsg<T>, IronRoseError> {
+        let estimate = self.estimator.estimate(&msg.estimator)?;
+        self.estimate = Some(estimate.value());
+        let params = params_for(estimate);
+        let size = params.size().min(self.budget.max_cells.max(1));
+        self.bytes_sent += estimator_msg_bytes(&self.estimator);
+        self.bytes_received += estimator_msg_bytes(&msg.estimator);
+        let first = self.build(1, SketchParams::new(size, params.hash_count()));
+        Ok(self.sent(first))
+    }
+
+    /// Skips the estimator exchange, building the first attempt's IBF in the
+    /// [realtime](profiles::REALTIME_IBF) shape right away, to send to the peer in place of
+    /// the estimator. The peer's IBF is then fed to [on_ibf](SyncSession::on_ibf) as usual.
+    /// Without an estimate, retries grow by the session's [GrowthPolicy](crate::GrowthPolicy)
+    /// alone.
+    pub fn speculate(&mut self) -> IbfMsg<T> {
+        let first = self.build(1, profiles::REALTIME_IBF);
+        self.sent(first)
+    }
+
+    /// What the session cost so far, or in all once it's done, for aggregating sync efficiency
+    /// across a fleet. Bytes are counted at their size in the binary wire format, the local
+    /// estimator's as sent once the peer's came in.
+    /// ```rust
+    /// use iron_rose::reconcile::{SyncOutcome, SyncSession};
+    ///
+    /// let mut alice = SyncSession::new((0..1000u64).collect());
+    /// let mut bob = SyncSession::new((3..1003u64).collect());
+    /// let to_bob = alice.on_estimator(&bob.start()).unwrap();
+    /// let to_alice = bob.on_estimator(&alice.start()).unwrap();
+    /// assert!(matches!(alice.on_ibf(&to_alice), Ok(SyncOutcome::Done(_))));
+    ///
+    /// let report = alice.report();
+    /// assert_eq!((report.estimated, report.actual, report.rounds), (Some(6), Some(6), 1));
+    /// assert_eq!(report.bytes_sent, alice.start().to_bytes().len() + to_bob.to_bytes().len());
+    /// assert_eq!(report.bytes_sent, report.bytes_received);
+    /// let json = serde_json::to_string(&report).unwrap();
+    /// # let _ = json;
+    /// ```
+    pub fn report(&self) -> ReconcileReport {
+        let (actual, duration) = match self.finished {
+            Some((actual, duration)) => (Some(actual), duration),
+            None => (None, self.started.elapsed()),
+        };
+        ReconcileReport {
+            estimated: self.estimate,
+            actual,
+            rounds: self.attempt,
+            bytes_sent: self.bytes_sent,
+            bytes_received: self.bytes_received,
+            duration,
+            strategy: self.growth,
+        }
+    }
+
+    /// Takes the peer's IBF, subtracting it from the local one and decoding the differences. If
+    /// they don't decode, grows the local IBF for the next attempt, to send to the peer, or
+    /// fails with [DecodeFailed](IronRoseError::DecodeFailed) if that would go over budget.
+    ///
+    /// The peer's IBF settles the shape: if the local IBF is shaped differently, say because the
+    /// peer runs another release, or is missing because the estimators weren't exchanged, it is
+    /// rebuilt in the peer's shape first, failing with
+    /// [OverCellBudget](IronRoseError::OverCellBudget) if that's larger than the budget allows.
+    pub fn on_ibf(&mut self, msg: &IbfMsg<T>) -> Result<SyncOutcome<T>, IronRoseError> {
+        let params = msg.ibf.params();
+        self.bytes_received += ibf_msg_bytes::<T>(params);
+        if self.ibf.as_ref().map(IBF::params) != Some(params) {
+            if params.size() > self.budget.max_cells {
+                return Err(IronRoseError::OverCellBudget {
+                    cells: params.size(),
+                    max: self.budget.max_cells,
+                });
+            }
+            self.build(msg.attempt, params);
+        }
+        let local = self.ibf.as_ref().expect("built above");
+        let subtracted = (local - &msg.ibf)?;
+        let remaining_cells = match self.deadline {
+            None => match subtracted.decode_difference() {
+                Ok(difference) => return Ok(self.done(difference)),
+                Err(err) => err.remaining_cells(),
+            },
+            Some(deadline) => match subtracted.decode_before(deadline) {
+                Ok(TimedDecode::Complete(difference)) => return Ok(self.done(difference)),
+                Ok(TimedDecode::OutOfTime { recovered }) => {
+                    return Ok(SyncOutcome::Expired { recovered })
+                }
+                Err(err) if Instant::now() >= deadline => {
+                    return Ok(SyncOutcome::Expired {
+                        recovered: err.decoded().to_vec(),
+                    })
+                }
+                Err(err) => err.remaining_cells(),
+            },
+        };
+        let (attempt, size) = (
+            self.attempt + 1,
+            self.growth.next_size(params.size(), self.estimate),
+        );
+        if !self.budget.allows(attempt, size) {
+            return Err(IronRoseError::DecodeFailed { remaining_cells });
+        }
+        let retry = self.build(attempt, SketchParams::new(size, params.hash_count()));
+        Ok(SyncOutcome::Retry(self.sent(retry)))
+    }
+
+    /// Counts msg as sent
+    fn sent(&mut self, msg: IbfMsg<T>) -> IbfMsg<T> {
+        self.bytes_sent += ibf_msg_bytes::<T>(msg.ibf.params());
+        msg
+    }
+
+    /// Ends the session with difference
+    fn done(&mut self, difference: Difference<T>) -> SyncOutcome<T> {
+        self.finished = Some((difference.len(), self.started.elapsed()));
+        SyncOutcome::Done(difference)
+    }
+
+    /// Rebuilds the local IBF for attempt, returning the message carrying it
+    fn build(&mut self, attempt: usize, params: SketchParams) -> IbfMsg<T> {
+        let ibf = encode_all(&self.elements, params);
+        self.attempt = attempt;
+        self.ibf = Some(ibf.clone());
+        IbfMsg { attempt, ibf }
+    }
+}
+
+/// Size of an [EstimatorMsg](EstimatorMsg) carrying estimator in the binary wire format
+fn estimator_msg_bytes(estimator: &StrataEstimator) -> usize {
+    let config = estimator.config();
+    let kind = SketchKind::StrataEstimator {
+        strata: config.strata(),
+    };
+    config.stratum().estimated_bytes(kind).wire
+}
+
+/// Size of an [IbfMsg](IbfMsg) carrying an IBF of T of the params in the binary wire format
+fn ibf_msg_bytes<T>(params: SketchParams) -> usize {
+    4 + params.estimated_bytes(SketchKind::ibf::<T>()).wire
+}
+
+/// Most cells an [IbfMsg](IbfMsg) carrying an IBF of T can have and still fit in bytes
+fn max_cells_within<T>(bytes: usize) -> usize {
+    let empty = ibf_msg_bytes::<T>(SketchParams::new(0, HASH_COUNT));
+    let cell = ibf_msg_bytes::<T>(SketchParams::new(1, HASH_COUNT)) - empty;
+    bytes.saturating_sub(empty) / cell
+}
+
+/// What one [SyncSession](SyncSession) cost, from [report](SyncSession::report)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ReconcileReport {
+    /// Number of differences the estimators predicted, None if they weren't exchanged
+    pub estimated: Option<usize>,
+    /// Number of differences decoded, None unless the session is done
+    pub actual: Option<usize>,
+    /// Number of IBFs built, the first attempt and every retry
+    pub rounds: usize,
+    /// Bytes of the messages sent to the peer
+    pub bytes_sent: usize,
+    /// Bytes of the messages received from the peer
+    pub bytes_received: usize,
+    /// Time from the start of the session until it was done, or until now if it isn't
+    pub duration: Duration,
+    /// How IBFs were grown on retries
+    pub strategy: GrowthPolicy,
+}
+
+/// How a reconciliation against a deadline ended, from
+/// [reconcile_with_deadline](reconcile_with_deadline). More outcomes may be added, so matches
+/// outside the crate need a wildcard arm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DeadlineOutcome<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// Reconciled in time, with local only elements on the left and remote only on the right
+    Done(Difference<T>),
+    /// The deadline passed first
+    Expired(Progress<T>),
+}
+
+/// How far a reconciliation got before its deadline passed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// The attempt the last IBF was built for, 0 if the estimators weren't exchanged yet
+    pub attempt: usize,
+    /// The estimated number of differences, once the estimators were exchanged
+    pub estimate: Option<usize>,
+    /// Differences recovered from the last attempt before the deadline, local only elements on
+    /// the left. They are genuine differences, but there may be more.
+    pub recovered: Vec<Side<T>>,
+}
+
+impl<T> SyncSession<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    fn progress(&self, recovered: Vec<Side<T>>) -> DeadlineOutcome<T> {
+        DeadlineOutcome::Expired(Progress {
+            attempt: self.attempt,
+            estimate: self.estimate,
+            recovered,
+        })
+    }
+}
+
+/// Reconciles local_ids with a peer's set by deadline, running a [SyncSession](SyncSession)
+/// with that [deadline](SyncSession::with_deadline), for sync jobs bound by an SLA. If the
+/// deadline passes first, [Expired](DeadlineOutcome::Expired) reports how far it got.
+///
+/// exchange moves messages: it sends its bytes to the peer and returns the peer's next message,
+/// with the time left until the deadline to use as its I/O timeout, e.g. with
+/// [set_read_timeout](std::net::TcpStream::set_read_timeout). Errors it returns are passed on,
+/// except for [TimedOut](std::io::ErrorKind::TimedOut) and
+/// [WouldBlock](std::io::ErrorKind::WouldBlock), which end the session as expired. Errors from
+/// the session come back as [InvalidData](std::io::ErrorKind::InvalidData) errors wrapping an
+/// [IronRoseError](IronRoseError).
+///
+/// Peers should work to about the same deadline: one that stops early leaves the other waiting
+/// on its exchange until its own deadline.
+/// ```rust
+/// use iron_rose::reconcile::{reconcile_with_deadline, DeadlineOutcome};
+/// use std::{io, sync::mpsc, thread, time::{Duration, Instant}};
+///
+/// let deadline = Instant::now() + Duration::from_secs(10);
+/// let (to_bob, from_alice) = mpsc::channel::<Vec<u8>>();
+/// let (to_alice, from_bob) = mpsc::channel::<Vec<u8>>();
+/// let bob = thread::spawn(move || {
+///     reconcile_with_deadline(deadline, (5..1005u64).collect(), |bytes, timeout| {
+///         to_alice.send(bytes.to_vec()).unwrap();
+///         from_alice
+///             .recv_timeout(timeout)
+///             .map_err(|_| io::ErrorKind::TimedOut.into())
+///     })
+/// });
+/// let alice = reconcile_with_deadline(deadline, (0..1000u64).collect(), |bytes, timeout| {
+///     to_bob.send(bytes.to_vec()).unwrap();
+///     from_bob
+///         .recv_timeout(timeout)
+///         .map_err(|_| io::ErrorKind::TimedOut.into())
+/// });
+/// match alice.unwrap() {
+///     DeadlineOutcome::Done(difference) => assert_eq!(difference.len(), 10),
+///     other => panic!("not done in time: {:?}", other),
+/// }
+/// assert!(matches!(bob.join().unwrap(), Ok(DeadlineOutcome::Done(_))));
+/// ```
+pub fn reconcile_with_deadline<T, F>(
+    deadline: Instant,
+    local_ids: Vec<T>,
+    mut exchange: F,
+) -> std::io::Result<DeadlineOutcome<T>>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + WireElement,
+    F: FnMut(&[u8], Duration) -> std::io::Result<Vec<u8>>,
+{
+    let mut session = SyncSession::new(local_ids).with_deadline(deadline);
+    let remote = match exchange_before(deadline, &mut exchange, &session.start().to_bytes())? {
+        Some(remote) => EstimatorMsg::from_bytes(&remote).map_err(invalid_data)?,
+        None => return Ok(session.progress(Vec::new())),
+    };
+    let mut msg = session.on_estimator(&remote).map_err(invalid_data)?;
+    loop {
+        let remote = match exchange_before(deadline, &mut exchange, &msg.to_bytes())? {
+            Some(remote) => IbfMsg::from_bytes(&remote).map_err(invalid_data)?,
+            None => return Ok(session.progress(Vec::new())),
+        };
+        match session.on_ibf(&remote).map_err(invalid_data)? {
+            SyncOutcome::Done(difference) => return Ok(DeadlineOutcome::Done(difference)),
+            SyncOutcome::Retry(retry) => msg = retry,
+            SyncOutcome::Expired { recovered } => return Ok(session.progress(recovered)),
+        }
+    }
+}
+
+/// Runs exchange with the time left until deadline, None if there is none left or it timed out
+fn exchange_before<F>(
+    deadline: Instant,
+    exchange: &mut F,
+    bytes: &[u8],
+) -> std::io::Result<Option<Vec<u8>>>
+where
+    F: FnMut(&[u8], Duration) -> std::io::Result<Vec<u8>>,
+{
+    let remaining = match deadline.checked_duration_since(Instant::now()) {
+        Some(remaining) if !remaining.is_zero() => remaining,
+        _ => return Ok(None),
+    };
+    match exchange(bytes, remaining) {
+        Ok(remote) => Ok(Some(remote)),
+        Err(err)
+            if matches!(
+                err.kind(),
+                std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+            ) =>
+        {
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Largest frame [sync_over](sync_over) accepts from a peer
+#[cfg(feature = "tokio")]
+const MAX_FRAME_BYTES: usize = 1 << 30;
+
+/// Reconciles local_ids with the set of the peer at the other end of stream, running a
+/// [SyncSession](SyncSession) to completion and returning the differences, local only elements
+/// on the [Left](crate::Side::Left). Messages go over the stream in their binary wire format,
+/// each prefixed with its length as a little endian u32.
+///
+/// Both ends call sync_over, with the same element type. Each end writes its message while it
+/// reads the peer's, so neither blocks on a full buffer. Errors from the session, and frames
+/// over 1 GiB, come back as [InvalidData](std::io::ErrorKind::InvalidData) errors wrapping an
+/// [IronRoseError](IronRoseError).
+/// ```rust
+/// use iron_rose::{reconcile::sync_over, Side};
+///
+/// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+/// let (alice, bob) = tokio::io::duplex(1024);
+/// let (alice, bob) = tokio::join!(
+///     sync_over(alice, (0..1000u64).collect()),
+///     sync_over(bob, (1..1001u64).collect()),
+/// );
+/// let mut alice = alice.unwrap();
+/// alice.sort();
+/// assert_eq!(alice, vec![Side::Left(0), Side::Right(1000)]);
+/// assert_eq!(bob.unwrap().len(), 2);
+/// # });
+/// ```
+#[cfg(feature = "tokio")]
+pub async fn sync_over<S, T>(stream: S, local_ids: Vec<T>) -> std::io::Result<Vec<crate::Side<T>>>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite,
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + WireElement,
+{
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let mut session = SyncSession::new(local_ids);
+    let local = session.start().to_bytes();
+    let (_, remote) = tokio::try_join!(write_frame(&mut writer, &local), read_frame(&mut reader))?;
+    let remote = EstimatorMsg::from_bytes(&remote).map_err(invalid_data)?;
+    let mut msg = session.on_estimator(&remote).map_err(invalid_data)?;
+    loop {
+        let local = msg.to_bytes();
+        let (_, remote) =
+            tokio::try_join!(write_frame(&mut writer, &local), read_frame(&mut reader))?;
+        let remote = IbfMsg::from_bytes(&remote).map_err(invalid_data)?;
+        match session.on_ibf(&remote).map_err(invalid_data)? {
+            SyncOutcome::Done(difference) => return Ok(difference.into_iter().collect()),
+            SyncOutcome::Retry(retry) => msg = retry,
+            SyncOutcome::Expired { .. } => unreachable!("no deadline was set"),
+        }
+    }
+}
+
+/// Like [sync_over](sync_over), but gives up by deadline, like
+/// [reconcile_with_deadline](reconcile_with_deadline). Reads and writes on stream are cut off
+/// at the deadline, so the runtime needs its time driver enabled.
+/// ```rust
+/// use iron_rose::reconcile::{sync_over_with_deadline, DeadlineOutcome};
+/// use std::time::{Duration, Instant};
+///
+/// # tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap().block_on(async {
+/// // A peer that never answers
+/// let (alice, _bob) = tokio::io::duplex(1024);
+/// let deadline = Instant::now() + Duration::from_millis(10);
+/// match sync_over_with_deadline(alice, (0..1000u64).collect(), deadline).await.unwrap() {
+///     DeadlineOutcome::Expired(progress) => assert_eq!(progress.attempt, 0),
+///     _ => unreachable!("nobody to reconcile with"),
+/// }
+/// # });
+/// ```
+#[cfg(feature = "tokio")]
+pub async fn sync_over_with_deadline<S, T>(
+    stream: S,
+    local_ids: Vec<T>,
+    deadline: Instant,
+) -> std::io::Result<DeadlineOutcome<T>>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite,
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + WireElement,
+{
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let mut session = SyncSession::new(local_ids).with_deadline(deadline);
+    let local = session.start().to_bytes();
+    let round =
+        async { tokio::try_join!(write_frame(&mut writer, &local), read_frame(&mut reader)) };
+    let remote = match tokio::time::timeout_at(deadline.into(), round).await {
+        Ok(round) => EstimatorMsg::from_bytes(&round?.1).map_err(invalid_data)?,
+        Err(_) => return Ok(session.progress(Vec::new())),
+    };
+    let mut msg = session.on_estimator(&remote).map_err(invalid_data)?;
+    loop {
+        let local = msg.to_bytes();
+        let round =
+            async { tokio::try_join!(write_frame(&mut writer, &local), read_frame(&mut reader)) };
+        let remote = match tokio::time::timeout_at(deadline.into(), round).await {
+            Ok(round) => IbfMsg::from_bytes(&round?.1).map_err(invalid_data)?,
+            Err(_) => return Ok(session.progress(Vec::new())),
+        };
+        match session.on_ibf(&remote).map_err(invalid_data)? {
+            SyncOutcome::Done(difference) => return Ok(DeadlineOutcome::Done(difference)),
+            SyncOutcome::Retry(retry) => msg = retry,
+            SyncOutcome::Expired { recovered } => return Ok(session.progress(recovered)),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+async fn write_frame<W>(writer: &mut W, bytes: &[u8]) -> std::io::Result<()>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    use tokio::io::AsyncWriteExt;
+
+    if bytes.len() > MAX_FRAME_BYTES {
+        return Err(invalid_data(IronRoseError::MalformedBytes {
+            reason: "frame too long",
+        }));
+    }
+    writer
+        .write_all(&(bytes.len() as u32).to_le_bytes())
+        .await?;
+    writer.write_all(bytes).await?;
+    writer.flush().await
+}
+
+#[cfg(feature = "tokio")]
+async fn read_frame<R>(reader: &mut R) -> std::io::Result<Vec<u8>>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    use tokio::io::AsyncReadExt;
+
+    let len = reader.read_u32_le().await? as usize;
+    if len > MAX_FRAME_BYTES {
+        return Err(invalid_data(IronRoseError::MalformedBytes {
+            reason: "frame too long",
+        }));
+    }
+    // Grown as the frame arrives, so a length the peer doesn't follow up on costs nothing
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes).await?;
+    if bytes.len() < len {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+fn invalid_data(err: IronRoseError) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+}
+
+/// What a peer sent a [Reconciler](Reconciler): its IBF, and optionally its estimator
+#[derive(Debug, Clone)]
+pub struct PeerSketches<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// The peer's estimator, to estimate the differences from along the way
+    pub estimator: Option<StrataEstimator>,
+    /// The peer's IBF
+    pub ibf: IBF<T>,
+}
+
+/// How reconciling with one peer went, from [against_many](Reconciler::against_many)
+#[derive(Debug, Clone)]
+pub struct Outcome<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// The estimated differences, if the peer sent an estimator
+    pub estimate: Option<Estimate>,
+    /// The differences, local only elements on the left and the peer's on the right, or why
+    /// they couldn't be had
+    pub difference: Result<Difference<T>, IronRoseError>,
+}
+
+/// The local side of reconciliations with many peers at once, for hub nodes that reconcile with
+/// dozens of spokes every cycle. The estimator and the IBF are built once, up front, and shared
+/// by every peer, rather than once per [Session](Session).
+///
+/// The IBF is built in the shape given, which spokes are expected to use. A peer sending an
+/// IBF of another shape is still served, from an IBF built for that shape once per call.
+/// ```rust
+/// use iron_rose::{
+///     reconcile::{PeerSketches, Reconciler},
+///     SketchParams, StrataEstimator, IBF,
+/// };
+///
+/// let params = SketchParams::new(60, 3);
+/// let hub = Reconciler::new((0..1000u64).collect(), params);
+/// let peers = (1..4u64)
+///     .map(|spoke| {
+///         let mut ibf = IBF::with_params(params);
+///         (spoke..1000).for_each(|i| ibf.encode(i));
+///         PeerSketches { estimator: None, ibf }
+///     })
+///     .collect::<Vec<_>>();
+/// let outcomes = hub.against_many(&peers);
+/// let missing = outcomes
+///     .iter()
+///     .map(|outcome| outcome.difference.as_ref().unwrap().left().len())
+///     .collect::<Vec<_>>();
+/// assert_eq!(missing, vec![1, 2, 3]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Reconciler<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    elements: Vec<T>,
+    estimator: StrataEstimator,
+    ibf: Arc<IBF<T>>,
+}
+
+impl<T> Reconciler<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// Encodes the local set, into an estimator and an IBF of the given shape
+    pub fn new(elements: Vec<T>, params: SketchParams) -> Self {
+        let mut estimator = StrataEstimator::default();
+        for element in elements.iter() {
+            estimator.encode(element);
+        }
+        let ibf = Arc::new(encode_all(&elements, params));
+        Self {
+            elements,
+            estimator,
+            ibf,
+        }
+    }
+
+    /// The local estimator, to send to every peer
+    pub fn estimator(&self) -> &StrataEstimator {
+        &self.estimator
+    }
+
+    /// The local IBF, to send to every peer
+    pub fn ibf(&self) -> &IBF<T> {
+        &self.ibf
+    }
+
+    /// Reconciles with every peer, returning an outcome for each, in the same order. Peers are
+    /// subtracted and decoded in parallel on the rayon thread pool if the `rayon` feature is on,
+    /// one after the other otherwise.
+    pub fn against_many(&self, peers: &[PeerSketches<T>]) -> Vec<Outcome<T>>
+    where
+        T: Send + Sync,
+    {
+        let mut ibfs = HashMap::new();
+        ibfs.insert(self.ibf.params(), Arc::clone(&self.ibf));
+        for peer in peers {
+            ibfs.entry(peer.ibf.params())
+                .or_insert_with_key(|params| Arc::new(encode_all(&self.elements, *params)));
+        }
+        let against = |peer: &PeerSketches<T>| self.against(&ibfs[&peer.ibf.params()], peer);
+
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+            peers.par_iter().map(against).collect()
+        }
+        #[cfg(not(feature = "rayon"))]
+        {
+            peers.iter().map(against).collect()
+        }
+    }
+
+    fn against(&self, local: &IBF<T>, peer: &PeerSketches<T>) -> Outcome<T> {
+        let estimate = match &peer.estimator {
+            Some(remote) => match self.estimator.estimate(remote) {
+                Ok(estimate) => Some(estimate),
+                Err(err) => {
+                    return Outcome {
+                        estimate: None,
+                        difference: Err(err),
+                    }
+                }
+            },
+            None => None,
+        };
+        let difference = (local - &peer.ibf)
+            .and_then(|diff| diff.decode_difference().map_err(IronRoseError::from));
+        Outcome {
+            estimate,
+            difference,
+        }
+    }
+}
+
+/// Recently computed [Differences](crate::Difference), keyed by peer and epoch, so that repeated
+/// identical requests (retries, duplicates from a load balancer) are answered without
+/// subtracting and decoding the sketches again. Entries expire ttl after they were inserted,
+/// and expired entries are dropped whenever the cache is written to.
+/// ```rust
+/// use std::time::Duration;
+/// use iron_rose::{reconcile::DiffCache, IronRoseError, IBF};
+///
+/// let mut cache = DiffCache::new(Duration::from_secs(30));
+/// let (mut local, remote) = (IBF::new(20), IBF::new(20));
+/// local.encode(1u64);
+/// let diff = cache
+///     .get_or_compute("peer-a", 7, || -> Result<_, IronRoseError> {
+///         Ok((&local - &remote)?.decode_difference()?)
+///     })
+///     .unwrap();
+/// assert_eq!(diff.left(), &[1]);
+/// // Served from the cache, without decoding
+/// assert!(cache.get(&"peer-a", 7).is_some());
+/// ```
+#[derive(Debug, Clone)]
+pub struct DiffCache<P, T>
+where
+    P: Eq + Hash,
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    ttl: Duration,
+    entries: HashMap<(P, u64), (Instant, Arc<Difference<T>>)>,
+}
+
+impl<P, T> DiffCache<P, T>
+where
+    P: Eq + Hash,
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// New, empty cache whose entries live for ttl
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// The cached difference for peer at epoch, unless it's missing or expired
+    pub fn get(&self, peer: &P, epoch: u64) -> Option<Arc<Difference<T>>>
+    where
+        P: Clone,
+    {
+        self.entries
+            .get(&(peer.clone(), epoch))
+            .filter(|(inserted, _)| inserted.elapsed() < self.ttl)
+            .map(|(_, diff)| Arc::clone(diff))
+    }
+
+    /// Caches the difference for peer at epoch, replacing any earlier one
+    pub fn insert(&mut self, peer: P, epoch: u64, diff: Difference<T>) -> Arc<Difference<T>> {
+        self.evict_expired();
+        let diff = Arc::new(diff);
+        self.entries
+            .insert((peer, epoch), (Instant::now(), Arc::clone(&diff)));
+        diff
+    }
+
+    /// The cached difference for peer at epoch, computing and caching it if there is none. An
+    /// Err from compute is returned as is, and not cached.
+    pub fn get_or_compute<F, E>(
+        &mut self,
+        peer: P,
+        epoch: u64,
+        compute: F,
+    ) -> Result<Arc<Difference<T>>, E>
+    where
+        P: Clone,
+        F: FnOnce() -> Result<Difference<T>, E>,
+    {
+        match self.get(&peer, epoch) {
+            Some(diff) => Ok(diff),
+            None => Ok(self.insert(peer, epoch, compute()?)),
+        }
+    }
+
+    /// Drops every expired entry
+    pub fn evict_expired(&mut self) {
+        let ttl = self.ttl;
+        self.entries
+            .retain(|_, (inserted, _)| inserted.elapsed() < ttl);
+    }
+
+    /// Number of entries, including expired ones that haven't been dropped yet
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// True if there are no entries
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saturated_estimates_saturate_the_size() {
+        let params = params_for(Estimate::Approximate(usize::MAX));
+        assert_eq!(params.size(), usize::MAX);
+        assert_eq!(params_for(Estimate::Exact(0)).size(), MIN_CELLS);
+    }
+
+    #[test]
+    fn hellos_negotiate_versions_and_budgets() {
+        let mut alice = SyncSession::new((0..1000u64).collect());
+        let mut older = alice.hello();
+        older.capabilities.wire_version = crate::MIN_WIRE_VERSION;
+        older.capabilities.min_wire_version = 0;
+        older.max_sketch_bytes = 60 * 1024;
+        alice.on_hello(&older).unwrap();
+        assert_eq!(alice.wire_version(), crate::MIN_WIRE_VERSION);
+        let budget = max_cells_within::<u64>(60 * 1024);
+        assert_eq!(alice.budget.max_cells, budget);
+        assert!(ibf_msg_bytes::<u64>(SketchParams::new(budget, HASH_COUNT)) <= 60 * 1024);
+        assert!(ibf_msg_bytes::<u64>(SketchParams::new(budget + 1, HASH_COUNT)) > 60 * 1024);
+
+        // What alice sends an older peer is in a version it reads
+        let msg = alice.on_estimator(&alice.start()).unwrap();
+        let bytes = msg.to_bytes_as(alice.wire_version()).unwrap();
+        assert_eq!(bytes[4], crate::MIN_WIRE_VERSION);
+        assert_eq!(IbfMsg::<u64>::from_bytes(&bytes).unwrap(), msg);
+
+        let mut ancient = older.clone();
+        ancient.capabilities.wire_version = crate::MIN_WIRE_VERSION - 1;
+        assert_eq!(
+            alice.on_hello(&ancient).unwrap_err().kind(),
+            crate::ErrorKind::Incompatible
+        );
+        let mut tiny = older.clone();
+        tiny.max_sketch_bytes = 1024;
+        assert!(matches!(
+            alice.on_hello(&tiny),
+            Err(IronRoseError::OverWireBudget { max: 1024, .. })
+        ));
+
+        // A newer peer's profiles and fields this release doesn't know are skipped over
+        let newer = r#"{
+            "capabilities": {"wire_version": 200, "min_wire_version": 2, "quantum": true},
+            "profiles": [{"profile": "Standard"}, {"profile": "Telepathic"}],
+            "max_sketch_bytes": 1000000,
+            "compression": "zstd"
+        }"#;
+        let newer: Hello = serde_json::from_str(newer).unwrap();
+        assert_eq!(
+            newer.profiles,
+            vec![SessionProfile::Standard, SessionProfile::Unknown]
+        );
+        alice.on_hello(&newer).unwrap();
+        assert_eq!(alice.wire_version(), crate::WIRE_VERSION);
+    }
+
+    #[test]
+    fn sync_sessions_retry_until_decoded() {
+        let mut alice = SyncSession::new((0..1000u64).collect());
+        let mut bob = SyncSession::new((100..1100u64).collect());
+        // Sized from their own estimators, the IBFs are far too small at first
+        let mut to_bob = alice.on_estimator(&alice.start()).unwrap();
+        let mut to_alice = bob.on_estimator(&bob.start()).unwrap();
+        assert_eq!(to_bob.ibf.params().size(), MIN_CELLS);
+        let difference = loop {
+            match (
+                alice.on_ibf(&to_alice).unwrap(),
+                bob.on_ibf(&to_bob).unwrap(),
+            ) {
+                (SyncOutcome::Done(difference), SyncOutcome::Done(_)) => break difference,
+                (SyncOutcome::Retry(a), SyncOutcome::Retry(b)) => {
+                    assert_eq!(a.ibf.params(), b.ibf.params());
+                    to_bob = a;
+                    to_alice = b;
+                }
+                _ => panic!("peers retried apart"),
+            }
+        };
+        assert_eq!(difference.len(), 200);
+        assert_eq!(alice.attempt(), bob.attempt());
+        assert!(alice.attempt() > 1);
+        let report = alice.report();
+        assert_eq!((report.actual, report.rounds), (Some(200), alice.attempt()));
+        assert_eq!(report.bytes_sent, bob.report().bytes_received);
+        assert!(report.bytes_sent > alice.start().to_bytes().len() + alice.attempt() * MIN_CELLS);
+        let json = serde_json::to_string(&to_bob).unwrap();
+        assert_eq!(serde_json::from_str::<IbfMsg<u64>>(&json).unwrap(), to_bob);
+        assert_eq!(IbfMsg::from_bytes(&to_bob.to_bytes()).unwrap(), to_bob);
+        assert!(IbfMsg::<u64>::from_bytes(&[1, 0]).is_err());
+
+        // A peer that skipped the estimators is answered in the shape it sent, until the budget
+        // runs out
+        let budget = RetryBudget {
+            max_attempts: 2,
+            max_cells: 1000,
+        };
+        let mut carol = SyncSession::new((0..1000u64).collect()).with_budget(budget);
+        let mut dave = SyncSession::new((500..1500u64).collect());
+        let first = dave.on_estimator(&dave.start()).unwrap();
+        let retry = match carol.on_ibf(&first).unwrap() {
+            SyncOutcome::Retry(retry) => retry,
+            _ => panic!("too small to decode"),
+        };
+        assert_eq!(retry.attempt, 2);
+        assert_eq!(retry.ibf.params().size(), 2 * MIN_CELLS);
+        let retry = match dave.on_ibf(&retry).unwrap() {
+            SyncOutcome::Retry(retry) => retry,
+            _ => panic!("too small to decode"),
+        };
+        assert_eq!(retry.attempt, 3);
+        assert!(matches!(
+            carol.on_ibf(&retry),
+            Err(IronRoseError::DecodeFailed { .. })
+        ));
+
+        // Nor can a peer get past the budget by sending a larger IBF than it allows
+        let mut erin = SyncSession::new((0..1000u64).collect()).with_budget(budget);
+        let huge = IbfMsg {
+            attempt: 1,
+            ibf: IBF::new(1001),
+        };
+        assert_eq!(
+            erin.on_ibf(&huge).unwrap_err(),
+            IronRoseError::OverCellBudget {
+                cells: 1001,
+                max: 1000
+            }
+        );
+    }
+
+    #[test]
+    fn deadlines_end_sessions_with_progress() {
+        let past = Instant::now();
+        let mut calls = 0;
+        let outcome = reconcile_with_deadline(past, vec![1u64], |_, _| {
+            calls += 1;
+            Ok(Vec::new())
+        });
+        assert_eq!(calls, 0);
+        match outcome.unwrap() {
+            DeadlineOutcome::Expired(progress) => {
+                assert_eq!((progress.attempt, progress.estimate), (0, None));
+                assert!(progress.recovered.is_empty());
+            }
+            DeadlineOutcome::Done(_) => panic!("past the deadline"),
+        }
+
+        // A transport timing out ends the session, other errors are passed on
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let bob = SyncSession::new((10..1010u64).collect());
+        let outcome = reconcile_with_deadline(deadline, (0..1000u64).collect(), |bytes, _| {
+            match EstimatorMsg::from_bytes(bytes) {
+                Ok(_) => Ok(bob.start().to_bytes()),
+                Err(_) => Err(std::io::ErrorKind::TimedOut.into()),
+            }
+        });
+        match outcome.unwrap() {
+            DeadlineOutcome::Expired(progress) => {
+                assert_eq!(progress.attempt, 1);
+                assert_eq!(progress.estimate, Some(20));
+            }
+            DeadlineOutcome::Done(_) => panic!("the peer never sent an IBF"),
+        }
+        let err = reconcile_with_deadline(deadline, vec![1u64], |_, _| {
+            Err(std::io::ErrorKind::ConnectionReset.into())
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionReset);
+
+        // Once the deadline passed, a session stops decoding and doesn't retry
+        let mut alice = SyncSession::new((0..1000u64).collect()).with_deadline(Instant::now());
+        let mut carol = SyncSession::new((500..1500u64).collect());
+        let to_alice = carol.on_estimator(&carol.start()).unwrap();
+        assert_eq!(
+            alice.on_ibf(&to_alice).unwrap(),
+            SyncOutcome::Expired {
+                recovered: Vec::new()
+            }
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn sync_over_small_buffers() {
+        use tokio::io::AsyncWriteExt;
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            // Far smaller than the messages, so writes only finish as the peer reads
+            let (alice, bob) = tokio::io::duplex(64);
+            let (alice, bob) = tokio::join!(
+                sync_over(alice, (0..5000u128).collect()),
+                sync_over(bob, (300..5300u128).collect()),
+            );
+            assert_eq!(alice.unwrap().len(), 600);
+            assert_eq!(bob.unwrap().len(), 600);
+
+            let (alice, bob) = tokio::io::duplex(64);
+            drop(bob);
+            let err = sync_over(alice, vec![1u64]).await.unwrap_err();
+            assert!(matches!(
+                err.kind(),
+                std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::BrokenPipe
+            ));
+
+            let (alice, mut bob) = tokio::io::duplex(1 << 16);
+            bob.write_all(&[3, 0, 0, 0, 1, 2, 3]).await.unwrap();
+            let err = sync_over(alice, vec![1u64]).await.unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        });
+    }
+
+    #[test]
+    fn hub_serves_every_shape() {
+        let hub = Reconciler::new((0..500u64).collect(), SketchParams::new(40, 3));
+        let spoke = |params: SketchParams, with_estimator: bool| {
+            let mut ibf = IBF::with_params(params);
+            let mut estimator = StrataEstimator::default();
+            for i in 5..505u64 {
+                ibf.encode(i);
+                estimator.encode(i);
+            }
+            PeerSketches {
+                estimator: Some(estimator).filter(|_| with_estimator),
+                ibf,
+            }
+        };
+        let peers = vec![
+            spoke(SketchParams::new(40, 3), true),
+            spoke(SketchParams::new(80, 4), false),
+            PeerSketches {
+                estimator: Some(StrataEstimator::new_with_size(8)),
+                ibf: IBF::new(40),
+            },
+        ];
+        let outcomes = hub.against_many(&peers);
+        assert_eq!(outcomes[0].estimate, Some(Estimate::Exact(10)));
+        assert_eq!(outcomes[0].difference.as_ref().unwrap().len(), 10);
+        assert_eq!(outcomes[1].estimate, None);
+        assert_eq!(outcomes[1].difference.as_ref().unwrap().len(), 10);
+        assert!(matches!(
+            outcomes[2].difference,
+            Err(IronRoseError::EstimatorSizeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn identical_sets_reconcile_to_nothing() {
+        let alice = Session::new((0..100u64).collect());
+        let bob = Session::new((0..100u64).collect());
+        let alice = alice
+            .on_estimator(bob.estimator())
+            .unwrap()
+            .proceed()
+            .unwrap();
+        let bob = bob
+            .on_estimator(&alice.estimator)
+            .unwrap()
+            .proceed()
+            .unwrap();
+        assert_eq!(alice.ibf().params().size(), MIN_CELLS);
+        let alice = alice.on_ibf(bob.ibf()).unwrap();
+        assert!(alice.into_difference().is_empty());
+    }
+
+    #[test]
+    fn realtime_sessions_speculate() {
+        for (differences, rounds) in [(0u64, 1), (5, 1), (60, 3)] {
+            let mut alice = SyncSession::realtime((0..5000u64).collect());
+            let mut bob = SyncSession::realtime((differences..5000).collect());
+            let (mut to_bob, mut to_alice) = (alice.speculate(), bob.speculate());
+            let difference = loop {
+                match (
+                    alice.on_ibf(&to_alice).unwrap(),
+                    bob.on_ibf(&to_bob).unwrap(),
+                ) {
+                    (SyncOutcome::Done(difference), SyncOutcome::Done(_)) => break difference,
+                    (SyncOutcome::Retry(a), SyncOutcome::Retry(b)) => {
+                        to_bob = a;
+                        to_alice = b;
+                    }
+                    _ => panic!("peers retried apart"),
+                }
+            };
+            assert_eq!(difference.len() as u64, differences);
+            let report = alice.report();
+            assert_eq!((report.estimated, report.rounds), (None, rounds));
+            if rounds == 1 {
+                assert!(report.bytes_sent < 1024, "{:?}", report);
+            }
+        }
+        let estimator = SyncSession::<u64>::realtime(Vec::new()).start().to_bytes();
+        assert!(estimator.len() < 1024);
+    }
+
+    #[test]
+    fn realtime_sessions_fall_back_to_estimators() {
+        let mut alice = SyncSession::realtime((0..5000u64).collect());
+        let mut bob = SyncSession::realtime((500..5500u64).collect());
+        let (to_bob, to_alice) = (alice.speculate(), bob.speculate());
+        assert!(matches!(alice.on_ibf(&to_alice), Ok(SyncOutcome::Retry(_))));
+        assert!(matches!(bob.on_ibf(&to_bob), Ok(SyncOutcome::Retry(_))));
+
+        // The speculative IBF failed, so the peers size the next one from their estimators
+        let (alice_estimator, bob_estimator) = (alice.start(), bob.start());
+        let mut to_bob = alice.on_estimator(&bob_estimator).unwrap();
+        let mut to_alice = bob.on_estimator(&alice_estimator).unwrap();
+        assert!(alice.estimate().unwrap() > 0);
+        let difference = loop {
+            match (
+                alice.on_ibf(&to_alice).unwrap(),
+                bob.on_ibf(&to_bob).unwrap(),
+            ) {
+                (SyncOutcome::Done(difference), SyncOutcome::Done(_)) => break difference,
+                (SyncOutcome::Retry(a), SyncOutcome::Retry(b)) => {
+                    to_bob = a;
+                    to_alice = b;
+                }
+                _ => panic!("peers retried apart"),
+            }
+        };
+        assert_eq!(difference.len(), 1000);
+    }
+
+    #[test]
+    fn diff_cache_expires_entries() {
+        let diff = || -> Result<_, IronRoseError> {
+            Ok((&IBF::<u64>::new(10) - &IBF::new(10))?.decode_difference()?)
+        };
+        let mut cache = DiffCache::new(Duration::from_secs(3600));
+        cache.get_or_compute(1u8, 1, diff).unwrap();
+        let cached = cache.get_or_compute(1, 1, || Err("not called".to_string()));
+        assert!(cached.is_ok());
+        assert!(cache.get(&1, 2).is_none());
+        assert!(cache
+            .get_or_compute(1, 2, || Err("failed".to_string()))
+            .is_err());
+        assert_eq!(cache.len(), 1);
+
+        let mut cache = DiffCache::new(Duration::from_secs(0));
+        cache.insert(1u8, 1, diff().unwrap());
+        assert!(cache.get(&1, 1).is_none());
+        cache.evict_expired();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn dry_run_reports_guardrail() {
+        let alice = Session::new((0..1000u64).collect()).with_max_reconcilable_diff(10);
+        let bob = Session::new((500..1500u64).collect());
+        let plan = alice.dry_run(bob.estimator()).unwrap();
+        assert!(plan.too_divergent);
+        assert_eq!(plan.memory_bytes, plan.params.size() * 24);
+        assert!(alice
+            .on_estimator(bob.estimator())
+            .unwrap()
+            .proceed()
+            .is_none());
+    }
+
+    #[test]
+    fn guardrail_allows_small_differences() {
+        let alice = Session::new((0..100u64).collect()).with_max_reconcilable_diff(10);
+        let bob = Session::new((2..102u64).collect());
+        let alice = alice.on_estimator(bob.estimator()).unwrap();
+        assert!(alice.proceed().is_some());
+    }
+
+    #[test]
+    fn proposals_settle_on_the_larger_shape() {
+        let alice = Session::new((0..100u64).collect());
+        let alice = alice
+            .on_estimator(&StrataEstimator::default())
+            .unwrap()
+            .proceed()
+            .unwrap();
+        let bob = Session::new((0..100u64).collect());
+        let same = bob.estimator().clone();
+        let bob = bob.on_estimator(&same).unwrap().proceed().unwrap();
+        assert_ne!(alice.proposal(), bob.proposal());
+
+        let (alice_proposal, bob_proposal) = (alice.proposal(), bob.proposal());
+        let alice = alice.on_proposal(bob_proposal).unwrap();
+        let bob = bob.on_proposal(alice_proposal).unwrap();
+        assert_eq!(alice.proposal(), bob.proposal());
+        assert_eq!(alice.proposal(), alice_proposal);
+        assert!(alice.on_ibf(bob.ibf()).unwrap().difference().is_empty());
+    }
+
+    #[test]
+    fn hostile_proposals_are_refused() {
+        let alice = || {
+            Session::new((0..100u64).collect())
+                .on_estimator(&StrataEstimator::default())
+                .unwrap()
+                .proceed()
+                .unwrap()
+        };
+        let max = RetryBudget::default().max_cells;
+        assert_eq!(
+            alice()
+                .on_proposal(SketchParams::new(usize::MAX / 2, 3))
+                .err(),
+            Some(IronRoseError::OverCellBudget {
+                cells: usize::MAX / 2,
+                max
+            })
+        );
+        assert_eq!(
+            alice().on_proposal(SketchParams::new(10, 11)).err(),
+            Some(IronRoseError::HashCountOutOfRange {
+                hash_count: 11,
+                size: 10
+            })
+        );
+        assert!(alice().on_proposal(SketchParams::new(100, 0)).is_err());
+
+        // A maximum number of differences caps proposals at the IBF it calls for
+        let capped = Session::new((0..100u64).collect())
+            .with_max_reconcilable_diff(1000)
+            .on_estimator(&StrataEstimator::default())
+            .unwrap()
+            .proceed()
+            .unwrap();
+        assert!(capped.on_proposal(SketchParams::new(2001, 3)).is_err());
+    }
+
+    #[test]
+    fn mismatched_ibfs_are_an_error() {
+        let alice = Session::new((0..100u64).collect());
+        let alice = alice
+            .on_estimator(&StrataEstimator::default())
+            .unwrap()
+            .proceed()
+            .unwrap();
+        let bob = Session::new((0..100u64).collect());
+        let same = bob.estimator().clone();
+        let bob = bob.on_estimator(&same).unwrap().proceed().unwrap();
+        assert!(alice.on_ibf(bob.ibf()).is_err());
+    }
+}
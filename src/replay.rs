@@ -0,0 +1,113 @@
+use crate::{SketchParams, IBF};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Debug,
+    ops::{BitXor, BitXorAssign},
+};
+
+/// A record of which cells elements were placed in, to be replayed on another machine to find
+/// placement mismatches between platforms (such as hashing that differs between ARM and x86).
+/// Record on one machine, serialize, [replay](PlacementTrace::replay) on the other.
+/// ```rust
+/// use iron_rose::{PlacementTrace, SketchParams};
+///
+/// let trace = PlacementTrace::record(SketchParams::new(100, 3), 0..1000u64);
+/// let json = serde_json::to_string(&trace).unwrap();
+/// let trace: PlacementTrace<u64> = serde_json::from_str(&json).unwrap();
+/// assert!(trace.replay().is_empty());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlacementTrace<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    params: SketchParams,
+    placements: Vec<(T, Vec<usize>)>,
+}
+
+/// An element whose cells differ between the recording and the replay
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlacementMismatch<T> {
+    /// The element
+    pub element: T,
+    /// Cells it was placed in when recorded
+    pub recorded: Vec<usize>,
+    /// Cells it is placed in now
+    pub replayed: Vec<usize>,
+}
+
+impl<T> PlacementTrace<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// Records the cells each of elements is placed in by an IBF of the given shape, one per
+    /// hash function, in hash function order
+    pub fn record<I: IntoIterator<Item = T>>(params: SketchParams, elements: I) -> Self {
+        let ibf = IBF::with_params(params);
+        let placements = elements
+            .into_iter()
+            .map(|element| {
+                let cells = ibf.placement(&element);
+                (element, cells)
+            })
+            .collect();
+        Self { params, placements }
+    }
+
+    /// Shape of the IBF the trace was recorded for
+    pub fn params(&self) -> SketchParams {
+        self.params
+    }
+
+    /// Recorded elements and their cells
+    pub fn placements(&self) -> &[(T, Vec<usize>)] {
+        &self.placements
+    }
+
+    /// Recomputes every placement on this machine, returning the ones that differ from the
+    /// recording
+    pub fn replay(&self) -> Vec<PlacementMismatch<T>> {
+        let ibf = IBF::with_params(self.params);
+        self.placements
+            .iter()
+            .filter_map(|(element, recorded)| {
+                let replayed = ibf.placement(element);
+                (replayed != *recorded).then(|| PlacementMismatch {
+                    element: element.clone(),
+                    recorded: recorded.clone(),
+                    replayed,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tampered_trace_reports_mismatch() {
+        let mut trace = PlacementTrace::record(SketchParams::new(50, 3), 0..10u64);
+        assert_eq!(trace.placements().len(), 10);
+        trace.placements[3].1[1] += 1;
+        let mismatches = trace.replay();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].element, 3);
+        assert_eq!(mismatches[0].recorded[1], mismatches[0].replayed[1] + 1);
+    }
+}
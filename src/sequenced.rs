@@ -0,0 +1,129 @@
+use crate::IronRoseError;
+use serde::{Deserialize, Serialize};
+
+/// A sketch tagged with a sequence number that goes up on every mutation, so a peer can tell
+/// a sketch it's sent is older than one it already reconciled against. Diffing against a stale
+/// sketch reports changes that were already made as differences, so a peer keeps a
+/// [SequenceWatermark](SequenceWatermark) per sender and asks for a fresh sketch instead.
+///
+/// The sketch is only reachable mutably through [mutate](Sequenced::mutate) (and
+/// [apply_change](crate::ApplyChange) with the `std` feature), which keeps the sequence number
+/// honest. It serializes along with the sketch, to be sent in the handshake.
+/// ```rust
+/// use iron_rose::{IronRoseError, Sequenced, SequenceWatermark, IBF};
+///
+/// let mut local = Sequenced::new(IBF::new(20));
+/// local.mutate(|ibf| ibf.encode(1u64));
+/// let old = local.clone();
+/// local.mutate(|ibf| ibf.encode(2u64));
+/// assert_eq!(local.sequence(), 2);
+///
+/// // The peer has seen the newer sketch, so a late copy of the older one is refused
+/// let mut watermark = SequenceWatermark::new();
+/// watermark.observe(local.sequence()).unwrap();
+/// assert_eq!(
+///     watermark.observe(old.sequence()),
+///     Err(IronRoseError::StaleSketch { seen: 2, received: 1 })
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Sequenced<S> {
+    sketch: S,
+    sequence: u64,
+}
+
+impl<S> Sequenced<S> {
+    /// Tags sketch as sequence 0
+    pub fn new(sketch: S) -> Self {
+        Self::with_sequence(sketch, 0)
+    }
+
+    /// Tags sketch with sequence, such as one restored from a [snapshot](crate::IBF::snapshot)
+    pub fn with_sequence(sketch: S, sequence: u64) -> Self {
+        Self { sketch, sequence }
+    }
+
+    /// Number of mutations the sketch has been through
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// The sketch
+    pub fn sketch(&self) -> &S {
+        &self.sketch
+    }
+
+    /// Mutates the sketch and bumps the sequence number
+    pub fn mutate<R>(&mut self, f: impl FnOnce(&mut S) -> R) -> R {
+        self.sequence += 1;
+        f(&mut self.sketch)
+    }
+
+    /// The sketch and its sequence number
+    pub fn into_parts(self) -> (S, u64) {
+        (self.sketch, self.sequence)
+    }
+}
+
+/// Highest sequence number seen from one peer's [Sequenced](Sequenced) sketches. The same
+/// sketch can be received again, but not one older than the latest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SequenceWatermark {
+    seen: Option<u64>,
+}
+
+impl SequenceWatermark {
+    /// Watermark of a peer nothing was received from yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Highest sequence number seen, if any
+    pub fn seen(&self) -> Option<u64> {
+        self.seen
+    }
+
+    /// Checks the sequence number of a received sketch, raising the watermark to it. Fails
+    /// with [StaleSketch](IronRoseError::StaleSketch) if a newer sketch was already seen,
+    /// leaving the watermark as it was.
+    pub fn observe(&mut self, sequence: u64) -> Result<(), IronRoseError> {
+        match self.seen {
+            Some(seen) if sequence < seen => Err(IronRoseError::StaleSketch {
+                seen,
+                received: sequence,
+            }),
+            _ => {
+                self.seen = Some(sequence);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IBF;
+
+    #[test]
+    fn sequences_follow_mutations_and_round_trip() {
+        let mut sketch = Sequenced::with_sequence(IBF::<u64>::new(10), 41);
+        let occupied = sketch.mutate(|ibf| {
+            ibf.encode(7);
+            ibf.occupied_cells()
+        });
+        assert_eq!((occupied, sketch.sequence()), (3, 42));
+
+        let json = serde_json::to_string(&sketch).unwrap();
+        let received: Sequenced<IBF<u64>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(received, sketch);
+
+        let mut watermark = SequenceWatermark::new();
+        assert_eq!(watermark.observe(received.sequence()), Ok(()));
+        assert_eq!(watermark.observe(42), Ok(()));
+        assert!(watermark.observe(3).is_err());
+        assert_eq!(watermark.seen(), Some(42));
+        let (ibf, sequence) = received.into_parts();
+        assert_eq!((ibf.occupied_cells(), sequence), (3, 42));
+    }
+}
@@ -0,0 +1,380 @@
+use crate::metro::MetroHasher as ElmHasher;
+use crate::IronRoseError;
+use serde::{Deserialize, Serialize};
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::iter::FromIterator;
+
+/// Small, order independent summary of a set. After applying the differences recovered from an
+/// IBF, both peers can exchange their digests and use [verify_reconciled](verify_reconciled) to
+/// confirm they actually converged, rather than trusting the decode.
+/// ```rust
+/// use iron_rose::{verify_reconciled, SetDigest};
+///
+/// let local = (0..100).collect::<SetDigest>();
+/// let remote = (0..100).rev().collect::<SetDigest>();
+/// assert!(verify_reconciled(&local, &remote).is_ok());
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SetDigest {
+    hash_xor: u64,
+    count: u64,
+}
+
+impl SetDigest {
+    /// Empty digest, representing the empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an element to the digest
+    pub fn insert<T: Hash>(&mut self, element: &T) {
+        self.hash_xor ^= Self::hash(element);
+        self.count += 1;
+    }
+
+    /// Removes a previously inserted element from the digest
+    pub fn remove<T: Hash>(&mut self, element: &T) {
+        self.hash_xor ^= Self::hash(element);
+        self.count = self.count.saturating_sub(1);
+    }
+
+    /// Number of elements summarized by this digest
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    /// True if the digest represents the empty set
+    pub fn is_empty(&self) -> bool {
+        self.count == 0 && self.hash_xor == 0
+    }
+
+    fn hash<T: Hash>(element: &T) -> u64 {
+        let mut hasher: ElmHasher = Default::default();
+        element.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+impl<T: Hash> FromIterator<T> for SetDigest {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut digest = Self::new();
+        for element in iter {
+            digest.insert(&element);
+        }
+        digest
+    }
+}
+
+impl<T: Hash> Extend<T> for SetDigest {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for element in iter {
+            self.insert(&element);
+        }
+    }
+}
+
+/// Lanes of a [SetRoot](SetRoot), each summing a differently seeded hash of the elements
+const ROOT_LANES: usize = 4;
+
+/// 256 bit, order independent hash of a set, for a stronger check than a
+/// [SetDigest](SetDigest) that two peers hold exactly the same set after reconciling. It equals
+/// a hash of the elements in any canonical order, but is kept up to date incrementally, one
+/// insert or remove at a time, next to the set's IBF.
+///
+/// Each element is hashed with 4 differently seeded MetroHashes and each is added, mod 2^64,
+/// into its own lane. Unlike the XOR of a [SetDigest](SetDigest), an element inserted twice
+/// doesn't cancel out, and two different sets share a root with probability around 2^-256.
+/// MetroHash isn't cryptographic though, so this guards against bugs and corruption, not
+/// against a peer forging a set on purpose.
+/// ```rust
+/// use iron_rose::{SetRoot, IBF};
+///
+/// let (mut ibf, mut root) = (IBF::new(20), SetRoot::new());
+/// for i in 0..100u64 {
+///     ibf.encode(i);
+///     root.insert(&i);
+/// }
+/// // After reconciling, the peer sends just its 32 byte root
+/// let remote = (0..100u64).rev().collect::<SetRoot>().root_hash();
+/// assert!(root.verify(&remote).is_ok());
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SetRoot {
+    lanes: [u64; ROOT_LANES],
+}
+
+impl SetRoot {
+    /// Root of the empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an element to the root
+    pub fn insert<T: Hash + ?Sized>(&mut self, element: &T) {
+        for (lane, hash) in self.lanes.iter_mut().zip(Self::hashes(element).iter()) {
+            *lane = lane.wrapping_add(*hash);
+        }
+    }
+
+    /// Removes a previously inserted element from the root
+    pub fn remove<T: Hash + ?Sized>(&mut self, element: &T) {
+        for (lane, hash) in self.lanes.iter_mut().zip(Self::hashes(element).iter()) {
+            *lane = lane.wrapping_sub(*hash);
+        }
+    }
+
+    /// The 32 byte root, the lanes as little endian u64s, to send to the peer
+    pub fn root_hash(&self) -> [u8; 32] {
+        let mut root = [0; 32];
+        for (bytes, lane) in root.chunks_exact_mut(8).zip(self.lanes.iter()) {
+            bytes.copy_from_slice(&lane.to_le_bytes());
+        }
+        root
+    }
+
+    /// Compares this root with the peer's [root_hash](SetRoot::root_hash), failing with
+    /// [RootMismatch](IronRoseError::RootMismatch) if the sets differ
+    pub fn verify(&self, remote: &[u8; 32]) -> Result<(), IronRoseError> {
+        if self.root_hash() == *remote {
+            Ok(())
+        } else {
+            Err(IronRoseError::RootMismatch)
+        }
+    }
+
+    fn hashes<T: Hash + ?Sized>(element: &T) -> [u64; ROOT_LANES] {
+        seeded_hashes(element)
+    }
+}
+
+/// Hashes of the bytes element writes to a [Hasher](Hasher), the same bytes
+/// [element_hash](crate::element_hash) and IBFs hash, with seeds 0 to N - 1. The seed 0 hash is
+/// the element_hash.
+fn seeded_hashes<T: Hash + ?Sized, const N: usize>(element: &T) -> [u64; N] {
+    let mut hasher: ElmHasher = Default::default();
+    element.hash(&mut hasher);
+    let mut hashes = [0; N];
+    for (seed, hash) in hashes.iter_mut().enumerate() {
+        *hash = hasher.finish_seeded(seed as u32);
+    }
+    hashes
+}
+
+/// Lanes of an [IncrementalSetHash](IncrementalSetHash)
+const SET_HASH_LANES: usize = 16;
+
+/// Homomorphic set hash in the style of LtHash: a vector of lanes, each summing, mod 2^64, a
+/// differently seeded hash of every element. Elements can be added and removed one at a time,
+/// and the hashes of disjoint sets [combined](IncrementalSetHash::combine) into the hash of
+/// their union, so shards or replicas can be checked for convergence without ever rehashing
+/// the whole set.
+///
+/// Elements are identified by the same bytes IBFs hash, the first lane summing their
+/// [element_hash](crate::element_hash)es, so an element an IBF takes as equal to another is
+/// equal here too. At 128 bytes this is a wider, stronger check than a [SetRoot](SetRoot), but
+/// like it, not a defence against a peer forging a set on purpose.
+/// ```rust
+/// use iron_rose::IncrementalSetHash;
+///
+/// let mut evens = (0..100u64).filter(|i| i % 2 == 0).collect::<IncrementalSetHash>();
+/// let odds = (0..100u64).filter(|i| i % 2 == 1).collect::<IncrementalSetHash>();
+/// evens.combine(&odds);
+/// assert_eq!(evens, (0..100u64).collect::<IncrementalSetHash>());
+///
+/// evens.remove(&99u64);
+/// assert!(evens.verify(&(0..100u64).collect()).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct IncrementalSetHash {
+    lanes: [u64; SET_HASH_LANES],
+}
+
+impl IncrementalSetHash {
+    /// Hash of the empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an element to the hash
+    pub fn insert<T: Hash + ?Sized>(&mut self, element: &T) {
+        self.add_lanes(&seeded_hashes(element));
+    }
+
+    /// Removes a previously inserted element from the hash
+    pub fn remove<T: Hash + ?Sized>(&mut self, element: &T) {
+        self.sub_lanes(&seeded_hashes(element));
+    }
+
+    /// Adds the elements of other, which should be disjoint from this set, making this the hash
+    /// of their union
+    pub fn combine(&mut self, other: &IncrementalSetHash) {
+        self.add_lanes(&other.lanes);
+    }
+
+    /// Removes the elements of other, which should be a subset of this set
+    pub fn subtract(&mut self, other: &IncrementalSetHash) {
+        self.sub_lanes(&other.lanes);
+    }
+
+    /// True if this is the hash of the empty set, or of elements that cancelled out
+    pub fn is_empty(&self) -> bool {
+        self.lanes.iter().all(|lane| *lane == 0)
+    }
+
+    /// The lanes as little endian u64s
+    pub fn to_bytes(&self) -> [u8; SET_HASH_LANES * 8] {
+        let mut bytes = [0; SET_HASH_LANES * 8];
+        for (chunk, lane) in bytes.chunks_exact_mut(8).zip(self.lanes.iter()) {
+            chunk.copy_from_slice(&lane.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Compares this hash with the peer's, failing with
+    /// [RootMismatch](IronRoseError::RootMismatch) if the sets differ
+    pub fn verify(&self, remote: &IncrementalSetHash) -> Result<(), IronRoseError> {
+        if self == remote {
+            Ok(())
+        } else {
+            Err(IronRoseError::RootMismatch)
+        }
+    }
+
+    fn add_lanes(&mut self, hashes: &[u64; SET_HASH_LANES]) {
+        for (lane, hash) in self.lanes.iter_mut().zip(hashes.iter()) {
+            *lane = lane.wrapping_add(*hash);
+        }
+    }
+
+    fn sub_lanes(&mut self, hashes: &[u64; SET_HASH_LANES]) {
+        for (lane, hash) in self.lanes.iter_mut().zip(hashes.iter()) {
+            *lane = lane.wrapping_sub(*hash);
+        }
+    }
+}
+
+impl<T: Hash> FromIterator<T> for IncrementalSetHash {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut hash = Self::new();
+        hash.extend(iter);
+        hash
+    }
+}
+
+impl<T: Hash> Extend<T> for IncrementalSetHash {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for element in iter {
+            self.insert(&element);
+        }
+    }
+}
+
+impl<T: Hash> FromIterator<T> for SetRoot {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut root = Self::new();
+        root.extend(iter);
+        root
+    }
+}
+
+impl<T: Hash> Extend<T> for SetRoot {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for element in iter {
+            self.insert(&element);
+        }
+    }
+}
+
+/// Confirmation round of a reconciliation. Given the digests of both peers, computed after the
+/// differences have been applied, returns an Err describing the mismatch if the sets have not
+/// converged.
+pub fn verify_reconciled(local: &SetDigest, remote: &SetDigest) -> Result<(), IronRoseError> {
+    if local == remote {
+        Ok(())
+    } else {
+        Err(IronRoseError::NotConverged {
+            local: local.count,
+            remote: remote.count,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn order_independent() {
+        let a = vec![1u128, 2, 3].into_iter().collect::<SetDigest>();
+        let b = vec![3u128, 1, 2].into_iter().collect::<SetDigest>();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn detects_divergence() {
+        let mut local = (0..10u64).collect::<SetDigest>();
+        let remote = (1..11u64).collect::<SetDigest>();
+        assert_eq!(
+            verify_reconciled(&local, &remote),
+            Err(IronRoseError::NotConverged {
+                local: 10,
+                remote: 10
+            })
+        );
+        local.remove(&0u64);
+        local.insert(&10u64);
+        assert!(verify_reconciled(&local, &remote).is_ok());
+    }
+
+    #[test]
+    fn roots_catch_what_xor_misses() {
+        // The same element twice cancels out of the XOR, but not out of the root
+        let twice = vec![7u64, 7, 1].into_iter().collect::<SetDigest>();
+        let once = vec![1u64].into_iter().collect::<SetDigest>();
+        assert_eq!(twice.hash_xor, once.hash_xor);
+        let twice = vec![7u64, 7, 1].into_iter().collect::<SetRoot>();
+        assert!(twice
+            .verify(&SetRoot::from_iter(vec![1u64]).root_hash())
+            .is_err());
+
+        let mut root = (0..50u64).collect::<SetRoot>();
+        root.remove(&49u64);
+        assert_eq!(root, (0..49u64).rev().collect::<SetRoot>());
+        root.remove(&0u64);
+        assert_eq!(
+            root.verify(&(0..49u64).collect::<SetRoot>().root_hash()),
+            Err(IronRoseError::RootMismatch)
+        );
+        assert_eq!(SetRoot::new().root_hash(), [0; 32]);
+    }
+
+    #[test]
+    fn set_hashes_combine_and_share_element_identity() {
+        let mut whole = IncrementalSetHash::new();
+        let mut shards = [IncrementalSetHash::new(); 3];
+        for i in 0..300u64 {
+            whole.insert(&i);
+            shards[(i % 3) as usize].insert(&i);
+        }
+        let mut combined = IncrementalSetHash::new();
+        shards.iter().for_each(|shard| combined.combine(shard));
+        assert_eq!(combined, whole);
+        combined.subtract(&shards[0]);
+        combined.subtract(&shards[1]);
+        assert_eq!(combined, shards[2]);
+
+        let mut one = IncrementalSetHash::new();
+        one.insert("orders/1");
+        assert_eq!(one.lanes[0], crate::element_hash("orders/1"));
+        one.remove("orders/1");
+        assert!(one.is_empty());
+
+        let json = serde_json::to_string(&whole).unwrap();
+        assert_eq!(
+            serde_json::from_str::<IncrementalSetHash>(&json).unwrap(),
+            whole
+        );
+        assert_eq!(&whole.to_bytes()[..8], &whole.lanes[0].to_le_bytes());
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{element_hash, SketchParams, IBF};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Debug,
+    hash::Hash,
+    ops::{BitXor, BitXorAssign},
+};
+
+/// Jump consistent hash (Lamping & Veach, "A Fast, Minimal Memory, Consistent Hash Algorithm"),
+/// mapping key to one of buckets. Going from n to n + 1 buckets only moves 1 / (n + 1) of the
+/// keys, all of them into the new bucket. Panics if buckets is 0.
+/// ```rust
+/// use iron_rose::jump_consistent_hash;
+///
+/// assert_eq!(jump_consistent_hash(42, 1), 0);
+/// assert!(jump_consistent_hash(42, 10) < 10);
+/// ```
+pub fn jump_consistent_hash(mut key: u64, buckets: u32) -> u32 {
+    assert!(buckets > 0, "need at least one bucket");
+    let (mut b, mut j) = (-1i64, 0i64);
+    while j < buckets as i64 {
+        b = j;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        j = ((b + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    b as u32
+}
+
+/// How a set is split over a number of shard IBFs. Elements go to shards by
+/// [jump consistent hash](jump_consistent_hash) of their [element_hash](crate::element_hash),
+/// so peers that agree on the topology (it's serializable, to be exchanged up front) agree on
+/// which shard every element is in, and their shards can be subtracted pairwise.
+/// ```rust
+/// use iron_rose::{ShardTopology, SketchParams};
+///
+/// let topology = ShardTopology::new(4, SketchParams::new(40, 3));
+/// let mut left = topology.build();
+/// let mut right = topology.build();
+/// for i in 0..100u64 {
+///     left[topology.shard_of(&i)].encode(i);
+///     right[topology.shard_of(&(i + 1))].encode(i + 1);
+/// }
+/// let differences: usize = left
+///     .into_iter()
+///     .zip(right)
+///     .map(|(l, r)| (l - r).unwrap().decode().unwrap().len())
+///     .sum();
+/// assert_eq!(differences, 2);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ShardTopology {
+    shards: u32,
+    params: SketchParams,
+}
+
+impl ShardTopology {
+    /// Topology of shards IBFs, each with the given params. Panics if shards is 0.
+    pub fn new(shards: u32, params: SketchParams) -> Self {
+        assert!(shards > 0, "need at least one shard");
+        Self { shards, params }
+    }
+
+    /// Number of shards
+    pub fn shards(&self) -> u32 {
+        self.shards
+    }
+
+    /// Shape of every shard IBF
+    pub fn params(&self) -> SketchParams {
+        self.params
+    }
+
+    /// The shard element belongs to
+    pub fn shard_of<T: Hash + ?Sized>(&self, element: &T) -> usize {
+        jump_consistent_hash(element_hash(element), self.shards) as usize
+    }
+
+    /// One empty IBF per shard
+    pub fn build<T>(&self) -> Vec<IBF<T>>
+    where
+        T: Clone
+            + std::hash::Hash
+            + BitXor<Output = T>
+            + BitXorAssign
+            + Default
+            + PartialEq
+            + Eq
+            + Debug,
+    {
+        (0..self.shards)
+            .map(|_| IBF::with_params(self.params))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn growing_only_moves_keys_to_the_new_bucket() {
+        for key in 0..10_000u64 {
+            let key = element_hash(&key);
+            let (before, after) = (jump_consistent_hash(key, 10), jump_consistent_hash(key, 11));
+            assert!(after == before || after == 10);
+        }
+    }
+
+    #[test]
+    fn pinned_assignments() {
+        // Peers on different platforms and versions must agree on these, which match the
+        // reference implementation
+        assert_eq!(jump_consistent_hash(1, 1), 0);
+        assert_eq!(jump_consistent_hash(42, 57), 43);
+        assert_eq!(jump_consistent_hash(0xDEAD10CC, 666), 361);
+        assert_eq!(jump_consistent_hash(256, 1024), 520);
+    }
+}
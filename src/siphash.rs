@@ -0,0 +1,221 @@
+use core::fmt::{self, Debug};
+use core::hash::{BuildHasher, Hasher};
+
+/// A [BuildHasher](BuildHasher) keyed with a 128 bit secret, for IBFs whose peers don't trust
+/// each other. Cell placement and the checksum that tells a pure cell apart both depend on the
+/// key, so without it nobody can craft elements that collide in a sketch, or cells that pass
+/// for pure and derail decoding. Peers share the key out of band and build their IBFs
+/// [with](crate::IBF::with_hasher) it.
+///
+/// Hashes are SipHash-2-4, with integers written little endian so every platform agrees. The
+/// key is left out of the Debug output.
+/// ```rust
+/// use iron_rose::{SeededHasher, Side, SketchParams, IBF};
+///
+/// let hasher = SeededHasher::new(*b"shared secret 16");
+/// let params = SketchParams::new(10, 3);
+/// let mut local = IBF::with_hasher(params, hasher);
+/// let mut remote = IBF::with_hasher(params, hasher);
+/// local.encode(1u64);
+/// remote.encode(2u64);
+///
+/// // Bytes from the peer carry no hasher, the shared key goes back on when they arrive
+/// let bytes = remote.to_bytes();
+/// let remote = IBF::<u64>::from_bytes(&bytes).unwrap().into_hasher(hasher);
+/// let set = (local - remote).unwrap().decode().unwrap();
+/// assert!(set.contains(&Side::Left(1)));
+/// assert!(set.contains(&Side::Right(2)));
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SeededHasher {
+    k0: u64,
+    k1: u64,
+}
+
+impl SeededHasher {
+    /// Hasher keyed with key, which should be random and kept secret from outsiders
+    pub fn new(key: [u8; 16]) -> Self {
+        let mut k0 = [0; 8];
+        let mut k1 = [0; 8];
+        k0.copy_from_slice(&key[..8]);
+        k1.copy_from_slice(&key[8..]);
+        Self {
+            k0: u64::from_le_bytes(k0),
+            k1: u64::from_le_bytes(k1),
+        }
+    }
+}
+
+impl Debug for SeededHasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SeededHasher").finish_non_exhaustive()
+    }
+}
+
+impl BuildHasher for SeededHasher {
+    type Hasher = SipHasher;
+
+    fn build_hasher(&self) -> SipHasher {
+        SipHasher::new_with_keys(self.k0, self.k1)
+    }
+}
+
+/// SipHash-2-4, the [Hasher](Hasher) built by a [SeededHasher](SeededHasher)
+#[derive(Clone)]
+pub struct SipHasher {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+    /// Bytes written since the last full 8 byte word, little endian
+    tail: u64,
+    tail_len: usize,
+    length: usize,
+}
+
+impl SipHasher {
+    /// SipHash-2-4 keyed with k0 and k1
+    pub fn new_with_keys(k0: u64, k1: u64) -> Self {
+        Self {
+            v0: k0 ^ 0x736f_6d65_7073_6575,
+            v1: k1 ^ 0x646f_7261_6e64_6f6d,
+            v2: k0 ^ 0x6c79_6765_6e65_7261,
+            v3: k1 ^ 0x7465_6462_7974_6573,
+            tail: 0,
+            tail_len: 0,
+            length: 0,
+        }
+    }
+
+    #[inline]
+    fn round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13) ^ self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16) ^ self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21) ^ self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17) ^ self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+
+    #[inline]
+    fn compress(&mut self, word: u64) {
+        self.v3 ^= word;
+        self.round();
+        self.round();
+        self.v0 ^= word;
+    }
+}
+
+impl Debug for SipHasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SipHasher").finish_non_exhaustive()
+    }
+}
+
+impl Hasher for SipHasher {
+    fn finish(&self) -> u64 {
+        let mut state = self.clone();
+        let last = ((self.length as u64 & 0xff) << 56) | self.tail;
+        state.compress(last);
+        state.v2 ^= 0xff;
+        for _ in 0..4 {
+            state.round();
+        }
+        state.v0 ^ state.v1 ^ state.v2 ^ state.v3
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.length = self.length.wrapping_add(bytes.len());
+        let mut bytes = bytes;
+        while self.tail_len > 0 && !bytes.is_empty() {
+            self.tail |= (bytes[0] as u64) << (8 * self.tail_len);
+            self.tail_len += 1;
+            bytes = &bytes[1..];
+            if self.tail_len == 8 {
+                let word = self.tail;
+                self.compress(word);
+                self.tail = 0;
+                self.tail_len = 0;
+            }
+        }
+        if bytes.is_empty() {
+            return;
+        }
+        let mut words = bytes.chunks_exact(8);
+        for word in words.by_ref() {
+            let mut buf = [0; 8];
+            buf.copy_from_slice(word);
+            self.compress(u64::from_le_bytes(buf));
+        }
+        for (i, byte) in words.remainder().iter().enumerate() {
+            self.tail |= (*byte as u64) << (8 * i);
+        }
+        self.tail_len = words.remainder().len();
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Vectors from the SipHash reference implementation, key 00..0f and message 00..len
+    #[test]
+    fn matches_reference_vectors() {
+        let key: [u8; 16] = core::array::from_fn(|i| i as u8);
+        let message: [u8; 64] = core::array::from_fn(|i| i as u8);
+        let by_len = [
+            (0, 0x726f_db47_dd0e_0e31),
+            (1, 0x74f8_39c5_93dc_67fd),
+            (7, 0xab02_00f5_8b01_d137),
+            (8, 0x93f5_f579_9a93_2462),
+            (15, 0xa129_ca61_49be_45e5),
+            (63, 0x958a_324c_eb06_4572),
+        ];
+        let hasher = SeededHasher::new(key);
+        for (len, expected) in by_len.iter() {
+            let mut whole = hasher.build_hasher();
+            whole.write(&message[..*len]);
+            assert_eq!(whole.finish(), *expected, "len {}", len);
+
+            // Split across writes at every point
+            for split in 0..*len {
+                let mut parts = hasher.build_hasher();
+                parts.write(&message[..split]);
+                parts.write(&message[split..*len]);
+                assert_eq!(parts.finish(), *expected, "len {} split {}", len, split);
+            }
+        }
+        assert_ne!(
+            SeededHasher::new([1; 16]).hash_one(7u64),
+            SeededHasher::new([2; 16]).hash_one(7u64)
+        );
+        assert_eq!(
+            format!("{:?}", SeededHasher::new(key)),
+            "SeededHasher { .. }"
+        );
+    }
+}
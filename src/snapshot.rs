@@ -0,0 +1,169 @@
+use crate::metro::MetroHasher as ElmHasher;
+use crate::wire::{self, Reader, WireElement};
+use crate::{IronRoseError, IBF};
+use alloc::vec::Vec;
+use core::{
+    fmt::Debug,
+    hash::{BuildHasher, Hasher},
+    ops::{BitXor, BitXorAssign},
+};
+
+/// Deltas, for keeping a long lived sketch in step with a changing set. Changes go into a small
+/// [delta](IBF::delta) as they happen, with [encode](IBF::encode) for added elements and
+/// [remove](IBF::remove) for removed ones, and are folded into the sketch with
+/// [apply_delta](IBF::apply_delta). The delta between two states of the same sketch is also just
+/// `later - earlier`.
+impl<T, H> IBF<T, H>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    H: BuildHasher + Clone,
+{
+    /// New empty IBF of the same shape, keyspace and hasher, to record changes in
+    pub fn delta(&self) -> Self {
+        let mut delta = Self::with_hasher(self.params(), self.hasher().clone());
+        if let Some(keyspace) = self.keyspace() {
+            delta = delta.with_keyspace(keyspace);
+        }
+        delta
+    }
+
+    /// Applies the changes recorded in delta. This is a [merge](IBF::merge), and fails the same
+    /// way, on a delta of another shape or keyspace.
+    pub fn apply_delta(&mut self, delta: &Self) -> Result<(), IronRoseError> {
+        self.merge(delta)
+    }
+}
+
+/// On disk encoding, for checkpointing a sketch and writing deltas to a log. A snapshot is the
+/// [wire format](IBF::to_bytes) of the IBF, tagged with a caller chosen sequence number, such as
+/// the log position it's up to date with, and followed by a checksum so a write torn by a crash
+/// is caught on restore.
+///
+/// Layout: [WIRE_VERSION](crate::WIRE_VERSION), a kind byte of 2, the element width and a zero
+/// flags byte, then the sequence number as u64, the IBF's [to_bytes](IBF::to_bytes), and a 64
+/// bit MetroHash of everything before it, little endian.
+/// ```rust
+/// use iron_rose::IBF;
+///
+/// let mut live = IBF::new(50);
+/// (0..1000u64).for_each(|x| live.encode(x));
+/// let checkpoint = live.snapshot(7);
+///
+/// // Every later change is logged as a delta, with the next sequence number
+/// let mut log = Vec::new();
+/// let mut delta = live.delta();
+/// delta.encode(1000);
+/// delta.remove(3);
+/// live.apply_delta(&delta).unwrap();
+/// log.push(delta.snapshot(8));
+///
+/// // On startup, restore the checkpoint and replay what came after it
+/// let (mut restored, up_to) = IBF::<u64>::restore(&checkpoint).unwrap();
+/// for entry in log.iter() {
+///     let (delta, sequence) = IBF::<u64>::restore(entry).unwrap();
+///     if sequence > up_to {
+///         restored.apply_delta(&delta).unwrap();
+///     }
+/// }
+/// assert_eq!(restored, live);
+/// assert!(IBF::<u64>::restore(&checkpoint[..checkpoint.len() - 1]).is_err());
+/// ```
+impl<T> IBF<T>
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + WireElement,
+{
+    /// Encodes the IBF with sequence, for [restore](IBF::restore)
+    pub fn snapshot(&self, sequence: u64) -> Vec<u8> {
+        self.snapshot_as(sequence, crate::WIRE_VERSION)
+            .expect("IBFs on the wire are under 2^32 cells")
+    }
+
+    /// Encodes the IBF with sequence in the given version of the wire format, failing like
+    /// [to_bytes_as](IBF::to_bytes_as)
+    pub fn snapshot_as(&self, sequence: u64, version: u8) -> Result<Vec<u8>, IronRoseError> {
+        let ibf = self.to_bytes_as(version)?;
+        let mut out = Vec::with_capacity(12 + ibf.len() + 8);
+        wire::write_header(&mut out, version, wire::KIND_SNAPSHOT, T::WIDTH, 0);
+        out.extend_from_slice(&sequence.to_le_bytes());
+        out.extend_from_slice(&ibf);
+        let checksum = snapshot_checksum(&out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+        Ok(out)
+    }
+
+    /// Reads a [snapshot](IBF::snapshot) back, with its sequence number. A snapshot that fails
+    /// its checksum is rejected with [SnapshotChecksum](IronRoseError::SnapshotChecksum) before
+    /// anything else is read.
+    pub fn restore(bytes: &[u8]) -> Result<(Self, u64), IronRoseError> {
+        if bytes.len() < 8 {
+            return Err(IronRoseError::MalformedBytes {
+                reason: "truncated",
+            });
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 8);
+        if snapshot_checksum(body) != u64::read_le(checksum) {
+            return Err(IronRoseError::SnapshotChecksum);
+        }
+        let mut reader = Reader::new(body);
+        reader.header(wire::KIND_SNAPSHOT, T::WIDTH)?;
+        let sequence = reader.u64()?;
+        let ibf = Self::from_bytes(reader.take(body.len() - 12)?)?;
+        Ok((ibf, sequence))
+    }
+}
+
+fn snapshot_checksum(bytes: &[u8]) -> u64 {
+    let mut hasher: ElmHasher = Default::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SketchParams;
+
+    #[test]
+    fn snapshots_survive_round_trips_and_catch_tears() {
+        let mut ibf = IBF::with_params(SketchParams::new(30, 4)).with_keyspace("orders");
+        (0..100u128).for_each(|x| ibf.encode(x));
+        let bytes = ibf.snapshot(u64::MAX);
+        assert_eq!(IBF::<u128>::restore(&bytes), Ok((ibf.clone(), u64::MAX)));
+
+        let mut flipped = bytes.clone();
+        flipped[40] ^= 1;
+        assert_eq!(
+            IBF::<u128>::restore(&flipped),
+            Err(IronRoseError::SnapshotChecksum)
+        );
+        for len in 0..bytes.len() {
+            assert!(IBF::<u128>::restore(&bytes[..len]).is_err());
+        }
+        assert!(IBF::<u64>::restore(&bytes).is_err());
+        assert!(IBF::<u128>::restore(&ibf.to_bytes()).is_err());
+
+        // A delta carries the keyspace, and states diff into deltas
+        let before = ibf.clone();
+        let mut delta = ibf.delta();
+        delta.encode(500);
+        delta.remove(5);
+        ibf.apply_delta(&delta).unwrap();
+        assert_eq!((&ibf - &before).unwrap(), delta);
+        assert!(ibf.apply_delta(&IBF::new(30)).is_err());
+    }
+}
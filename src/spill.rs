@@ -0,0 +1,179 @@
+use crate::{IronRoseError, StrataEstimator, StrataEstimatorConfig};
+use std::{
+    fs,
+    hash::Hash,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Builds a [StrataEstimator](StrataEstimator) over a dataset read in chunks, such as table
+/// scans by key range or files of an export, spilling the partial estimator of every chunk to a
+/// directory and [merging](StrataEstimator::merge) them at the end. At most two estimators are
+/// in memory at once, however many chunks there are.
+///
+/// Spills are written whole or not at all, so a build that's interrupted can be picked up with
+/// [resume](SpillingEstimatorBuilder::resume) from the chunks already spilled, the caller
+/// skipping those. Spill files are removed once [finish](SpillingEstimatorBuilder::finish)
+/// merged them.
+/// ```rust
+/// use iron_rose::{SpillingEstimatorBuilder, StrataEstimator, StrataEstimatorConfig};
+///
+/// let dir = std::env::temp_dir().join(format!("iron_rose_spill_doc_{}", std::process::id()));
+/// let chunks = (0..10u64).map(|chunk| chunk * 1000..(chunk + 1) * 1000);
+/// let built = SpillingEstimatorBuilder::new(StrataEstimatorConfig::default(), &dir)
+///     .build(chunks)
+///     .unwrap();
+///
+/// let mut whole = StrataEstimator::default();
+/// (0..10_000u64).for_each(|x| whole.encode(x));
+/// assert_eq!(built.estimate_differences(&whole), Ok(0));
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct SpillingEstimatorBuilder {
+    config: StrataEstimatorConfig,
+    dir: PathBuf,
+    spills: Vec<PathBuf>,
+}
+
+impl SpillingEstimatorBuilder {
+    /// Builder of an estimator of the given shape, spilling to dir, which is created when the
+    /// first chunk is spilled
+    pub fn new(config: StrataEstimatorConfig, dir: impl AsRef<Path>) -> Self {
+        Self {
+            config,
+            dir: dir.as_ref().to_path_buf(),
+            spills: Vec::new(),
+        }
+    }
+
+    /// Builder picking up the chunks an interrupted build already spilled to dir
+    pub fn resume(config: StrataEstimatorConfig, dir: impl AsRef<Path>) -> io::Result<Self> {
+        let mut builder = Self::new(config, dir);
+        if builder.dir.is_dir() {
+            for entry in fs::read_dir(&builder.dir)? {
+                let path = entry?.path();
+                if spill_index(&path).is_some() {
+                    builder.spills.push(path);
+                }
+            }
+        }
+        builder.spills.sort_by_key(|path| spill_index(path));
+        Ok(builder)
+    }
+
+    /// Number of chunks spilled so far
+    pub fn spilled(&self) -> usize {
+        self.spills.len()
+    }
+
+    /// Encodes a chunk into an estimator of its own and spills it
+    pub fn add_chunk<T, C>(&mut self, chunk: C) -> io::Result<()>
+    where
+        T: Hash,
+        C: IntoIterator<Item = T>,
+    {
+        let mut estimator = StrataEstimator::with_config(self.config);
+        chunk
+            .into_iter()
+            .for_each(|element| estimator.encode(element));
+        fs::create_dir_all(&self.dir)?;
+        let index = self
+            .spills
+            .iter()
+            .filter_map(|path| spill_index(path))
+            .max();
+        let path = self.dir.join(format!(
+            "strata-{}.spill",
+            index.map_or(0, |index| index + 1)
+        ));
+        // Renamed into place, so a crash never leaves a partly written spill behind
+        let partial = path.with_extension("partial");
+        fs::write(&partial, estimator.to_bytes())?;
+        fs::rename(&partial, &path)?;
+        self.spills.push(path);
+        Ok(())
+    }
+
+    /// Merges every spilled chunk into one estimator, removing the spills. Spills that don't
+    /// read back as estimators of the builder's shape fail with
+    /// [InvalidData](io::ErrorKind::InvalidData) errors wrapping an
+    /// [IronRoseError](IronRoseError).
+    pub fn finish(self) -> io::Result<StrataEstimator> {
+        let mut estimator = StrataEstimator::with_config(self.config);
+        for path in self.spills.iter() {
+            let spill = StrataEstimator::from_bytes(&fs::read(path)?).map_err(invalid_data)?;
+            estimator.merge(&spill).map_err(invalid_data)?;
+        }
+        for path in self.spills.iter() {
+            fs::remove_file(path)?;
+        }
+        Ok(estimator)
+    }
+
+    /// Spills every chunk, then [finishes](SpillingEstimatorBuilder::finish)
+    pub fn build<T, C, I>(mut self, chunks: I) -> io::Result<StrataEstimator>
+    where
+        T: Hash,
+        C: IntoIterator<Item = T>,
+        I: IntoIterator<Item = C>,
+    {
+        for chunk in chunks {
+            self.add_chunk(chunk)?;
+        }
+        self.finish()
+    }
+}
+
+/// Index of the chunk spilled to path, None if it isn't a spill
+fn spill_index(path: &Path) -> Option<usize> {
+    let name = path.file_name()?.to_str()?;
+    name.strip_prefix("strata-")?
+        .strip_suffix(".spill")?
+        .parse()
+        .ok()
+}
+
+fn invalid_data(err: IronRoseError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SketchParams;
+
+    #[test]
+    fn resumed_builds_match_one_pass() {
+        let dir = std::env::temp_dir().join(format!("iron_rose_spill_{}", std::process::id()));
+        let config = StrataEstimatorConfig::new(16, SketchParams::new(40, 3));
+        let mut interrupted = SpillingEstimatorBuilder::new(config, &dir);
+        interrupted.add_chunk(0..500u64).unwrap();
+        interrupted.add_chunk(500..1000u64).unwrap();
+        // Left over from a crash mid write
+        fs::write(dir.join("strata-2.partial"), b"torn").unwrap();
+        drop(interrupted);
+
+        let mut resumed = SpillingEstimatorBuilder::resume(config, &dir).unwrap();
+        assert_eq!(resumed.spilled(), 2);
+        resumed.add_chunk(1000..1200u64).unwrap();
+        let built = resumed.finish().unwrap();
+        let mut whole = StrataEstimator::with_config(config);
+        (0..1200u64).for_each(|x| whole.encode(x));
+        assert_eq!(built.to_bytes(), whole.to_bytes());
+        assert_eq!(
+            SpillingEstimatorBuilder::resume(config, &dir)
+                .unwrap()
+                .spilled(),
+            0
+        );
+
+        fs::write(dir.join("strata-0.spill"), b"not an estimator").unwrap();
+        let broken = SpillingEstimatorBuilder::resume(config, &dir).unwrap();
+        assert_eq!(
+            broken.finish().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
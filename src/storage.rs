@@ -0,0 +1,43 @@
+use crate::{Cell, Count};
+use core::fmt::Debug;
+use core::ops::{BitXor, BitXorAssign, Deref, DerefMut};
+
+/// Memory an [IBF](crate::IBF) keeps its cells in: a `Box<[Cell<T, C>]>` unless it was built
+/// [with_storage](crate::IBF::with_storage). Anything that derefs to a mutable slice of cells
+/// and can be cloned will do, and implements this trait without further work, such as a wrapper
+/// around a buffer from a hugepage or NUMA pinned allocator, or on nightly a
+/// `Box<[Cell<T, C>], A>` with a custom allocator, so that sketches of several GiB live where the
+/// application wants them.
+///
+/// Subtracting, adding and decoding keep the storage of the IBFs involved, cloning it where
+/// they borrow them. Sketches read from the [wire](crate::IBF::from_bytes), or converted with
+/// [into_count_width](crate::IBF::into_count_width), are boxed, and serde reads into any storage
+/// that converts from a boxed slice of cells.
+pub trait CellStorage<T, C = i32>: Deref<Target = [Cell<T, C>]> + DerefMut + Clone
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    C: Count,
+{
+}
+
+impl<T, C, S> CellStorage<T, C> for S
+where
+    T: Clone
+        + core::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+    C: Count,
+    S: Deref<Target = [Cell<T, C>]> + DerefMut + Clone,
+{
+}
@@ -1,9 +1,12 @@
-use fasthash::MetroHasher as ElmHasher;
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::convert::TryFrom;
+use core::hash::Hash;
 use serde::{Deserialize, Serialize};
-use std::hash::Hash;
-use std::hash::Hasher;
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
 
-use crate::IBF;
+use crate::wire::{self, Reader, WireElement};
+use crate::{element_hash, profiles, IronRoseError, SketchParams, IBF};
 
 /// Strata Estimator for determining the size of IBF needed to successfuly decode the differences
 /// in two sets.
@@ -17,57 +20,878 @@ use crate::IBF;
 ///    se2.encode(i + 25);
 /// }
 /// assert_eq!(se1.estimate_differences(&se2), Ok(100));
-#[derive(Debug, Serialize, Deserialize)]
+/// ```
+///
+/// The estimator is not generic: [encode](StrataEstimator::encode) takes any `Hash` element and
+/// only keeps its 64 bit hash, so the same estimator type works for u64s, u128s, strings or
+/// `[u8; 32]` digests alike. Both sides only have to encode the same element type.
+///
+/// Strata are reference counted and copied on write, so cloning an estimator is cheap: a live
+/// estimator can be snapshotted for every incoming request, and only strata that are encoded
+/// into afterwards get copied.
+///
+/// An estimator serializes as a single `ibfs` field, so like an [IBF](IBF) it can be embedded in
+/// application messages with `#[serde(flatten)]`, provided they have no `ibfs` field of their
+/// own. Deserialization checks the strata like [from_bytes](StrataEstimator::from_bytes) does.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "RawStrataEstimator")]
 pub struct StrataEstimator {
-    ibfs: Vec<IBF<u64>>,
+    ibfs: Vec<Arc<IBF<u64>>>,
+    /// Local only, opt-in filter of the element hashes seen so far
+    #[serde(skip)]
+    duplicates: Option<Arc<DuplicateFilter>>,
+}
+
+/// A deserialized estimator, before it's checked to have a valid number of strata all of the
+/// same shape
+#[derive(Deserialize)]
+pub(crate) struct RawStrataEstimator {
+    pub(crate) ibfs: Vec<Arc<IBF<u64>>>,
+}
+
+impl TryFrom<RawStrataEstimator> for StrataEstimator {
+    type Error = IronRoseError;
+
+    fn try_from(raw: RawStrataEstimator) -> Result<Self, Self::Error> {
+        if raw.ibfs.is_empty() || raw.ibfs.len() > profiles::MAX_STRATA {
+            return Err(IronRoseError::MalformedBytes {
+                reason: "number of strata out of range",
+            });
+        }
+        let shape = raw.ibfs[0].params();
+        if raw.ibfs.iter().any(|ibf| ibf.params() != shape) {
+            return Err(IronRoseError::MalformedBytes {
+                reason: "strata differ in shape",
+            });
+        }
+        Ok(Self {
+            ibfs: raw.ibfs,
+            duplicates: None,
+        })
+    }
+}
+
+/// Bloom filter over element hashes, used to spot elements encoded more than once
+#[derive(Debug, Clone)]
+struct DuplicateFilter {
+    bits: Vec<u64>,
+    hash_count: u32,
+    suspected: usize,
+}
+
+impl DuplicateFilter {
+    /// Sized for a 1% false positive rate at expected elements
+    fn new(expected: usize) -> Self {
+        // 9.6 bits per element, in integers as float rounding needs std
+        let bits = (expected.max(1) * 48).div_ceil(5);
+        Self {
+            bits: vec![0; bits.div_ceil(64)],
+            hash_count: 7,
+            suspected: 0,
+        }
+    }
+
+    /// Inserts hash, returning true if it was (probably) already present
+    fn insert(&mut self, hash: u64) -> bool {
+        let len = (self.bits.len() * 64) as u64;
+        let (h1, h2) = (hash, hash.rotate_left(32) | 1);
+        let mut present = true;
+        for i in 0..self.hash_count as u64 {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % len;
+            let (word, mask) = ((bit / 64) as usize, 1 << (bit % 64));
+            present &= self.bits[word] & mask != 0;
+            self.bits[word] |= mask;
+        }
+        present
+    }
+}
+
+/// Random estimators for fuzzing, of the default shape so that they can be compared with each
+/// other, holding arbitrary elements
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for StrataEstimator {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut estimator = StrataEstimator::default();
+        for element in u.arbitrary_iter::<u64>()? {
+            estimator.encode(element?);
+        }
+        Ok(estimator)
+    }
 }
 
 impl Default for StrataEstimator {
     fn default() -> Self {
-        Self::new_with_size(32)
+        Self::with_config(StrataEstimatorConfig::default())
+    }
+}
+
+/// The shape of a [StrataEstimator](StrataEstimator): how many strata it has and the
+/// [params](SketchParams) of the IBF in each. Two estimators can only be compared when their
+/// configs are equal, and like [SketchParams](SketchParams) a config is small and serializable,
+/// so peers can check they agree before exchanging whole estimators.
+///
+/// Smaller strata make a smaller estimator, but each stratum can decode fewer differences, so
+/// more of them have to be extrapolated from a smaller sample.
+/// ```rust
+/// use iron_rose::{SketchParams, StrataEstimator, StrataEstimatorConfig};
+///
+/// let config = StrataEstimatorConfig::new(16, SketchParams::new(40, 4));
+/// let local = StrataEstimator::with_config(config);
+/// assert_eq!(local.config(), config);
+/// // Received from the peer in its hello
+/// let remote = StrataEstimatorConfig::default();
+/// assert!(config.check_compatible(&remote).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct StrataEstimatorConfig {
+    strata: usize,
+    stratum: SketchParams,
+}
+
+impl Default for StrataEstimatorConfig {
+    fn default() -> Self {
+        Self::new(profiles::DEFAULT_STRATA, profiles::STRATUM)
+    }
+}
+
+impl StrataEstimatorConfig {
+    /// Config for an estimator of strata IBFs shaped by stratum. Panics unless
+    /// `0 < strata <= MAX_STRATA` (see [checked_strata](profiles::checked_strata)) and
+    /// `0 < hash_count <= size` (see [SketchParams::checked](SketchParams::checked)), in a
+    /// const context these are compile errors.
+    pub const fn new(strata: usize, stratum: SketchParams) -> Self {
+        Self {
+            strata: profiles::checked_strata(strata),
+            stratum: SketchParams::checked(stratum.size(), stratum.hash_count()),
+        }
+    }
+
+    /// Number of strata
+    pub const fn strata(&self) -> usize {
+        self.strata
+    }
+
+    /// Shape of the IBF in each stratum
+    pub const fn stratum(&self) -> SketchParams {
+        self.stratum
+    }
+
+    /// Fails with [EstimatorSizeMismatch](IronRoseError::EstimatorSizeMismatch) unless
+    /// estimators of the two configs can be compared
+    pub fn check_compatible(&self, other: &StrataEstimatorConfig) -> Result<(), IronRoseError> {
+        if self != other {
+            return Err(IronRoseError::EstimatorSizeMismatch {
+                left_strata: self.strata,
+                left: self.stratum,
+                right_strata: other.strata,
+                right: other.stratum,
+            });
+        }
+        Ok(())
     }
 }
 
 impl StrataEstimator {
-    /// Returns a strata estimator with 32 ibfs allowing you to determin differences as high as
-    /// 2^32
+    /// Returns a strata estimator with size ibfs allowing you to determin differences as high as
+    /// 2^size. Strata are picked by the trailing zeros of a 64 bit hash, so deeper strata could
+    /// never be reached: panics unless `0 < size <= MAX_STRATA`, see
+    /// [checked_strata](profiles::checked_strata).
     pub fn new_with_size(size: usize) -> Self {
+        Self::with_config(StrataEstimatorConfig::new(size, profiles::STRATUM))
+    }
+
+    /// Returns an empty strata estimator of the given shape
+    /// ```rust
+    /// use iron_rose::{Estimate, SketchParams, StrataEstimator, StrataEstimatorConfig};
+    ///
+    /// let config = StrataEstimatorConfig::new(24, SketchParams::new(40, 4));
+    /// let (mut se1, mut se2) = (
+    ///     StrataEstimator::with_config(config),
+    ///     StrataEstimator::with_config(config),
+    /// );
+    /// for i in 0..1000 {
+    ///     se1.encode(i);
+    ///     se2.encode(i + 5);
+    /// }
+    /// assert_eq!(se1.stratum_size(), 40);
+    /// assert_eq!(se1.estimate(&se2), Ok(Estimate::Exact(10)));
+    /// ```
+    pub fn with_config(config: StrataEstimatorConfig) -> Self {
         Self {
-            ibfs: (0..size).map(|_| IBF::new(80)).collect::<Vec<_>>(),
+            ibfs: (0..config.strata())
+                .map(|_| Arc::new(IBF::with_params(config.stratum())))
+                .collect::<Vec<_>>(),
+            duplicates: None,
         }
     }
 
+    /// An estimator holding the given strata, shallowest first, with duplicate detection off
+    #[cfg(feature = "legacy-v0")]
+    pub(crate) fn from_strata(ibfs: Vec<IBF<u64>>) -> Self {
+        Self {
+            ibfs: ibfs.into_iter().map(Arc::new).collect(),
+            duplicates: None,
+        }
+    }
+
+    /// The strata, shallowest first
+    #[cfg(feature = "legacy-v0")]
+    pub(crate) fn strata_ibfs(&self) -> impl Iterator<Item = &IBF<u64>> {
+        self.ibfs.iter().map(|ibf| &**ibf)
+    }
+
+    /// The shape of this estimator, to check against a peer's with
+    /// [check_compatible](StrataEstimatorConfig::check_compatible)
+    pub fn config(&self) -> StrataEstimatorConfig {
+        StrataEstimatorConfig {
+            strata: self.strata(),
+            stratum: SketchParams::new(self.stratum_size(), self.hash_count()),
+        }
+    }
+
+    /// Turns on detection of elements encoded more than once, which silently inflate estimates
+    /// (a buggy upstream query returning duplicate ids, for example). A local bloom filter sized
+    /// for expected_elements with a 1% false positive rate tracks the elements seen so far; it
+    /// is never serialized.
+    /// ```rust
+    /// use iron_rose::StrataEstimator;
+    ///
+    /// let mut estimator = StrataEstimator::default().with_duplicate_detection(100);
+    /// estimator.encode(1);
+    /// assert!(estimator.try_encode(1).is_err());
+    /// estimator.encode(1);
+    /// assert_eq!(estimator.suspected_duplicates(), Some(2));
+    /// ```
+    pub fn with_duplicate_detection(mut self, expected_elements: usize) -> Self {
+        self.duplicates = Some(Arc::new(DuplicateFilter::new(expected_elements)));
+        self
+    }
+
+    /// Number of encodes that looked like duplicates, or None if duplicate detection is off.
+    /// Includes bloom filter false positives, so treat this as an upper bound.
+    pub fn suspected_duplicates(&self) -> Option<usize> {
+        self.duplicates.as_ref().map(|filter| filter.suspected)
+    }
+
+    /// Number of strata (IBFs) in this estimator
+    pub fn strata(&self) -> usize {
+        self.ibfs.len()
+    }
+
+    /// Number of cells in each stratum
+    pub fn stratum_size(&self) -> usize {
+        self.ibfs
+            .first()
+            .map_or(profiles::STRATUM.size(), |ibf| ibf.params().size())
+    }
+
+    /// hash_count of each stratum
+    pub fn hash_count(&self) -> usize {
+        self.ibfs
+            .first()
+            .map_or(profiles::STRATUM.hash_count(), |ibf| {
+                ibf.params().hash_count()
+            })
+    }
+
+    /// Serializes the estimator in the binary wire format: the same 4 byte header as
+    /// [IBF::to_bytes](IBF::to_bytes), the number of strata, hash_count and stratum size as
+    /// little endian u32s, the cells of every stratum in order, then the CRC32 footer.
+    /// ```rust
+    /// use iron_rose::StrataEstimator;
+    ///
+    /// let mut se = StrataEstimator::default();
+    /// (0..100).for_each(|i| se.encode(i));
+    /// let bytes = se.to_bytes();
+    /// assert_eq!(bytes.len(), 16 + 32 * 80 * 20 + 4);
+    /// let back = StrataEstimator::from_bytes(&bytes).unwrap();
+    /// assert!(se.estimate(&back).unwrap().is_exact());
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_as(crate::WIRE_VERSION)
+            .expect("the current version is supported")
+    }
+
+    /// Serializes the estimator in the given version of the binary wire format, like
+    /// [IBF::to_bytes_as](IBF::to_bytes_as), failing only on a version this build can't write
+    pub fn to_bytes_as(&self, version: u8) -> Result<Vec<u8>, IronRoseError> {
+        wire::check_version(version)?;
+        let mut out = Vec::new();
+        wire::write_header(&mut out, version, wire::KIND_STRATA, u64::WIDTH, 0);
+        let strata = u32::try_from(self.strata()).expect("at most MAX_STRATA strata");
+        out.extend_from_slice(&strata.to_le_bytes());
+        match self.ibfs.first() {
+            Some(first) => first.write_shape(&mut out),
+            None => IBF::<u64>::with_params(profiles::STRATUM).write_shape(&mut out),
+        }
+        for ibf in self.ibfs.iter() {
+            assert_eq!(
+                ibf.params(),
+                self.ibfs[0].params(),
+                "strata differ in shape"
+            );
+            ibf.write_cells(&mut out);
+        }
+        wire::seal(&mut out);
+        Ok(out)
+    }
+
+    /// Reads an estimator written by [to_bytes](StrataEstimator::to_bytes) or
+    /// [to_bytes_as](StrataEstimator::to_bytes_as), failing on an unsupported format version, a
+    /// checksum mismatch or malformed input. Duplicate detection is off.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, IronRoseError> {
+        let mut reader = Reader::new(bytes);
+        reader.header(wire::KIND_STRATA, u64::WIDTH)?;
+        let strata = reader.u32()? as usize;
+        if strata == 0 || strata > profiles::MAX_STRATA {
+            return Err(IronRoseError::MalformedBytes {
+                reason: "number of strata out of range",
+            });
+        }
+        let (hash_count, size) = (reader.u32()? as usize, reader.u32()? as usize);
+        let ibfs = (0..strata)
+            .map(|_| IBF::read_cells(&mut reader, hash_count, size).map(Arc::new))
+            .collect::<Result<Vec<_>, _>>()?;
+        reader.finish()?;
+        Ok(Self {
+            ibfs,
+            duplicates: None,
+        })
+    }
+
+    /// How the encoded elements are spread over the strata, for offline analysis. Stratum i
+    /// should hold about twice as many elements as stratum i + 1.
+    /// ```rust
+    /// use iron_rose::StrataEstimator;
+    ///
+    /// let mut estimator = StrataEstimator::default();
+    /// (0..100).for_each(|i| estimator.encode(i));
+    /// let report = estimator.distribution_report();
+    /// assert_eq!(report.len(), estimator.strata());
+    /// assert!(report[0].occupied_cells > report[5].occupied_cells);
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn distribution_report(&self) -> Vec<StratumLoad> {
+        self.ibfs
+            .iter()
+            .enumerate()
+            .map(|(stratum, ibf)| StratumLoad {
+                stratum,
+                occupied_cells: ibf.occupied_cells(),
+                estimated_elements: ibf.occupancy_estimate(),
+                load_histogram: ibf.load_histogram(),
+            })
+            .collect()
+    }
+
+    fn check_shape(&self, other: &StrataEstimator) -> Result<(), IronRoseError> {
+        self.config().check_compatible(&other.config())
+    }
+
     /// Encodes an element into the strata estimator that will eventually to determine the size of
     /// differences between two sets
     pub fn encode<T: Hash>(&mut self, element: T) {
-        let mut hasher: ElmHasher = Default::default();
-        element.hash(&mut hasher);
-        let new_elm = hasher.finish();
-        let trailing = new_elm.trailing_zeros();
+        self.encode_with(element, &TrailingZeros)
+    }
+
+    /// Undoes an [encode](StrataEstimator::encode) of element, so an estimator can follow a set
+    /// that changes over time. The [duplicate filter](StrataEstimator::with_duplicate_detection)
+    /// can't forget elements, so one removed and encoded again counts as a likely duplicate.
+    ///
+    /// Only elements added with [encode](StrataEstimator::encode) can be removed this way, ones
+    /// added with [encode_with](StrataEstimator::encode_with) have to be removed with
+    /// [remove_with](StrataEstimator::remove_with) and the same selector, or they're taken out
+    /// of the wrong stratum.
+    /// ```rust
+    /// use iron_rose::StrataEstimator;
+    ///
+    /// let (mut live, mut fresh) = (StrataEstimator::default(), StrataEstimator::default());
+    /// (0..10u64).for_each(|x| live.encode(x));
+    /// (0..9u64).for_each(|x| fresh.encode(x));
+    /// live.remove(9u64);
+    /// assert_eq!(live.estimate_differences(&fresh), Ok(0));
+    /// ```
+    pub fn remove<T: Hash>(&mut self, element: T) {
+        self.remove_with(element, &TrailingZeros)
+    }
+
+    /// Undoes an [encode_with](StrataEstimator::encode_with) of element with the same selector
+    /// ```rust
+    /// use iron_rose::StrataEstimator;
+    ///
+    /// let last = |_: &u64, _hash: u64, strata: usize| strata - 1;
+    /// let (mut live, mut fresh) = (StrataEstimator::default(), StrataEstimator::default());
+    /// (0..10u64).for_each(|x| live.encode_with(x, &last));
+    /// (0..9u64).for_each(|x| fresh.encode_with(x, &last));
+    /// live.remove_with(9u64, &last);
+    /// assert_eq!(live.estimate_differences(&fresh), Ok(0));
+    /// ```
+    pub fn remove_with<T: Hash, S: StratumSelector<T>>(&mut self, element: T, selector: &S) {
+        let hash = element_hash(&element);
+        let len = self.ibfs.len();
+        let stratum = selector.stratum(&element, hash, len);
+        Arc::make_mut(&mut self.ibfs[stratum % len]).remove(hash);
+    }
+
+    /// Like [encode](StrataEstimator::encode), but the stratum is picked by selector. Both
+    /// estimators being compared have to use the same selector.
+    /// ```rust
+    /// use iron_rose::{Estimate, StrataEstimator};
+    ///
+    /// // Put every element in the last stratum, which has to decode for any estimate
+    /// let last = |_: &u64, _hash: u64, strata: usize| strata - 1;
+    /// let (mut se1, mut se2) = (StrataEstimator::default(), StrataEstimator::default());
+    /// se1.encode_with(1, &last);
+    /// se2.encode_with(2, &last);
+    /// assert_eq!(se1.estimate(&se2), Ok(Estimate::Exact(2)));
+    /// ```
+    pub fn encode_with<T: Hash, S: StratumSelector<T>>(&mut self, element: T, selector: &S) {
+        let new_elm = element_hash(&element);
+        self.check_duplicate(new_elm);
+        let stratum = selector.stratum(&element, new_elm, self.ibfs.len());
+        self.encode_hash(stratum, new_elm);
+    }
+
+    /// Encodes an element by its [element_hash](crate::element_hash) alone, for callers that
+    /// already have the hashes on hand. `encode_prehashed(element_hash(&x))` is the same as
+    /// `encode(x)`.
+    pub fn encode_prehashed(&mut self, hash: u64) {
+        self.check_duplicate(hash);
+        let stratum = TrailingZeros.stratum(&hash, hash, self.ibfs.len());
+        self.encode_hash(stratum, hash);
+    }
+
+    /// Like [encode](StrataEstimator::encode), but when duplicate detection is on, an element
+    /// that looks like it was already encoded is rejected with an Err instead.
+    pub fn try_encode<T: Hash>(&mut self, element: T) -> Result<(), IronRoseError> {
+        let new_elm = element_hash(&element);
+        if self.check_duplicate(new_elm) {
+            return Err(IronRoseError::LikelyDuplicate);
+        }
+        let stratum = TrailingZeros.stratum(&element, new_elm, self.ibfs.len());
+        self.encode_hash(stratum, new_elm);
+        Ok(())
+    }
+
+    /// Records hash with the duplicate filter, if there is one, returning true if it was
+    /// probably seen before
+    fn check_duplicate(&mut self, hash: u64) -> bool {
+        match self.duplicates.as_mut() {
+            Some(filter) => {
+                let filter = Arc::make_mut(filter);
+                let seen = filter.insert(hash);
+                if seen {
+                    filter.suspected += 1;
+                }
+                seen
+            }
+            None => false,
+        }
+    }
+
+    fn encode_hash(&mut self, stratum: usize, new_elm: u64) {
         let len = self.ibfs.len();
-        self.ibfs[(trailing as usize % len)].encode(new_elm);
+        Arc::make_mut(&mut self.ibfs[stratum % len]).encode(new_elm);
+    }
+
+    /// Same as [encode_prehashed](StrataEstimator::encode_prehashed), reusing indexes as the
+    /// buffer for cell indexes across calls
+    #[cfg(feature = "roaring")]
+    pub(crate) fn encode_prehashed_buffered(&mut self, hash: u64, indexes: &mut Vec<usize>) {
+        self.check_duplicate(hash);
+        let len = self.ibfs.len();
+        let stratum = TrailingZeros.stratum(&hash, hash, len);
+        Arc::make_mut(&mut self.ibfs[stratum % len]).encode_buffered(hash, indexes);
+    }
+
+    /// Adds calibrated noise to the estimator before sharing it with a partially trusted peer,
+    /// so the estimate it enables doesn't reveal the exact number of differences.
+    ///
+    /// Only the per stratum difference counts, and so the estimate, are protected. Each element
+    /// lands in exactly one stratum, so adding or removing one element changes one stratum's
+    /// count by one. Each stratum gets `max(0, round(shift + Lap(1 / epsilon)))` random noise
+    /// elements, where `shift = ln(1 / (2 * delta)) / epsilon`, which makes the counts the peer
+    /// can decode (epsilon, delta)-differentially private with respect to a single element.
+    /// Releasing noised counts of the same set twice costs 2 * epsilon. The cells themselves are
+    /// not private: a peer that holds an element can still test whether the estimator does, so
+    /// don't share estimators with peers that mustn't learn membership.
+    ///
+    /// Noise elements look like differences to the peer, so estimates against a noised
+    /// estimator are biased upwards by roughly `shift` elements per stratum that decodes.
+    /// Small epsilon and delta mean more privacy and larger IBFs.
+    #[cfg(feature = "dp")]
+    pub fn add_noise<R: rand::Rng>(&mut self, epsilon: f64, delta: f64, rng: &mut R) {
+        assert!(epsilon > 0.0, "epsilon must be positive");
+        assert!(delta > 0.0 && delta < 0.5, "delta must be in (0, 0.5)");
+        let scale = 1.0 / epsilon;
+        let shift = (1.0 / (2.0 * delta)).ln() * scale;
+        for stratum in self.ibfs.iter_mut() {
+            let u: f64 = rng.gen_range(-0.5..0.5);
+            let laplace = -scale * u.signum() * (1.0 - 2.0 * u.abs()).ln();
+            let noise = (shift + laplace).round().max(0.0) as usize;
+            let stratum = Arc::make_mut(stratum);
+            for _ in 0..noise {
+                stratum.encode(rng.gen());
+            }
+        }
+    }
+
+    /// Adds other's elements to this estimator, stratum by stratum, failing unless both are the
+    /// same shape. Estimators built over disjoint shards of a table merge into the estimator of
+    /// the whole table. The [duplicate filter](StrataEstimator::with_duplicate_detection) is
+    /// local and only knows about this estimator's own elements.
+    /// ```rust
+    /// use iron_rose::{Estimate, StrataEstimator};
+    ///
+    /// let (mut shard1, mut shard2) = (StrataEstimator::default(), StrataEstimator::default());
+    /// let mut remote = StrataEstimator::default();
+    /// for i in 0..1000 {
+    ///     if i < 500 { shard1.encode(i) } else { shard2.encode(i) }
+    ///     remote.encode(i + 5);
+    /// }
+    /// shard1.merge(&shard2).unwrap();
+    /// assert_eq!(shard1.estimate(&remote), Ok(Estimate::Exact(10)));
+    /// ```
+    pub fn merge(&mut self, other: &StrataEstimator) -> Result<(), IronRoseError> {
+        self.check_shape(other)?;
+        for (l, r) in self.ibfs.iter_mut().zip(other.ibfs.iter()) {
+            Arc::make_mut(l).merge(r)?;
+        }
+        Ok(())
     }
 
     /// Given another strata estimator, how big of an IBF should you make to successfully
     /// decode the differences provided the IBFs are made of the same elements that went
     /// into these strata estimators.
-    pub fn estimate_differences(&self, other: &StrataEstimator) -> Result<usize, String> {
-        if self.ibfs.len() != other.ibfs.len() {
-            return Err("Strata Estimators are of different sizes".to_string());
-        }
-
-        let mut count = 0usize;
-        for (i, (l, r)) in self.ibfs.iter().zip(other.ibfs.iter()).enumerate().rev() {
-            let ibf = (l - r)?;
-            if let Ok(set) = ibf.decode() {
-                count += set.len();
-            } else {
-                count *= 2_usize.pow((i as u32) + 2);
-                break;
-            }
+    pub fn estimate_differences(&self, other: &StrataEstimator) -> Result<usize, IronRoseError> {
+        Ok(self.estimate(other)?.value().saturating_mul(2))
+    }
+
+    /// Estimates the number of differences between the sets encoded into the two estimators.
+    /// When every stratum decodes, the estimators have proven the exact number of differences
+    /// and [Exact](Estimate::Exact) is returned, otherwise the count is extrapolated from the
+    /// strata that did decode. Unlike [estimate_differences](StrataEstimator::estimate_differences)
+    /// no overhead multiplier is applied.
+    ///
+    /// Strata are decoded from the last down, until one fails. Stratum j holds the elements with
+    /// j trailing zeros (mod the number of strata L), a share of `2^-(j+1) / (1 - 2^-L)` of
+    /// them, so when stratum i fails, the differences counted in the strata above it are divided
+    /// by the share those strata hold, `(2^-(i+1) - 2^-L) / (1 - 2^-L)`. That is about
+    /// `count * 2^(i+1)`, as in the paper, but exact for shallow estimators. If no stratum above
+    /// it decoded anything, the stratum's cell count is divided by its own share instead, a lower
+    /// bound on what it held, so sets that clearly differ are never estimated at 0.
+    /// ```rust
+    /// use iron_rose::{Estimate, StrataEstimator};
+    ///
+    /// let mut se1 = StrataEstimator::default();
+    /// let mut se2 = StrataEstimator::default();
+    /// for i in 0..1000 {
+    ///    se1.encode(i);
+    ///    se2.encode(i + 5);
+    /// }
+    /// assert_eq!(se1.estimate(&se2), Ok(Estimate::Exact(10)));
+    /// ```
+    pub fn estimate(&self, other: &StrataEstimator) -> Result<Estimate, IronRoseError> {
+        Ok(self.estimate_traced(other)?.0)
+    }
+
+    /// Same as [estimate](StrataEstimator::estimate), along with a trace of how each stratum
+    /// decoded, so a miscalibrated estimate can be debugged from logs.
+    /// ```rust
+    /// use iron_rose::StrataEstimator;
+    ///
+    /// let (mut se1, se2) = (StrataEstimator::default(), StrataEstimator::default());
+    /// (0..10_000).for_each(|i| se1.encode(i));
+    /// let (estimate, trace) = se1.estimate_traced(&se2).unwrap();
+    /// let stuck = trace.extrapolated_at.unwrap();
+    /// assert_eq!(trace.strata[0].stratum, stuck);
+    /// assert_eq!(trace.strata[0].recovered, None);
+    /// assert!(!estimate.is_exact());
+    /// ```
+    pub fn estimate_traced(
+        &self,
+        other: &StrataEstimator,
+    ) -> Result<(Estimate, EstimateTrace), IronRoseError> {
+        self.check_shape(other)?;
+        decode_strata(&self.ibfs, &other.ibfs)
+    }
+
+    /// Like [estimate](StrataEstimator::estimate), but estimators of different depths are
+    /// compared too, instead of failing, so peers running different defaults during a rolling
+    /// upgrade can still sync. Their strata still have to be the same shape.
+    ///
+    /// The deeper estimator's strata are folded onto the shallower one's, stratum j onto
+    /// stratum j mod the shallower depth, which is where the shallower estimator put the same
+    /// elements, except for the one in 2^depth of them with more trailing zeros than the deeper
+    /// estimator has strata. Those may be counted as differences, so an estimate across depths
+    /// is never [Exact](Estimate::Exact) and is [degraded](OverlappingEstimate::is_degraded).
+    /// ```rust
+    /// use iron_rose::StrataEstimator;
+    ///
+    /// let (mut old, mut new) = (StrataEstimator::new_with_size(16), StrataEstimator::default());
+    /// for i in 0..1000 {
+    ///     old.encode(i);
+    ///     new.encode(i + 5);
+    /// }
+    /// assert!(old.estimate(&new).is_err());
+    /// let estimate = old.estimate_overlapping(&new).unwrap();
+    /// assert!(estimate.is_degraded());
+    /// assert_eq!((estimate.common_strata, estimate.estimate.value()), (16, 10));
+    /// ```
+    pub fn estimate_overlapping(
+        &self,
+        other: &StrataEstimator,
+    ) -> Result<OverlappingEstimate, IronRoseError> {
+        let (left, right) = (self.config(), other.config());
+        if left.stratum() != right.stratum() {
+            return Err(IronRoseError::EstimatorSizeMismatch {
+                left_strata: left.strata(),
+                left: left.stratum(),
+                right_strata: right.strata(),
+                right: right.stratum(),
+            });
+        }
+        let common = self.strata().min(other.strata());
+        let (left, right) = (self.folded(common)?, other.folded(common)?);
+        let (mut estimate, _) = decode_strata(&left, &right)?;
+        if self.strata() != other.strata() {
+            estimate = Estimate::Approximate(estimate.value());
+        }
+        Ok(OverlappingEstimate {
+            estimate,
+            common_strata: common,
+            left_strata: self.strata(),
+            right_strata: other.strata(),
+        })
+    }
+
+    /// The strata folded down to depth strata, stratum j onto stratum j mod depth
+    fn folded(&self, depth: usize) -> Result<Vec<Arc<IBF<u64>>>, IronRoseError> {
+        let mut strata = self.ibfs[..depth].to_vec();
+        for (j, stratum) in self.ibfs.iter().enumerate().skip(depth) {
+            Arc::make_mut(&mut strata[j % depth]).merge(stratum)?;
+        }
+        Ok(strata)
+    }
+}
+
+/// Decodes the strata differences from the last down, extrapolating from the first that fails
+fn decode_strata(
+    left: &[Arc<IBF<u64>>],
+    right: &[Arc<IBF<u64>>],
+) -> Result<(Estimate, EstimateTrace), IronRoseError> {
+    let strata = left.len();
+    let mut trace = EstimateTrace {
+        strata: Vec::with_capacity(strata),
+        extrapolated_at: None,
+    };
+    let mut count = 0usize;
+    for (i, (l, r)) in left.iter().zip(right.iter()).enumerate().rev() {
+        let ibf = (&**l - &**r)?;
+        let mut sides = Vec::new();
+        let recovered = ibf.decode_into(&mut sides).ok().map(|()| sides.len());
+        trace.strata.push(StratumTrace {
+            stratum: i,
+            recovered,
+        });
+        if let Some(recovered) = recovered {
+            count += recovered;
+        } else {
+            trace.extrapolated_at = Some(i);
+            trace.strata.reverse();
+            let estimate = extrapolate(count, i, strata, l.params().size());
+            return Ok((Estimate::Approximate(estimate), trace));
+        }
+    }
+
+    trace.strata.reverse();
+    Ok((Estimate::Exact(count), trace))
+}
+
+/// Scales the count of differences decoded from the strata above the one that failed up to all
+/// of them, by the share of elements those strata hold. Saturates rather than overflowing when
+/// one of the deepest strata fails.
+///
+/// When nothing was decoded above the failed stratum, as when the last one fails, there is
+/// nothing to scale, but the failed stratum held more differences than its cells could decode,
+/// so its cell count is scaled by its own share instead, a lower bound rather than 0.
+fn extrapolate(count: usize, failed: usize, strata: usize, cells: usize) -> usize {
+    // 2^-j, for j up to MAX_STRATA
+    let pow = |j: usize| 1.0 / (1u128 << j) as f64;
+    let (count, share) = if count == 0 {
+        (cells, pow(failed + 1) / (1.0 - pow(strata)))
+    } else {
+        (count, (pow(failed + 1) - pow(strata)) / (1.0 - pow(strata)))
+    };
+    // Float to int casts saturate
+    (count as f64 / share + 0.5) as usize
+}
+
+/// How an [Estimate](Estimate) came about, from
+/// [StrataEstimator::estimate_traced](StrataEstimator::estimate_traced)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EstimateTrace {
+    /// The strata that were subtracted and decoded, in stratum order. Strata are visited from
+    /// the last down, so the ones below the stratum that failed to decode are absent.
+    pub strata: Vec<StratumTrace>,
+    /// The stratum that failed to decode, triggering extrapolation, if any did
+    pub extrapolated_at: Option<usize>,
+}
+
+/// Load of one stratum, from
+/// [StrataEstimator::distribution_report](StrataEstimator::distribution_report)
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StratumLoad {
+    /// Index of the stratum
+    pub stratum: usize,
+    /// Number of non-empty cells
+    pub occupied_cells: usize,
+    /// Elements in the stratum, estimated from the share of empty cells
+    pub estimated_elements: f64,
+    /// How many cells hold each count
+    pub load_histogram: BTreeMap<i32, usize>,
+}
+
+/// An estimate between estimators of possibly different depths, from
+/// [StrataEstimator::estimate_overlapping](StrataEstimator::estimate_overlapping)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct OverlappingEstimate {
+    /// The estimate, never exact when the depths differ
+    pub estimate: Estimate,
+    /// Number of strata compared, the depth of the shallower estimator
+    pub common_strata: usize,
+    /// Number of strata of the left hand side
+    pub left_strata: usize,
+    /// Number of strata of the right hand side
+    pub right_strata: usize,
+}
+
+impl OverlappingEstimate {
+    /// True if the estimators differed in depth, so the estimate is less accurate than
+    /// [estimate](StrataEstimator::estimate) would give for estimators of the same depth.
+    /// Worth logging, as it means peers are configured differently.
+    pub fn is_degraded(&self) -> bool {
+        self.left_strata != self.right_strata
+    }
+}
+
+/// One stratum's part in an [EstimateTrace](EstimateTrace)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StratumTrace {
+    /// Index of the stratum
+    pub stratum: usize,
+    /// Number of differences recovered from the stratum, None if it failed to decode
+    pub recovered: Option<usize>,
+}
+
+/// Picks which stratum of a [StrataEstimator](StrataEstimator) an element is encoded into, given
+/// the element, its hash and the number of strata. Out of range strata wrap around.
+///
+/// The estimate assumes stratum i holds roughly 1 / 2^(i+1) of the elements, which is what
+/// [TrailingZeros](TrailingZeros) gives, or [Recency](Recency) for keys appended at a steady
+/// rate. Other selectors are mostly useful for building exact scenarios in tests. Closures
+/// taking `(&T, u64, usize)` are selectors too.
+pub trait StratumSelector<T: ?Sized> {
+    /// The stratum element belongs in
+    fn stratum(&self, element: &T, hash: u64, strata: usize) -> usize;
+}
+
+/// The default [StratumSelector](StratumSelector): the number of trailing zeros in the element's
+/// hash
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrailingZeros;
+
+impl<T: ?Sized> StratumSelector<T> for TrailingZeros {
+    fn stratum(&self, _element: &T, hash: u64, strata: usize) -> usize {
+        hash.trailing_zeros() as usize % strata
+    }
+}
+
+/// A [StratumSelector](StratumSelector) for time ordered keys (ULIDs, KSUIDs, ...) in append
+/// mostly sets, where differences are clustered in the most recent keys. Keys younger than
+/// resolution go in the last stratum, and each doubling of age moves a key one stratum down, the
+/// oldest keys all sharing stratum 0. Recent differences are recovered from the deep strata
+/// exactly, instead of being extrapolated from a sample of every key.
+///
+/// When keys are appended at a steady rate, each stratum holds about twice the keys of the one
+/// above it, as with [TrailingZeros](TrailingZeros), so extrapolation stays in the right
+/// ballpark. Both peers must agree on now, exchanging it along with the estimators.
+/// ```rust
+/// use iron_rose::{Estimate, Recency, StrataEstimator};
+///
+/// // Keys are (millisecond timestamp, sequence) pairs
+/// let recency = Recency::new(|key: &(u64, u64)| key.0, 100_000, 10);
+/// let (mut se1, mut se2) = (StrataEstimator::default(), StrataEstimator::default());
+/// for ts in 0..100_000u64 {
+///     se1.encode_with((ts, 0), &recency);
+///     if ts < 99_950 {
+///         se2.encode_with((ts, 0), &recency);
+///     }
+/// }
+/// assert_eq!(se1.estimate(&se2), Ok(Estimate::Exact(50)));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Recency<F> {
+    key_time: F,
+    now: u64,
+    resolution: u64,
+}
+
+impl<F> Recency<F> {
+    /// Selector placing keys by the age of the time key_time extracts from them, relative to
+    /// now, in units of resolution. Panics if resolution is 0.
+    pub fn new(key_time: F, now: u64, resolution: u64) -> Self {
+        assert!(resolution > 0, "resolution must be positive");
+        Self {
+            key_time,
+            now,
+            resolution,
+        }
+    }
+}
+
+impl<T: ?Sized, F> StratumSelector<T> for Recency<F>
+where
+    F: Fn(&T) -> u64,
+{
+    fn stratum(&self, element: &T, _hash: u64, strata: usize) -> usize {
+        let age = self.now.saturating_sub((self.key_time)(element)) / self.resolution;
+        // 0 for the youngest keys, then one more for every doubling of age
+        let depth = (u64::BITS - age.leading_zeros()) as usize;
+        (strata - 1).saturating_sub(depth)
+    }
+}
+
+impl<T: ?Sized, F> StratumSelector<T> for F
+where
+    F: Fn(&T, u64, usize) -> usize,
+{
+    fn stratum(&self, element: &T, hash: u64, strata: usize) -> usize {
+        self(element, hash, strata)
+    }
+}
+
+/// Number of differences between two sets, as estimated by a [StrataEstimator](StrataEstimator)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Estimate {
+    /// Every stratum decoded, so this is exactly the number of differences
+    Exact(usize),
+    /// Extrapolated from the strata that decoded
+    Approximate(usize),
+}
+
+impl Estimate {
+    /// The estimated number of differences, exact or not
+    pub fn value(&self) -> usize {
+        match self {
+            Estimate::Exact(n) | Estimate::Approximate(n) => *n,
         }
+    }
 
-        Ok(count * 2)
+    /// True if the number of differences is known exactly
+    pub fn is_exact(&self) -> bool {
+        matches!(self, Estimate::Exact(_))
     }
 }
 
@@ -84,5 +908,350 @@ mod test {
             se2.encode(i + 1000);
         }
         assert!(se1.estimate_differences(&se2).unwrap() > 1000);
+        assert!(!se1.estimate(&se2).unwrap().is_exact());
+    }
+
+    #[test]
+    fn clone_is_copy_on_write() {
+        let mut live = StrataEstimator::default();
+        for i in 0..100 {
+            live.encode(i);
+        }
+        let snapshot = live.clone();
+        assert!(Arc::ptr_eq(&live.ibfs[0], &snapshot.ibfs[0]));
+
+        live.encode(1000);
+        let changed = live
+            .ibfs
+            .iter()
+            .zip(snapshot.ibfs.iter())
+            .filter(|(l, r)| !Arc::ptr_eq(l, r))
+            .count();
+        assert_eq!(changed, 1);
+        assert_eq!(live.estimate(&snapshot), Ok(Estimate::Exact(1)));
+    }
+
+    #[test]
+    fn shape_mismatch_is_reported() {
+        let se1 = StrataEstimator::default();
+        let se2 = StrataEstimator::new_with_size(16);
+        assert_eq!(se1.strata(), 32);
+        assert_eq!(se1.stratum_size(), 80);
+        assert_eq!(se1.hash_count(), 3);
+        let err = se1.estimate(&se2).unwrap_err().to_string();
+        assert!(err.contains("expected 32 strata"), "{}", err);
+        assert!(err.contains("found 16 strata"), "{}", err);
+    }
+
+    #[test]
+    fn merged_shards_match_the_whole() {
+        let mut whole = StrataEstimator::default();
+        let mut shards = vec![StrataEstimator::default(); 4];
+        for i in 0..2000u64 {
+            whole.encode(i);
+            shards[(i % 4) as usize].encode(i);
+        }
+        let mut merged = shards[0].clone();
+        for shard in &shards[1..] {
+            merged.merge(shard).unwrap();
+        }
+        for (l, r) in merged.ibfs.iter().zip(whole.ibfs.iter()) {
+            assert_eq!(l, r);
+        }
+        assert!(merged.merge(&StrataEstimator::new_with_size(16)).is_err());
+    }
+
+    #[test]
+    fn recency_doubles_age_per_stratum() {
+        let recency = Recency::new(|x: &u64| *x, 1000, 2);
+        let stratum = |x: u64| recency.stratum(&x, element_hash(&x), 32);
+        assert_eq!(stratum(1000), 31);
+        assert_eq!(stratum(1001), 31);
+        assert_eq!(stratum(999), 31);
+        assert_eq!(stratum(998), 30);
+        assert_eq!(stratum(996), 29);
+        assert_eq!(stratum(993), 29);
+        assert_eq!(stratum(992), 28);
+        assert_eq!(stratum(0), 31 - 9);
+        assert_eq!(
+            Recency::new(|x: &u64| *x, u64::MAX, 1).stratum(&0, 0, 32),
+            0
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn strata_past_the_hash_width_are_rejected() {
+        StrataEstimator::new_with_size(profiles::MAX_STRATA + 1);
+    }
+
+    #[test]
+    fn deepest_strata_saturate() {
+        let deepest = profiles::MAX_STRATA - 1;
+        let (mut se1, se2) = (
+            StrataEstimator::new_with_size(profiles::MAX_STRATA),
+            StrataEstimator::new_with_size(profiles::MAX_STRATA),
+        );
+        se1.encode_with(0u64, &|_: &u64, _, _| deepest);
+        for i in 1..200u64 {
+            se1.encode_with(i, &|_: &u64, _, _| deepest - 1);
+        }
+        let (estimate, trace) = se1.estimate_traced(&se2).unwrap();
+        assert_eq!(trace.extrapolated_at, Some(deepest - 1));
+        assert_eq!(estimate, Estimate::Approximate(usize::MAX));
+        assert_eq!(se1.estimate_differences(&se2), Ok(usize::MAX));
+    }
+
+    #[cfg(feature = "dp")]
+    #[test]
+    fn noise_only_inflates() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut se1 = StrataEstimator::default();
+        let mut se2 = StrataEstimator::default();
+        for i in 0..1000 {
+            se1.encode(i);
+            se2.encode(i);
+        }
+        se1.add_noise(1.0, 1e-3, &mut rng);
+        let noised = se1.estimate(&se2).unwrap().value();
+        // Every stratum gets shifted noise of about ln(500) ~ 6 elements
+        assert!(noised > 100 && noised < 300, "{}", noised);
+    }
+
+    #[test]
+    fn duplicate_detection_is_opt_in() {
+        let mut plain = StrataEstimator::default();
+        plain.encode(1);
+        plain.encode(1);
+        assert_eq!(plain.suspected_duplicates(), None);
+
+        let mut checked = StrataEstimator::default().with_duplicate_detection(100_000);
+        for i in 0..1000 {
+            assert!(checked.try_encode(i).is_ok());
+        }
+        for i in 0..10 {
+            assert!(checked.try_encode(i).is_err());
+        }
+        let mut reference = StrataEstimator::default();
+        for i in 0..1000 {
+            reference.encode(i);
+        }
+        assert_eq!(checked.estimate(&reference), Ok(Estimate::Exact(0)));
+        assert!(checked.suspected_duplicates().unwrap() >= 10);
+    }
+
+    #[test]
+    fn removals_follow_the_selector() {
+        let first = |_: &u64, _: u64, _: usize| 0;
+        let (mut live, empty) = (StrataEstimator::default(), StrataEstimator::default());
+        (0..100u64).for_each(|x| live.encode_with(x, &first));
+        (0..100u64).for_each(|x| live.remove_with(x, &first));
+        assert_eq!(live.estimate(&empty), Ok(Estimate::Exact(0)));
+
+        // Removing with the default selector takes elements out of the wrong strata
+        live.encode_with(7u64, &first);
+        live.remove(7u64);
+        assert_ne!(live.estimate(&empty), Ok(Estimate::Exact(0)));
+    }
+
+    #[test]
+    fn failure_at_a_chosen_stratum() {
+        // 200 differences in stratum 5 can't decode, 3 in stratum 6 can
+        let selector = |e: &u64, _: u64, _: usize| if *e < 200 { 5 } else { 6 };
+        let (mut left, right) = (StrataEstimator::default(), StrataEstimator::default());
+        for i in 0..203u64 {
+            left.encode_with(i, &selector);
+        }
+        assert_eq!(
+            left.estimate(&right),
+            Ok(Estimate::Approximate(3 * 2usize.pow(5 + 1)))
+        );
+        let (_, trace) = left.estimate_traced(&right).unwrap();
+        assert_eq!(trace.extrapolated_at, Some(5));
+        assert_eq!(trace.strata.len(), 32 - 5);
+        assert_eq!(trace.strata[1].recovered, Some(3));
+    }
+
+    #[test]
+    fn extrapolation_is_unbiased() {
+        // With 2 strata, stratum 1 holds a third of the elements
+        assert_eq!(extrapolate(10, 0, 2, 80), 30);
+        assert_eq!(extrapolate(10, 4, 32, 80), 320);
+        // With nothing above the failed stratum, its cells are scaled by its share
+        assert_eq!(extrapolate(0, 3, 32, 80), 80 * 16);
+        assert_eq!(extrapolate(0, 3, 4, 10), 150);
+
+        // Small strata fail early for 4000 differences, so the estimate is extrapolated
+        let config = StrataEstimatorConfig::new(32, SketchParams::new(20, 3));
+        let trials = 20;
+        let total: usize = (0..trials)
+            .map(|t| {
+                let (mut se1, mut se2) = (
+                    StrataEstimator::with_config(config),
+                    StrataEstimator::with_config(config),
+                );
+                for i in 0..8000u64 {
+                    se1.encode((t, i));
+                    se2.encode((t, i + 2000));
+                }
+                let estimate = se1.estimate(&se2).unwrap();
+                assert!(!estimate.is_exact());
+                estimate.value()
+            })
+            .sum();
+        let mean = total / trials as usize;
+        assert!(mean > 3200 && mean < 4800, "{}", mean);
+    }
+
+    #[test]
+    fn shallow_estimators_never_estimate_zero_for_differing_sets() {
+        let config = profiles::REALTIME_ESTIMATOR;
+        for &differences in [100u64, 1000, 5000].iter() {
+            let (mut se1, se2) = (
+                StrataEstimator::with_config(config),
+                StrataEstimator::with_config(config),
+            );
+            (0..differences).for_each(|i| se1.encode(i));
+            let (estimate, trace) = se1.estimate_traced(&se2).unwrap();
+            assert_eq!(trace.extrapolated_at, Some(config.strata() - 1));
+            assert!(!estimate.is_exact());
+            // The last stratum's 10 cells over its share of a fifteenth
+            assert!(estimate.value() >= 150, "{:?}", estimate);
+        }
+    }
+
+    #[test]
+    fn compact_strata_estimate() {
+        let config = StrataEstimatorConfig::new(32, profiles::COMPACT_STRATUM);
+        let (mut se1, mut se2) = (
+            StrataEstimator::with_config(config),
+            StrataEstimator::with_config(config),
+        );
+        for i in 0..1000u64 {
+            se1.encode(i);
+            se2.encode(i + 2);
+        }
+        assert_eq!(se1.estimate(&se2), Ok(Estimate::Exact(4)));
+
+        // Single estimates are noisy, but unbiased enough on average
+        let trials = 10;
+        let total: usize = (0..trials)
+            .map(|t| {
+                let mut se = StrataEstimator::with_config(config);
+                (0..1000u64).for_each(|i| se.encode((t, i)));
+                se.estimate(&StrataEstimator::with_config(config))
+                    .unwrap()
+                    .value()
+            })
+            .sum();
+        let mean = total / trials as usize;
+        assert!(mean > 600 && mean < 1400, "{}", mean);
+    }
+
+    #[test]
+    fn prehashed_matches_encode() {
+        let (mut plain, mut prehashed) = (StrataEstimator::default(), StrataEstimator::default());
+        for i in 0..500u64 {
+            plain.encode(i);
+            prehashed.encode_prehashed(element_hash(&i));
+        }
+        assert_eq!(plain.estimate(&prehashed), Ok(Estimate::Exact(0)));
+    }
+
+    #[test]
+    fn flattens_into_messages() {
+        #[derive(Serialize, Deserialize)]
+        struct Hello {
+            peer: String,
+            #[serde(flatten)]
+            estimator: StrataEstimator,
+        }
+
+        let mut estimator = StrataEstimator::default();
+        (0..100).for_each(|i| estimator.encode(i));
+        let hello = Hello {
+            peer: "a".to_string(),
+            estimator: estimator.clone(),
+        };
+        let json = serde_json::to_string(&hello).unwrap();
+        let back: Hello = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.peer, "a");
+        assert_eq!(back.estimator.estimate(&estimator), Ok(Estimate::Exact(0)));
+    }
+
+    #[test]
+    fn deserialization_is_checked() {
+        assert!(serde_json::from_str::<StrataEstimator>(r#"{"ibfs":[]}"#).is_err());
+
+        let mut json = serde_json::to_value(StrataEstimator::default()).unwrap();
+        let small = serde_json::to_value(IBF::<u64>::new(4)).unwrap();
+        json["ibfs"][3] = small.clone();
+        assert!(serde_json::from_value::<StrataEstimator>(json).is_err());
+
+        let too_many = vec![small; profiles::MAX_STRATA + 1];
+        let json = serde_json::json!({ "ibfs": too_many });
+        assert!(serde_json::from_value::<StrataEstimator>(json).is_err());
+    }
+
+    #[test]
+    fn any_hashable_element_type() {
+        let digest = |i: u8| [i; 32];
+        let (mut se1, mut se2) = (StrataEstimator::default(), StrataEstimator::default());
+        for i in 0..200u8 {
+            se1.encode(digest(i));
+            if i % 20 != 0 {
+                se2.encode(digest(i));
+            }
+        }
+        assert_eq!(se1.estimate(&se2), Ok(Estimate::Exact(10)));
+
+        let (mut se1, mut se2) = (StrataEstimator::default(), StrataEstimator::default());
+        se1.encode("orders/1".to_string());
+        se2.encode("orders/1");
+        assert_eq!(se1.estimate(&se2), Ok(Estimate::Exact(0)));
+    }
+
+    #[test]
+    fn identical_sets_are_exact() {
+        let mut se1 = StrataEstimator::default();
+        let mut se2 = StrataEstimator::default();
+        for i in 0..1000 {
+            se1.encode(i);
+            se2.encode(i);
+        }
+        assert_eq!(se1.estimate(&se2), Ok(Estimate::Exact(0)));
+        assert_eq!(se1.estimate_differences(&se2), Ok(0));
+    }
+
+    #[test]
+    fn depths_overlap_when_folded() {
+        let (mut shallow, mut deep) = (
+            StrataEstimator::new_with_size(8),
+            StrataEstimator::default(),
+        );
+        for i in 0..50_000u64 {
+            shallow.encode(i);
+            deep.encode(i + 2_000);
+        }
+        let same_depth = StrataEstimator::new_with_size(8);
+        let mut folded = same_depth.clone();
+        (2_000..52_000u64).for_each(|i| folded.encode(i));
+        // Folding puts the deeper estimator's elements where the shallower one would have
+        let overlapping = shallow.estimate_overlapping(&deep).unwrap();
+        assert_eq!(
+            overlapping.estimate.value(),
+            shallow.estimate(&folded).unwrap().value()
+        );
+        assert_eq!((overlapping.left_strata, overlapping.right_strata), (8, 32));
+        assert!(!overlapping.estimate.is_exact());
+
+        let even = shallow.estimate_overlapping(&shallow).unwrap();
+        assert!(!even.is_degraded());
+        assert_eq!(even.estimate, Estimate::Exact(0));
+        let compact =
+            StrataEstimator::with_config(StrataEstimatorConfig::new(8, profiles::COMPACT_STRATUM));
+        assert!(shallow.estimate_overlapping(&compact).is_err());
     }
 }
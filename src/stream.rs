@@ -0,0 +1,369 @@
+//! Reading messages in the binary wire format from a stream, checking what their headers claim
+//! as they arrive rather than trusting them up front.
+use crate::archive::{ARCHIVE_SINCE, FLAG_CREATED_AT, FLAG_CREATOR, FLAG_LABEL};
+use crate::ibf::check_hash_count;
+use crate::wire::{self, WireElement};
+use crate::{
+    profiles, AdaptiveStrataEstimator, Count, DefaultBuildHasher, IronRoseError, SketchArchive,
+    StrataEstimator, IBF,
+};
+use std::{
+    fmt::Debug,
+    hash::Hash,
+    io::{self, Read},
+    ops::{BitXor, BitXorAssign},
+};
+
+/// Most bytes read into a message at a time, so memory grows with the bytes that actually
+/// arrive rather than with what a header claims
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Reads one message in the binary wire format from reader, leaving anything after it unread,
+/// and returns its bytes for the `from_bytes` of its [kind](crate::MessageKind). Sizes the
+/// headers declare are checked against max_bytes before any of what they describe is read, and
+/// the message is read in bounded chunks, so a short header claiming gigabytes costs no more
+/// than the bytes that arrive, and a stream that ends early fails as soon as it does.
+///
+/// Malformed headers, and messages over max_bytes, fail with
+/// [InvalidData](io::ErrorKind::InvalidData) errors wrapping an [IronRoseError](IronRoseError),
+/// such as [OverWireBudget](IronRoseError::OverWireBudget), and a stream that ends early with
+/// [UnexpectedEof](io::ErrorKind::UnexpectedEof). The message's checksum and contents are
+/// checked by `from_bytes`.
+/// ```rust
+/// use iron_rose::{read_message, IronRoseError, IBF};
+/// use std::io::ErrorKind;
+///
+/// let bytes = IBF::<u64>::new(1000).to_bytes();
+/// let mut stream = bytes.repeat(2);
+/// stream.extend_from_slice(&bytes[..100]);
+/// let mut stream = &stream[..];
+/// assert_eq!(read_message(&mut stream, 64 * 1024).unwrap(), bytes);
+/// assert_eq!(read_message(&mut stream, 64 * 1024).unwrap(), bytes);
+/// assert_eq!(read_message(&mut stream, 64 * 1024).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+///
+/// // The size is checked from the header, before the cells are read
+/// let err = read_message(&bytes[..12], 1024).unwrap_err();
+/// let err = err.into_inner().unwrap().downcast::<IronRoseError>().unwrap();
+/// assert_eq!(*err, IronRoseError::OverWireBudget { bytes: 20_016, max: 1024 });
+/// ```
+pub fn read_message<R: Read>(reader: R, max_bytes: usize) -> io::Result<Vec<u8>> {
+    let mut stream = Stream::new(reader, max_bytes);
+    stream.message(None)?;
+    Ok(stream.bytes)
+}
+
+/// A message being read, and where from
+struct Stream<R> {
+    reader: R,
+    bytes: Vec<u8>,
+    max_bytes: usize,
+}
+
+impl<R: Read> Stream<R> {
+    fn new(reader: R, max_bytes: usize) -> Self {
+        Self {
+            reader,
+            bytes: Vec::new(),
+            max_bytes,
+        }
+    }
+
+    /// Reads a whole message onto the bytes read so far, failing early unless it's of kind
+    fn message(&mut self, kind: Option<u8>) -> io::Result<()> {
+        let header = self.fill(4)?;
+        let (version, found, width, flags) = (header[0], header[1], header[2] as usize, header[3]);
+        wire::check_version(version).map_err(invalid_data)?;
+        if kind.is_some_and(|kind| kind != found) {
+            return Err(malformed("not the expected kind of sketch"));
+        }
+        let footer = if wire::has_checksum(version, found) {
+            4
+        } else {
+            0
+        };
+        match found {
+            wire::KIND_IBF => {
+                let cell = width + 8 + wire::count_width(flags).map_err(invalid_data)?;
+                let (hash_count, size) = (self.u32()?, self.u32()?);
+                check_hash_count(hash_count, size).map_err(invalid_data)?;
+                if flags & wire::FLAG_KEYSPACE != 0 {
+                    let len = self.u16()?;
+                    self.fill(len)?;
+                }
+                self.fill(size.saturating_mul(cell).saturating_add(footer))?;
+            }
+            wire::KIND_STRATA | wire::KIND_ADAPTIVE_STRATA => {
+                let strata = self.u32()?;
+                if strata == 0 || strata > profiles::MAX_STRATA {
+                    return Err(malformed("number of strata out of range"));
+                }
+                let (hash_count, size) = (self.u32()?, self.u32()?);
+                check_hash_count(hash_count, size).map_err(invalid_data)?;
+                // Strata always count in i32
+                let cell = width + 8 + 4;
+                let cells = strata.saturating_mul(size).saturating_mul(cell);
+                self.fill(cells.saturating_add(footer))?;
+            }
+            wire::KIND_SNAPSHOT => {
+                // The sequence number, the IBF, and the snapshot's own checksum
+                self.fill(8)?;
+                self.message(Some(wire::KIND_IBF))?;
+                self.fill(8)?;
+            }
+            wire::KIND_ARCHIVE if version >= ARCHIVE_SINCE => {
+                for flag in [FLAG_CREATOR, FLAG_CREATED_AT, FLAG_LABEL].iter() {
+                    match flags & flag {
+                        0 => {}
+                        FLAG_CREATED_AT => {
+                            self.fill(8)?;
+                        }
+                        _ => self.string()?,
+                    }
+                }
+                for _ in 0..self.u32()? {
+                    self.string()?;
+                    self.string()?;
+                }
+                let sketch = self.u32()?;
+                self.fill(sketch.saturating_add(footer))?;
+            }
+            wire::KIND_ARCHIVE => {
+                return Err(invalid_data(IronRoseError::UnsupportedVersion { version }))
+            }
+            _ => return Err(malformed("unknown kind of sketch")),
+        }
+        Ok(())
+    }
+
+    /// Reads n more bytes, unless that would take the message over the limit, and returns them
+    fn fill(&mut self, n: usize) -> io::Result<&[u8]> {
+        let start = self.bytes.len();
+        let end = start.saturating_add(n);
+        if end > self.max_bytes {
+            return Err(invalid_data(IronRoseError::OverWireBudget {
+                bytes: end,
+                max: self.max_bytes,
+            }));
+        }
+        while self.bytes.len() < end {
+            let from = self.bytes.len();
+            self.bytes.resize(from + (end - from).min(READ_CHUNK), 0);
+            self.reader.read_exact(&mut self.bytes[from..])?;
+        }
+        Ok(&self.bytes[start..])
+    }
+
+    fn u16(&mut self) -> io::Result<usize> {
+        Ok(u16::read_le(self.fill(2)?) as usize)
+    }
+
+    fn u32(&mut self) -> io::Result<usize> {
+        Ok(u32::read_le(self.fill(4)?) as usize)
+    }
+
+    /// Skips over a string as an archive writes them
+    fn string(&mut self) -> io::Result<()> {
+        let len = self.u16()?;
+        self.fill(len).map(|_| ())
+    }
+}
+
+fn invalid_data(err: IronRoseError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn malformed(reason: &'static str) -> io::Error {
+    invalid_data(IronRoseError::MalformedBytes { reason })
+}
+
+/// Reads a message of kind with [read_message](read_message)'s checks, and parses it
+fn read_kind<R, S, F>(reader: R, kind: u8, max_bytes: usize, parse: F) -> io::Result<S>
+where
+    R: Read,
+    F: FnOnce(&[u8]) -> Result<S, IronRoseError>,
+{
+    let mut stream = Stream::new(reader, max_bytes);
+    stream.message(Some(kind))?;
+    parse(&stream.bytes).map_err(invalid_data)
+}
+
+impl<T, C> IBF<T, DefaultBuildHasher, C>
+where
+    T: Clone
+        + Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + WireElement,
+    C: Count,
+{
+    /// Reads an IBF from a stream, like [from_bytes](IBF::from_bytes) with the checks of
+    /// [read_message](crate::read_message): the message may be at most max_bytes, and its
+    /// declared size is checked before the cells are read.
+    /// ```rust
+    /// use iron_rose::IBF;
+    ///
+    /// let mut ibf = IBF::new(100);
+    /// ibf.encode(7u64);
+    /// let bytes = ibf.to_bytes();
+    /// assert_eq!(IBF::<u64>::read_from(&bytes[..], 4096).unwrap(), ibf);
+    /// assert!(IBF::<u64>::read_from(&bytes[..], 1024).is_err());
+    /// ```
+    pub fn read_from<R: Read>(reader: R, max_bytes: usize) -> io::Result<Self> {
+        read_kind(reader, wire::KIND_IBF, max_bytes, Self::from_bytes)
+    }
+}
+
+impl StrataEstimator {
+    /// Reads an estimator from a stream, like [from_bytes](StrataEstimator::from_bytes) with
+    /// the checks of [read_message](crate::read_message)
+    pub fn read_from<R: Read>(reader: R, max_bytes: usize) -> io::Result<Self> {
+        read_kind(reader, wire::KIND_STRATA, max_bytes, Self::from_bytes)
+    }
+}
+
+impl AdaptiveStrataEstimator {
+    /// Reads an estimator from a stream, like
+    /// [from_bytes](AdaptiveStrataEstimator::from_bytes) with the checks of
+    /// [read_message](crate::read_message)
+    pub fn read_from<R: Read>(reader: R, max_bytes: usize) -> io::Result<Self> {
+        read_kind(
+            reader,
+            wire::KIND_ADAPTIVE_STRATA,
+            max_bytes,
+            Self::from_bytes,
+        )
+    }
+}
+
+impl SketchArchive {
+    /// Reads an archive from a stream, like [from_bytes](SketchArchive::from_bytes) with the
+    /// checks of [read_message](crate::read_message)
+    pub fn read_from<R: Read>(reader: R, max_bytes: usize) -> io::Result<Self> {
+        read_kind(reader, wire::KIND_ARCHIVE, max_bytes, Self::from_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SketchMetadata, SketchParams};
+
+    /// A reader that fails the test if more than limit bytes are asked of it
+    struct Bounded<'a> {
+        bytes: &'a [u8],
+        limit: usize,
+    }
+
+    impl Read for Bounded<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            assert!(buf.len() <= self.limit, "read {} bytes at once", buf.len());
+            self.bytes.read(buf)
+        }
+    }
+
+    #[test]
+    fn every_kind_is_read_whole() {
+        let mut ibf = IBF::new(30).with_keyspace("orders");
+        (0..20u64).for_each(|x| ibf.encode(x));
+        let mut strata = StrataEstimator::default();
+        let mut adaptive = AdaptiveStrataEstimator::new(2, SketchParams::new(8, 2));
+        (0..20u64).for_each(|x| {
+            strata.encode(x);
+            adaptive.encode(x);
+        });
+        let metadata = SketchMetadata::default()
+            .with_creator("test")
+            .with_created_at(7)
+            .with_entry("a", "b");
+        let archive = SketchArchive::new(ibf.snapshot(1), metadata).unwrap();
+        let compact = ibf.clone().into_count_width::<i16>().unwrap();
+        let wide = ibf.clone().into_count_width::<i64>().unwrap();
+        let messages = [
+            ibf.to_bytes(),
+            ibf.to_bytes_as(crate::MIN_WIRE_VERSION).unwrap(),
+            ibf.snapshot(1),
+            strata.to_bytes(),
+            adaptive.to_bytes(),
+            archive.to_bytes(),
+            compact.to_bytes(),
+            wide.to_bytes(),
+        ];
+        let stream = messages.concat();
+        let mut reader = &stream[..];
+        for message in messages.iter() {
+            assert_eq!(&read_message(&mut reader, usize::MAX).unwrap(), message);
+        }
+        assert!(reader.is_empty());
+
+        assert_eq!(
+            IBF::<u64>::read_from(&messages[0][..], 1 << 20).unwrap(),
+            ibf
+        );
+        assert!(StrataEstimator::read_from(&messages[3][..], 1 << 20).is_ok());
+        assert!(AdaptiveStrataEstimator::read_from(&messages[4][..], 1 << 20).is_ok());
+        assert_eq!(
+            SketchArchive::read_from(&messages[5][..], 1 << 20).unwrap(),
+            archive
+        );
+        assert_eq!(IBF::read_from(&messages[6][..], 1 << 20).unwrap(), compact);
+        assert_eq!(IBF::read_from(&messages[7][..], 1 << 20).unwrap(), wide);
+        // The wrong kind fails on the header
+        let err = StrataEstimator::read_from(&messages[0][..4], 1 << 20).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn huge_claims_cost_only_what_arrives() {
+        // 100 bytes claiming an IBF of 2^32 - 1 cells
+        let mut forged = IBF::<u64>::new(10).to_bytes();
+        forged[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        forged.truncate(100);
+        let err = read_message(&forged[..], 1 << 20).unwrap_err();
+        let err = err
+            .into_inner()
+            .unwrap()
+            .downcast::<IronRoseError>()
+            .unwrap();
+        assert_eq!(
+            *err,
+            IronRoseError::OverWireBudget {
+                bytes: 12 + (u32::MAX as usize) * 20 + 4,
+                max: 1 << 20
+            }
+        );
+
+        // With no limit, reading stops when the stream does, a chunk at a time
+        let reader = Bounded {
+            bytes: &forged,
+            limit: READ_CHUNK,
+        };
+        let err = read_message(reader, usize::MAX).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        // As do strata and archives
+        let mut strata = StrataEstimator::default().to_bytes();
+        strata[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        strata.truncate(100);
+        let reader = Bounded {
+            bytes: &strata,
+            limit: READ_CHUNK,
+        };
+        assert!(read_message(reader, usize::MAX).is_err());
+        let archive = SketchArchive::new(IBF::<u64>::new(10).to_bytes(), Default::default())
+            .unwrap()
+            .to_bytes();
+        let mut claimed = archive[..8].to_vec();
+        claimed.extend_from_slice(&u32::MAX.to_le_bytes());
+        claimed.extend_from_slice(&[0; 50]);
+        let reader = Bounded {
+            bytes: &claimed,
+            limit: READ_CHUNK,
+        };
+        let err = read_message(reader, usize::MAX).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,162 @@
+//! Fixtures and assertions for testing code built on top of iron_rose, enabled with the
+//! `testkit` feature. Intended for use from downstream `dev-dependencies`.
+//! ```rust
+//! use iron_rose::{assert_reconciles, testkit::overlapping_sets};
+//!
+//! let (local, remote) = overlapping_sets(1000, 10, 15, 42);
+//! assert_reconciles!(local, remote, 100);
+//! ```
+use crate::{IronRoseError, Side, IBF};
+use std::{
+    collections::HashSet,
+    fmt::Debug,
+    ops::{BitXor, BitXorAssign},
+};
+
+/// Builds two deterministic sets of ids sharing `core` elements, with `left_only` elements only
+/// in the first set and `right_only` only in the second. The same seed always produces the same
+/// sets.
+pub fn overlapping_sets(
+    core: usize,
+    left_only: usize,
+    right_only: usize,
+    seed: u64,
+) -> (Vec<u128>, Vec<u128>) {
+    let mut state = seed;
+    let mut next = || {
+        let hi = splitmix64(&mut state) as u128;
+        let lo = splitmix64(&mut state) as u128;
+        (hi << 64) | lo
+    };
+    let shared = (0..core).map(|_| next()).collect::<Vec<_>>();
+    let mut left = shared.clone();
+    left.extend((0..left_only).map(|_| next()));
+    let mut right = shared;
+    right.extend((0..right_only).map(|_| next()));
+    (left, right)
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// The differences a correct reconciliation of left against right must produce, computed
+/// directly from the sets.
+pub fn expected_differences<T>(left: &[T], right: &[T]) -> HashSet<Side<T>>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    let (l, r): (HashSet<&T>, HashSet<&T>) = (left.iter().collect(), right.iter().collect());
+    l.difference(&r)
+        .map(|x| Side::Left((*x).clone()))
+        .chain(r.difference(&l).map(|x| Side::Right((*x).clone())))
+        .collect()
+}
+
+/// Encodes both sets into IBFs of the given shape, subtracts and decodes them, and returns the
+/// decoded differences.
+pub fn reconcile<T>(
+    left: &[T],
+    right: &[T],
+    size: usize,
+    hash_count: usize,
+) -> Result<HashSet<Side<T>>, IronRoseError>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    let mut l = IBF::new_with_hash_count(size, hash_count);
+    let mut r = IBF::new_with_hash_count(size, hash_count);
+    for x in left {
+        l.encode(x.clone());
+    }
+    for x in right {
+        r.encode(x.clone());
+    }
+    Ok((l - r)?.decode()?)
+}
+
+/// Panics with a description of what went wrong unless reconciling left against right with
+/// IBFs of the given shape recovers exactly the expected differences. Used by
+/// [assert_reconciles](crate::assert_reconciles).
+pub fn check_reconciles<T>(left: &[T], right: &[T], size: usize, hash_count: usize)
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    let expected = expected_differences(left, right);
+    let decoded = match reconcile(left, right, size, hash_count) {
+        Ok(decoded) => decoded,
+        Err(e) => panic!(
+            "failed to reconcile {} differences with size {} and hash_count {}: {}",
+            expected.len(),
+            size,
+            hash_count,
+            e
+        ),
+    };
+    let missing = expected.difference(&decoded).collect::<Vec<_>>();
+    let unexpected = decoded.difference(&expected).collect::<Vec<_>>();
+    assert!(
+        missing.is_empty() && unexpected.is_empty(),
+        "reconciliation mismatch, missing: {:?}, unexpected: {:?}",
+        missing,
+        unexpected
+    );
+}
+
+/// Asserts that two sets reconcile through IBFs of the given size (and optionally hash_count,
+/// defaulting to 3), recovering exactly the elements unique to each side.
+#[macro_export]
+macro_rules! assert_reconciles {
+    ($left:expr, $right:expr, $size:expr) => {
+        $crate::assert_reconciles!($left, $right, $size, 3)
+    };
+    ($left:expr, $right:expr, $size:expr, $hash_count:expr) => {{
+        let left = ::std::iter::IntoIterator::into_iter($left).collect::<::std::vec::Vec<_>>();
+        let right = ::std::iter::IntoIterator::into_iter($right).collect::<::std::vec::Vec<_>>();
+        $crate::testkit::check_reconciles(&left, &right, $size, $hash_count)
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixtures_are_deterministic() {
+        assert_eq!(overlapping_sets(10, 2, 3, 7), overlapping_sets(10, 2, 3, 7));
+        let (left, right) = overlapping_sets(10, 2, 3, 7);
+        assert_eq!(expected_differences(&left, &right).len(), 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "failed to reconcile")]
+    fn undersized_panics() {
+        let (left, right) = overlapping_sets(100, 50, 50, 1);
+        assert_reconciles!(left, right, 10);
+    }
+}
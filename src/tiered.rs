@@ -0,0 +1,161 @@
+use crate::{IronRoseError, Side, SketchParams, IBF};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fmt::Debug,
+    ops::{BitXor, BitXorAssign, Sub},
+};
+
+/// A sketch split into a small hot tier for recently changed elements and a large cold tier for
+/// stable ones. Frequent reconciliations can exchange and subtract only the
+/// [hot](TieredIBF::hot) tiers, falling back to the whole sketch every so often.
+///
+/// Elements move from hot to cold with [settle](TieredIBF::settle). When two replicas settle an
+/// element at different times it shows up in both tier differences, on opposite sides, and the
+/// combined [decode](TieredIBF::decode) cancels it out.
+/// ```rust
+/// use iron_rose::{Side, SketchParams, TieredIBF};
+///
+/// let (hot, cold) = (SketchParams::new(20, 3), SketchParams::new(200, 3));
+/// let (mut left, mut right) = (TieredIBF::new(hot, cold), TieredIBF::new(hot, cold));
+/// for i in 0..100u64 {
+///     left.encode_cold(i);
+///     right.encode_cold(i);
+/// }
+/// left.encode(100);
+/// right.encode(100);
+/// right.settle(&100);
+/// left.encode(101);
+/// let diff = (&left - &right).unwrap().decode().unwrap();
+/// assert_eq!(diff.into_iter().collect::<Vec<_>>(), vec![Side::Left(101)]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TieredIBF<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    hot: IBF<T>,
+    cold: IBF<T>,
+}
+
+impl<T> TieredIBF<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// New tiered IBF with the given shapes for each tier
+    pub fn new(hot: SketchParams, cold: SketchParams) -> Self {
+        Self {
+            hot: IBF::with_params(hot),
+            cold: IBF::with_params(cold),
+        }
+    }
+
+    /// Encodes a recently changed element into the hot tier
+    pub fn encode(&mut self, element: T) {
+        self.hot.encode(element)
+    }
+
+    /// Encodes a stable element straight into the cold tier, such as when loading a sketch
+    pub fn encode_cold(&mut self, element: T) {
+        self.cold.encode(element)
+    }
+
+    /// Moves an element, previously [encoded](TieredIBF::encode) into the hot tier, into the
+    /// cold tier
+    pub fn settle(&mut self, element: &T) {
+        self.hot.remove(element.clone());
+        self.cold.encode(element.clone());
+    }
+
+    /// The hot tier, which is all that needs exchanging for a quick reconciliation
+    pub fn hot(&self) -> &IBF<T> {
+        &self.hot
+    }
+
+    /// The cold tier
+    pub fn cold(&self) -> &IBF<T> {
+        &self.cold
+    }
+
+    /// Decodes both tiers of a subtracted tiered IBF, cancelling out elements that are only
+    /// different because they were settled at different times.
+    pub fn decode(self) -> Result<HashSet<Side<T>>, IronRoseError> {
+        let hot = self.hot.decode()?;
+        let mut cold = self.cold.decode()?;
+        let mut set = HashSet::new();
+        for side in hot {
+            let flipped = match &side {
+                Side::Left(elm) => Side::Right(elm.clone()),
+                Side::Right(elm) => Side::Left(elm.clone()),
+            };
+            if !cold.remove(&flipped) {
+                set.insert(side);
+            }
+        }
+        set.extend(cold);
+        Ok(set)
+    }
+}
+
+impl<T> Sub for &TieredIBF<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    type Output = Result<TieredIBF<T>, IronRoseError>;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Ok(TieredIBF {
+            hot: (&self.hot - &rhs.hot)?,
+            cold: (&self.cold - &rhs.cold)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hot_tier_alone_reconciles_recent_changes() {
+        let (hot, cold) = (SketchParams::new(30, 3), SketchParams::new(300, 3));
+        let (mut left, mut right) = (TieredIBF::new(hot, cold), TieredIBF::new(hot, cold));
+        for i in 0..1000u64 {
+            left.encode_cold(i);
+            right.encode_cold(i);
+        }
+        for i in 1000..1005u64 {
+            left.encode(i);
+        }
+        let hot_diff = (left.hot() - right.hot()).unwrap().decode().unwrap();
+        assert_eq!(hot_diff.len(), 5);
+
+        for i in 1000..1005u64 {
+            left.settle(&i);
+        }
+        assert!(left.hot().is_empty());
+        let diff = (&left - &right).unwrap().decode().unwrap();
+        assert_eq!(diff.len(), 5);
+        assert!(diff.iter().all(|side| matches!(side, Side::Left(_))));
+    }
+}
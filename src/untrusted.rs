@@ -0,0 +1,202 @@
+//! What the crate guarantees about input from an untrusted peer.
+//!
+//! Every function on this list returns an error on bad input rather than panicking, whatever
+//! the bytes or sketch it's given, and allocates no more than the input's size calls for:
+//!
+//! - Reading sketches: [IBF::from_bytes](crate::IBF::from_bytes),
+//!   [StrataEstimator::from_bytes](crate::StrataEstimator::from_bytes),
+//!   [AdaptiveStrataEstimator::from_bytes](crate::AdaptiveStrataEstimator::from_bytes),
+//!   [IBF::restore](crate::IBF::restore),
+//!   [SketchArchive::from_bytes](crate::SketchArchive::from_bytes),
+//!   [read_message](crate::read_message) and the `read_from` methods, the `from_bytes` of the
+//!   [reconcile](crate::reconcile) messages, and serde deserialization of IBFs and estimators.
+//! - Combining them with local sketches: subtraction, [merge](crate::IBF::merge),
+//!   [negate](crate::IBF::negate), [estimate](crate::StrataEstimator::estimate) and
+//!   [estimate_overlapping](crate::StrataEstimator::estimate_overlapping).
+//! - Decoding the results, with any of the `decode` methods or a
+//!   [Decoder](crate::Decoder), and inspecting them, such as with
+//!   [stuck_core](crate::IBF::stuck_core).
+//!
+//! Forged cells can't make a decode loop forever or recover more elements than the IBF has
+//! cells, and counts that would overflow fail with
+//! [CountOverflow](crate::IronRoseError::CountOverflow). The crate has no unsafe code.
+//!
+//! Constructors taking local configuration, such as [IBF::new](crate::IBF::new) or
+//! [StrataEstimatorConfig::new](crate::StrataEstimatorConfig::new), do panic on nonsensical
+//! arguments, and are not part of the guarantee. Sizes decided by a peer should go through
+//! [IBF::try_with_params](crate::IBF::try_with_params) instead.
+//!
+//! The guarantee is checked by [exercise](exercise), which a fuzz target and a property test
+//! run over arbitrary and corrupted input.
+use crate::reconcile::{EstimatorMsg, IbfMsg};
+use crate::strata_estimator::RawStrataEstimator;
+use crate::wire;
+use crate::{
+    read_message, AdaptiveStrataEstimator, SketchArchive, StrataEstimator, WireElement, IBF,
+};
+use std::{
+    convert::TryFrom,
+    fmt::Debug,
+    hash::Hash,
+    ops::{BitXor, BitXorAssign},
+    sync::Arc,
+};
+
+/// Runs data through every function the guarantee covers, ignoring their errors. It panics
+/// only if one of them does, for fuzzers and tests to catch. The first byte picks where the
+/// rest is split in two, to be read as sketches of each kind and combined with each other.
+/// Each half is also tried with its [checksum](crate::WIRE_VERSION) recomputed, since almost
+/// all corrupted input would otherwise fail it before reaching the parsing behind it.
+pub fn exercise(data: &[u8]) {
+    let (split, data) = match data.split_first() {
+        Some((split, data)) => (*split as usize * data.len() / 256, data),
+        None => return,
+    };
+    let (left, right) = data.split_at(split);
+    exercise_halves(left, right);
+    let (resealed_left, resealed_right) = (wire::resealed(left), wire::resealed(right));
+    if resealed_left != left || resealed_right != right {
+        exercise_halves(&resealed_left, &resealed_right);
+    }
+}
+
+fn exercise_halves(left: &[u8], right: &[u8]) {
+    exercise_ibfs::<u64>(left, right);
+    exercise_ibfs::<u128>(left, right);
+
+    let _ = EstimatorMsg::from_bytes(left);
+    let _ = read_message(left, 1 << 20);
+    let _ = StrataEstimator::read_from(right, 1 << 20);
+    if let Ok(archive) = SketchArchive::from_bytes(left) {
+        let _ = archive.to_bytes();
+    }
+    if let (Ok(l), Ok(r)) = (
+        StrataEstimator::from_bytes(left),
+        StrataEstimator::from_bytes(right),
+    ) {
+        exercise_estimators(l, &r);
+    }
+    // Deserialized estimators go through the same check as serde, with strata of whatever
+    // number and shapes the input holds
+    let strata = [left, right]
+        .iter()
+        .filter_map(|bytes| IBF::<u64>::from_bytes(bytes).ok())
+        .map(Arc::new)
+        .collect::<Vec<_>>();
+    if let Ok(deserialized) = StrataEstimator::try_from(RawStrataEstimator { ibfs: strata }) {
+        exercise_estimators(deserialized.clone(), &StrataEstimator::default());
+        exercise_estimators(StrataEstimator::default(), &deserialized);
+    }
+    if let (Ok(l), Ok(r)) = (
+        AdaptiveStrataEstimator::from_bytes(left),
+        AdaptiveStrataEstimator::from_bytes(right),
+    ) {
+        let _ = l.estimate(&r);
+        let _ = l.to_bytes();
+    }
+}
+
+fn exercise_estimators(mut left: StrataEstimator, right: &StrataEstimator) {
+    let _ = left.estimate_traced(right);
+    let _ = left.estimate_overlapping(right);
+    let _ = left.clone().merge(right);
+    let _ = StrataEstimator::from_bytes(&left.to_bytes());
+    left.encode(0u64);
+}
+
+fn exercise_ibfs<T>(left: &[u8], right: &[u8])
+where
+    T: Clone
+        + Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug
+        + WireElement,
+{
+    let _ = IBF::<T>::restore(left);
+    let _ = IbfMsg::<T>::from_bytes(left);
+    let (left, right) = match (IBF::<T>::from_bytes(left), IBF::<T>::from_bytes(right)) {
+        (Ok(left), Ok(right)) => (left, right),
+        (Ok(one), Err(_)) | (Err(_), Ok(one)) => (one.clone(), one.negate()),
+        _ => return,
+    };
+    let _ = left.to_bytes();
+    let _ = left.maybe_contains(&T::default());
+    let _ = left.stuck_core();
+    let _ = left.load_histogram();
+    let mut merged = left.clone();
+    let _ = merged.merge(&right);
+    let diff = match &left - &right {
+        Ok(diff) => diff,
+        Err(_) => return,
+    };
+    let _ = diff.clone().decode();
+    let _ = diff.clone().decode_difference();
+    let _ = diff.clone().decode_validated(|_| false);
+    let _ = diff.clone().decode_up_to(4);
+    let _ = diff.clone().decode_into(&mut Vec::new());
+    let _ = diff.clone().negate().decode_partial();
+    let _ = diff.into_decoder().count();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SketchMetadata, SketchParams, StrataEstimatorConfig};
+    use proptest::prelude::*;
+
+    /// Encodings of valid sketches, so corruption gets past the header checks
+    fn valid_bytes(elements: &[u64], size: usize) -> Vec<Vec<u8>> {
+        let mut ibf = IBF::with_params(SketchParams::new(size, 3.min(size)));
+        let mut estimator =
+            StrataEstimator::with_config(StrataEstimatorConfig::new(4, SketchParams::new(8, 2)));
+        let mut adaptive = AdaptiveStrataEstimator::new(2, SketchParams::new(8, 2));
+        for x in elements {
+            ibf.encode(*x);
+            estimator.encode(x);
+            adaptive.encode(x);
+        }
+        let wide: IBF<u128> = IBF::with_params(ibf.params());
+        let metadata = SketchMetadata::default()
+            .with_label("fuzz")
+            .with_entry("size", "small");
+        let archive = SketchArchive::new(ibf.to_bytes(), metadata).unwrap();
+        vec![
+            ibf.to_bytes(),
+            ibf.snapshot(3),
+            wide.to_bytes(),
+            estimator.to_bytes(),
+            adaptive.to_bytes(),
+            archive.to_bytes(),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn corrupted_sketches_never_panic(
+            elements in prop::collection::vec(any::<u64>(), 0..40),
+            size in 1usize..24,
+            kind in 0usize..6,
+            split in prop_oneof![Just(128u8), any::<u8>()],
+            flips in prop::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 0..6),
+        ) {
+            let valid = valid_bytes(&elements, size).swap_remove(kind);
+            let mut data = vec![split];
+            data.extend_from_slice(&valid);
+            data.extend_from_slice(&valid);
+            for (at, byte) in flips {
+                let at = at.index(data.len());
+                data[at] ^= byte;
+            }
+            exercise(&data);
+        }
+
+        #[test]
+        fn arbitrary_bytes_never_panic(data in prop::collection::vec(any::<u8>(), 0..512)) {
+            exercise(&data);
+        }
+    }
+}
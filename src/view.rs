@@ -0,0 +1,220 @@
+use crate::{element_hash, SketchParams, IBF};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    hash::Hash,
+    ops::{BitXor, BitXorAssign},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// A filtered logical view of a collection, such as one tenant's rows or the results of an API
+/// query, described by data rather than a closure so that it can be hashed. Views that hash the
+/// same are taken to select the same elements, which is what lets a [ViewCache](ViewCache)
+/// share a sketch between every request for the view.
+/// ```rust
+/// use iron_rose::{SketchParams, ViewFilter, IBF};
+///
+/// #[derive(Hash)]
+/// struct Tenant(u64);
+///
+/// impl ViewFilter<u64> for Tenant {
+///     fn matches(&self, id: &u64) -> bool {
+///         id % 100 == self.0
+///     }
+/// }
+///
+/// let ibf = IBF::from_view(SketchParams::new(20, 3), 0..1000u64, &Tenant(7));
+/// assert_eq!(ibf, IBF::filtered_from(SketchParams::new(20, 3), 0..1000u64, |id| id % 100 == 7));
+/// ```
+pub trait ViewFilter<T>: Hash {
+    /// True if element is in the view
+    fn matches(&self, element: &T) -> bool;
+
+    /// Key identifying the view, its [element_hash](crate::element_hash), which is the same in
+    /// every process
+    fn filter_key(&self) -> u64 {
+        element_hash(self)
+    }
+}
+
+impl<T> IBF<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// New IBF with the given shape, holding the elements of src that are in view, the same as
+    /// [filtered_from](IBF::filtered_from) with the view's [matches](ViewFilter::matches)
+    pub fn from_view<I, F>(params: SketchParams, src: I, view: &F) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        F: ViewFilter<T> + ?Sized,
+    {
+        Self::filtered_from(params, src, |element| view.matches(element))
+    }
+}
+
+/// Sketches of filtered views of a collection, keyed by [filter key](ViewFilter::filter_key)
+/// and epoch, so an API server can offer reconciliation per collection and filter without
+/// scanning the collection for every request. Bump the epoch whenever the collection changes.
+/// Entries expire ttl after they were built, and expired entries are dropped whenever the cache
+/// is written to. Large sketches can be served in pages with [slice](IBF::slice).
+/// ```rust
+/// use std::time::Duration;
+/// use iron_rose::{SketchParams, ViewCache, ViewFilter};
+///
+/// #[derive(Hash)]
+/// struct Even;
+///
+/// impl ViewFilter<u64> for Even {
+///     fn matches(&self, id: &u64) -> bool {
+///         id % 2 == 0
+///     }
+/// }
+///
+/// let mut cache = ViewCache::new(SketchParams::new(40, 3), Duration::from_secs(60));
+/// let sketch = cache.get_or_build(&Even, 1, || 0..100u64);
+/// // Served from the cache, without scanning the collection
+/// let again = cache.get_or_build(&Even, 1, || -> std::ops::Range<u64> { unreachable!() });
+/// assert_eq!(sketch, again);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ViewCache<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    params: SketchParams,
+    ttl: Duration,
+    entries: HashMap<(u64, u64), (Instant, Arc<IBF<T>>)>,
+}
+
+impl<T> ViewCache<T>
+where
+    T: Clone
+        + std::hash::Hash
+        + BitXor<Output = T>
+        + BitXorAssign
+        + Default
+        + PartialEq
+        + Eq
+        + Debug,
+{
+    /// New, empty cache of sketches with the given shape, whose entries live for ttl
+    pub fn new(params: SketchParams, ttl: Duration) -> Self {
+        Self {
+            params,
+            ttl,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Shape of every sketch in the cache
+    pub fn params(&self) -> SketchParams {
+        self.params
+    }
+
+    /// The cached sketch of view at epoch, unless it's missing or expired
+    pub fn get<F>(&self, view: &F, epoch: u64) -> Option<Arc<IBF<T>>>
+    where
+        F: ViewFilter<T> + ?Sized,
+    {
+        self.entries
+            .get(&(view.filter_key(), epoch))
+            .filter(|(built, _)| built.elapsed() < self.ttl)
+            .map(|(_, ibf)| Arc::clone(ibf))
+    }
+
+    /// The cached sketch of view at epoch, building it from the elements src returns if there
+    /// is none
+    pub fn get_or_build<F, S, I>(&mut self, view: &F, epoch: u64, src: S) -> Arc<IBF<T>>
+    where
+        F: ViewFilter<T> + ?Sized,
+        S: FnOnce() -> I,
+        I: IntoIterator<Item = T>,
+    {
+        if let Some(ibf) = self.get(view, epoch) {
+            return ibf;
+        }
+        self.evict_expired();
+        let ibf = Arc::new(IBF::from_view(self.params, src(), view));
+        self.entries.insert(
+            (view.filter_key(), epoch),
+            (Instant::now(), Arc::clone(&ibf)),
+        );
+        ibf
+    }
+
+    /// Drops the sketches of view, at every epoch
+    pub fn invalidate<F>(&mut self, view: &F)
+    where
+        F: ViewFilter<T> + ?Sized,
+    {
+        let key = view.filter_key();
+        self.entries.retain(|(filter, _), _| *filter != key);
+    }
+
+    /// Drops every expired entry
+    pub fn evict_expired(&mut self) {
+        let ttl = self.ttl;
+        self.entries.retain(|_, (built, _)| built.elapsed() < ttl);
+    }
+
+    /// Number of entries, including expired ones that haven't been dropped yet
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// True if there are no entries
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Hash)]
+    struct Modulo(u64, u64);
+
+    impl ViewFilter<u64> for Modulo {
+        fn matches(&self, element: &u64) -> bool {
+            element % self.0 == self.1
+        }
+    }
+
+    #[test]
+    fn views_are_cached_per_filter_and_epoch() {
+        let mut cache = ViewCache::new(SketchParams::new(20, 3), Duration::from_secs(3600));
+        let (threes, fives) = (Modulo(3, 0), Modulo(5, 0));
+        let built = cache.get_or_build(&threes, 1, || 0..30u64);
+        cache.get_or_build(&fives, 1, || 0..30u64);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&threes, 1), Some(built));
+        assert!(cache.get(&threes, 2).is_none());
+
+        let bigger = cache.get_or_build(&threes, 2, || 0..33u64);
+        let diff = (&*bigger - &*cache.get(&threes, 1).unwrap())
+            .unwrap()
+            .decode_difference()
+            .unwrap();
+        assert_eq!(diff.left(), &[30]);
+
+        cache.invalidate(&threes);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get(&fives, 1).is_some());
+    }
+}
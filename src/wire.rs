@@ -0,0 +1,301 @@
+use crate::IronRoseError;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+/// Version of the binary format written by [IBF::to_bytes](crate::IBF::to_bytes) and
+/// [StrataEstimator::to_bytes](crate::StrataEstimator::to_bytes). Readers accept versions from
+/// [MIN_WIRE_VERSION](MIN_WIRE_VERSION) up to this one and reject any other, so the layout can
+/// change without peers misreading each other.
+///
+/// Version 2 adds an element to a cell only once when several of its hash functions pick that
+/// cell, where version 1 added it once per hash function. Version 3 ends every message but a
+/// [snapshot](crate::IBF::snapshot), which has its own checksum, with a little endian CRC32
+/// (IEEE) of everything before it, so a truncated or corrupted transfer fails with
+/// [ChecksumMismatch](crate::IronRoseError::ChecksumMismatch) before anything is parsed.
+pub const WIRE_VERSION: u8 = 3;
+
+/// Oldest version of the binary format this build still reads, and writes through the
+/// `to_bytes_as` methods, for talking to peers that haven't been upgraded yet
+pub const MIN_WIRE_VERSION: u8 = 2;
+
+/// First version whose messages end with a CRC32
+const CHECKSUMMED_SINCE: u8 = 3;
+
+/// True if a message of kind written in version ends with a CRC32
+pub(crate) fn has_checksum(version: u8, kind: u8) -> bool {
+    version >= CHECKSUMMED_SINCE && kind != KIND_SNAPSHOT
+}
+
+/// Kind byte of a serialized [IBF](crate::IBF)
+pub(crate) const KIND_IBF: u8 = 0;
+/// Kind byte of a serialized [StrataEstimator](crate::StrataEstimator)
+pub(crate) const KIND_STRATA: u8 = 1;
+/// Kind byte of an [IBF snapshot](crate::IBF::snapshot)
+pub(crate) const KIND_SNAPSHOT: u8 = 2;
+/// Kind byte of a serialized [AdaptiveStrataEstimator](crate::AdaptiveStrataEstimator)
+pub(crate) const KIND_ADAPTIVE_STRATA: u8 = 3;
+/// Kind byte of a sketch archive
+pub(crate) const KIND_ARCHIVE: u8 = 4;
+/// Flag byte bit set when an IBF carries a keyspace
+pub(crate) const FLAG_KEYSPACE: u8 = 1;
+/// Flag byte bits holding the [width](crate::Count) of an IBF's cell counts, all clear for i32
+pub(crate) const FLAGS_COUNT_WIDTH: u8 = 0b110;
+
+/// Bytes per cell count of an IBF with flags, failing on a width this build doesn't know
+pub(crate) fn count_width(flags: u8) -> Result<usize, IronRoseError> {
+    match flags & FLAGS_COUNT_WIDTH {
+        0 => Ok(4),
+        2 => Ok(2),
+        4 => Ok(8),
+        _ => Err(IronRoseError::MalformedBytes {
+            reason: "unknown cell count width",
+        }),
+    }
+}
+
+/// What a message in the binary wire format holds, read from its header with
+/// [of](MessageKind::of), so a receiver can tell which `from_bytes` to hand it to. New kinds of
+/// sketch get new variants, so matches outside the crate need a wildcard arm.
+/// ```rust
+/// use iron_rose::{MessageKind, StrataEstimator, IBF};
+///
+/// let bytes = IBF::<u64>::new(10).to_bytes();
+/// assert_eq!(MessageKind::of(&bytes), Ok(MessageKind::Ibf));
+/// let bytes = StrataEstimator::default().to_bytes();
+/// assert_eq!(MessageKind::of(&bytes), Ok(MessageKind::StrataEstimator));
+/// assert!(MessageKind::of(&[]).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum MessageKind {
+    /// An [IBF](crate::IBF)
+    Ibf,
+    /// A [StrataEstimator](crate::StrataEstimator)
+    StrataEstimator,
+    /// An [IBF snapshot](crate::IBF::snapshot)
+    Snapshot,
+    /// An [AdaptiveStrataEstimator](crate::AdaptiveStrataEstimator)
+    AdaptiveStrataEstimator,
+    /// A sketch wrapped with metadata in an archive, read with `SketchArchive::from_bytes`
+    Archive,
+}
+
+impl MessageKind {
+    /// The kind of message bytes hold, failing on an unsupported format version or an unknown kind.
+    /// The rest of the message isn't checked.
+    pub fn of(bytes: &[u8]) -> Result<Self, IronRoseError> {
+        let mut reader = Reader::new(bytes);
+        check_version(reader.u8()?)?;
+        match reader.u8()? {
+            KIND_IBF => Ok(MessageKind::Ibf),
+            KIND_STRATA => Ok(MessageKind::StrataEstimator),
+            KIND_SNAPSHOT => Ok(MessageKind::Snapshot),
+            KIND_ADAPTIVE_STRATA => Ok(MessageKind::AdaptiveStrataEstimator),
+            KIND_ARCHIVE => Ok(MessageKind::Archive),
+            _ => Err(IronRoseError::MalformedBytes {
+                reason: "unknown kind of sketch",
+            }),
+        }
+    }
+}
+
+/// Element types with a fixed width, little endian encoding, which is what lets
+/// [IBF::to_bytes](crate::IBF::to_bytes) lay cells out at fixed offsets.
+pub trait WireElement: Sized {
+    /// Number of bytes an element takes
+    const WIDTH: usize;
+
+    /// Appends the element's WIDTH bytes to out
+    fn write_le(&self, out: &mut Vec<u8>);
+
+    /// Reads an element back from exactly WIDTH bytes
+    fn read_le(bytes: &[u8]) -> Self;
+}
+
+macro_rules! wire_int {
+    ($($t:ty),*) => {
+        $(
+            impl WireElement for $t {
+                const WIDTH: usize = core::mem::size_of::<$t>();
+
+                fn write_le(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn read_le(bytes: &[u8]) -> Self {
+                    <$t>::from_le_bytes(bytes.try_into().expect("WIDTH bytes"))
+                }
+            }
+        )*
+    };
+}
+
+wire_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+/// Fails unless this build reads and writes version
+pub(crate) fn check_version(version: u8) -> Result<(), IronRoseError> {
+    if (MIN_WIRE_VERSION..=WIRE_VERSION).contains(&version) {
+        Ok(())
+    } else {
+        Err(IronRoseError::UnsupportedVersion { version })
+    }
+}
+
+/// Writes the fixed header every message starts with, in a version passed by
+/// [check_version](check_version)
+pub(crate) fn write_header(out: &mut Vec<u8>, version: u8, kind: u8, width: usize, flags: u8) {
+    out.push(version);
+    out.push(kind);
+    out.push(width as u8);
+    out.push(flags);
+}
+
+/// Ends the message in out, adding its checksum if its version has one
+pub(crate) fn seal(out: &mut Vec<u8>) {
+    if has_checksum(out[0], out[1]) {
+        let checksum = crc32(out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+    }
+}
+
+/// Copy of a message with its checksum recomputed over whatever it now holds, so tests and
+/// fuzzers can get corrupted messages past the checksum to the parsing behind it
+#[cfg(any(test, feature = "std"))]
+pub(crate) fn resealed(bytes: &[u8]) -> Vec<u8> {
+    let mut out = bytes.to_vec();
+    if out.len() >= 2 + 4 && has_checksum(out[0], out[1]) {
+        out.truncate(out.len() - 4);
+        seal(&mut out);
+    }
+    out
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC32 with the IEEE polynomial, as used by zlib and Ethernet
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc: u32, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Cursor over a received message, failing instead of panicking on short input
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    /// Reads and checks the header, returning its flags. From version 3 on, the message's
+    /// checksum is verified and dropped from what's left to read.
+    pub(crate) fn header(&mut self, kind: u8, width: usize) -> Result<u8, IronRoseError> {
+        let message = self.bytes;
+        let version = self.u8()?;
+        check_version(version)?;
+        if self.u8()? != kind {
+            return Err(IronRoseError::MalformedBytes {
+                reason: "not the expected kind of sketch",
+            });
+        }
+        if has_checksum(version, kind) {
+            if message.len() < 2 + 4 {
+                return Err(IronRoseError::MalformedBytes {
+                    reason: "truncated",
+                });
+            }
+            let (body, checksum) = message.split_at(message.len() - 4);
+            if crc32(body) != u32::read_le(checksum) {
+                return Err(IronRoseError::ChecksumMismatch);
+            }
+            self.bytes = &body[2..];
+        }
+        if self.u8()? as usize != width {
+            return Err(IronRoseError::MalformedBytes {
+                reason: "element width doesn't match the element type",
+            });
+        }
+        self.u8()
+    }
+
+    pub(crate) fn take(&mut self, n: usize) -> Result<&'a [u8], IronRoseError> {
+        if self.bytes.len() < n {
+            return Err(IronRoseError::MalformedBytes {
+                reason: "truncated",
+            });
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    /// Fails unless at least n bytes are left, checked before allocating for them
+    pub(crate) fn expect_at_least(&self, n: Option<usize>) -> Result<(), IronRoseError> {
+        match n {
+            Some(n) if n <= self.bytes.len() => Ok(()),
+            _ => Err(IronRoseError::MalformedBytes {
+                reason: "truncated",
+            }),
+        }
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, IronRoseError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, IronRoseError> {
+        Ok(u16::read_le(self.take(2)?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, IronRoseError> {
+        Ok(u32::read_le(self.take(4)?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, IronRoseError> {
+        Ok(u64::read_le(self.take(8)?))
+    }
+
+    /// Fails if anything is left over
+    pub(crate) fn finish(self) -> Result<(), IronRoseError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(IronRoseError::MalformedBytes {
+                reason: "trailing bytes",
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_matches_the_standard_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+}
@@ -0,0 +1,138 @@
+//! Reconciles randomized sets between two processes over a localhost TCP socket, with
+//! [sync_over](iron_rose::reconcile::sync_over) at both ends, so the wire format and protocol
+//! are exercised the way two machines would, not just through in-memory pipes.
+//!
+//! It spawns processes and binds ports, so it's ignored by default:
+//!
+//! ```text
+//! cargo test --features tokio --test two_processes -- --ignored
+//! ```
+//!
+//! Seeds are random and printed; set `IRON_ROSE_SEED` to rerun one.
+#![cfg(feature = "tokio")]
+
+use iron_rose::{reconcile::sync_over, Side};
+use std::{
+    collections::BTreeSet,
+    env,
+    net::{TcpListener, TcpStream},
+    process::Command,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Where the spawned peer finds the address to connect to and the seed of its set
+const PEER_ENV: &str = "IRON_ROSE_PEER";
+const SEED_ENV: &str = "IRON_ROSE_SEED";
+const ROUNDS: u64 = 4;
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// SplitMix64, so both processes derive the same sets from a seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+/// The elements both sides share, then the ones only the first side has, then the ones only
+/// the second side has
+fn sets(seed: u64) -> (Vec<u64>, Vec<u64>, Vec<u64>) {
+    let mut rng = Rng(seed);
+    let counts = [rng.below(5000), rng.below(300), rng.below(300)];
+    let mut seen = BTreeSet::new();
+    let mut sets = counts.iter().map(|&count| {
+        let mut set = Vec::new();
+        while (set.len() as u64) < count {
+            let x = rng.next();
+            if seen.insert(x) {
+                set.push(x);
+            }
+        }
+        set
+    });
+    let shared = sets.next().unwrap();
+    let first = sets.next().unwrap();
+    let second = sets.next().unwrap();
+    (shared, first, second)
+}
+
+/// Reconciles local with whoever is at the other end of stream, and checks the difference is
+/// local_only on the left and remote_only on the right
+fn reconcile(stream: TcpStream, local: Vec<u64>, local_only: &[u64], remote_only: &[u64]) {
+    stream.set_nonblocking(true).unwrap();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let mut diff = runtime.block_on(async {
+        let stream = tokio::net::TcpStream::from_std(stream).unwrap();
+        tokio::time::timeout(TIMEOUT, sync_over(stream, local))
+            .await
+            .expect("peer stalled")
+            .unwrap()
+    });
+    let mut expected = local_only
+        .iter()
+        .map(|x| Side::Left(*x))
+        .chain(remote_only.iter().map(|x| Side::Right(*x)))
+        .collect::<Vec<_>>();
+    expected.sort();
+    diff.sort();
+    assert_eq!(diff, expected);
+}
+
+#[test]
+#[ignore]
+fn reconcile_across_processes() {
+    let first_seed = match env::var(SEED_ENV) {
+        Ok(seed) => seed.parse().expect("IRON_ROSE_SEED is a u64"),
+        Err(_) => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64,
+    };
+    for seed in (0..ROUNDS).map(|round| first_seed.wrapping_add(round)) {
+        println!("seed {}", seed);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut peer = Command::new(env::current_exe().unwrap())
+            .args(["peer", "--exact", "--nocapture"])
+            .env(
+                PEER_ENV,
+                format!("{} {}", listener.local_addr().unwrap(), seed),
+            )
+            .spawn()
+            .unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        let (mut local, first, second) = sets(seed);
+        local.extend_from_slice(&first);
+        reconcile(stream, local, &first, &second);
+        assert!(peer.wait().unwrap().success(), "peer failed, seed {}", seed);
+    }
+}
+
+/// The second process, spawned by [reconcile_across_processes]. Run directly, it has nothing
+/// to connect to and passes.
+#[test]
+fn peer() {
+    let config = match env::var(PEER_ENV) {
+        Ok(config) => config,
+        Err(_) => return,
+    };
+    let mut config = config.split(' ');
+    let addr = config.next().unwrap();
+    let seed = config.next().unwrap().parse().unwrap();
+
+    let (mut local, first, second) = sets(seed);
+    local.extend_from_slice(&second);
+    reconcile(TcpStream::connect(addr).unwrap(), local, &second, &first);
+}
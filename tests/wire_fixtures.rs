@@ -0,0 +1,167 @@
+//! Sketches stored in every released version of the binary wire format, which current code must
+//! keep reading, and producing byte for byte through the `to_bytes_as` methods. Run with
+//! `IRON_ROSE_BLESS=1` to write the fixtures for a new version, then commit them; fixtures of
+//! released versions must never change.
+use iron_rose::{
+    AdaptiveStrataEstimator, SketchArchive, SketchMetadata, SketchParams, StrataEstimator,
+    StrataEstimatorConfig, IBF,
+};
+use std::{env, fs, path::PathBuf};
+
+/// Every wire version that has been released
+const RELEASED: [u8; 2] = [2, 3];
+
+fn fixture_dir(version: u8) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/wire")
+        .join(format!("v{}", version))
+}
+
+fn ibf() -> IBF<u64> {
+    let mut ibf = IBF::with_params(SketchParams::new(40, 3));
+    (0..30u64).for_each(|x| ibf.encode(x * 7919));
+    ibf
+}
+
+fn wide_ibf() -> IBF<u128> {
+    let mut ibf = IBF::with_params(SketchParams::new(24, 4)).with_keyspace("orders");
+    (0..15u128).for_each(|x| ibf.encode(x << 70 | x));
+    ibf.remove(3 << 70 | 3);
+    ibf
+}
+
+fn estimator() -> StrataEstimator {
+    let mut estimator =
+        StrataEstimator::with_config(StrataEstimatorConfig::new(8, SketchParams::new(20, 3)));
+    (0..500u64).for_each(|x| estimator.encode(x));
+    estimator
+}
+
+fn adaptive() -> AdaptiveStrataEstimator {
+    let mut estimator = AdaptiveStrataEstimator::new(4, SketchParams::new(12, 2));
+    (0..200u64).for_each(|x| estimator.encode(x));
+    estimator
+}
+
+fn archive() -> SketchArchive {
+    let metadata = SketchMetadata::default()
+        .with_creator("fixtures")
+        .with_created_at(1_792_195_200)
+        .with_label("ibf_u64")
+        .with_entry("elements", "30");
+    SketchArchive::new(ibf().to_bytes(), metadata).unwrap()
+}
+
+/// The stored sketches, by file name, as current code writes them in version
+fn fixtures(version: u8) -> Vec<(&'static str, Vec<u8>)> {
+    let mut fixtures = vec![
+        ("ibf_u64.bin", ibf().to_bytes_as(version).unwrap()),
+        (
+            "ibf_u128_keyspace.bin",
+            wide_ibf().to_bytes_as(version).unwrap(),
+        ),
+        ("strata.bin", estimator().to_bytes_as(version).unwrap()),
+        (
+            "adaptive_strata.bin",
+            adaptive().to_bytes_as(version).unwrap(),
+        ),
+        ("snapshot.bin", ibf().snapshot_as(42, version).unwrap()),
+    ];
+    // Archives came with version 3
+    if version >= 3 {
+        fixtures.push(("archive.bin", archive().to_bytes()));
+    }
+    fixtures
+}
+
+fn read_fixture(version: u8, name: &str) -> Vec<u8> {
+    let path = fixture_dir(version).join(name);
+    fs::read(&path).unwrap_or_else(|e| panic!("reading {}: {}", path.display(), e))
+}
+
+#[test]
+fn current_code_produces_the_fixtures() {
+    let current = iron_rose::WIRE_VERSION;
+    assert_eq!(RELEASED.last(), Some(&current));
+    if env::var_os("IRON_ROSE_BLESS").is_some() {
+        fs::create_dir_all(fixture_dir(current)).unwrap();
+        for (name, bytes) in fixtures(current) {
+            fs::write(fixture_dir(current).join(name), bytes).unwrap();
+        }
+    }
+    for &version in RELEASED.iter() {
+        for (name, bytes) in fixtures(version) {
+            assert!(
+                read_fixture(version, name) == bytes,
+                "{} no longer matches wire version {}",
+                name,
+                version
+            );
+        }
+    }
+}
+
+#[test]
+fn every_released_version_is_readable() {
+    for &version in RELEASED.iter() {
+        assert_eq!(
+            IBF::<u64>::from_bytes(&read_fixture(version, "ibf_u64.bin")),
+            Ok(ibf())
+        );
+        assert_eq!(
+            IBF::<u128>::from_bytes(&read_fixture(version, "ibf_u128_keyspace.bin")),
+            Ok(wide_ibf())
+        );
+
+        let strata = StrataEstimator::from_bytes(&read_fixture(version, "strata.bin")).unwrap();
+        assert_eq!(strata.config(), estimator().config());
+        assert_eq!(strata.estimate_differences(&estimator()), Ok(0));
+        assert_eq!(strata.to_bytes(), estimator().to_bytes());
+
+        let grown =
+            AdaptiveStrataEstimator::from_bytes(&read_fixture(version, "adaptive_strata.bin"))
+                .unwrap();
+        assert_eq!(grown.strata(), adaptive().strata());
+        assert_eq!(grown.estimate_differences(&adaptive()), Ok(0));
+        assert_eq!(grown.to_bytes(), adaptive().to_bytes());
+
+        assert_eq!(
+            IBF::<u64>::restore(&read_fixture(version, "snapshot.bin")),
+            Ok((ibf(), 42))
+        );
+
+        if version >= 3 {
+            let stored = SketchArchive::from_bytes(&read_fixture(version, "archive.bin"));
+            assert_eq!(stored, Ok(archive()));
+        }
+    }
+}
+
+/// The v0 serde format, kept behind the legacy-v0 feature
+#[cfg(feature = "legacy-v0")]
+#[test]
+fn legacy_serde_fixtures_are_read_and_produced() {
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Stored {
+        #[serde(with = "iron_rose::legacy::ibf_serde")]
+        ibf: IBF<u64>,
+        #[serde(with = "iron_rose::legacy::strata_serde")]
+        strata: StrataEstimator,
+    }
+
+    let path = fixture_dir(0).join("sketches.json");
+    let current = serde_json::to_string(&Stored {
+        ibf: ibf(),
+        strata: estimator(),
+    })
+    .unwrap();
+    if env::var_os("IRON_ROSE_BLESS").is_some() {
+        fs::create_dir_all(fixture_dir(0)).unwrap();
+        fs::write(&path, &current).unwrap();
+    }
+    let stored = fs::read_to_string(&path).unwrap();
+    assert_eq!(stored, current);
+    let back: Stored = serde_json::from_str(&stored).unwrap();
+    assert_eq!(back.ibf, ibf());
+    assert_eq!(back.strata.estimate_differences(&estimator()), Ok(0));
+}